@@ -1,12 +1,17 @@
 mod credits_window;
+mod db_errors;
 pub mod ifdb;
+mod images;
 mod licenses;
 mod main_help_window;
+mod maintenance_window;
 mod preferences_window;
+mod shelves;
 mod stats_window;
 mod story_details_window;
 mod story_list_window;
 mod story_load_window;
+mod trash_window;
 
 mod terp;
 
@@ -14,6 +19,7 @@ use crate::testmode_println;
 use eframe::{egui, epi};
 use egui::*;
 use egui::{Pos2, Vec2};
+use ifdb::store::IfdbStore;
 use ifdb::{IfdbConnection, WindowDetails, WindowType};
 use story_list_window::{draw_story_list, StoryListState};
 
@@ -176,6 +182,8 @@ impl FerrifApp {
             testmode_println!("TERP: Restoring window state");
             self.story_list_window_state
                 .restore_themes(&self.connection);
+            self.story_list_window_state
+                .restore_story_list_settings(&self.connection);
         }
         // First time update is run, pull size from database and if it is present,
         // set size to the stored size.
@@ -0,0 +1,40 @@
+use super::ifdb::IfdbError;
+use eframe::egui;
+use egui::{Color32, Ui};
+use native_dialog::{MessageDialog, MessageType};
+
+/// Tell the player about a database error. Problems they can fix, such as a duplicate name
+/// or a library open in another copy of Ferrif, are warnings. Anything else is an error
+pub fn show_db_error(title: &str, error: &IfdbError) {
+    let (message_type, text) = match error {
+        IfdbError::Invalid(_)
+        | IfdbError::Constraint(_)
+        | IfdbError::NotFound(_)
+        | IfdbError::Busy(_) => (MessageType::Warning, error.to_string()),
+        IfdbError::Corrupt(_) => (
+            MessageType::Error,
+            format!(
+                "{}\n\nThe library may be damaged. A backup can be restored with --restore.",
+                error
+            ),
+        ),
+        IfdbError::Io(_) | IfdbError::Sql(_) => (MessageType::Error, error.to_string()),
+    };
+
+    MessageDialog::new()
+        .set_type(message_type)
+        .set_title(title)
+        .set_text(text.as_str())
+        .show_alert()
+        .unwrap();
+}
+
+/// Show an error loading something in place of it. Used while drawing, where a dialog would
+/// be shown again every frame
+pub fn draw_db_error(ui: &mut Ui, what: &str, error: &IfdbError) {
+    let text = match error {
+        IfdbError::Busy(_) => format!("Waiting to load {}. {}", what, error),
+        _ => format!("Unable to load {}. {}", what, error),
+    };
+    ui.colored_label(Color32::RED, text);
+}
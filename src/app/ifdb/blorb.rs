@@ -0,0 +1,106 @@
+///
+/// Reader for Blorb packages (.zblorb / .blb)
+/// See https://www.eblong.com/zarf/blorb/blorb.html
+///
+use super::iff::{read_chunks, read_u32, Chunk};
+
+const IFRS_ID: &[u8; 4] = b"IFRS";
+const RESOURCE_INDEX_ID: &[u8; 4] = b"RIdx";
+const ZCODE_ID: &[u8; 4] = b"ZCOD";
+const METADATA_ID: &[u8; 4] = b"IFmd";
+const FRONTISPIECE_ID: &[u8; 4] = b"Fspc";
+const PNG_ID: &[u8; 4] = b"PNG ";
+const JPEG_ID: &[u8; 4] = b"JPEG";
+
+const EXEC_USAGE: &[u8; 4] = b"Exec";
+const PICT_USAGE: &[u8; 4] = b"Pict";
+
+// Size of a single entry in the resource index
+const RESOURCE_ENTRY_SIZE: usize = 12;
+
+/// The parts of a blorb package we import. Any other resources are ignored.
+#[derive(PartialEq, Debug)]
+pub struct Blorb {
+    pub zcode: Option<Vec<u8>>,
+    pub ifiction: Option<Vec<u8>>,
+    pub cover: Option<Vec<u8>>,
+}
+
+struct ResourceEntry<'a> {
+    usage: &'a [u8],
+    number: u32,
+    offset: usize,
+}
+
+fn read_resource_index<'a>(chunks: &[Chunk<'a>]) -> Result<Vec<ResourceEntry<'a>>, String> {
+    let mut entries = vec![];
+    if let Some(chunk) = chunks.iter().find(|c| c.id == RESOURCE_INDEX_ID) {
+        let count = read_u32(chunk.data, 0).unwrap_or(0) as usize;
+        for i in 0..count {
+            let entry_offset = 4 + i * RESOURCE_ENTRY_SIZE;
+            match (
+                chunk.data.get(entry_offset..entry_offset + 4),
+                read_u32(chunk.data, entry_offset + 4),
+                read_u32(chunk.data, entry_offset + 8),
+            ) {
+                (Some(usage), Some(number), Some(offset)) => entries.push(ResourceEntry {
+                    usage,
+                    number,
+                    offset: offset as usize,
+                }),
+                _ => return Err(String::from("Resource index is truncated")),
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Find the chunk for a resource using the resource index
+fn find_resource<'a, 'b>(
+    chunks: &'b [Chunk<'a>],
+    entries: &[ResourceEntry<'_>],
+    usage: &[u8; 4],
+    number: u32,
+) -> Option<&'b Chunk<'a>> {
+    entries
+        .iter()
+        .find(|e| e.usage == usage && e.number == number)
+        .and_then(|e| chunks.iter().find(|c| c.offset == e.offset))
+}
+
+/// Extract the story file, ifiction metadata and cover image from blorb data
+pub fn read_blorb(data: &[u8]) -> Result<Blorb, String> {
+    let chunks = read_chunks(data, IFRS_ID)?;
+    let entries = read_resource_index(&chunks)?;
+
+    // The executable should be resource 0 in the index, but fall back to the first
+    // ZCOD chunk for files with a missing or broken index
+    let zcode = match find_resource(&chunks, &entries, EXEC_USAGE, 0) {
+        Some(chunk) if chunk.id == ZCODE_ID => Some(chunk.data.to_vec()),
+        Some(_) => None,
+        None => chunks
+            .iter()
+            .find(|c| c.id == ZCODE_ID)
+            .map(|c| c.data.to_vec()),
+    };
+
+    let ifiction = chunks
+        .iter()
+        .find(|c| c.id == METADATA_ID)
+        .map(|c| c.data.to_vec());
+
+    let cover = chunks
+        .iter()
+        .find(|c| c.id == FRONTISPIECE_ID)
+        .and_then(|c| read_u32(c.data, 0))
+        .and_then(|number| find_resource(&chunks, &entries, PICT_USAGE, number))
+        .filter(|c| c.id == PNG_ID || c.id == JPEG_ID)
+        .map(|c| c.data.to_vec());
+
+    Ok(Blorb {
+        zcode,
+        ifiction,
+        cover,
+    })
+}
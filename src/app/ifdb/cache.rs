@@ -0,0 +1,194 @@
+///
+/// Results of the queries the windows make every frame, kept until a write through the same
+/// connection changes them. Writes made by another copy of Ferrif are not seen until this
+/// connection next writes, or the library is reopened
+///
+use super::{ClueSection, DbSave, Note, StoryFilter, StorySort, StorySummary};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Story list queries to keep. Each search text typed and page scrolled to is a separate
+/// query, so the oldest are dropped rather than kept for the life of the app
+const MAX_SUMMARY_QUERIES: usize = 64;
+
+/// The arguments to a story list query
+#[derive(PartialEq)]
+pub struct SummaryQuery {
+    has_data: bool,
+    search_text: Option<String>,
+    sort: Option<StorySort>,
+    filter: StoryFilter,
+    page: Option<(usize, usize)>, // Offset and limit
+}
+
+impl SummaryQuery {
+    pub fn new(
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+    ) -> SummaryQuery {
+        SummaryQuery {
+            has_data,
+            search_text: search_text.map(String::from),
+            sort,
+            filter: filter.clone(),
+            page: None,
+        }
+    }
+
+    /// Only the rows from offset to offset + limit
+    pub fn page(self, offset: usize, limit: usize) -> SummaryQuery {
+        SummaryQuery {
+            page: Some((offset, limit)),
+            ..self
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StoreCache {
+    summaries: RefCell<Vec<(SummaryQuery, Vec<StorySummary>)>>, // Oldest query first
+    counts: RefCell<Vec<(SummaryQuery, Option<String>, usize)>>, // Query, title counted before, count
+    tags_by_story: RefCell<Option<HashMap<u32, Vec<String>>>>,
+    notes: RefCell<HashMap<(i64, bool), Vec<Note>>>, // Keyed by story id and whether done notes are included
+    saves: RefCell<HashMap<(String, bool), Vec<DbSave>>>, // Keyed by IFID and whether only manual saves are included
+    clues: RefCell<HashMap<u32, Vec<ClueSection>>>,       // Keyed by story id
+}
+
+impl StoreCache {
+    ///
+    /// Library
+    ///
+    pub fn get_story_summaries(&self, query: &SummaryQuery) -> Option<Vec<StorySummary>> {
+        self.summaries
+            .borrow()
+            .iter()
+            .find(|(q, _)| q == query)
+            .map(|(_, summaries)| summaries.clone())
+    }
+
+    pub fn store_story_summaries(
+        &self,
+        query: SummaryQuery,
+        summaries: Vec<StorySummary>,
+    ) -> Vec<StorySummary> {
+        let mut cached = self.summaries.borrow_mut();
+        if cached.len() >= MAX_SUMMARY_QUERIES {
+            cached.remove(0);
+        }
+        cached.push((query, summaries.clone()));
+        summaries
+    }
+
+    pub fn get_story_count(
+        &self,
+        query: &SummaryQuery,
+        before_title: Option<&str>,
+    ) -> Option<usize> {
+        self.counts
+            .borrow()
+            .iter()
+            .find(|(q, title, _)| q == query && title.as_deref() == before_title)
+            .map(|(_, _, count)| *count)
+    }
+
+    pub fn store_story_count(
+        &self,
+        query: SummaryQuery,
+        before_title: Option<&str>,
+        count: usize,
+    ) -> usize {
+        let mut cached = self.counts.borrow_mut();
+        if cached.len() >= MAX_SUMMARY_QUERIES {
+            cached.remove(0);
+        }
+        cached.push((query, before_title.map(String::from), count));
+        count
+    }
+
+    pub fn get_tags_by_story(&self) -> Option<HashMap<u32, Vec<String>>> {
+        self.tags_by_story.borrow().clone()
+    }
+
+    pub fn store_tags_by_story(
+        &self,
+        tags: HashMap<u32, Vec<String>>,
+    ) -> HashMap<u32, Vec<String>> {
+        *self.tags_by_story.borrow_mut() = Some(tags.clone());
+        tags
+    }
+
+    /// Forget everything shown in the story list. Called when stories, their play details,
+    /// tags or shelves change
+    pub fn clear_library(&self) {
+        self.summaries.borrow_mut().clear();
+        self.counts.borrow_mut().clear();
+        *self.tags_by_story.borrow_mut() = None;
+    }
+
+    ///
+    /// Notes
+    ///
+    pub fn get_notes(&self, story_id: i64, include_done: bool) -> Option<Vec<Note>> {
+        self.notes.borrow().get(&(story_id, include_done)).cloned()
+    }
+
+    pub fn store_notes(&self, story_id: i64, include_done: bool, notes: Vec<Note>) -> Vec<Note> {
+        self.notes
+            .borrow_mut()
+            .insert((story_id, include_done), notes.clone());
+        notes
+    }
+
+    /// Notes are updated by note id, so all stories' notes are forgotten
+    pub fn clear_notes(&self) {
+        self.notes.borrow_mut().clear();
+    }
+
+    ///
+    /// Saves
+    ///
+    pub fn get_saves(&self, ifid: &str, manual_only: bool) -> Option<Vec<DbSave>> {
+        self.saves
+            .borrow()
+            .get(&(ifid.to_string(), manual_only))
+            .cloned()
+    }
+
+    pub fn store_saves(&self, ifid: &str, manual_only: bool, saves: Vec<DbSave>) -> Vec<DbSave> {
+        self.saves
+            .borrow_mut()
+            .insert((ifid.to_string(), manual_only), saves.clone());
+        saves
+    }
+
+    pub fn clear_saves(&self, ifid: &str) {
+        self.saves.borrow_mut().retain(|(i, _), _| i != ifid);
+    }
+
+    ///
+    /// Clues
+    ///
+    pub fn get_clues(&self, story_id: u32) -> Option<Vec<ClueSection>> {
+        self.clues.borrow().get(&story_id).cloned()
+    }
+
+    pub fn store_clues(&self, story_id: u32, clues: Vec<ClueSection>) -> Vec<ClueSection> {
+        self.clues.borrow_mut().insert(story_id, clues.clone());
+        clues
+    }
+
+    /// Clues are revealed by clue id, so all stories' clues are forgotten
+    pub fn clear_clues(&self) {
+        self.clues.borrow_mut().clear();
+    }
+
+    /// Forget everything. Called after imports, merges and deletes, which change many tables
+    pub fn clear(&self) {
+        self.clear_library();
+        self.clear_notes();
+        self.saves.borrow_mut().clear();
+        self.clear_clues();
+    }
+}
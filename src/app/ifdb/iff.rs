@@ -0,0 +1,56 @@
+///
+/// Reader for IFF files, the container format used by Blorb and Quetzal
+/// See https://www.eblong.com/zarf/blorb/blorb.html#iff
+///
+use std::convert::TryInto;
+
+const FORM_ID: &[u8; 4] = b"FORM";
+
+// Size of a chunk type plus chunk length
+const CHUNK_HEADER_SIZE: usize = 8;
+
+pub struct Chunk<'a> {
+    pub id: &'a [u8],
+    pub offset: usize, // Offset of the start of the chunk header in the file
+    pub data: &'a [u8],
+}
+
+/// Read a big-endian u32, if there are enough bytes
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+}
+
+/// Split the body of an IFF FORM of the given type into chunks. Chunks are padded to an even length.
+pub fn read_chunks<'a>(data: &'a [u8], form_type: &[u8; 4]) -> Result<Vec<Chunk<'a>>, String> {
+    if data.len() < 12 || &data[0..4] != FORM_ID || &data[8..12] != form_type {
+        return Err(format!(
+            "Not an IFF file of type {}",
+            String::from_utf8_lossy(form_type)
+        ));
+    }
+
+    let form_length = read_u32(data, 4).unwrap() as usize;
+    let end = std::cmp::min(data.len(), form_length + CHUNK_HEADER_SIZE);
+
+    let mut chunks = vec![];
+    let mut offset = 12;
+    while offset + CHUNK_HEADER_SIZE <= end {
+        let length = read_u32(data, offset + 4).unwrap() as usize;
+        let start = offset + CHUNK_HEADER_SIZE;
+        if start + length > end {
+            return Err(format!(
+                "Chunk at offset {} extends past end of file",
+                offset
+            ));
+        }
+        chunks.push(Chunk {
+            id: &data[offset..offset + 4],
+            offset,
+            data: &data[start..start + length],
+        });
+        offset = start + length + (length % 2);
+    }
+
+    Ok(chunks)
+}
@@ -6,9 +6,13 @@ extern crate xml;
 
 use serde::{Serialize, Serializer};
 
+use chrono::NaiveDate;
 use regex::Regex;
-use std::io::Read;
+use std::io::{Read, Write};
 use xml::reader::{EventReader, XmlEvent};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent as WriterEvent};
+
+const IFICTION_NAMESPACE: &str = "http://babel.ifarchive.org/protocol/iFiction/";
 
 // No other formats are supported, and will be ignored
 // See 5.5.2
@@ -62,6 +66,27 @@ pub fn convert_ifictiondate_to_str(d: Option<IFictionDate>) -> Option<String> {
     }
 }
 
+/// Parse a date entered by hand. Unlike dates read from files this must be exactly
+/// YYYY or YYYY-MM-DD, and a real day
+pub fn validate_ifictiondate(s: &str) -> Result<IFictionDate, String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^(\d{4})(?:-(\d{2})-(\d{2}))?$").unwrap();
+    }
+
+    let error = || format!("{} is not a valid date. Use YYYY or YYYY-MM-DD.", s);
+    let captures = RE.captures(s.trim()).ok_or_else(error)?;
+    let year = captures[1].parse::<u32>().map_err(|_| error())?;
+    match (captures.get(2), captures.get(3)) {
+        (Some(month), Some(day)) => {
+            let month = month.as_str().parse::<u32>().map_err(|_| error())?;
+            let day = day.as_str().parse::<u32>().map_err(|_| error())?;
+            NaiveDate::from_ymd_opt(year as i32, month, day).ok_or_else(error)?;
+            Ok(IFictionDate::YearMonthDay(year, month, day))
+        }
+        _ => Ok(IFictionDate::Year(year)),
+    }
+}
+
 #[allow(dead_code)]
 pub fn convert_str_to_ifictiondate(s: Option<String>) -> Option<IFictionDate> {
     match s {
@@ -83,6 +108,14 @@ pub enum Forgiveness {
     Cruel,
 }
 
+pub const FORGIVENESS_LEVELS: [Forgiveness; 5] = [
+    Forgiveness::Merciful,
+    Forgiveness::Polite,
+    Forgiveness::Tough,
+    Forgiveness::Nasty,
+    Forgiveness::Cruel,
+];
+
 pub fn convert_forgiveness_to_str(d: Option<Forgiveness>) -> Option<String> {
     match d {
         None => None,
@@ -219,7 +252,7 @@ pub struct Story {
 }
 
 fn remove_ifiction_prefix(s: String) -> String {
-    s.replace("{http://babel.ifarchive.org/protocol/iFiction/}", "")
+    s.replace(format!("{{{}}}", IFICTION_NAMESPACE).as_str(), "")
         .to_lowercase()
 }
 
@@ -358,6 +391,7 @@ fn parse_cover<R: Read>(parser: &mut EventReader<R>) -> Result<Cover, String> {
             }
             Ok(XmlEvent::EndElement { name, .. }) => {
                 let match_name = remove_ifiction_prefix(name.to_string());
+                let mut clear_text = true;
                 match match_name.as_str() {
                     COVER_TAG => {
                         break;
@@ -384,9 +418,16 @@ fn parse_cover<R: Read>(parser: &mut EventReader<R>) -> Result<Cover, String> {
                             return Err("Unable to parse height in cover".to_string());
                         }
                     },
+                    "br" => {
+                        // Converted to a newline by cleanup_description
+                        text.push_str("<br/>");
+                        clear_text = false;
+                    }
                     _ => (),
                 }
-                text.clear();
+                if clear_text {
+                    text.clear();
+                }
             }
             Err(e) => return Err(e.to_string()),
             _ => (),
@@ -711,7 +752,8 @@ fn parse_bibliographic<R: Read>(parser: &mut EventReader<R>) -> Result<Bibilogra
                         Err(msg) => return Err(msg.to_string()),
                     },
                     "br" => {
-                        text.push('\n');
+                        // Converted to a newline by cleanup_description
+                        text.push_str("<br/>");
                         clear_text = false;
                     }
                     _ => (),
@@ -917,3 +959,209 @@ pub fn read_stories_from_xml(reader: impl Read) -> Result<Vec<Result<Story, Stri
 
     Ok(stories)
 }
+
+//
+// Writing
+//
+
+type XmlWriteResult = Result<(), xml::writer::Error>;
+
+fn write_start<W: Write>(writer: &mut EventWriter<W>, name: &str) -> XmlWriteResult {
+    writer.write(WriterEvent::start_element(name))
+}
+
+fn write_end<W: Write>(writer: &mut EventWriter<W>) -> XmlWriteResult {
+    writer.write(WriterEvent::end_element())
+}
+
+/// Write a single element containing only text
+fn write_text_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    text: &str,
+) -> XmlWriteResult {
+    write_start(writer, name)?;
+    writer.write(WriterEvent::characters(text))?;
+    write_end(writer)
+}
+
+fn write_optional_element<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    text: &Option<impl ToString>,
+) -> XmlWriteResult {
+    match text {
+        Some(text) => write_text_element(writer, name, text.to_string().as_str()),
+        None => Ok(()),
+    }
+}
+
+/// Write a description, with newlines written as <br/> per 5.6.9
+fn write_description<W: Write>(writer: &mut EventWriter<W>, description: &str) -> XmlWriteResult {
+    write_start(writer, "description")?;
+    for (i, line) in description.split('\n').enumerate() {
+        if i > 0 {
+            write_start(writer, "br")?;
+            write_end(writer)?;
+        }
+        if !line.is_empty() {
+            writer.write(WriterEvent::characters(line))?;
+        }
+    }
+    write_end(writer)
+}
+
+fn write_story<W: Write>(writer: &mut EventWriter<W>, story: &Story) -> XmlWriteResult {
+    write_start(writer, STORY_TAG)?;
+
+    // 5.5
+    write_start(writer, IDENTIFICATION_TAG)?;
+    for ifid in story.identification.ifids.iter() {
+        write_text_element(writer, "ifid", ifid)?;
+    }
+    write_text_element(writer, "format", "zcode")?;
+    write_end(writer)?;
+
+    // 5.6
+    let bibliographic = &story.bibliographic;
+    write_start(writer, BIBLIOGRAPHIC_TAG)?;
+    write_text_element(writer, "title", bibliographic.title.as_str())?;
+    write_text_element(writer, "author", bibliographic.author.as_str())?;
+    write_optional_element(writer, "language", &bibliographic.language)?;
+    write_optional_element(writer, "headline", &bibliographic.headline)?;
+    write_optional_element(
+        writer,
+        "firstpublished",
+        &convert_ifictiondate_to_str(bibliographic.first_published),
+    )?;
+    write_optional_element(writer, "genre", &bibliographic.genre)?;
+    write_optional_element(writer, "group", &bibliographic.group)?;
+    write_optional_element(writer, "series", &bibliographic.series)?;
+    write_optional_element(writer, "seriesnumber", &bibliographic.series_number)?;
+    write_optional_element(
+        writer,
+        "forgiveness",
+        &convert_forgiveness_to_str(bibliographic.forgiveness),
+    )?;
+    if let Some(description) = &bibliographic.description {
+        write_description(writer, description.as_str())?;
+    }
+    write_end(writer)?;
+
+    // 5.7
+    for resource in story.resources.iter() {
+        write_start(writer, AUXILARY_TAG)?;
+        write_text_element(writer, "leafname", resource.leafname.as_str())?;
+        write_text_element(writer, "description", resource.description.as_str())?;
+        write_end(writer)?;
+    }
+
+    // 5.8. The parser requires at least one child in each section, so skip empty ones
+    if let Some(contacts) = &story.contacts {
+        if contacts.url.is_some() || contacts.author_email.is_some() {
+            write_start(writer, CONTACTS_TAG)?;
+            write_optional_element(writer, "url", &contacts.url)?;
+            write_optional_element(writer, "authoremail", &contacts.author_email)?;
+            write_end(writer)?;
+        }
+    }
+
+    // 5.9
+    if let Some(cover) = &story.cover {
+        write_start(writer, COVER_TAG)?;
+        write_text_element(
+            writer,
+            "format",
+            convert_cover_format_to_str(cover.cover_format)
+                .to_lowercase()
+                .as_str(),
+        )?;
+        write_text_element(writer, "height", cover.height.to_string().as_str())?;
+        write_text_element(writer, "width", cover.width.to_string().as_str())?;
+        if let Some(description) = &cover.description {
+            write_description(writer, description.as_str())?;
+        }
+        write_end(writer)?;
+    }
+
+    // 5.10
+    if let Some(zcode) = &story.zcode {
+        if zcode.version.is_some()
+            || zcode.release.is_some()
+            || zcode.serial.is_some()
+            || zcode.checksum.is_some()
+            || zcode.compiler.is_some()
+            || zcode.cover_picture.is_some()
+        {
+            write_start(writer, ZCODE_TAG)?;
+            write_optional_element(writer, "version", &zcode.version)?;
+            write_optional_element(writer, "release", &zcode.release)?;
+            write_optional_element(writer, "serial", &zcode.serial)?;
+            write_optional_element(writer, "checksum", &zcode.checksum)?;
+            write_optional_element(writer, "compiler", &zcode.compiler)?;
+            write_optional_element(writer, "coverpicture", &zcode.cover_picture)?;
+            write_end(writer)?;
+        }
+    }
+
+    // 5.11
+    if !story.releases.is_empty() {
+        write_start(writer, "releases")?;
+        write_start(writer, "attached")?;
+        for release in story.releases.iter() {
+            write_start(writer, RELEASE_TAG)?;
+            write_text_element(writer, "version", release.version.to_string().as_str())?;
+            write_optional_element(
+                writer,
+                "releasedate",
+                &convert_ifictiondate_to_str(Some(release.release_date)),
+            )?;
+            write_optional_element(writer, "compiler", &release.compiler)?;
+            write_optional_element(writer, "compilerversion", &release.compiler_version)?;
+            write_end(writer)?;
+        }
+        write_end(writer)?;
+        write_end(writer)?;
+    }
+
+    // 5.12
+    if let Some(colophon) = &story.colophon {
+        write_start(writer, COLOPHON_TAG)?;
+        write_text_element(writer, "generator", colophon.generator.as_str())?;
+        write_optional_element(writer, "generatorversion", &colophon.generator_version)?;
+        write_optional_element(
+            writer,
+            "originated",
+            &convert_ifictiondate_to_str(Some(colophon.originated)),
+        )?;
+        write_end(writer)?;
+    }
+
+    write_end(writer)
+}
+
+/// Write Story objects out as ifiction xml. Cover images are not included.
+pub fn write_stories_to_xml(writer: impl Write, stories: &[Story]) -> Result<(), String> {
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(writer);
+
+    let result = || -> XmlWriteResult {
+        writer.write(WriterEvent::StartDocument {
+            version: xml::common::XmlVersion::Version10,
+            encoding: Some("UTF-8"),
+            standalone: None,
+        })?;
+        writer.write(
+            WriterEvent::start_element("ifindex")
+                .attr("version", "1.0")
+                .default_ns(IFICTION_NAMESPACE),
+        )?;
+        for story in stories {
+            write_story(&mut writer, story)?;
+        }
+        write_end(&mut writer)
+    }();
+
+    result.map_err(|e| e.to_string())
+}
@@ -0,0 +1,310 @@
+///
+/// Keep the database healthy: check its integrity, remove rows left behind by deleted
+/// stories, report how much space each table uses and compact it
+///
+use super::{IfdbConnection, IfdbError};
+use rusqlite::{params, NO_PARAMS};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Rows that belong to something no longer in the database. The schema has no foreign keys,
+/// so nothing removes these when their story goes. Each condition is written against the
+/// rows that are still live, so rows whose parent is itself orphaned are found in the same
+/// pass, and the rules can be counted or deleted in any order
+struct OrphanRule {
+    table: &'static str,
+    description: &'static str,
+    condition: &'static str, // Matches the orphaned rows in table
+}
+
+const ORPHAN_RULES: &[OrphanRule] = &[
+    OrphanRule {
+        table: "story_ifid",
+        description: "IFIDs",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_resource",
+        description: "auxiliary files",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_release",
+        description: "releases",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_zcode",
+        description: "Z-code details",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_fts",
+        description: "search index entries",
+        condition: "rowid NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "saves",
+        description: "saves",
+        condition:
+            "ifid NOT IN (SELECT ifid FROM story_ifid WHERE story_id IN (SELECT id FROM story))",
+    },
+    OrphanRule {
+        table: "session",
+        description: "play sessions",
+        condition:
+            "ifid NOT IN (SELECT ifid FROM story_ifid WHERE story_id IN (SELECT id FROM story))",
+    },
+    OrphanRule {
+        table: "cover_thumbnail",
+        description: "cover thumbnails",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_history",
+        description: "history entries",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "map_room",
+        description: "map rooms",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "notes",
+        description: "notes",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "clue_section",
+        description: "clue sections",
+        condition: "story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "clue_subsection",
+        description: "clue subsections",
+        condition:
+            "section_id NOT IN (SELECT id FROM clue_section WHERE story_id IN (SELECT id FROM story))",
+    },
+    OrphanRule {
+        table: "clue",
+        description: "clues",
+        condition: "subsection_id NOT IN (SELECT s.id FROM clue_subsection s JOIN clue_section c ON c.id = s.section_id WHERE c.story_id IN (SELECT id FROM story))",
+    },
+    // Story id 0 holds the main window
+    OrphanRule {
+        table: "window_details",
+        description: "window positions",
+        condition: "story_id != 0 AND story_id NOT IN (SELECT id FROM story)",
+    },
+    OrphanRule {
+        table: "story_tag",
+        description: "story tags",
+        condition: "story_id NOT IN (SELECT id FROM story) OR tag_id NOT IN (SELECT id FROM tag)",
+    },
+    // Tags only exist while a story has them
+    OrphanRule {
+        table: "tag",
+        description: "unused tags",
+        condition: "id NOT IN (SELECT tag_id FROM story_tag WHERE story_id IN (SELECT id FROM story))",
+    },
+    OrphanRule {
+        table: "shelf_story",
+        description: "shelf entries",
+        condition:
+            "story_id NOT IN (SELECT id FROM story) OR shelf_id NOT IN (SELECT id FROM shelf)",
+    },
+];
+
+/// Orphaned rows found in, or removed from, one table
+#[derive(PartialEq, Debug, Clone)]
+pub struct OrphanedRows {
+    pub table: String,
+    pub description: String, // What the rows hold, such as "saves"
+    pub count: usize,
+}
+
+impl fmt::Display for OrphanedRows {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} with no story ({})",
+            self.count, self.description, self.table
+        )
+    }
+}
+
+/// Rows in a table and the space it takes, including its indexes
+#[derive(PartialEq, Debug, Clone)]
+pub struct TableSize {
+    pub name: String,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+impl fmt::Display for TableSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} rows, {} kB",
+            self.name,
+            self.rows,
+            self.bytes / 1024
+        )
+    }
+}
+
+impl IfdbConnection {
+    /// Run SQLite's integrity check. Returns the problems found, which is empty for a
+    /// healthy database
+    pub fn check_integrity(&self) -> Result<Vec<String>, IfdbError> {
+        let result = || -> Result<Vec<String>, rusqlite::Error> {
+            let mut statement = self.connection.prepare("PRAGMA integrity_check")?;
+            let rows = statement.query_map(NO_PARAMS, |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(messages) => Ok(messages.into_iter().filter(|m| m != "ok").collect()),
+        }
+    }
+
+    /// Count rows left behind by deleted stories, for each table that has any
+    pub fn find_orphaned_rows(&self) -> Result<Vec<OrphanedRows>, IfdbError> {
+        let result = || -> Result<Vec<OrphanedRows>, rusqlite::Error> {
+            let mut orphans = vec![];
+            for rule in ORPHAN_RULES {
+                let count: i64 = self.connection.query_row(
+                    format!(
+                        "SELECT COUNT(*) FROM {} WHERE {}",
+                        rule.table, rule.condition
+                    )
+                    .as_str(),
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )?;
+                if count > 0 {
+                    orphans.push(OrphanedRows::new(rule, count as usize));
+                }
+            }
+            Ok(orphans)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(orphans) => Ok(orphans),
+        }
+    }
+
+    /// Delete rows left behind by deleted stories. Returns what was removed from each table.
+    /// Nothing is removed if any table fails
+    pub fn remove_orphaned_rows(&self) -> Result<Vec<OrphanedRows>, IfdbError> {
+        self.cache.clear();
+        let result = || -> Result<Vec<OrphanedRows>, rusqlite::Error> {
+            let transaction = self.connection.unchecked_transaction()?;
+            let mut removed = vec![];
+            for rule in ORPHAN_RULES {
+                let count = self.connection.execute(
+                    format!("DELETE FROM {} WHERE {}", rule.table, rule.condition).as_str(),
+                    params![],
+                )?;
+                if count > 0 {
+                    removed.push(OrphanedRows::new(rule, count));
+                }
+            }
+            transaction.commit()?;
+            Ok(removed)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(removed) => Ok(removed),
+        }
+    }
+
+    /// Rows and space used by each table, largest first
+    pub fn table_sizes(&self) -> Result<Vec<TableSize>, IfdbError> {
+        let result = || -> Result<Vec<TableSize>, rusqlite::Error> {
+            // dbstat lists pages by table or index, so indexes are added to their table
+            let mut bytes: HashMap<String, u64> = HashMap::new();
+            let mut statement = self.connection.prepare(
+                "SELECT m.tbl_name, SUM(d.pgsize) FROM dbstat d
+                JOIN sqlite_master m ON m.name = d.name GROUP BY m.tbl_name",
+            )?;
+            let rows = statement.query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            for row in rows {
+                let (name, size) = row?;
+                bytes.insert(name, size as u64);
+            }
+
+            let mut statement = self.connection.prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table'
+                AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            )?;
+            let names = statement
+                .query_map(NO_PARAMS, |row| row.get(0))?
+                .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+            let mut sizes = vec![];
+            for name in names {
+                let rows: i64 = self.connection.query_row(
+                    format!("SELECT COUNT(*) FROM \"{}\"", name).as_str(),
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )?;
+                sizes.push(TableSize {
+                    bytes: bytes.get(&name).copied().unwrap_or(0),
+                    name,
+                    rows: rows as usize,
+                });
+            }
+            sizes.sort_by_key(|size| Reverse(size.bytes));
+            Ok(sizes)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(sizes) => Ok(sizes),
+        }
+    }
+
+    /// Size of the database in bytes, whether or not it is stored in a file
+    pub fn database_size(&self) -> Result<u64, IfdbError> {
+        let result = || -> Result<i64, rusqlite::Error> {
+            let page_count: i64 =
+                self.connection
+                    .query_row("PRAGMA page_count", NO_PARAMS, |row| row.get(0))?;
+            let page_size: i64 =
+                self.connection
+                    .query_row("PRAGMA page_size", NO_PARAMS, |row| row.get(0))?;
+            Ok(page_count * page_size)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(size) => Ok(size as u64),
+        }
+    }
+
+    /// Rebuild the database to reclaim space left by deleted rows, such as old autosaves.
+    /// Returns the size before and after
+    pub fn vacuum(&self) -> Result<(u64, u64), IfdbError> {
+        let before = self.database_size()?;
+        self.connection.execute("VACUUM", params![])?;
+        Ok((before, self.database_size()?))
+    }
+}
+
+impl OrphanedRows {
+    fn new(rule: &OrphanRule, count: usize) -> OrphanedRows {
+        OrphanedRows {
+            table: rule.table.to_string(),
+            description: rule.description.to_string(),
+            count,
+        }
+    }
+}
@@ -0,0 +1,1645 @@
+///
+/// Storage kept in memory, for testing code that uses the library without a database file.
+/// Behaves like the SQLite database, except that search matches are ordered by title rather
+/// than ranked, and the contents of auxiliary files are never stored
+///
+use super::ifiction::{convert_forgiveness_to_str, convert_ifictiondate_to_str, Resource, Story};
+use super::store::IfdbStore;
+use super::{
+    default_shelves, make_cover_thumbnail, parse_search_terms, placeholder_story,
+    zcode_from_header, Clue, ClueSection, ClueSubsection, DBSession, DbFont, DbSave, DbShelf,
+    DbStory, DbStoryHistory, DbStoryRelease, DbStoryResource, DbTheme, DbTrashItem, IfdbError,
+    MapRoom, Note, PlayStatus, SaveType, SearchTerm, ShelfQuery, StoryFilter, StoryFilterField,
+    StoryReview, StorySort, StorySummary, TrashContents, WindowDetails, WindowType,
+    HEADER_CHECKSUM, HEADER_RELEASE_NUMBER, HEADER_SERIAL, MAX_RATING, SEARCH_FIELDS,
+    STORY_FILTER_FIELDS,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+struct MemoryStory {
+    id: u32,
+    story: Story, // IFIDs and resources are kept in the fields below
+    ifids: Vec<(String, Option<Vec<u8>>)>, // IFID and story file data
+    preferred_ifid: Option<String>,
+    resources: Vec<DbStoryResource>,
+    cover_image: Option<Vec<u8>>,
+    cover_thumbnail: Option<Vec<u8>>,
+    date_added: NaiveDateTime,
+    last_played: Option<NaiveDateTime>,
+    time_played: i64,
+    review: StoryReview,
+    deleted_when: Option<NaiveDateTime>, // Set while the story is in the trash
+}
+
+impl MemoryStory {
+    fn has_data(&self) -> bool {
+        self.ifids.iter().any(|(_, data)| data.is_some())
+    }
+
+    fn has_ifid(&self, ifid: &str, playable_only: bool) -> bool {
+        self.ifids
+            .iter()
+            .any(|(i, data)| i == ifid && (data.is_some() || !playable_only))
+    }
+
+    /// The release Play launches: the preferred one, or else the most recently added
+    fn preferred_ifid(&self, playable_only: bool) -> Option<&String> {
+        let mut ifids = self
+            .ifids
+            .iter()
+            .filter(|(_, data)| data.is_some() || !playable_only)
+            .map(|(ifid, _)| ifid);
+        let latest = ifids.clone().next_back();
+        ifids
+            .find(|ifid| Some(*ifid) == self.preferred_ifid.as_ref())
+            .or(latest)
+    }
+
+    fn to_db_story(&self, playable: bool) -> DbStory {
+        let mut story = self.story.clone();
+        story.identification.ifids = self
+            .ifids
+            .iter()
+            .filter(|(_, data)| data.is_some() || !playable)
+            .map(|(ifid, _)| ifid.clone())
+            .collect();
+        story.resources = self
+            .resources
+            .iter()
+            .map(|r| Resource {
+                leafname: r.leafname.clone(),
+                description: r.description.clone(),
+            })
+            .collect();
+        if let Some(cover) = &mut story.cover {
+            cover.cover_image = self.cover_image.clone();
+        }
+
+        DbStory {
+            story_id: self.id,
+            story,
+            last_played: self.last_played,
+            time_played: self.time_played,
+        }
+    }
+
+    fn summary(&self, ifid: &str) -> StorySummary {
+        StorySummary {
+            story_id: self.id,
+            ifid: ifid.to_string(),
+            title: self.story.bibliographic.title.clone(),
+            last_played: self.last_played,
+            time_played: self.time_played,
+            play_status: self.review.play_status,
+            rating: self.review.rating,
+        }
+    }
+
+    /// The value of a filter field, as it would be stored in the story table
+    fn field_value(&self, field: StoryFilterField) -> Option<String> {
+        let bibliographic = &self.story.bibliographic;
+        match field {
+            StoryFilterField::Genre => bibliographic.genre.clone(),
+            StoryFilterField::Language => bibliographic.language.clone(),
+            StoryFilterField::Forgiveness => convert_forgiveness_to_str(bibliographic.forgiveness),
+            StoryFilterField::Series => bibliographic.series.clone(),
+        }
+    }
+
+    fn search_field(&self, field: &str) -> Option<&str> {
+        let bibliographic = &self.story.bibliographic;
+        match field {
+            "title" => Some(bibliographic.title.as_str()),
+            "author" => Some(bibliographic.author.as_str()),
+            "headline" => bibliographic.headline.as_deref(),
+            "genre" => bibliographic.genre.as_deref(),
+            "series" => bibliographic.series.as_deref(),
+            "description" => bibliographic.description.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Match a search term the way the full text index does, by words from the start
+    fn matches_term(&self, term: &SearchTerm) -> bool {
+        let query = search_words(&term.value);
+        let fields = match &term.field {
+            Some(field) => vec![field.as_str()],
+            None => SEARCH_FIELDS.to_vec(),
+        };
+
+        fields
+            .into_iter()
+            .filter_map(|field| self.search_field(field))
+            .any(|text| {
+                let words = search_words(text);
+                words.windows(query.len().max(1)).any(|window| {
+                    window
+                        .iter()
+                        .zip(query.iter())
+                        .enumerate()
+                        .all(|(i, (w, q))| {
+                            if i + 1 == query.len() && !term.is_phrase {
+                                w.starts_with(q.as_str())
+                            } else {
+                                w == q
+                            }
+                        })
+                })
+            })
+    }
+
+    /// Match anywhere in the title or description, ignoring case, like a LIKE pattern
+    fn matches_text(&self, text: &str) -> bool {
+        let text = text.to_ascii_lowercase();
+        let bibliographic = &self.story.bibliographic;
+        bibliographic
+            .title
+            .to_ascii_lowercase()
+            .contains(text.as_str())
+            || bibliographic
+                .description
+                .as_deref()
+                .map(|d| d.to_ascii_lowercase().contains(text.as_str()))
+                .unwrap_or(false)
+    }
+
+    fn compare(&self, other: &MemoryStory, sort: StorySort) -> Ordering {
+        // Stories never played or without a date go at the end
+        let last = |a: bool, b: bool| a.cmp(&b);
+        match sort {
+            StorySort::Title => compare_nocase(
+                &self.story.bibliographic.title,
+                &other.story.bibliographic.title,
+            ),
+            StorySort::Author => compare_nocase(
+                &self.story.bibliographic.author,
+                &other.story.bibliographic.author,
+            ),
+            StorySort::FirstPublished => {
+                let a = convert_ifictiondate_to_str(self.story.bibliographic.first_published);
+                let b = convert_ifictiondate_to_str(other.story.bibliographic.first_published);
+                last(a.is_none(), b.is_none()).then(a.cmp(&b))
+            }
+            StorySort::LastPlayed => last(self.last_played.is_none(), other.last_played.is_none())
+                .then(other.last_played.cmp(&self.last_played)),
+            StorySort::TimePlayed => other.time_played.cmp(&self.time_played),
+            StorySort::DateAdded => other.date_added.cmp(&self.date_added),
+        }
+    }
+}
+
+/// Lowercase words in text, split on anything that isn't a letter or number
+fn search_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Compare ignoring ASCII case, as with COLLATE NOCASE
+fn compare_nocase(a: &str, b: &str) -> Ordering {
+    a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+}
+
+fn next_id(ids: impl Iterator<Item = i64>) -> i64 {
+    ids.max().unwrap_or(0) + 1
+}
+
+#[derive(Default)]
+struct MemoryData {
+    stories: Vec<MemoryStory>,
+    tags: Vec<String>,              // Tag names, unique ignoring case
+    story_tags: Vec<(u32, String)>, // Story id and tag name
+    shelves: Vec<DbShelf>,
+    shelf_stories: Vec<(i64, u32)>, // Shelf id and story id
+    saves: Vec<DbSave>,
+    trashed_autosaves: Vec<(DbSave, NaiveDateTime)>, // Autosave and when it was trashed
+    sessions: Vec<DBSession>,
+    clue_sections: Vec<ClueSection>, // Clue text is stored unrevealed
+    notes: Vec<Note>,
+    rooms: Vec<MapRoom>,
+    fonts: Vec<DbFont>,
+    themes: Vec<DbTheme>,
+    window_details: Vec<WindowDetails>,
+    history: Vec<(u32, DbStoryHistory)>, // Story id and entry, oldest first
+    current_story: Option<i64>,
+    story_list_settings: Option<(StorySort, StoryFilter)>,
+}
+
+impl MemoryData {
+    fn story(&self, story_id: u32) -> Option<&MemoryStory> {
+        self.stories.iter().find(|s| s.id == story_id)
+    }
+
+    fn story_mut(&mut self, story_id: u32) -> Option<&mut MemoryStory> {
+        self.stories.iter_mut().find(|s| s.id == story_id)
+    }
+
+    fn story_for_ifid(&self, ifid: &str, playable_only: bool) -> Option<&MemoryStory> {
+        self.stories
+            .iter()
+            .find(|s| s.has_ifid(ifid, playable_only))
+    }
+
+    fn has_tag(&self, story_id: u32, tag: &str) -> bool {
+        self.story_tags
+            .iter()
+            .any(|(id, name)| *id == story_id && name.eq_ignore_ascii_case(tag))
+    }
+
+    fn delete_unused_tags(&mut self) {
+        let story_tags = &self.story_tags;
+        self.tags
+            .retain(|tag| story_tags.iter().any(|(_, name)| name == tag));
+    }
+
+    fn matches_filter(&self, story: &MemoryStory, filter: &StoryFilter) -> bool {
+        let fields_match = STORY_FILTER_FIELDS.iter().all(|field| {
+            filter.get(*field).is_none() || story.field_value(*field) == *filter.get(*field)
+        });
+        let tag_matches = filter
+            .tag
+            .as_ref()
+            .map(|tag| self.has_tag(story.id, tag))
+            .unwrap_or(true);
+        let status_matches = filter
+            .play_status
+            .map(|status| story.review.play_status == status)
+            .unwrap_or(true);
+
+        // A missing shelf matches everything, as it may have been deleted
+        let shelf_matches = match filter
+            .shelf_id
+            .and_then(|id| self.shelves.iter().find(|s| s.dbid == id))
+        {
+            Some(DbShelf {
+                query: Some(query), ..
+            }) => self.matches_query(story, query),
+            Some(shelf) => self.shelf_stories.contains(&(shelf.dbid, story.id)),
+            None => true,
+        };
+
+        fields_match && tag_matches && status_matches && shelf_matches
+    }
+
+    fn matches_query(&self, story: &MemoryStory, query: &ShelfQuery) -> bool {
+        let search_matches = query
+            .search
+            .as_deref()
+            .map(|text| {
+                parse_search_terms(text)
+                    .iter()
+                    .all(|term| story.matches_term(term))
+            })
+            .unwrap_or(true);
+
+        let columns = [
+            (story.field_value(StoryFilterField::Genre), &query.genre),
+            (
+                story.field_value(StoryFilterField::Language),
+                &query.language,
+            ),
+            (
+                story.field_value(StoryFilterField::Forgiveness),
+                &query.forgiveness,
+            ),
+            (story.field_value(StoryFilterField::Series), &query.series),
+            (story.story.bibliographic.group.clone(), &query.group),
+        ];
+        let columns_match = columns
+            .iter()
+            .all(|(value, wanted)| wanted.is_none() || value == *wanted);
+
+        let tag_matches = query
+            .tag
+            .as_ref()
+            .map(|tag| self.has_tag(story.id, tag))
+            .unwrap_or(true);
+        let status_matches = query
+            .play_status
+            .map(|status| story.review.play_status == status)
+            .unwrap_or(true);
+        let played_matches = query
+            .played
+            .map(|played| story.last_played.is_some() == played)
+            .unwrap_or(true);
+
+        // last_played is stored in UTC
+        let recent_matches = query
+            .played_within_days
+            .map(|days| {
+                let since = Utc::now().naive_utc() - Duration::days(i64::from(days));
+                story.last_played.map(|p| p >= since).unwrap_or(false)
+            })
+            .unwrap_or(true);
+
+        search_matches
+            && columns_match
+            && tag_matches
+            && status_matches
+            && played_matches
+            && recent_matches
+    }
+
+    fn story_summaries(
+        &self,
+        has_data: bool,
+        sort: Option<StorySort>,
+        matches: impl Fn(&MemoryStory) -> bool,
+    ) -> Vec<StorySummary> {
+        let mut stories: Vec<&MemoryStory> = self
+            .stories
+            .iter()
+            .filter(|s| s.deleted_when.is_none() && matches(s))
+            .collect();
+        stories.sort_by(|a, b| {
+            sort.map(|sort| a.compare(b, sort))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.compare(b, StorySort::Title))
+        });
+
+        stories
+            .into_iter()
+            .filter_map(|story| {
+                story
+                    .preferred_ifid(has_data)
+                    .map(|ifid| story.summary(ifid))
+            })
+            .collect()
+    }
+}
+
+pub struct MemoryStore {
+    data: RefCell<MemoryData>,
+}
+
+impl MemoryStore {
+    /// An empty library, with the same default shelves as a new database
+    pub fn new() -> MemoryStore {
+        let mut data = MemoryData::default();
+        for (name, query) in default_shelves() {
+            let dbid = next_id(data.shelves.iter().map(|s| s.dbid));
+            data.shelves.push(DbShelf {
+                dbid,
+                name: name.to_string(),
+                query: Some(query),
+            });
+        }
+
+        MemoryStore {
+            data: RefCell::new(data),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl IfdbStore for MemoryStore {
+    ///
+    /// Stories
+    ///
+    fn count_stories(&self) -> Result<u32, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .stories
+            .iter()
+            .filter(|s| s.deleted_when.is_none())
+            .count() as u32)
+    }
+
+    fn get_story_id(&self, ifid: &str) -> Result<Option<u32>, IfdbError> {
+        Ok(self.data.borrow().story_for_ifid(ifid, false).map(|s| s.id))
+    }
+
+    fn get_story_data(&self, story_id: u32, ifid: &str) -> Result<Option<Vec<u8>>, IfdbError> {
+        Ok(self.data.borrow().story(story_id).and_then(|s| {
+            s.ifids
+                .iter()
+                .find(|(i, _)| i == ifid)
+                .and_then(|(_, data)| data.clone())
+        }))
+    }
+
+    fn get_story(&self, story_id: u32) -> Result<Option<DbStory>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story(story_id)
+            .map(|s| s.to_db_story(false)))
+    }
+
+    fn delete_story(&self, story_id: u32) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        let ifids: Vec<String> = match data.story(story_id) {
+            Some(story) => story.ifids.iter().map(|(ifid, _)| ifid.clone()).collect(),
+            None => vec![],
+        };
+
+        data.stories.retain(|s| s.id != story_id);
+        data.saves.retain(|s| !ifids.contains(&s.ifid));
+        data.trashed_autosaves
+            .retain(|(s, _)| !ifids.contains(&s.ifid));
+        data.sessions.retain(|s| !ifids.contains(&s.ifid));
+        data.rooms.retain(|r| r.story_id != i64::from(story_id));
+        data.notes.retain(|n| n.story_id != i64::from(story_id));
+        data.clue_sections.retain(|s| s.story_id != story_id);
+        data.window_details
+            .retain(|w| w.story_id != i64::from(story_id));
+        data.shelf_stories.retain(|(_, id)| *id != story_id);
+        data.story_tags.retain(|(id, _)| *id != story_id);
+        data.history.retain(|(id, _)| *id != story_id);
+        data.delete_unused_tags();
+        Ok(())
+    }
+
+    fn fetch_ifids_for_story(
+        &self,
+        story_id: u32,
+        playable: bool,
+    ) -> Result<Vec<String>, IfdbError> {
+        Ok(match self.data.borrow().story(story_id) {
+            Some(story) => story
+                .ifids
+                .iter()
+                .filter(|(_, data)| data.is_some() || !playable)
+                .map(|(ifid, _)| ifid.clone())
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    fn fetch_sorted_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+    ) -> Result<Vec<StorySummary>, IfdbError> {
+        let data = self.data.borrow();
+        let terms = search_text.map(parse_search_terms).unwrap_or_default();
+        if terms.is_empty() {
+            return Ok(data.story_summaries(has_data, sort, |s| data.matches_filter(s, filter)));
+        }
+
+        let rows = data.story_summaries(has_data, sort, |s| {
+            terms.iter().all(|term| s.matches_term(term)) && data.matches_filter(s, filter)
+        });
+
+        // Word matches only match from the start of words, so fall back to matching
+        // anywhere in the title or description
+        if rows.is_empty() {
+            let text = search_text.unwrap_or_default().trim();
+            Ok(data.story_summaries(has_data, sort, |s| {
+                s.matches_text(text) && data.matches_filter(s, filter)
+            }))
+        } else {
+            Ok(rows)
+        }
+    }
+
+    fn fetch_story_filter_values(&self, field: StoryFilterField) -> Result<Vec<String>, IfdbError> {
+        let mut values: Vec<String> = self
+            .data
+            .borrow()
+            .stories
+            .iter()
+            .filter(|s| s.has_data() && s.deleted_when.is_none())
+            .filter_map(|s| s.field_value(field))
+            .filter(|v| !v.is_empty())
+            .collect();
+        values.sort();
+        values.dedup();
+        Ok(values)
+    }
+
+    fn fetch_story_releases(&self, story_id: u32) -> Result<Vec<DbStoryRelease>, IfdbError> {
+        let data = self.data.borrow();
+        let story = match data.story(story_id) {
+            Some(story) => story,
+            None => return Ok(vec![]),
+        };
+        let preferred = story.preferred_ifid(true);
+        Ok(story
+            .ifids
+            .iter()
+            .filter_map(|(ifid, data)| {
+                data.as_ref().map(|data| DbStoryRelease {
+                    ifid: ifid.clone(),
+                    zcode: zcode_from_header(data),
+                    preferred: Some(ifid) == preferred,
+                })
+            })
+            .collect())
+    }
+
+    fn store_preferred_release(&self, story_id: u32, ifid: &str) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        match data
+            .stories
+            .iter_mut()
+            .find(|s| s.id == story_id && s.has_ifid(ifid, true))
+        {
+            Some(story) => {
+                story.preferred_ifid = Some(ifid.to_string());
+                Ok(())
+            }
+            None => Err(IfdbError::NotFound(format!(
+                "No story file for IFID {} in story {}",
+                ifid, story_id
+            ))),
+        }
+    }
+
+    fn get_story_summary_by_id(&self, story_id: u32) -> Result<Option<StorySummary>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story(story_id)
+            .filter(|s| s.deleted_when.is_none())
+            .and_then(|s| s.preferred_ifid(true).map(|ifid| s.summary(ifid))))
+    }
+
+    fn get_story_id_for_ifid(
+        &self,
+        ifid: &str,
+        playable_only: bool,
+    ) -> Result<Option<u32>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story_for_ifid(ifid, playable_only)
+            .map(|s| s.id))
+    }
+
+    fn create_story(&self, story: Story) -> Result<(), IfdbError> {
+        if story.identification.ifids.is_empty() {
+            return Err(IfdbError::Invalid("Story has no ifids".to_string()));
+        }
+
+        let mut data = self.data.borrow_mut();
+        for ifid in &story.identification.ifids {
+            if data.story_for_ifid(ifid, true).is_some() {
+                return Err(IfdbError::Constraint(format!(
+                    "Story data already exists for IFID {}",
+                    ifid
+                )));
+            }
+            if data.story_for_ifid(ifid, false).is_some() {
+                return Err(IfdbError::Constraint(format!(
+                    "A story already exists for IFID {}",
+                    ifid
+                )));
+            }
+        }
+
+        let id = next_id(data.stories.iter().map(|s| i64::from(s.id))) as u32;
+        let resource_id = next_id(
+            data.stories
+                .iter()
+                .flat_map(|s| s.resources.iter().map(|r| r.dbid)),
+        );
+        let resources = story
+            .resources
+            .iter()
+            .enumerate()
+            .map(|(i, resource)| DbStoryResource {
+                dbid: resource_id + i as i64,
+                leafname: resource.leafname.clone(),
+                description: resource.description.clone(),
+                size: None,
+            })
+            .collect();
+
+        data.stories.push(MemoryStory {
+            id,
+            ifids: story
+                .identification
+                .ifids
+                .iter()
+                .map(|ifid| (ifid.clone(), None))
+                .collect(),
+            preferred_ifid: None,
+            resources,
+            cover_image: None,
+            cover_thumbnail: None,
+            date_added: Utc::now().naive_utc(),
+            last_played: None,
+            time_played: 0,
+            review: StoryReview {
+                play_status: PlayStatus::Unplayed,
+                rating: None,
+                review: None,
+            },
+            deleted_when: None,
+            story,
+        });
+        Ok(())
+    }
+
+    fn update_last_played_to_now(&self, story_id: i64) -> Result<(), IfdbError> {
+        if let Some(story) = self.data.borrow_mut().story_mut(story_id as u32) {
+            story.last_played = Some(Utc::now().naive_local());
+
+            // Opening a story for the first time starts it
+            if story.review.play_status == PlayStatus::Unplayed {
+                story.review.play_status = PlayStatus::InProgress;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_story_review(&self, story_id: u32) -> Result<Option<StoryReview>, IfdbError> {
+        Ok(self.data.borrow().story(story_id).map(|s| s.review.clone()))
+    }
+
+    fn update_story_review(&self, story_id: u32, review: &StoryReview) -> Result<(), IfdbError> {
+        if let Some(rating) = review.rating {
+            if !(1..=MAX_RATING).contains(&rating) {
+                return Err(IfdbError::Invalid(format!(
+                    "Rating must be from 1 to {}.",
+                    MAX_RATING
+                )));
+            }
+        }
+
+        if let Some(story) = self.data.borrow_mut().story_mut(story_id) {
+            // Empty reviews are stored as no review
+            story.review = StoryReview {
+                play_status: review.play_status,
+                rating: review.rating,
+                review: review
+                    .review
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|r| !r.is_empty())
+                    .map(str::to_string),
+            };
+        }
+        Ok(())
+    }
+
+    fn add_to_time_played(&self, story_id: i64, elapsed: i64) -> Result<(), IfdbError> {
+        if let Some(story) = self.data.borrow_mut().story_mut(story_id as u32) {
+            story.time_played += elapsed;
+        }
+        Ok(())
+    }
+
+    fn update_story(&self, dbstory: DbStory) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        let mut resource_id = next_id(
+            data.stories
+                .iter()
+                .flat_map(|s| s.resources.iter().map(|r| r.dbid)),
+        );
+
+        if let Some(story) = data.story_mut(dbstory.story_id) {
+            // Resources are matched by leafname, so their ids are kept
+            let mut resources = vec![];
+            for resource in &dbstory.story.resources {
+                let dbid = match story
+                    .resources
+                    .iter()
+                    .find(|r| r.leafname == resource.leafname)
+                {
+                    Some(existing) => existing.dbid,
+                    None => {
+                        resource_id += 1;
+                        resource_id - 1
+                    }
+                };
+                resources.push(DbStoryResource {
+                    dbid,
+                    leafname: resource.leafname.clone(),
+                    description: resource.description.clone(),
+                    size: None,
+                });
+            }
+            resources.sort_by_key(|r| r.dbid);
+            story.resources = resources;
+            story.story = dbstory.story;
+        }
+        Ok(())
+    }
+
+    fn fetch_story_resources(&self, story_id: u32) -> Result<Vec<DbStoryResource>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story(story_id)
+            .map(|s| s.resources.clone())
+            .unwrap_or_default())
+    }
+
+    fn get_resource_data(&self, _: i64) -> Result<Option<Vec<u8>>, IfdbError> {
+        Ok(None)
+    }
+
+    fn store_cover_image(&self, ifid: &str, data: Vec<u8>) -> Result<(), IfdbError> {
+        let mut stories = self.data.borrow_mut();
+        match stories.stories.iter_mut().find(|s| s.has_ifid(ifid, false)) {
+            Some(story) => {
+                story.cover_thumbnail = make_cover_thumbnail(&data);
+                story.cover_image = Some(data);
+                Ok(())
+            }
+            None => Err(IfdbError::NotFound(format!(
+                "No story found for ifid {}",
+                ifid
+            ))),
+        }
+    }
+
+    fn fetch_cover_image(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story(story_id)
+            .and_then(|s| s.cover_image.clone()))
+    }
+
+    fn fetch_cover_thumbnail(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story(story_id)
+            .and_then(|s| s.cover_thumbnail.clone()))
+    }
+
+    fn add_story_data(
+        &self,
+        ifid: &str,
+        data: Vec<u8>,
+        default_name: &str,
+    ) -> Result<(), IfdbError> {
+        if self.get_story_id_for_ifid(ifid, false)?.is_none() {
+            self.create_story(placeholder_story(ifid, default_name))?;
+        }
+
+        for story in self.data.borrow_mut().stories.iter_mut() {
+            for (i, story_data) in story.ifids.iter_mut() {
+                if i == ifid {
+                    *story_data = Some(data.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn replace_story_data(
+        &self,
+        story_id: u32,
+        ifid: &str,
+        new_ifid: &str,
+        data: Vec<u8>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError> {
+        {
+            let mut memory = self.data.borrow_mut();
+            let story = memory.story_mut(story_id).ok_or_else(|| {
+                IfdbError::NotFound(format!("No story found for id {}", story_id))
+            })?;
+            match story.ifids.iter_mut().find(|(i, _)| i == ifid) {
+                Some(release) => *release = (new_ifid.to_string(), Some(data)),
+                None => {
+                    return Err(IfdbError::NotFound(format!(
+                        "No story file for IFID {} in story {}",
+                        ifid, story_id
+                    )))
+                }
+            }
+            if story.preferred_ifid.as_deref() == Some(ifid) {
+                story.preferred_ifid = Some(new_ifid.to_string());
+            }
+
+            let MemoryData {
+                saves,
+                trashed_autosaves,
+                sessions,
+                history,
+                ..
+            } = &mut *memory;
+            let trashed = trashed_autosaves.iter_mut().map(|(save, _)| save);
+            for save in saves.iter_mut().chain(trashed).filter(|s| s.ifid == ifid) {
+                save.ifid = new_ifid.to_string();
+            }
+            for session in sessions.iter_mut().filter(|s| s.ifid == ifid) {
+                session.ifid = new_ifid.to_string();
+            }
+            for (_, entry) in history.iter_mut() {
+                if entry.ifid.as_deref() == Some(ifid) {
+                    entry.ifid = Some(new_ifid.to_string());
+                }
+            }
+        }
+        self.add_story_history(story_id, Some(new_ifid), description)
+    }
+
+    fn add_story_history(
+        &self,
+        story_id: u32,
+        ifid: Option<&str>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError> {
+        let mut data = self.data.borrow_mut();
+        let entry = DbStoryHistory {
+            dbid: next_id(data.history.iter().map(|(_, h)| h.dbid)),
+            ifid: ifid.map(String::from),
+            recorded_when: Utc::now().naive_utc(),
+            description: description.to_string(),
+        };
+        data.history.push((story_id, entry.clone()));
+        Ok(entry)
+    }
+
+    fn fetch_story_history(&self, story_id: u32) -> Result<Vec<DbStoryHistory>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .history
+            .iter()
+            .rev()
+            .filter(|(id, _)| *id == story_id)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    ///
+    /// Saves
+    ///
+    fn count_saves(&self) -> Result<u32, IfdbError> {
+        Ok(self.data.borrow().saves.len() as u32)
+    }
+
+    fn count_autosaves_for_story(&self, ifid: String) -> Result<u32, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .saves
+            .iter()
+            .filter(|s| s.ifid == ifid && s.save_type == SaveType::Autosave)
+            .count() as u32)
+    }
+
+    fn get_save(&self, ifid: String, name: String) -> Result<Option<DbSave>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .saves
+            .iter()
+            .find(|s| s.ifid == ifid && s.name == name)
+            .cloned())
+    }
+
+    fn get_save_by_id(&self, ifid: String, dbid: i64) -> Result<Option<DbSave>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .saves
+            .iter()
+            .find(|s| s.ifid == ifid && s.dbid == dbid)
+            .cloned())
+    }
+
+    fn fetch_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError> {
+        let mut saves: Vec<DbSave> = self
+            .data
+            .borrow()
+            .saves
+            .iter()
+            .filter(|s| s.ifid == ifid)
+            .cloned()
+            .collect();
+        saves.sort_by(|a, b| b.saved_when.cmp(&a.saved_when));
+        Ok(saves)
+    }
+
+    fn fetch_manual_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError> {
+        Ok(self
+            .fetch_saves_for_ifid(ifid)?
+            .into_iter()
+            .filter(|s| s.save_type == SaveType::Normal)
+            .collect())
+    }
+
+    fn store_save(&self, dbsave: &DbSave, overwrite: bool) -> Result<i64, IfdbError> {
+        // If there is a save with the exact same data and save type as this save with the same
+        // parent id, just return that save instead. This avoids branching saves unless necessary
+        if let Some(existing) = self
+            .fetch_saves_for_ifid(dbsave.ifid.clone())?
+            .into_iter()
+            .find(|s| {
+                s.save_type == dbsave.save_type
+                    && s.parent_id == dbsave.parent_id
+                    && s.data == dbsave.data
+            })
+        {
+            return Ok(existing.dbid);
+        }
+
+        let mut data = self.data.borrow_mut();
+        let mut save = dbsave.clone();
+        match dbsave.save_type {
+            SaveType::Autosave => {
+                // Autosaves should have unique names, since the name itself isn't important
+                let ids = data
+                    .saves
+                    .iter()
+                    .chain(data.trashed_autosaves.iter().map(|(s, _)| s))
+                    .filter(|s| s.ifid == dbsave.ifid);
+                if let Some(max) = ids.map(|s| s.dbid).max() {
+                    save.name = format!("{} - {}", dbsave.name, max);
+                }
+            }
+            SaveType::Normal => {
+                if overwrite {
+                    data.saves
+                        .retain(|s| s.ifid != dbsave.ifid || s.name != dbsave.name);
+                }
+            }
+        }
+
+        if data
+            .saves
+            .iter()
+            .any(|s| s.ifid == save.ifid && s.name == save.name)
+        {
+            return Err(IfdbError::Constraint(format!(
+                "There is already a save named {}.",
+                save.name
+            )));
+        }
+
+        save.dbid = next_id(
+            data.saves
+                .iter()
+                .chain(data.trashed_autosaves.iter().map(|(s, _)| s))
+                .map(|s| s.dbid),
+        );
+        data.saves.push(save.clone());
+        Ok(save.dbid)
+    }
+
+    fn find_ifid_for_release(
+        &self,
+        release: u16,
+        serial: &[u8; 6],
+        checksum: u16,
+    ) -> Result<Option<String>, IfdbError> {
+        let matches = |data: &[u8]| {
+            data.get(HEADER_RELEASE_NUMBER..HEADER_RELEASE_NUMBER + 2)
+                == Some(&release.to_be_bytes()[..])
+                && data.get(HEADER_SERIAL..HEADER_SERIAL + 6) == Some(&serial[..])
+                && data.get(HEADER_CHECKSUM..HEADER_CHECKSUM + 2)
+                    == Some(&checksum.to_be_bytes()[..])
+        };
+
+        Ok(self
+            .data
+            .borrow()
+            .stories
+            .iter()
+            .flat_map(|s| s.ifids.iter())
+            .find(|(_, data)| data.as_deref().map(matches).unwrap_or(false))
+            .map(|(ifid, _)| ifid.clone()))
+    }
+
+    ///
+    /// Trash
+    ///
+    fn set_story_trashed(&self, story_id: u32, trashed: bool) -> Result<(), IfdbError> {
+        match self.data.borrow_mut().story_mut(story_id) {
+            Some(story) => {
+                // A story already in the trash keeps the time it was first deleted
+                story.deleted_when = if trashed {
+                    story.deleted_when.or_else(|| Some(Utc::now().naive_utc()))
+                } else {
+                    None
+                };
+                Ok(())
+            }
+            None => Err(IfdbError::NotFound(format!(
+                "No story with id {}",
+                story_id
+            ))),
+        }
+    }
+
+    fn set_autosaves_trashed(&self, ifid: &str, trashed: bool) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if trashed {
+            let (autosaves, saves): (Vec<DbSave>, Vec<DbSave>) = data
+                .saves
+                .drain(..)
+                .partition(|s| s.ifid == ifid && s.save_type == SaveType::Autosave);
+            data.saves = saves;
+            let deleted_when = Utc::now().naive_utc();
+            data.trashed_autosaves
+                .extend(autosaves.into_iter().map(|s| (s, deleted_when)));
+        } else {
+            for (save, deleted_when) in std::mem::take(&mut data.trashed_autosaves) {
+                if save.ifid == ifid {
+                    data.saves.push(save);
+                } else {
+                    data.trashed_autosaves.push((save, deleted_when));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn purge_trashed_autosaves(&self, ifid: &str) -> Result<(), IfdbError> {
+        self.data
+            .borrow_mut()
+            .trashed_autosaves
+            .retain(|(s, _)| s.ifid != ifid);
+        Ok(())
+    }
+
+    fn fetch_trash(&self) -> Result<Vec<DbTrashItem>, IfdbError> {
+        let data = self.data.borrow();
+        let mut items: Vec<DbTrashItem> = data
+            .stories
+            .iter()
+            .filter_map(|story| {
+                story.deleted_when.map(|deleted_when| DbTrashItem {
+                    story_id: story.id,
+                    title: story.story.bibliographic.title.clone(),
+                    contents: TrashContents::Story,
+                    deleted_when,
+                })
+            })
+            .collect();
+
+        for (save, deleted_when) in &data.trashed_autosaves {
+            let story = match data.story_for_ifid(&save.ifid, false) {
+                Some(story) => story,
+                None => continue,
+            };
+            match items.iter_mut().find(|item| {
+                matches!(&item.contents, TrashContents::Autosaves(ifid, _) if *ifid == save.ifid)
+            }) {
+                Some(item) => {
+                    if let TrashContents::Autosaves(_, count) = &mut item.contents {
+                        *count += 1;
+                    }
+                    item.deleted_when = item.deleted_when.max(*deleted_when);
+                }
+                None => items.push(DbTrashItem {
+                    story_id: story.id,
+                    title: story.story.bibliographic.title.clone(),
+                    contents: TrashContents::Autosaves(save.ifid.clone(), 1),
+                    deleted_when: *deleted_when,
+                }),
+            }
+        }
+
+        items.sort_by(|a, b| {
+            b.deleted_when
+                .cmp(&a.deleted_when)
+                .then_with(|| a.title.cmp(&b.title))
+        });
+        Ok(items)
+    }
+
+    ///
+    /// Sessions
+    ///
+    fn get_or_create_session(&self, ifid: String) -> Result<DBSession, IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if let Some(session) = data.sessions.iter().find(|s| s.ifid == ifid) {
+            return Ok(session.clone());
+        }
+
+        // Create a new session with defaults if it doesn't exist
+        let session = DBSession {
+            transcript_name: format!("transcript_{}.log", ifid),
+            command_out_name: format!("commands_{}.commands", ifid),
+            ifid,
+            tools_open: false,
+            details_open: false,
+            debug_open: false,
+            transcript_active: false,
+            command_out_active: false,
+            clues_open: false,
+            notes_open: false,
+            map_open: false,
+            saves_open: false,
+            last_clue_section: String::new(),
+        };
+        data.sessions.push(session.clone());
+        Ok(session)
+    }
+
+    fn store_session(&self, session: DBSession) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if let Some(existing) = data.sessions.iter_mut().find(|s| s.ifid == session.ifid) {
+            *existing = session;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Clues
+    ///
+    fn count_clues(&self) -> Result<u32, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .clue_sections
+            .iter()
+            .flat_map(|s| s.subsections.iter())
+            .map(|s| s.clues.len() as u32)
+            .sum())
+    }
+
+    fn reveal_clue(&self, clue_id: u32) -> Result<Option<Clue>, IfdbError> {
+        let mut data = self.data.borrow_mut();
+        let clue = data
+            .clue_sections
+            .iter_mut()
+            .flat_map(|s| s.subsections.iter_mut())
+            .flat_map(|s| s.clues.iter_mut())
+            .find(|c| c.dbid == clue_id);
+
+        Ok(clue.map(|clue| {
+            let revealed = clue.clone();
+            clue.is_revealed = true;
+            revealed
+        }))
+    }
+
+    fn hide_clue(&self, clue_id: u32) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        for clue in data
+            .clue_sections
+            .iter_mut()
+            .flat_map(|s| s.subsections.iter_mut())
+            .flat_map(|s| s.clues.iter_mut())
+            .filter(|c| c.dbid == clue_id)
+        {
+            clue.is_revealed = false;
+        }
+        Ok(())
+    }
+
+    fn story_has_clues(&self, story_id: u32) -> Result<bool, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .clue_sections
+            .iter()
+            .any(|s| s.story_id == story_id))
+    }
+
+    fn get_clues_for_story(&self, story_id: u32) -> Result<Vec<ClueSection>, IfdbError> {
+        let mut sections: Vec<ClueSection> = self
+            .data
+            .borrow()
+            .clue_sections
+            .iter()
+            .filter(|s| s.story_id == story_id)
+            .cloned()
+            .collect();
+
+        for clue in sections
+            .iter_mut()
+            .flat_map(|s| s.subsections.iter_mut())
+            .flat_map(|s| s.clues.iter_mut())
+            .filter(|c| c.is_revealed)
+        {
+            clue.text = clue.revealed_text();
+        }
+        Ok(sections)
+    }
+
+    fn add_clue(
+        &self,
+        story_id: u32,
+        section_name: String,
+        subsection_name: String,
+        clue_text: String,
+    ) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        let sections = &mut data.clue_sections;
+        let subsection_id = next_id(
+            sections
+                .iter()
+                .flat_map(|s| s.subsections.iter())
+                .map(|s| i64::from(s.dbid)),
+        ) as u32;
+        let clue_id = next_id(
+            sections
+                .iter()
+                .flat_map(|s| s.subsections.iter())
+                .flat_map(|s| s.clues.iter())
+                .map(|c| i64::from(c.dbid)),
+        ) as u32;
+
+        // Get or create the section
+        let section = match sections
+            .iter()
+            .position(|s| s.story_id == story_id && s.name == section_name)
+        {
+            Some(i) => &mut sections[i],
+            None => {
+                let dbid = next_id(sections.iter().map(|s| i64::from(s.dbid))) as u32;
+                sections.push(ClueSection {
+                    dbid,
+                    story_id,
+                    name: section_name,
+                    subsections: vec![],
+                });
+                sections.last_mut().unwrap()
+            }
+        };
+
+        // Get or create the subsection
+        let subsection = match section
+            .subsections
+            .iter()
+            .position(|s| s.name == subsection_name)
+        {
+            Some(i) => &mut section.subsections[i],
+            None => {
+                section.subsections.push(ClueSubsection {
+                    dbid: subsection_id,
+                    name: subsection_name,
+                    clues: vec![],
+                });
+                section.subsections.last_mut().unwrap()
+            }
+        };
+
+        // Create clue if needed
+        if !subsection.clues.iter().any(|c| c.text == clue_text) {
+            subsection.clues.push(Clue {
+                dbid: clue_id,
+                text: clue_text,
+                is_revealed: false,
+            });
+        }
+        Ok(())
+    }
+
+    ///
+    /// Notes
+    ///
+    fn count_notes(&self) -> Result<u32, IfdbError> {
+        Ok(self.data.borrow().notes.len() as u32)
+    }
+
+    fn get_notes_for_story(
+        &self,
+        story_id: i64,
+        include_done: bool,
+    ) -> Result<Vec<Note>, IfdbError> {
+        let data = self.data.borrow();
+        let mut notes: Vec<Note> = data
+            .notes
+            .iter()
+            .filter(|n| n.story_id == story_id && (include_done || !n.done))
+            .map(|n| Note {
+                room_name: data
+                    .rooms
+                    .iter()
+                    .find(|r| r.story_id == story_id && r.room_id == n.room_id)
+                    .map(|r| r.name.clone()),
+                ..n.clone()
+            })
+            .collect();
+        notes.sort_by_key(|n| (n.room_id, n.dbid));
+        Ok(notes)
+    }
+
+    fn set_note_done(&self, dbid: i64, done: bool) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if let Some(note) = data.notes.iter_mut().find(|n| n.dbid == dbid) {
+            note.done = done;
+        }
+        Ok(())
+    }
+
+    fn set_note_notes(&self, dbid: i64, notes: String, room_id: i32) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if let Some(note) = data.notes.iter_mut().find(|n| n.dbid == dbid) {
+            note.notes = notes;
+            note.room_id = room_id as u32;
+        }
+        Ok(())
+    }
+
+    fn save_note(&self, note: Note) -> Result<i64, IfdbError> {
+        let mut data = self.data.borrow_mut();
+
+        // Insert the room into the map for future reference, if no map record exists
+        if let Some(room_name) = &note.room_name {
+            if !data
+                .rooms
+                .iter()
+                .any(|r| r.story_id == note.story_id && r.room_id == note.room_id)
+            {
+                let dbid = next_id(data.rooms.iter().map(|r| r.dbid));
+                data.rooms.push(MapRoom {
+                    dbid,
+                    story_id: note.story_id,
+                    room_id: note.room_id,
+                    name: room_name.clone(),
+                });
+            }
+        }
+
+        let stored = Note {
+            room_name: None,
+            ..note
+        };
+        if stored.dbid != 0 {
+            if let Some(existing) = data.notes.iter_mut().find(|n| n.dbid == stored.dbid) {
+                *existing = stored.clone();
+            }
+            Ok(stored.dbid)
+        } else {
+            let dbid = next_id(data.notes.iter().map(|n| n.dbid));
+            data.notes.push(Note { dbid, ..stored });
+            Ok(dbid)
+        }
+    }
+
+    ///
+    /// Mapping
+    ///
+    fn get_rooms_for_story(&self, story_id: u32) -> Result<Vec<MapRoom>, IfdbError> {
+        let mut rooms: Vec<MapRoom> = self
+            .data
+            .borrow()
+            .rooms
+            .iter()
+            .filter(|r| r.story_id == i64::from(story_id))
+            .cloned()
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(rooms)
+    }
+
+    ///
+    /// Tags and shelves
+    ///
+    fn fetch_tags(&self) -> Result<Vec<String>, IfdbError> {
+        let mut tags = self.data.borrow().tags.clone();
+        tags.sort_by(|a, b| compare_nocase(a, b));
+        Ok(tags)
+    }
+
+    fn fetch_tags_by_story(&self) -> Result<HashMap<u32, Vec<String>>, IfdbError> {
+        let mut story_tags = self.data.borrow().story_tags.clone();
+        story_tags.sort_by(|(_, a), (_, b)| compare_nocase(a, b));
+
+        let mut tags: HashMap<u32, Vec<String>> = HashMap::new();
+        for (story_id, name) in story_tags {
+            tags.entry(story_id).or_default().push(name);
+        }
+        Ok(tags)
+    }
+
+    fn add_tag_to_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(IfdbError::Invalid(String::from("Please enter a tag name.")));
+        }
+
+        let mut data = self.data.borrow_mut();
+        // Tag names are not case sensitive, so the first spelling is kept
+        let tag = match data.tags.iter().find(|t| t.eq_ignore_ascii_case(name)) {
+            Some(tag) => tag.clone(),
+            None => {
+                data.tags.push(name.to_string());
+                name.to_string()
+            }
+        };
+        if !data.has_tag(story_id, &tag) {
+            data.story_tags.push((story_id, tag));
+        }
+        Ok(())
+    }
+
+    fn remove_tag_from_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        data.story_tags
+            .retain(|(id, tag)| *id != story_id || !tag.eq_ignore_ascii_case(name));
+        data.delete_unused_tags();
+        Ok(())
+    }
+
+    fn fetch_shelves(&self) -> Result<Vec<DbShelf>, IfdbError> {
+        let mut shelves = self.data.borrow().shelves.clone();
+        shelves.sort_by(|a, b| compare_nocase(&a.name, &b.name));
+        Ok(shelves)
+    }
+
+    fn create_shelf(&self, name: &str, query: Option<ShelfQuery>) -> Result<i64, IfdbError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(IfdbError::Invalid(String::from(
+                "Please enter a shelf name.",
+            )));
+        }
+
+        let mut data = self.data.borrow_mut();
+        if data
+            .shelves
+            .iter()
+            .any(|s| s.name.eq_ignore_ascii_case(name))
+        {
+            return Err(IfdbError::Constraint(format!(
+                "There is already a shelf named {}.",
+                name
+            )));
+        }
+
+        let dbid = next_id(data.shelves.iter().map(|s| s.dbid));
+        data.shelves.push(DbShelf {
+            dbid,
+            name: name.to_string(),
+            query,
+        });
+        Ok(dbid)
+    }
+
+    fn delete_shelf(&self, shelf_id: i64) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        data.shelf_stories.retain(|(id, _)| *id != shelf_id);
+        data.shelves.retain(|s| s.dbid != shelf_id);
+        Ok(())
+    }
+
+    fn fetch_shelf_ids_for_story(&self, story_id: u32) -> Result<Vec<i64>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .shelf_stories
+            .iter()
+            .filter(|(_, id)| *id == story_id)
+            .map(|(shelf_id, _)| *shelf_id)
+            .collect())
+    }
+
+    fn add_story_to_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if !data.shelf_stories.contains(&(shelf_id, story_id)) {
+            data.shelf_stories.push((shelf_id, story_id));
+        }
+        Ok(())
+    }
+
+    fn remove_story_from_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError> {
+        self.data
+            .borrow_mut()
+            .shelf_stories
+            .retain(|s| *s != (shelf_id, story_id));
+        Ok(())
+    }
+
+    ///
+    /// Fonts
+    ///
+    fn get_fonts(&self) -> Result<Vec<DbFont>, IfdbError> {
+        let mut fonts = self.data.borrow().fonts.clone();
+        fonts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(fonts)
+    }
+
+    fn add_font(&self, name: &str, data: Vec<u8>, monospace: bool) -> Result<(), IfdbError> {
+        let mut stored = self.data.borrow_mut();
+        if stored.fonts.iter().any(|f| f.name == name) {
+            return Err(IfdbError::Constraint(format!(
+                "There is already a font named {}.",
+                name
+            )));
+        }
+
+        let dbid = next_id(stored.fonts.iter().map(|f| f.dbid));
+        stored.fonts.push(DbFont {
+            dbid,
+            name: name.to_string(),
+            data,
+            monospace,
+        });
+        Ok(())
+    }
+
+    fn update_font_metadata(&self, font: DbFont) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if let Some(existing) = data.fonts.iter_mut().find(|f| f.dbid == font.dbid) {
+            existing.name = font.name;
+            existing.monospace = font.monospace;
+        }
+        Ok(())
+    }
+
+    fn delete_font(&self, dbid: i64) -> Result<(), IfdbError> {
+        self.data.borrow_mut().fonts.retain(|f| f.dbid != dbid);
+        Ok(())
+    }
+
+    ///
+    /// Preferences/Themes
+    ///
+    fn get_theme(&self, name: &str) -> Result<Option<DbTheme>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .themes
+            .iter()
+            .find(|t| t.name == name)
+            .cloned())
+    }
+
+    fn store_theme(&self, theme: DbTheme) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        data.themes.retain(|t| t.name != theme.name);
+        data.themes.push(theme);
+        Ok(())
+    }
+
+    ///
+    /// Windows and settings
+    ///
+    fn get_window_details(
+        &self,
+        story_id: u32,
+        window_type: WindowType,
+    ) -> Result<Option<WindowDetails>, IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .window_details
+            .iter()
+            .find(|w| w.story_id == i64::from(story_id) && w.window_type == window_type)
+            .cloned())
+    }
+
+    fn store_window_details(&self, details: &mut WindowDetails) -> Result<(), IfdbError> {
+        let mut data = self.data.borrow_mut();
+        if details.dbid == 0 {
+            if data
+                .window_details
+                .iter()
+                .any(|w| w.story_id == details.story_id && w.window_type == details.window_type)
+            {
+                return Err(IfdbError::Constraint(format!(
+                    "Window details already exist for story {}",
+                    details.story_id
+                )));
+            }
+
+            details.dbid = next_id(data.window_details.iter().map(|w| w.dbid));
+            data.window_details.push(details.clone());
+        } else if let Some(existing) = data
+            .window_details
+            .iter_mut()
+            .find(|w| w.dbid == details.dbid)
+        {
+            existing.x = details.x;
+            existing.y = details.y;
+            existing.width = details.width;
+            existing.height = details.height;
+            existing.open = details.open;
+        }
+        Ok(())
+    }
+
+    fn store_current_story(&self, story_id: Option<i64>) -> Result<(), IfdbError> {
+        self.data.borrow_mut().current_story = story_id;
+        Ok(())
+    }
+
+    fn get_current_story(&self) -> Result<Option<i64>, IfdbError> {
+        Ok(self.data.borrow().current_story)
+    }
+
+    fn get_story_list_settings(&self) -> Result<(StorySort, StoryFilter), IfdbError> {
+        Ok(self
+            .data
+            .borrow()
+            .story_list_settings
+            .clone()
+            .unwrap_or((StorySort::Title, StoryFilter::default())))
+    }
+
+    fn store_story_list_settings(
+        &self,
+        sort: StorySort,
+        filter: &StoryFilter,
+    ) -> Result<(), IfdbError> {
+        self.data.borrow_mut().story_list_settings = Some((sort, filter.clone()));
+        Ok(())
+    }
+}
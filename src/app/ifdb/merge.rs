@@ -0,0 +1,636 @@
+///
+/// Merge another Ferrif database into this one, such as the copy from another computer.
+/// Stories are matched by IFID
+///
+use super::store::IfdbStore;
+use super::{IfdbConnection, IfdbError, Note, PlayStatus, SaveType};
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// A change made to this database by a merge
+#[derive(PartialEq, Debug, Clone)]
+pub enum MergeChange {
+    StoryAdded(String),        // Story was only in the other database. String is title
+    IfidAdded(String, String), // IFID added to a story. First string is title, second IFID
+    StoryDataAdded(String, String), // Story file added. First string is title, second IFID
+    CoverAdded(String),        // Cover image added to a story without one. String is title
+    ResourceAdded(String, String), // Auxiliary file added. First string is title, second leafname
+    PlayUpdated(String),       // Time played, status, rating or review updated. String is title
+    TagAdded(String, String),  // Tag added to a story. First string is title, second tag
+    ShelfAdded(String),        // Shelf created. String is shelf name
+    ShelfStoryAdded(String, String), // Story added to a shelf. First string is shelf name, second title
+    SaveAdded(String, String),       // Manual save added. First string is title, second save name
+    SaveRenamed(String, String, String), // Manual save added under a new name, as a different save has its name. First string is title, second original name, third new name
+    AutosavesAdded(String, usize), // Autosaves added to the story's autosave history. First string is title, second count
+    NotesAdded(String, usize),     // First string is title, second count
+    NotesDone(String, usize),      // Notes marked done. First string is title, second count
+    CluesAdded(String, usize),     // First string is title, second count
+    CluesRevealed(String, usize),  // First string is title, second count
+}
+
+impl fmt::Display for MergeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeChange::StoryAdded(title) => write!(f, "Added story \"{}\"", title),
+            MergeChange::IfidAdded(title, ifid) => {
+                write!(f, "Added IFID {} to \"{}\"", ifid, title)
+            }
+            MergeChange::StoryDataAdded(title, ifid) => {
+                write!(f, "Added story file for IFID {} to \"{}\"", ifid, title)
+            }
+            MergeChange::CoverAdded(title) => write!(f, "Added cover image to \"{}\"", title),
+            MergeChange::ResourceAdded(title, leafname) => {
+                write!(f, "Added auxiliary file {} to \"{}\"", leafname, title)
+            }
+            MergeChange::PlayUpdated(title) => {
+                write!(f, "Updated time played and review for \"{}\"", title)
+            }
+            MergeChange::TagAdded(title, tag) => write!(f, "Tagged \"{}\" with {}", title, tag),
+            MergeChange::ShelfAdded(name) => write!(f, "Added shelf \"{}\"", name),
+            MergeChange::ShelfStoryAdded(name, title) => {
+                write!(f, "Added \"{}\" to shelf \"{}\"", title, name)
+            }
+            MergeChange::SaveAdded(title, name) => {
+                write!(f, "Added save \"{}\" for \"{}\"", name, title)
+            }
+            MergeChange::SaveRenamed(title, name, new_name) => write!(
+                f,
+                "Added save \"{}\" for \"{}\" as \"{}\", as a different save has that name",
+                name, title, new_name
+            ),
+            MergeChange::AutosavesAdded(title, count) => {
+                write!(f, "Added {} autosaves for \"{}\"", count, title)
+            }
+            MergeChange::NotesAdded(title, count) => {
+                write!(f, "Added {} notes for \"{}\"", count, title)
+            }
+            MergeChange::NotesDone(title, count) => {
+                write!(f, "Marked {} notes done for \"{}\"", count, title)
+            }
+            MergeChange::CluesAdded(title, count) => {
+                write!(f, "Added {} clues for \"{}\"", count, title)
+            }
+            MergeChange::CluesRevealed(title, count) => {
+                write!(f, "Revealed {} clues for \"{}\"", count, title)
+            }
+        }
+    }
+}
+
+// A clue as stored, with the text not yet revealed
+struct MergeClue {
+    section: String,
+    subsection: String,
+    text: String,
+    revealed: bool,
+}
+
+/// Autosaves are numbered when stored, so drop any number added in the other database
+fn autosave_base_name(name: &str) -> &str {
+    match name.rsplit_once(" - ") {
+        Some((base, number)) if number.parse::<u32>().is_ok() => base,
+        _ => name,
+    }
+}
+
+impl IfdbConnection {
+    /// Merge the stories, saves, notes and clues from the database at path into this one.
+    /// The other database is left as is. Everything is merged in one transaction, so nothing
+    /// is kept if there is an error. Returns everything that was changed
+    pub fn merge_from_path(&self, path: &str) -> Result<Vec<MergeChange>, IfdbError> {
+        if !Path::new(path).is_file() {
+            return Err(IfdbError::NotFound(format!(
+                "No database found at {}",
+                path
+            )));
+        }
+
+        // The merge reads from a snapshot, which can be migrated to match this database
+        let snapshot_path = std::env::temp_dir().join(format!(
+            "ferrif-merge-{}-{}.db",
+            std::process::id(),
+            Utc::now().format("%Y%m%d%H%M%S%f")
+        ));
+        let snapshot_str = snapshot_path.to_string_lossy().to_string();
+
+        let result = || -> Result<Vec<MergeChange>, IfdbError> {
+            let connection = Connection::open(path)?;
+            let migrations = IfdbConnection::fetch_migration_names(&connection).map_err(|e| {
+                IfdbError::Invalid(format!("{} is not a Ferrif database: {:?}", path, e))
+            })?;
+            if let Some(unknown) = migrations
+                .iter()
+                .find(|name| !IfdbConnection::is_known_migration(name))
+            {
+                return Err(IfdbError::Invalid(format!(
+                    "{} was made by a newer version of Ferrif (database version {})",
+                    path, unknown
+                )));
+            }
+            connection.execute("VACUUM INTO ?1", params![snapshot_str])?;
+            drop(connection);
+
+            let other = IfdbConnection::connect(snapshot_str.as_str())?;
+            other.migrate_without_backup()?;
+            self.merge_from(&other)
+        }();
+        let _ = fs::remove_file(&snapshot_path);
+        self.cache.clear();
+
+        result
+    }
+
+    fn merge_from(&self, other: &IfdbConnection) -> Result<Vec<MergeChange>, IfdbError> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let mut changes = vec![];
+
+        let shelf_ids = self.merge_shelves(other, &mut changes)?;
+        for story_id in other.fetch_story_ids()? {
+            self.merge_story(other, story_id, &shelf_ids, &mut changes)?;
+        }
+
+        transaction.commit()?;
+        Ok(changes)
+    }
+
+    /// Ids of the stories to merge. Stories in the trash are left out
+    fn fetch_story_ids(&self) -> Result<Vec<u32>, IfdbError> {
+        let result = || -> Result<Vec<u32>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM story WHERE deleted_when IS NULL ORDER BY id")?;
+            let rows = statement.query_map(params![], |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        result.map_err(IfdbError::from)
+    }
+
+    /// Add any shelves missing here, matched by name. Returns a map from the other
+    /// database's shelf ids to the ids here
+    fn merge_shelves(
+        &self,
+        other: &IfdbConnection,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<HashMap<i64, (i64, String)>, IfdbError> {
+        let shelves = self.fetch_shelves()?;
+        let mut shelf_ids = HashMap::new();
+        for other_shelf in other.fetch_shelves()? {
+            let shelf_id = match shelves
+                .iter()
+                .find(|s| s.name.to_lowercase() == other_shelf.name.to_lowercase())
+            {
+                Some(shelf) => shelf.dbid,
+                None => {
+                    changes.push(MergeChange::ShelfAdded(other_shelf.name.clone()));
+                    self.create_shelf(other_shelf.name.as_str(), other_shelf.query.clone())?
+                }
+            };
+            shelf_ids.insert(other_shelf.dbid, (shelf_id, other_shelf.name));
+        }
+
+        Ok(shelf_ids)
+    }
+
+    fn merge_story(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        shelf_ids: &HashMap<i64, (i64, String)>,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let other_story = match other.get_story(other_story_id)? {
+            Some(story) => story,
+            None => return Ok(()),
+        };
+        let title = other_story.story.bibliographic.title.clone();
+        let ifids = other.fetch_ifids_for_story(other_story_id, false)?;
+
+        // The story here is the first one with any of the IFIDs
+        let mut found = None;
+        for ifid in ifids.iter() {
+            found = self.get_story_id_for_ifid(ifid, false)?;
+            if found.is_some() {
+                break;
+            }
+        }
+
+        let story_id = match found {
+            Some(story_id) => {
+                for ifid in ifids.iter() {
+                    if self.get_story_id_for_ifid(ifid, false)?.is_none() {
+                        self.connection.execute(
+                            "INSERT INTO story_ifid (story_id, ifid) VALUES (?1,?2)",
+                            params![story_id, ifid],
+                        )?;
+                        changes.push(MergeChange::IfidAdded(title.clone(), ifid.clone()));
+                    }
+                }
+                story_id
+            }
+            None => {
+                let mut story = other_story.story.clone();
+                story.identification.ifids = ifids.clone();
+                self.create_story(story)?;
+                changes.push(MergeChange::StoryAdded(title.clone()));
+                match ifids.first() {
+                    Some(ifid) => self.get_story_id_for_ifid(ifid, false)?,
+                    None => None,
+                }
+                .ok_or_else(|| IfdbError::NotFound(format!("Unable to add story \"{}\"", title)))?
+            }
+        };
+
+        for ifid in ifids.iter() {
+            if let Some(data) = other.get_story_data_for_ifid(ifid)? {
+                if self.get_story_data_for_ifid(ifid)?.is_none() {
+                    self.add_story_data(ifid, data, &title)?;
+                    changes.push(MergeChange::StoryDataAdded(title.clone(), ifid.clone()));
+                }
+            }
+        }
+
+        self.merge_cover_and_resources(other, other_story_id, story_id, &title, changes)?;
+        self.merge_play(other, other_story_id, story_id, &title, changes)?;
+        self.merge_tags_and_shelves(other, other_story_id, story_id, &title, shelf_ids, changes)?;
+        for ifid in ifids.iter() {
+            self.merge_saves(other, ifid, &title, changes)?;
+        }
+        self.merge_notes(other, other_story_id, story_id, &title, changes)?;
+        self.merge_clues(other, other_story_id, story_id, &title, changes)
+    }
+
+    fn get_story_data_for_ifid(&self, ifid: &str) -> Result<Option<Vec<u8>>, IfdbError> {
+        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT story_data FROM story_ifid WHERE ifid = ?1")?;
+            let mut query = statement.query(params![ifid])?;
+            match query.next()? {
+                Some(row) => row.get(0),
+                None => Ok(None),
+            }
+        }();
+
+        result.map_err(IfdbError::from)
+    }
+
+    /// A cover or auxiliary file is only added if this story is missing it
+    fn merge_cover_and_resources(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        story_id: u32,
+        title: &str,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        if let Some(cover) = other.fetch_cover_image(other_story_id)? {
+            if self.fetch_cover_image(story_id)?.is_none() {
+                self.store_cover_sql(story_id, &cover)?;
+                changes.push(MergeChange::CoverAdded(title.to_string()));
+            }
+        }
+
+        let resources = self.fetch_story_resources(story_id)?;
+        for other_resource in other.fetch_story_resources(other_story_id)? {
+            let data = match other.get_resource_data(other_resource.dbid)? {
+                Some(data) => data,
+                None => continue,
+            };
+            match resources
+                .iter()
+                .find(|r| r.leafname == other_resource.leafname)
+            {
+                Some(resource) if resource.size.is_some() => continue,
+                Some(resource) => self.store_resource_data(resource.dbid, &data)?,
+                None => {
+                    self.connection
+                        .execute(
+                            "INSERT INTO story_resource (story_id, leafname, description, data) VALUES (?1,?2,?3,?4)",
+                            params![story_id, other_resource.leafname, other_resource.description, data],
+                        )?;
+                }
+            }
+            changes.push(MergeChange::ResourceAdded(
+                title.to_string(),
+                other_resource.leafname,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Both databases usually hold the time played before they diverged, so the larger
+    /// time is kept rather than the total. Status, rating and review are only filled in
+    fn merge_play(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        story_id: u32,
+        title: &str,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let (other_story, story) =
+            match (other.get_story(other_story_id)?, self.get_story(story_id)?) {
+                (Some(other_story), Some(story)) => (other_story, story),
+                _ => return Ok(()),
+            };
+        let (other_review, original) = match (
+            other.get_story_review(other_story_id)?,
+            self.get_story_review(story_id)?,
+        ) {
+            (Some(other_review), Some(review)) => (other_review, review),
+            _ => return Ok(()),
+        };
+
+        let mut review = original.clone();
+        if review.play_status == PlayStatus::Unplayed {
+            review.play_status = other_review.play_status;
+        }
+        if review.rating.is_none() {
+            review.rating = other_review.rating;
+        }
+        if review.review.is_none() {
+            review.review = other_review.review;
+        }
+
+        let time_played = story.time_played.max(other_story.time_played);
+        let last_played = story.last_played.max(other_story.last_played);
+        if review == original
+            && time_played == story.time_played
+            && last_played == story.last_played
+        {
+            return Ok(());
+        }
+
+        self.update_story_review(story_id, &review)?;
+        self.connection.execute(
+            "UPDATE story SET time_played = ?1, last_played = ?2 WHERE id = ?3",
+            params![time_played, last_played, story_id],
+        )?;
+        changes.push(MergeChange::PlayUpdated(title.to_string()));
+
+        Ok(())
+    }
+
+    fn merge_tags_and_shelves(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        story_id: u32,
+        title: &str,
+        shelf_ids: &HashMap<i64, (i64, String)>,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let tags: Vec<String> = self
+            .fetch_tags_for_story(story_id)?
+            .iter()
+            .map(|tag| tag.to_lowercase())
+            .collect();
+        for tag in other.fetch_tags_for_story(other_story_id)? {
+            if !tags.contains(&tag.to_lowercase()) {
+                self.add_tag_to_story(story_id, tag.as_str())?;
+                changes.push(MergeChange::TagAdded(title.to_string(), tag));
+            }
+        }
+
+        let story_shelf_ids = self.fetch_shelf_ids_for_story(story_id)?;
+        for other_shelf_id in other.fetch_shelf_ids_for_story(other_story_id)? {
+            if let Some((shelf_id, name)) = shelf_ids.get(&other_shelf_id) {
+                if !story_shelf_ids.contains(shelf_id) {
+                    self.add_story_to_shelf(*shelf_id, story_id)?;
+                    changes.push(MergeChange::ShelfStoryAdded(
+                        name.clone(),
+                        title.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn max_save_id(&self) -> Result<i64, IfdbError> {
+        let result = || -> Result<i64, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT coalesce(max(id), 0) FROM saves")?;
+            let mut query = statement.query(params![])?;
+            match query.next()? {
+                Some(row) => row.get(0),
+                None => Ok(0),
+            }
+        }();
+
+        result.map_err(IfdbError::from)
+    }
+
+    /// Saves are stored oldest first, with each parent_id changed to the parent's id here.
+    /// store_save reuses identical saves with the same parent, so the autosave history both
+    /// databases share is kept once and anything newer is added on to it
+    fn merge_saves(
+        &self,
+        other: &IfdbConnection,
+        ifid: &str,
+        title: &str,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let mut saves = other.fetch_saves_for_ifid(ifid.to_string())?;
+        saves.sort_by_key(|save| save.dbid);
+
+        // Saves with a higher id than this were added by the merge
+        let max_save_id = self.max_save_id()?;
+        let mut save_ids: HashMap<i64, i64> = HashMap::new();
+        let mut autosaves_added = 0;
+        for mut save in saves {
+            let other_dbid = save.dbid;
+            save.parent_id = save_ids.get(&save.parent_id).copied().unwrap_or(0);
+
+            let dbid = match save.save_type {
+                SaveType::Autosave => {
+                    save.name = autosave_base_name(&save.name).to_string();
+                    let dbid = self.store_save(&save, false)?;
+                    if dbid > max_save_id {
+                        autosaves_added += 1;
+                    }
+                    dbid
+                }
+                SaveType::Normal => match self.get_save(ifid.to_string(), save.name.clone())? {
+                    Some(existing) if existing.data == save.data => existing.dbid,
+                    existing => {
+                        let name = save.name.clone();
+                        if existing.is_some() {
+                            save.name = self.unused_save_name(ifid, &name)?;
+                        }
+                        let dbid = self.store_save(&save, false)?;
+                        if dbid > max_save_id {
+                            changes.push(if existing.is_some() {
+                                MergeChange::SaveRenamed(title.to_string(), name, save.name.clone())
+                            } else {
+                                MergeChange::SaveAdded(title.to_string(), name)
+                            });
+                        }
+                        dbid
+                    }
+                },
+            };
+            save_ids.insert(other_dbid, dbid);
+        }
+
+        if autosaves_added > 0 {
+            changes.push(MergeChange::AutosavesAdded(
+                title.to_string(),
+                autosaves_added,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Name for a save that is not used yet, such as "Before the troll (merged 2)"
+    fn unused_save_name(&self, ifid: &str, name: &str) -> Result<String, IfdbError> {
+        let mut count = 1;
+        loop {
+            let candidate = if count == 1 {
+                format!("{} (merged)", name)
+            } else {
+                format!("{} (merged {})", name, count)
+            };
+            if self
+                .get_save(ifid.to_string(), candidate.clone())?
+                .is_none()
+            {
+                return Ok(candidate);
+            }
+            count += 1;
+        }
+    }
+
+    /// Notes are matched by room and text. A note done in either database is done
+    fn merge_notes(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        story_id: u32,
+        title: &str,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let notes = self.get_notes_for_story(story_id as i64, true)?;
+        let mut added = 0;
+        let mut done = 0;
+        for other_note in other.get_notes_for_story(other_story_id as i64, true)? {
+            match notes
+                .iter()
+                .find(|n| n.room_id == other_note.room_id && n.notes == other_note.notes)
+            {
+                Some(note) => {
+                    if other_note.done && !note.done {
+                        self.set_note_done(note.dbid, true)?;
+                        done += 1;
+                    }
+                }
+                None => {
+                    self.save_note(Note {
+                        dbid: 0,
+                        story_id: story_id as i64,
+                        ..other_note
+                    })?;
+                    added += 1;
+                }
+            }
+        }
+
+        if added > 0 {
+            changes.push(MergeChange::NotesAdded(title.to_string(), added));
+        }
+        if done > 0 {
+            changes.push(MergeChange::NotesDone(title.to_string(), done));
+        }
+
+        Ok(())
+    }
+
+    fn fetch_merge_clues(&self, story_id: u32) -> Result<Vec<MergeClue>, IfdbError> {
+        let result = || -> Result<Vec<MergeClue>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT s.name, sb.name, c.text, c.revealed
+                FROM clue c
+                JOIN clue_subsection sb ON sb.id = c.subsection_id
+                JOIN clue_section s ON s.id = sb.section_id
+                WHERE s.story_id = ?1
+                ORDER BY c.id",
+            )?;
+            let rows = statement.query_map(params![story_id], |row| {
+                let revealed: u32 = row.get(3)?;
+                Ok(MergeClue {
+                    section: row.get(0)?,
+                    subsection: row.get(1)?,
+                    text: row.get(2)?,
+                    revealed: revealed == 1,
+                })
+            })?;
+            rows.collect()
+        }();
+
+        result.map_err(IfdbError::from)
+    }
+
+    /// Clues are added if missing. A clue revealed in either database is revealed
+    fn merge_clues(
+        &self,
+        other: &IfdbConnection,
+        other_story_id: u32,
+        story_id: u32,
+        title: &str,
+        changes: &mut Vec<MergeChange>,
+    ) -> Result<(), IfdbError> {
+        let clues = self.fetch_merge_clues(story_id)?;
+        let mut added = 0;
+        let mut revealed = 0;
+        for other_clue in other.fetch_merge_clues(other_story_id)? {
+            let clue = clues.iter().find(|c| {
+                c.section == other_clue.section
+                    && c.subsection == other_clue.subsection
+                    && c.text == other_clue.text
+            });
+            if clue.is_none() {
+                self.add_clue(
+                    story_id,
+                    other_clue.section.clone(),
+                    other_clue.subsection.clone(),
+                    other_clue.text.clone(),
+                )?;
+                added += 1;
+            }
+
+            if other_clue.revealed && !clue.map(|c| c.revealed).unwrap_or(false) {
+                self.connection.execute(
+                    "UPDATE clue SET revealed = 1 WHERE text = ?1 AND subsection_id IN (
+                            SELECT sb.id FROM clue_subsection sb
+                            JOIN clue_section s ON s.id = sb.section_id
+                            WHERE s.story_id = ?2 AND s.name = ?3 AND sb.name = ?4)",
+                    params![
+                        other_clue.text,
+                        story_id,
+                        other_clue.section,
+                        other_clue.subsection
+                    ],
+                )?;
+                revealed += 1;
+            }
+        }
+
+        if added > 0 {
+            changes.push(MergeChange::CluesAdded(title.to_string(), added));
+        }
+        if revealed > 0 {
+            changes.push(MergeChange::CluesRevealed(title.to_string(), revealed));
+        }
+
+        Ok(())
+    }
+}
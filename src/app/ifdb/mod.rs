@@ -1,30 +1,44 @@
+pub mod blorb;
+mod cache;
+pub mod iff;
 pub mod ifiction;
+pub mod maintenance;
+pub mod memory;
+pub mod merge;
+pub mod quetzal;
+mod sqlite;
+pub mod store;
+pub mod tar;
 pub mod tests;
 
-use chrono::{DateTime, Local, NaiveDateTime, Utc};
-use ifiction::read_stories_from_xml;
+use blorb::read_blorb;
+use cache::StoreCache;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
 use ifiction::{
     convert_cover_format_to_str, convert_forgiveness_to_str, convert_format_to_str,
     convert_ifictiondate_to_str, convert_str_to_cover_format, convert_str_to_forgiveness,
     convert_str_to_ifictiondate, Bibilographic, Colophon, Contacts, Cover, Format, Identification,
     Release, Resource, Story, Zcode,
 };
+use ifiction::{read_stories_from_xml, write_stories_to_xml};
 use regex::Regex;
-use rusqlite::{params, Connection, Result, NO_PARAMS};
-use serde::Serialize;
+use rusqlite::{params, Connection, OptionalExtension, Result, NO_PARAMS};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
-use std::thread;
 use std::fs::File;
 use std::hash::Hash;
 use std::time::Duration;
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::Path;
-use zip::read::ZipFile;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use store::IfdbStore;
+use tar::read_tar;
 
 const MIGRATION_TABLE_NAME: &str = "migrations";
 
@@ -40,20 +54,61 @@ const MIGRATION_9: &str = "0009_fonts";
 const MIGRATION_10: &str = "0010_themes";
 const MIGRATION_11: &str = "0011_monospace";
 const MIGRATION_12: &str = "0012_save_versions";
+const MIGRATION_13: &str = "0013_babel_ifids";
+const MIGRATION_14: &str = "0014_backup_settings";
+const MIGRATION_15: &str = "0015_story_search";
+const MIGRATION_16: &str = "0016_story_list_settings";
+const MIGRATION_17: &str = "0017_tags_shelves";
+const MIGRATION_18: &str = "0018_play_status";
+const MIGRATION_19: &str = "0019_imported_files";
+const MIGRATION_20: &str = "0020_story_resource_data";
+const MIGRATION_21: &str = "0021_cover_thumbnail";
+const MIGRATION_22: &str = "0022_preferred_release";
+const MIGRATION_23: &str = "0023_story_history";
+const MIGRATION_24: &str = "0024_trash";
 
 const CUSTOM_THEME: &str = "custom";
 const DARK_THEME: &str = "dark";
 const LIGHT_THEME: &str = "light";
 
-const LOCK_RETRIES: usize = 5;
-const RETRY_DELAY: Duration = Duration::new(0,1_000_000);
+// How long to wait for another connection (such as an import running in the background)
+// to release the database before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
 
-// When importing from a zipfile, cancel import if this number of files is hit
+// When importing from an archive, cancel import if this number of files is hit. Files in
+// nested archives count towards the total
 const MAX_SUPPORTED_ZIPFILE_SIZE: usize = 500;
 
+// Limits on extracting an archive and any archives inside it, to protect against archive bombs
+const ARCHIVE_LIMITS: ArchiveLimits = ArchiveLimits {
+    max_depth: 4,
+    max_files: MAX_SUPPORTED_ZIPFILE_SIZE,
+    max_size: 256 * 1024 * 1024,
+};
+
+// When importing a directory, don't descend further than this into subdirectories
+const MAX_DIRECTORY_DEPTH: usize = 20;
+
+// Cover thumbnails fit in a square this many pixels wide
+pub const COVER_THUMBNAIL_SIZE: u32 = 160;
+
+// Backup archives hold a database snapshot plus a manifest describing it
+const BACKUP_FORMAT_VERSION: u64 = 1;
+const BACKUP_MANIFEST_NAME: &str = "manifest.json";
+const BACKUP_DATABASE_NAME: &str = "ferrif.db";
+const DAILY_BACKUP_PREFIX: &str = "ferrif-backup-";
+const DAILY_BACKUPS_KEPT: usize = 7;
+
+// Number of names tried when an imported save has the same name as an existing save
+const MAX_SAVE_NAME_ATTEMPTS: usize = 100;
+
+// Extensions of Quetzal save files, wherever saves are imported from
+pub const SAVE_EXTENSIONS: &[&str] = &["qzl", "sav"];
+
 pub struct IfdbConnection {
     connection: Connection,
     pub database_path: String,
+    cache: StoreCache,
 }
 
 //
@@ -72,6 +127,20 @@ fn extract_filename_or_use_original(path_str: &str) -> &str {
     path_str
 }
 
+/// True if a file in an archive is the auxiliary file with the given leafname. Leafnames can
+/// include folders, so the end of the path is compared, ignoring case
+fn resource_matches_path(leafname: &str, path_str: &str) -> bool {
+    let parts = |s: &str| -> Vec<String> {
+        s.split(['/', '\\'])
+            .filter(|part| !part.is_empty())
+            .map(|part| part.to_lowercase())
+            .collect()
+    };
+    let leaf_parts = parts(leafname);
+
+    !leaf_parts.is_empty() && parts(path_str).ends_with(&leaf_parts)
+}
+
 //
 // Structs
 //
@@ -82,6 +151,72 @@ pub struct StorySummary {
     pub title: String,
     pub last_played: Option<NaiveDateTime>,
     pub time_played: i64, // time in seconds
+    pub play_status: PlayStatus,
+    pub rating: Option<u8>,
+}
+
+/// Orders a story's releases, in table r, so the one Play launches is first: the one chosen
+/// in the details window, or else the most recently imported
+const RELEASE_PREFERENCE_ORDER: &str = "ORDER BY COALESCE(r.ifid =
+    (SELECT p.preferred_ifid FROM story p WHERE p.id = r.story_id), 0) DESC, r.id DESC";
+
+const STORY_SUMMARY_COLUMNS: &str =
+    "s.id, s.bibliographic_title, i.ifid, s.last_played, s.time_played, s.play_status, s.rating";
+
+/// How far through a story the player is
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum PlayStatus {
+    Unplayed,
+    InProgress,
+    Finished,
+    Abandoned,
+}
+
+pub const PLAY_STATUSES: [PlayStatus; 4] = [
+    PlayStatus::Unplayed,
+    PlayStatus::InProgress,
+    PlayStatus::Finished,
+    PlayStatus::Abandoned,
+];
+
+impl PlayStatus {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            PlayStatus::Unplayed => "Unplayed",
+            PlayStatus::InProgress => "InProgress",
+            PlayStatus::Finished => "Finished",
+            PlayStatus::Abandoned => "Abandoned",
+        }
+    }
+
+    /// Convert a stored status back to a status. Returns Unplayed for unknown values
+    pub fn from_db_str(status: &str) -> PlayStatus {
+        PLAY_STATUSES
+            .iter()
+            .find(|s| s.to_string() == status)
+            .copied()
+            .unwrap_or(PlayStatus::Unplayed)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PlayStatus::Unplayed => "Unplayed",
+            PlayStatus::InProgress => "In progress",
+            PlayStatus::Finished => "Finished",
+            PlayStatus::Abandoned => "Abandoned",
+        }
+    }
+}
+
+// Ratings run from 1 to this
+pub const MAX_RATING: u8 = 5;
+
+/// The player's own status, rating and review for a story
+#[derive(PartialEq, Clone, Debug)]
+pub struct StoryReview {
+    pub play_status: PlayStatus,
+    pub rating: Option<u8>,
+    pub review: Option<String>,
 }
 
 const SECOND_IN_MS: i64 = 1000;
@@ -89,6 +224,20 @@ const MINUTE_IN_MS: i64 = 1000 * 60;
 const HOUR_IN_MS: i64 = 1000 * 60 * 60;
 
 impl StorySummary {
+    // Expects the columns selected by STORY_SUMMARY_COLUMNS
+    fn from_row(row: &rusqlite::Row<'_>) -> Result<StorySummary> {
+        let play_status: String = row.get(5)?;
+        Ok(StorySummary {
+            story_id: row.get(0)?,
+            title: row.get(1)?,
+            ifid: row.get(2)?,
+            last_played: row.get(3)?,
+            time_played: row.get(4)?,
+            play_status: PlayStatus::from_db_str(&play_status),
+            rating: row.get(6)?,
+        })
+    }
+
     // Return a string describing the time played for this story
     pub fn time_played_description(&self) -> String {
         if self.time_played < SECOND_IN_MS {
@@ -108,6 +257,359 @@ impl StorySummary {
     }
 }
 
+/// Orders for the story list
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum StorySort {
+    Title,
+    Author,
+    FirstPublished,
+    LastPlayed,
+    TimePlayed,
+    DateAdded,
+}
+
+pub const STORY_SORTS: [StorySort; 6] = [
+    StorySort::Title,
+    StorySort::Author,
+    StorySort::FirstPublished,
+    StorySort::LastPlayed,
+    StorySort::TimePlayed,
+    StorySort::DateAdded,
+];
+
+impl StorySort {
+    pub fn to_string(self) -> &'static str {
+        match self {
+            StorySort::Title => "Title",
+            StorySort::Author => "Author",
+            StorySort::FirstPublished => "FirstPublished",
+            StorySort::LastPlayed => "LastPlayed",
+            StorySort::TimePlayed => "TimePlayed",
+            StorySort::DateAdded => "DateAdded",
+        }
+    }
+
+    /// Convert a stored sort back to a sort. Returns Title for unknown values
+    pub fn from_db_str(sort: &str) -> StorySort {
+        STORY_SORTS
+            .iter()
+            .find(|s| s.to_string() == sort)
+            .copied()
+            .unwrap_or(StorySort::Title)
+    }
+
+    pub fn label(&self) -> &str {
+        match self {
+            StorySort::Title => "Title",
+            StorySort::Author => "Author",
+            StorySort::FirstPublished => "First published",
+            StorySort::LastPlayed => "Last played",
+            StorySort::TimePlayed => "Time played",
+            StorySort::DateAdded => "Date added",
+        }
+    }
+
+    // Stories never played or without a date go at the end
+    fn order_by(&self) -> &'static str {
+        match self {
+            StorySort::Title => "s.bibliographic_title COLLATE NOCASE",
+            StorySort::Author => "s.bibliographic_author COLLATE NOCASE",
+            StorySort::FirstPublished => {
+                "s.bibliographic_first_published IS NULL, s.bibliographic_first_published"
+            }
+            StorySort::LastPlayed => "s.last_played IS NULL, s.last_played DESC",
+            StorySort::TimePlayed => "s.time_played DESC",
+            StorySort::DateAdded => "s.date_added IS NULL, s.date_added DESC",
+        }
+    }
+}
+
+/// Story fields the story list can be filtered on
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum StoryFilterField {
+    Genre,
+    Language,
+    Forgiveness,
+    Series,
+}
+
+pub const STORY_FILTER_FIELDS: [StoryFilterField; 4] = [
+    StoryFilterField::Genre,
+    StoryFilterField::Language,
+    StoryFilterField::Forgiveness,
+    StoryFilterField::Series,
+];
+
+impl StoryFilterField {
+    pub fn label(&self) -> &str {
+        match self {
+            StoryFilterField::Genre => "Genre",
+            StoryFilterField::Language => "Language",
+            StoryFilterField::Forgiveness => "Forgiveness",
+            StoryFilterField::Series => "Series",
+        }
+    }
+
+    fn column(&self) -> &'static str {
+        match self {
+            StoryFilterField::Genre => "bibliographic_genre",
+            StoryFilterField::Language => "bibliographic_language",
+            StoryFilterField::Forgiveness => "bibliographic_forgiveness",
+            StoryFilterField::Series => "bibliographic_series",
+        }
+    }
+}
+
+/// Values the story list must match. None matches any value
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct StoryFilter {
+    pub genre: Option<String>,
+    pub language: Option<String>,
+    pub forgiveness: Option<String>,
+    pub series: Option<String>,
+    pub tag: Option<String>,
+    pub shelf_id: Option<i64>,
+    pub play_status: Option<PlayStatus>,
+}
+
+impl StoryFilter {
+    pub fn get(&self, field: StoryFilterField) -> &Option<String> {
+        match field {
+            StoryFilterField::Genre => &self.genre,
+            StoryFilterField::Language => &self.language,
+            StoryFilterField::Forgiveness => &self.forgiveness,
+            StoryFilterField::Series => &self.series,
+        }
+    }
+
+    pub fn get_mut(&mut self, field: StoryFilterField) -> &mut Option<String> {
+        match field {
+            StoryFilterField::Genre => &mut self.genre,
+            StoryFilterField::Language => &mut self.language,
+            StoryFilterField::Forgiveness => &mut self.forgiveness,
+            StoryFilterField::Series => &mut self.series,
+        }
+    }
+}
+
+/// Conditions for a smart shelf, stored as JSON. Unset conditions match any story
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShelfQuery {
+    pub search: Option<String>, // As typed in the search box
+    pub genre: Option<String>,
+    pub language: Option<String>,
+    pub forgiveness: Option<String>,
+    pub series: Option<String>,
+    pub group: Option<String>,
+    pub tag: Option<String>,
+    pub played: Option<bool>,
+    pub played_within_days: Option<u32>,
+    pub play_status: Option<PlayStatus>,
+}
+
+impl ShelfQuery {
+    /// Make a query from the story list search and filters
+    pub fn from_filter(search_text: &str, filter: &StoryFilter) -> ShelfQuery {
+        ShelfQuery {
+            search: Some(search_text.trim().to_string()).filter(|s| !s.is_empty()),
+            genre: filter.genre.clone(),
+            language: filter.language.clone(),
+            forgiveness: filter.forgiveness.clone(),
+            series: filter.series.clone(),
+            tag: filter.tag.clone(),
+            play_status: filter.play_status,
+            ..ShelfQuery::default()
+        }
+    }
+
+    /// Add the conditions for this query to a story summary query
+    fn push_sql(&self, sql: &mut String, params: &mut Vec<String>) {
+        if let Some(query) = self.search.as_deref().and_then(build_search_query) {
+            params.push(query);
+            sql.push_str(
+                format!(
+                    " AND s.id IN (SELECT rowid FROM story_fts WHERE story_fts MATCH ?{})",
+                    params.len()
+                )
+                .as_str(),
+            );
+        }
+
+        let columns = [
+            ("bibliographic_genre", &self.genre),
+            ("bibliographic_language", &self.language),
+            ("bibliographic_forgiveness", &self.forgiveness),
+            ("bibliographic_series", &self.series),
+            ("bibliographic_group", &self.group),
+        ];
+        for (column, value) in columns.iter() {
+            if let Some(value) = value {
+                params.push(value.clone());
+                sql.push_str(format!(" AND s.{} = ?{}", column, params.len()).as_str());
+            }
+        }
+
+        if let Some(tag) = &self.tag {
+            push_tag_sql(tag, sql, params);
+        }
+
+        if let Some(play_status) = self.play_status {
+            push_play_status_sql(play_status, sql, params);
+        }
+
+        match self.played {
+            Some(true) => sql.push_str(" AND s.last_played IS NOT NULL"),
+            Some(false) => sql.push_str(" AND s.last_played IS NULL"),
+            None => (),
+        }
+
+        // last_played is stored in UTC, in chrono's format rather than SQLite's, so it is
+        // compared as a julian day rather than as text
+        if let Some(days) = self.played_within_days {
+            params.push(format!("-{} days", days));
+            sql.push_str(
+                format!(
+                    " AND julianday(s.last_played) >= julianday('now', ?{})",
+                    params.len()
+                )
+                .as_str(),
+            );
+        }
+    }
+}
+
+fn push_play_status_sql(play_status: PlayStatus, sql: &mut String, params: &mut Vec<String>) {
+    params.push(play_status.to_string().to_string());
+    sql.push_str(format!(" AND s.play_status = ?{}", params.len()).as_str());
+}
+
+fn push_tag_sql(tag: &str, sql: &mut String, params: &mut Vec<String>) {
+    params.push(tag.to_string());
+    sql.push_str(
+        format!(
+            " AND s.id IN (SELECT st.story_id FROM story_tag st JOIN tag t ON t.id = st.tag_id WHERE t.name = ?{})",
+            params.len()
+        )
+        .as_str(),
+    );
+}
+
+/// A couple of useful smart shelves that a new library starts with
+fn default_shelves() -> Vec<(&'static str, ShelfQuery)> {
+    vec![
+        (
+            "Played in the last 30 days",
+            ShelfQuery {
+                played_within_days: Some(30),
+                ..ShelfQuery::default()
+            },
+        ),
+        (
+            "Unplayed",
+            ShelfQuery {
+                played: Some(false),
+                ..ShelfQuery::default()
+            },
+        ),
+    ]
+}
+
+/// A shelf of stories. Smart shelves have a query, other shelves have stories added by hand
+#[derive(PartialEq, Clone, Debug)]
+pub struct DbShelf {
+    pub dbid: i64,
+    pub name: String,
+    pub query: Option<ShelfQuery>,
+}
+
+/// An auxiliary file ("feelie") listed in a story's ifiction, such as a map or manual.
+/// Size is set if the file was found when the story was imported
+#[derive(PartialEq, Clone, Debug)]
+pub struct DbStoryResource {
+    pub dbid: i64,
+    pub leafname: String,
+    pub description: String,
+    pub size: Option<usize>,
+}
+
+/// One of a story's story files, each a release with its own IFID, saves and sessions. The
+/// Z-code details are read from the file's header
+#[derive(PartialEq, Clone, Debug)]
+pub struct DbStoryRelease {
+    pub ifid: String,
+    pub zcode: Option<Zcode>,
+    pub preferred: bool, // Whether Play launches this release
+}
+
+impl fmt::Display for DbStoryRelease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.zcode {
+            Some(Zcode {
+                release: Some(release),
+                serial: Some(serial),
+                checksum: Some(checksum),
+                ..
+            }) => write!(
+                f,
+                "Release {}, serial {}, checksum {}",
+                release, serial, checksum
+            ),
+            _ => write!(f, "{}", self.ifid),
+        }
+    }
+}
+
+/// What an item in the trash holds
+#[derive(PartialEq, Clone, Debug)]
+pub enum TrashContents {
+    Story,                  // The story, with its saves, notes, clues and map
+    Autosaves(String, u32), // The autosaves of one release. IFID and how many
+}
+
+/// A story, or autosaves from one of its releases, in the trash
+#[derive(PartialEq, Clone, Debug)]
+pub struct DbTrashItem {
+    pub story_id: u32,
+    pub title: String,
+    pub contents: TrashContents,
+    pub deleted_when: NaiveDateTime, // UTC
+}
+
+impl DbTrashItem {
+    pub fn formatted_deleted_when(&self) -> String {
+        Local
+            .from_utc_datetime(&self.deleted_when)
+            .format("%b %d, %Y %H:%M")
+            .to_string()
+    }
+}
+
+/// Something done to a story, shown in its details. Ifid is set if it was done to one release
+#[derive(PartialEq, Clone, Debug)]
+pub struct DbStoryHistory {
+    pub dbid: i64,
+    pub ifid: Option<String>,
+    pub recorded_when: NaiveDateTime, // UTC
+    pub description: String,
+}
+
+impl DbStoryHistory {
+    pub fn formatted_recorded_when(&self) -> String {
+        Local
+            .from_utc_datetime(&self.recorded_when)
+            .format("%b %d, %Y %H:%M")
+            .to_string()
+    }
+}
+
+// How the story list is searched
+enum StorySearch {
+    None,
+    FullText(String), // FTS5 query
+    Like(String),     // LIKE pattern for title and description
+}
+
 #[derive(Debug, Clone)]
 pub struct DbStory {
     pub story_id: u32,
@@ -131,6 +633,9 @@ impl SaveType {
     }
 }
 
+// Version of save data written by this version of the app
+pub const DEFAULT_SAVE_VERSION: i64 = 2;
+
 #[derive(PartialEq, Debug, Clone, Serialize)]
 pub struct DbSave {
     pub dbid: i64,
@@ -224,10 +729,133 @@ pub struct DbTheme {
     pub secondary_background_color: Option<DbColor>,
 }
 
-#[derive(PartialEq, Debug, Clone, Serialize)]
-pub enum DbSaveError {
-    ExistingSave,
-    Other(String),
+/// Errors from the database. Callers can match on the kind of error, and the display text
+/// is suitable to show to the player
+#[derive(PartialEq, Debug, Clone)]
+pub enum IfdbError {
+    NotFound(String),   // Record does not exist. String describes what was looked for
+    Constraint(String), // Change would break a constraint, such as two saves with the same name
+    Busy(String),       // Database is locked by another connection, even after waiting
+    Corrupt(String),    // Stored data or a file could not be read
+    Io(String),         // Error reading or writing a file
+    Invalid(String),    // Input was rejected, such as an empty tag name
+    Sql(String),        // Any other database error
+}
+
+impl fmt::Display for IfdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IfdbError::NotFound(msg)
+            | IfdbError::Constraint(msg)
+            | IfdbError::Invalid(msg)
+            | IfdbError::Io(msg) => write!(f, "{}", msg),
+            IfdbError::Busy(msg) => write!(
+                f,
+                "The database is in use by another copy of Ferrif. Close it and try again. ({})",
+                msg
+            ),
+            IfdbError::Corrupt(msg) => write!(f, "Data could not be read: {}", msg),
+            IfdbError::Sql(msg) => write!(f, "SQL error: {}", msg),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for IfdbError {
+    fn from(e: rusqlite::Error) -> IfdbError {
+        match e {
+            rusqlite::Error::SqliteFailure(failure, msg) => {
+                let msg = msg.unwrap_or_else(|| failure.to_string());
+                match failure.code {
+                    rusqlite::ErrorCode::ConstraintViolation => IfdbError::Constraint(msg),
+                    rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => {
+                        IfdbError::Busy(msg)
+                    }
+                    rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase => {
+                        IfdbError::Corrupt(msg)
+                    }
+                    rusqlite::ErrorCode::CannotOpen
+                    | rusqlite::ErrorCode::SystemIOFailure
+                    | rusqlite::ErrorCode::DiskFull
+                    | rusqlite::ErrorCode::PermissionDenied
+                    | rusqlite::ErrorCode::ReadOnly => IfdbError::Io(msg),
+                    _ => IfdbError::Sql(msg),
+                }
+            }
+            rusqlite::Error::QueryReturnedNoRows => {
+                IfdbError::NotFound(String::from("No matching record"))
+            }
+            rusqlite::Error::FromSqlConversionFailure(_, _, _)
+            | rusqlite::Error::IntegralValueOutOfRange(_, _)
+            | rusqlite::Error::InvalidColumnType(_, _, _)
+            | rusqlite::Error::Utf8Error(_) => IfdbError::Corrupt(e.to_string()),
+            _ => IfdbError::Sql(e.to_string()),
+        }
+    }
+}
+
+/// A migration, run against the connection being migrated. Returns notes for the player
+/// about anything it could not do
+type MigrationStep = fn(&IfdbConnection) -> Result<Vec<String>>;
+
+/// Why a database could not be brought up to date
+#[derive(PartialEq, Debug, Clone)]
+pub enum MigrationError {
+    Newer(Vec<String>), // Database has migrations this version doesn't know. Strings are their names
+    Backup(IfdbError),  // Database could not be copied aside, so no migrations were run
+    Failed(String, IfdbError, Option<String>), // Name of the migration that failed, its error, and the path of the copy made before migrating
+    Database(IfdbError),                       // Applied migrations could not be read
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Newer(names) => write!(
+                f,
+                "The database was made by a newer version of Ferrif (database version {}). Update Ferrif to open it.",
+                names.join(", ")
+            ),
+            MigrationError::Backup(e) => write!(
+                f,
+                "The database could not be backed up before updating it, so it was left as is. {}",
+                e
+            ),
+            MigrationError::Failed(name, e, backup_path) => {
+                write!(
+                    f,
+                    "Update {} failed and was undone. {}",
+                    name, e
+                )?;
+                match backup_path {
+                    Some(path) => write!(f, " A copy from before updating is at {}.", path),
+                    None => Ok(()),
+                }
+            }
+            MigrationError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> MigrationError {
+        MigrationError::Database(e.into())
+    }
+}
+
+impl From<MigrationError> for IfdbError {
+    fn from(e: MigrationError) -> IfdbError {
+        match e {
+            MigrationError::Newer(_) => IfdbError::Invalid(e.to_string()),
+            MigrationError::Backup(error)
+            | MigrationError::Failed(_, error, _)
+            | MigrationError::Database(error) => error,
+        }
+    }
+}
+
+impl From<std::io::Error> for IfdbError {
+    fn from(e: std::io::Error) -> IfdbError {
+        IfdbError::Io(e.to_string())
+    }
 }
 
 /// Clue support
@@ -449,6 +1077,35 @@ pub struct WindowDetails {
 
 /// Misc
 
+/// A story with only an IFID and title, for story files added without any ifiction
+fn placeholder_story(ifid: &str, title: &str) -> Story {
+    Story {
+        identification: Identification {
+            ifids: vec![ifid.to_string()],
+            format: Format::ZCODE,
+        },
+        bibliographic: Bibilographic {
+            title: title.to_string(),
+            author: String::from("Unknown"),
+            language: None,
+            headline: None,
+            first_published: None,
+            genre: None,
+            group: None,
+            series: None,
+            series_number: None,
+            forgiveness: None,
+            description: None,
+        },
+        resources: vec![],
+        contacts: None,
+        cover: None,
+        releases: vec![],
+        colophon: None,
+        zcode: None,
+    }
+}
+
 pub fn convert_int_to_str(d: Option<u32>) -> Option<String> {
     d.map(|e| e.to_string())
 }
@@ -461,48 +1118,303 @@ fn bool_to_int(b: bool) -> i32 {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum SupportedFiletype {
     Ifiction,
     Cover,
     Story,
     Clues,
+    Save,
 }
 
-#[derive(PartialEq, Debug)]
-#[allow(dead_code)]
-pub enum LoadFileResult {
-    CoverImageSuccess(String, String), // File loaded successfully as a cover image. First string is pathname, second IFID of image loaded
-    CoverImageFailure(String, String), // File loaded successfully as a cover image. First string is pathname, second error
-    StoryFileSuccess(String, String), // File loaded as a story file. First string is pathname, second IFID
-    StoryFileFailureVersion(String, String), // File failed to load as a story file due to unsupported version. First string is pathname, second is IFID.
-    StoryFileFailureGeneral(String, String), // File failed to load as story file due to unspecfied reason. First string is pathname ,second error.\
-    StoryFileFailureDuplicate(String, String), // File failed to load as there is already a story for this IFID
-    IFictionStorySuccess(String, String), // Ifiction record loaded. First is path to ifiction file, second is title of loaded
-    IFictionStoryIgnored(String, String), // Ifiction record valid but skipped. First is path to ifiction file, second is title of loaded
-    IFictionStoryFailure(String, String), // Failed to load part of a story. . First is path to ifiction file, second error.
-    IFictionGeneralFailure(String, String), // Failed to load an ifiction file. First is path to ifiction file, second error.
-    ZipfileFailure(String, String), // File failed to load because of issues decompressing a zipfile. First is path to file, second is error message.
-    ClueSuccess(String, String),    // Clue data loaded. First string is filename, second IFID
-    ClueFailure(String, String), // Clue data failed to load. First string is filename, second IFID
-    UnsupportedFormat(String), // File failed to load because file type is unsupported. String is pathname
-    LoadCompleted(),           // Load is completed
+// Order files are imported in. IFiction files should be loaded before story files,
+// loaded before image files, loaded before clue files, loaded before saves. This ensures
+// any data is there for subsequent data
+const IMPORT_ORDER: [SupportedFiletype; 5] = [
+    SupportedFiletype::Ifiction,
+    SupportedFiletype::Story,
+    SupportedFiletype::Cover,
+    SupportedFiletype::Clues,
+    SupportedFiletype::Save,
+];
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map(|ext| ext.to_lowercase())
 }
 
-impl fmt::Display for LoadFileResult {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &*self {
-            LoadFileResult::LoadCompleted() => {
-                write!(f, "Done")
+/// The type of a file that can be imported, based on extension
+fn importable_filetype(path: &Path) -> Option<SupportedFiletype> {
+    match lowercase_extension(path).as_deref() {
+        Some("xml" | "ifiction") => Some(SupportedFiletype::Ifiction),
+        Some("z1" | "z2" | "z3" | "z4" | "z5" | "z6" | "z7" | "z8" | "zblorb" | "blb") => {
+            Some(SupportedFiletype::Story)
+        }
+        Some("png" | "jpg" | "jpeg") => Some(SupportedFiletype::Cover),
+        Some("json") => Some(SupportedFiletype::Clues),
+        Some(ext) if SAVE_EXTENSIONS.contains(&ext) => Some(SupportedFiletype::Save),
+        _ => None,
+    }
+}
+
+fn is_blorb(path: &Path) -> bool {
+    matches!(lowercase_extension(path).as_deref(), Some("zblorb" | "blb"))
+}
+
+/// The type of file found in a directory import. Archives are imported alongside
+/// story files, as most hold a story and its extras
+fn directory_filetype(path: &Path) -> Option<SupportedFiletype> {
+    importable_filetype(path).or_else(|| archive_type(path).map(|_| SupportedFiletype::Story))
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ArchiveType {
+    Zip,
+    Tar,
+    Gzip,
+}
+
+fn archive_type(path: &Path) -> Option<ArchiveType> {
+    match lowercase_extension(path).as_deref() {
+        Some("zip") => Some(ArchiveType::Zip),
+        Some("tar") => Some(ArchiveType::Tar),
+        Some("gz" | "tgz") => Some(ArchiveType::Gzip),
+        _ => None,
+    }
+}
+
+struct ArchiveLimits {
+    max_depth: usize,
+    max_files: usize,
+    max_size: u64, // Total uncompressed size in bytes
+}
+
+/// A file extracted from an archive. The path includes any archives it was nested in
+struct ArchiveFile {
+    path: String,
+    data: Vec<u8>,
+}
+
+/// Extracts the files in an archive, and recursively in any archives inside it,
+/// stopping if any of the limits are hit
+struct ArchiveReader<'a> {
+    limits: &'a ArchiveLimits,
+    files: Vec<ArchiveFile>,
+    failures: Vec<LoadFileResult>, // Nested archives that could not be read
+    file_count: usize,
+    size: u64,
+    limit_hit: bool,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn new(limits: &'a ArchiveLimits) -> ArchiveReader<'a> {
+        ArchiveReader {
+            limits,
+            files: vec![],
+            failures: vec![],
+            file_count: 0,
+            size: 0,
+            limit_hit: false,
+        }
+    }
+
+    fn limit_error(&mut self, msg: String) -> String {
+        self.limit_hit = true;
+        msg
+    }
+
+    /// Read the archive at path, which is only used for its name
+    fn read_archive(&mut self, data: &[u8], path: &str, depth: usize) -> Result<(), String> {
+        if depth > self.limits.max_depth {
+            return Err(self.limit_error(format!(
+                "Hit maximum supported depth of nested archives of {}",
+                self.limits.max_depth
+            )));
+        }
+
+        match archive_type(Path::new(path)) {
+            Some(ArchiveType::Zip) => {
+                let mut archive =
+                    zip::ZipArchive::new(Cursor::new(data)).map_err(|e| format!("{:?}", e))?;
+                for i in 0..archive.len() {
+                    let mut file = archive.by_index(i).map_err(|e| format!("{:?}", e))?;
+                    if file.is_dir() {
+                        continue;
+                    }
+                    let name = format!("{}/{}", path, file.name());
+                    let contents = self.read_limited(&mut file)?;
+                    self.add_file(name, contents, depth)?;
+                }
             }
-            LoadFileResult::CoverImageSuccess(path, ifid) => {
-                write!(f, "Loaded cover image at {} to IFID {}", path, ifid)
+            Some(ArchiveType::Tar) => {
+                for entry in read_tar(data)? {
+                    self.count_size(entry.data.len())?;
+                    self.add_file(
+                        format!("{}/{}", path, entry.name),
+                        entry.data.to_vec(),
+                        depth,
+                    )?;
+                }
             }
-            LoadFileResult::CoverImageFailure(path, err) => {
-                write!(f, "Error loading cover image at {}: {}", path, err)
+            Some(ArchiveType::Gzip) => {
+                let contents = self.read_limited(GzDecoder::new(data))?;
+                // A .tgz holds a tar, and story.z5.gz holds story.z5
+                let lowercase = path.to_lowercase();
+                let name = if lowercase.ends_with(".tgz") {
+                    format!("{}.tar", &path[..path.len() - 4])
+                } else {
+                    path[..path.len() - 3].to_string()
+                };
+                self.add_file(name, contents, depth)?;
             }
+            None => return Err(format!("{} is not a supported archive", path)),
+        }
 
-            LoadFileResult::StoryFileSuccess(path, ifid) => {
+        Ok(())
+    }
+
+    /// Keep a file, or extract it if it is an archive itself
+    fn add_file(&mut self, path: String, data: Vec<u8>, depth: usize) -> Result<(), String> {
+        self.file_count += 1;
+        if self.file_count > self.limits.max_files {
+            return Err(self.limit_error(format!(
+                "Hit maximum supported number of files of {}",
+                self.limits.max_files
+            )));
+        }
+
+        if archive_type(Path::new(&path)).is_none() {
+            self.files.push(ArchiveFile { path, data });
+            return Ok(());
+        }
+
+        match self.read_archive(&data, path.as_str(), depth + 1) {
+            Err(msg) if self.limit_hit => Err(msg),
+            Err(msg) => {
+                self.failures
+                    .push(LoadFileResult::ArchivePartFailure(path, msg));
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Read all data, failing if it would take the total past the size limit
+    fn read_limited(&mut self, reader: impl Read) -> Result<Vec<u8>, String> {
+        let remaining = self.limits.max_size.saturating_sub(self.size);
+        let mut contents = vec![];
+        reader
+            .take(remaining + 1)
+            .read_to_end(&mut contents)
+            .map_err(|e| e.to_string())?;
+        self.count_size(contents.len())?;
+        Ok(contents)
+    }
+
+    fn count_size(&mut self, len: usize) -> Result<(), String> {
+        self.size += len as u64;
+        if self.size > self.limits.max_size {
+            return Err(self.limit_error(format!(
+                "Hit maximum supported uncompressed size of {} MB",
+                self.limits.max_size / (1024 * 1024)
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Add every importable file under a directory to files. Hidden directories and
+/// symlinked directories are skipped
+fn find_importable_files(
+    path: &Path,
+    depth: usize,
+    files: &mut Vec<(SupportedFiletype, PathBuf)>,
+) -> Result<(), String> {
+    if depth > MAX_DIRECTORY_DEPTH {
+        return Err(format!(
+            "Hit maximum supported folder depth of {}",
+            MAX_DIRECTORY_DEPTH
+        ));
+    }
+
+    for entry in fs::read_dir(path).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let entry_path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => {
+                if !hidden {
+                    find_importable_files(&entry_path, depth + 1, files)?;
+                }
+            }
+            Ok(_) => {
+                if let Some(filetype) = directory_filetype(&entry_path) {
+                    files.push((filetype, entry_path));
+                }
+            }
+            Err(msg) => return Err(msg.to_string()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Size and modification time (in seconds) of a file, used to spot files that changed
+/// since they were imported
+fn file_signature(path: &Path) -> Result<(i64, i64), String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata
+        .modified()
+        .map(|t| DateTime::<Utc>::from(t).timestamp())
+        .unwrap_or(0);
+    Ok((metadata.len() as i64, modified))
+}
+
+#[derive(PartialEq, Debug)]
+#[allow(dead_code)]
+pub enum LoadFileResult {
+    CoverImageSuccess(String, String), // File loaded successfully as a cover image. First string is pathname, second IFID of image loaded
+    CoverImageFailure(String, String), // File loaded successfully as a cover image. First string is pathname, second error
+    StoryFileSuccess(String, String), // File loaded as a story file. First string is pathname, second IFID
+    StoryFileFailureVersion(String, String), // File failed to load as a story file due to unsupported version. First string is pathname, second is IFID.
+    StoryFileFailureGeneral(String, String), // File failed to load as story file due to unspecfied reason. First string is pathname ,second error.\
+    StoryFileFailureDuplicate(String, String), // File failed to load as there is already a story for this IFID
+    StoryFileFailureTrashed(String, String), // File failed to load as the story for this IFID is in the trash. First string is pathname, second IFID
+    IFictionStorySuccess(String, String), // Ifiction record loaded. First is path to ifiction file, second is title of loaded
+    IFictionStoryIgnored(String, String), // Ifiction record valid but skipped. First is path to ifiction file, second is title of loaded
+    IFictionStoryFailure(String, String), // Failed to load part of a story. . First is path to ifiction file, second error.
+    IFictionGeneralFailure(String, String), // Failed to load an ifiction file. First is path to ifiction file, second error.
+    ZipfileFailure(String, String), // File failed to load because of issues decompressing a zipfile. First is path to file, second is error message.
+    ArchivePartFailure(String, String), // Part of an archive was not loaded, as a nested archive could not be read or a limit was hit. The rest is loaded. First is path, second error
+    ClueSuccess(String, String),        // Clue data loaded. First string is filename, second IFID
+    ClueFailure(String, String), // Clue data failed to load. First string is filename, second IFID
+    BlorbFailure(String, String), // File failed to load as a blorb package. First string is pathname, second error
+    SaveSuccess(String, String), // Quetzal save loaded. First string is pathname, second IFID of story
+    SaveFailure(String, String), // Quetzal save failed to load. First string is pathname, second error
+    ResourceSuccess(String, String), // Auxiliary file stored with its story. First string is pathname, second leafname from the ifiction
+    ResourceFailure(String, String), // Auxiliary file failed to store. First string is pathname, second error
+    AlreadyImported(String), // File in a directory skipped as it was imported before and is unchanged. String is pathname
+    ImportRolledBack(String, String), // Import undone after a fatal error. First string is pathname, second error
+    DirectoryFailure(String, String), // Failed to read a directory or file in it. First string is pathname, second error
+    UnsupportedFormat(String), // File failed to load because file type is unsupported. String is pathname
+    LoadCompleted(),           // Load is completed
+}
+
+impl fmt::Display for LoadFileResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self {
+            LoadFileResult::LoadCompleted() => {
+                write!(f, "Done")
+            }
+            LoadFileResult::CoverImageSuccess(path, ifid) => {
+                write!(f, "Loaded cover image at {} to IFID {}", path, ifid)
+            }
+            LoadFileResult::CoverImageFailure(path, err) => {
+                write!(f, "Error loading cover image at {}: {}", path, err)
+            }
+
+            LoadFileResult::StoryFileSuccess(path, ifid) => {
                 write!(f, "Loaded story file at {} to IFID {}", path, ifid)
             }
             LoadFileResult::StoryFileFailureVersion(path, _) => write!(
@@ -516,7 +1428,14 @@ impl fmt::Display for LoadFileResult {
             LoadFileResult::StoryFileFailureDuplicate(path, ifid) => {
                 write!(
                     f,
-                    "A story with the same IFID {} already exists for the file \"{}\". Delete the story if you want to replace it.",
+                    "A story with the same IFID {} already exists for the file \"{}\". Use Replace story file in the story's details if you want to replace it.",
+                    ifid, path
+                )
+            }
+            LoadFileResult::StoryFileFailureTrashed(path, ifid) => {
+                write!(
+                    f,
+                    "The story with the same IFID {} as the file \"{}\" is in the trash. Restore it from the trash instead.",
                     ifid, path
                 )
             }
@@ -536,12 +1455,43 @@ impl fmt::Display for LoadFileResult {
             LoadFileResult::ZipfileFailure(path, err) => {
                 write!(f, "Error loading zipfile at {}: {}", path, err)
             }
+            LoadFileResult::ArchivePartFailure(path, err) => {
+                write!(f, "Error loading part of archive at {}: {}", path, err)
+            }
             LoadFileResult::ClueSuccess(path, ifid) => {
                 write!(f, "Loaded clue data at {} to IFID {}", path, ifid)
             }
             LoadFileResult::ClueFailure(path, err) => {
                 write!(f, "Error loading clue file at {}: {}", path, err)
             }
+            LoadFileResult::BlorbFailure(path, err) => {
+                write!(f, "Error loading blorb file at {}: {}", path, err)
+            }
+            LoadFileResult::SaveSuccess(path, ifid) => {
+                write!(f, "Loaded save at {} to IFID {}", path, ifid)
+            }
+            LoadFileResult::SaveFailure(path, err) => {
+                write!(f, "Error loading save at {}: {}", path, err)
+            }
+            LoadFileResult::ResourceSuccess(path, leafname) => {
+                write!(f, "Stored auxiliary file {} from {}", leafname, path)
+            }
+            LoadFileResult::ResourceFailure(path, err) => {
+                write!(f, "Error storing auxiliary file {}: {}", path, err)
+            }
+            LoadFileResult::AlreadyImported(path) => {
+                write!(f, "Skipping {} as it has already been imported", path)
+            }
+            LoadFileResult::DirectoryFailure(path, err) => {
+                write!(f, "Error reading {}: {}", path, err)
+            }
+            LoadFileResult::ImportRolledBack(path, err) => {
+                write!(
+                    f,
+                    "Nothing was imported from {} because of an error: {}",
+                    path, err
+                )
+            }
             LoadFileResult::UnsupportedFormat(path) => {
                 write!(f, "Unable to load file at {}: unsupported format", path)
             }
@@ -549,198 +1499,64 @@ impl fmt::Display for LoadFileResult {
     }
 }
 
-impl IfdbConnection {
-    /// Connect to a SQLLite database. Will create if database does not exist
-    pub fn connect(path: &str) -> Result<IfdbConnection, String> {
-        match Connection::open(path) {
-            Ok(connection) => Ok(IfdbConnection {
-                connection,
-                database_path: path.to_string(),
-            }),
-            Err(msg) => Err(format!("Connection error: {:?}", msg)),
-        }
-    }
-
-    /// Take the result of a database call from the connection and always return a default value,
-    /// outputting any error to log
-    pub fn wrap_db_error(&self, r: Result<u32, String>) -> u32 {
-        match r {
-            Ok(v) => v,
-            Err(msg) => {
-                println!("Error running sql. {}", msg);
-                0
-            }
-        }
-    }
-
-    ///
-    /// Stories
-    ///
-
-    /// Count all stories in the database and return the result
-    pub fn count_stories(&self) -> Result<u32, String> {
-        let result = || -> Result<u32, rusqlite::Error> {
-            let mut statement = self.connection.prepare("SELECT count(id) FROM story")?;
-
-            let mut query = statement.query(params![])?;
-
-            if let Some(row) = query.next()? {
-                Ok(row.get(0)?)
-            } else {
-                Ok(0)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
-    }
-    /// Return the story ID for an IFID, or None if story is not in the database
-    pub fn get_story_id(&self, ifid: &str) -> Result<Option<u32>, String> {
-        let result = || -> Result<Option<u32>, rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("SELECT story_id FROM story_ifid WHERE  ifid = ?1")?;
-
-            let mut query = statement.query(params![ifid])?;
-
-            if let Some(row) = query.next()? {
-                Ok(Some(row.get(0)?))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
+impl LoadFileResult {
+    /// True if this result means some or all of a file was not loaded
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            LoadFileResult::CoverImageFailure(_, _)
+                | LoadFileResult::StoryFileFailureVersion(_, _)
+                | LoadFileResult::StoryFileFailureGeneral(_, _)
+                | LoadFileResult::IFictionStoryFailure(_, _)
+                | LoadFileResult::IFictionGeneralFailure(_, _)
+                | LoadFileResult::ZipfileFailure(_, _)
+                | LoadFileResult::ArchivePartFailure(_, _)
+                | LoadFileResult::ClueFailure(_, _)
+                | LoadFileResult::BlorbFailure(_, _)
+                | LoadFileResult::SaveFailure(_, _)
+                | LoadFileResult::ResourceFailure(_, _)
+                | LoadFileResult::DirectoryFailure(_, _)
+                | LoadFileResult::ImportRolledBack(_, _)
+        )
     }
-    /// Return story data for a given ifid and story id
-    pub fn get_story_data(&self, story_id: u32, ifid: &str) -> Result<Option<Vec<u8>>, String> {
-        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("SELECT story_data FROM story_ifid WHERE story_id = ?1 AND ifid = ?2")?;
-
-            let mut query = statement.query(params![story_id, ifid])?;
 
-            if let Some(row) = query.next()? {
-                Ok(Some(row.get(0)?))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
+    /// True if this result means the archive or directory being imported could not be opened,
+    /// so the file being imported is rolled back. Failures inside an archive are not fatal
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            LoadFileResult::ZipfileFailure(_, _) | LoadFileResult::DirectoryFailure(_, _)
+        )
     }
+}
 
-    /// Return a story for a given id
-    pub fn get_story(&self, story_id: u32) -> Result<Option<DbStory>, String> {
-        match self.get_stories_query(Some(story_id), false) {
-            Ok(stories) => {
-                if stories.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(stories[0].clone()))
+impl IfdbConnection {
+    /// Connect to a SQLLite database. Will create if database does not exist
+    pub fn connect(path: &str) -> Result<IfdbConnection, IfdbError> {
+        match Connection::open(path) {
+            Ok(connection) => {
+                if let Err(e) = connection.busy_timeout(BUSY_TIMEOUT) {
+                    return Err(e.into());
                 }
+                Ok(IfdbConnection {
+                    connection,
+                    database_path: path.to_string(),
+                    cache: StoreCache::default(),
+                })
             }
-            Err(e) => Err(format!("Connection error: {:?}", e)),
+            Err(e) => Err(e.into()),
         }
     }
 
-    pub fn delete_story(&self, story_id: u32) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection
-                .execute("DELETE FROM  story WHERE id = ?1", params![story_id,])?;
-            self.connection.execute(
-                "DELETE FROM  story_resource WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM  story_release WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM  story_zcode WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM saves WHERE ifid IN (SELECT ifid FROM story_ifid WHERE story_id = ?1)",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM session WHERE ifid IN (SELECT ifid FROM story_ifid WHERE story_id = ?1)",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM map_room WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection
-                .execute("DELETE FROM notes WHERE story_id = ?1", params![story_id,])?;
-            self.connection
-            .execute("DELETE FROM clue WHERE subsection_id IN (SELECT id from clue_subsection WHERE section_id IN (SELECT id from clue_section WHERE story_id = ?))", params![story_id,])?;
-            self.connection
-            .execute("DELETE FROM clue_subsection WHERE section_id IN (SELECT id from clue_section WHERE story_id = ?)", params![story_id,])?;
-
-            self.connection.execute(
-                "DELETE FROM clue_section WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM  window_details WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            self.connection.execute(
-                "DELETE FROM  story_ifid WHERE story_id = ?1",
-                params![story_id,],
-            )?;
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
+    /// Forget cached query results. Called after another connection, such as a background
+    /// import, writes to the database
+    pub fn clear_cache(&self) {
+        self.cache.clear();
     }
 
-    /// Return ifids -- all if no story id, or for a specific story with id
-    #[allow(dead_code)]
-    pub fn fetch_ifids_for_story(
-        &self,
-        story_id: u32,
-        playable: bool,
-    ) -> Result<Vec<String>, String> {
-        let result = || -> Result<Vec<String>, rusqlite::Error> {
-            let mut ifids: Vec<String> = vec![];
-
-            let mut sql = String::from("SELECT  ifid FROM story_ifid  WHERE story_id = ?1 ");
-            if playable {
-                sql.push_str(" AND story_data is not null ");
-            }
-
-            let mut statement = self.connection.prepare(sql.as_str())?;
-
-            let mut query = statement.query(params![story_id])?;
-
-            while let Some(row) = query.next()? {
-                let ifid: String = row.get(0)?;
-                ifids.push(ifid);
-            }
-
-            Ok(ifids)
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
-    }
+    ///
+    /// Stories
+    ///
 
     /// Query stories and return structs
     #[allow(clippy::unnecessary_unwrap)]
@@ -921,164 +1737,197 @@ impl IfdbConnection {
         Ok(stories)
     }
 
-    /// Return all ifids, linked to story data
-    pub fn fetch_story_summaries(
+    /// The FROM and WHERE clauses shared by the story list queries
+    fn story_summaries_from_sql(
         &self,
         has_data: bool,
-        search_text: Option<&str>,
-    ) -> Result<Vec<StorySummary>, String> {
-        let result = || -> Result<Vec<StorySummary>, rusqlite::Error> {
-            let mut params = vec![];
-            let mut sql = String::from(
-                "SELECT s.id, s.bibliographic_title, i.ifid, s.last_played, s.time_played
-            FROM story_ifid i 
-            JOIN story s ON i.story_id = s.id ",
-            );
+        search: &StorySearch,
+        filter: &StoryFilter,
+        params: &mut Vec<String>,
+    ) -> Result<String, rusqlite::Error> {
+        let mut sql = String::from(" FROM story_ifid i JOIN story s ON i.story_id = s.id ");
+
+        if let StorySearch::FullText(_) = search {
+            sql.push_str(" JOIN story_fts ON story_fts.rowid = s.id ");
+        }
 
-            if has_data || search_text.is_some() {
-                sql.push_str(" WHERE 1=1 ");
+        // Stories in the trash are hidden
+        sql.push_str(" WHERE s.deleted_when IS NULL ");
 
+        if has_data {
+            sql.push_str(" AND i.story_data is not null ");
+        }
+
+        // One row for each story, for its preferred release
+        sql.push_str(
+            format!(
+                " AND i.id = (SELECT r.id FROM story_ifid r WHERE r.story_id = s.id {} {} LIMIT 1) ",
                 if has_data {
-                    sql.push_str(" AND i.story_data is not null ");
-                }
+                    "AND r.story_data is not null"
+                } else {
+                    ""
+                },
+                RELEASE_PREFERENCE_ORDER
+            )
+            .as_str(),
+        );
 
-                if let Some(text) = search_text {
-                    if text.chars().count() > 0 {
-                        sql.push_str(" AND (s.bibliographic_title LIKE ?1 OR s.bibliographic_description LIKE ?2)");
-                        params.push(format!("%{}%", text));
-                        params.push(format!("%{}%", text));
-                    }
-                }
+        match search {
+            StorySearch::FullText(query) => {
+                params.push(query.clone());
+                sql.push_str(format!(" AND story_fts MATCH ?{}", params.len()).as_str());
+            }
+            StorySearch::Like(pattern) => {
+                params.push(pattern.clone());
+                sql.push_str(
+                    format!(
+                        " AND (s.bibliographic_title LIKE ?{0} OR s.bibliographic_description LIKE ?{0})",
+                        params.len()
+                    )
+                    .as_str(),
+                );
             }
+            StorySearch::None => (),
+        }
 
-            sql.push_str(" ORDER BY bibliographic_title, ifid ");
-            let mut statement = self.connection.prepare(sql.as_str())?;
+        for field in STORY_FILTER_FIELDS.iter() {
+            if let Some(value) = filter.get(*field) {
+                params.push(value.clone());
+                sql.push_str(format!(" AND s.{} = ?{}", field.column(), params.len()).as_str());
+            }
+        }
 
-            let row_iter = statement.query_map(params, |row| {
-                Ok(StorySummary {
-                    story_id: row.get(0)?,
-                    title: row.get(1)?,
-                    ifid: row.get(2)?,
-                    last_played: row.get(3)?,
-                    time_played: row.get(4)?,
-                })
-            })?;
+        if let Some(tag) = &filter.tag {
+            push_tag_sql(tag, &mut sql, params);
+        }
 
-            let mut rows = vec![];
+        if let Some(play_status) = filter.play_status {
+            push_play_status_sql(play_status, &mut sql, params);
+        }
 
-            for row in row_iter {
-                match row {
-                    Ok(row) => rows.push(row),
-                    Err(msg) => println!("Error: {}", msg),
+        // A missing shelf matches everything, as it may have been deleted
+        if let Some(shelf) = filter
+            .shelf_id
+            .map(|id| self.get_shelf_sql(id))
+            .transpose()?
+            .flatten()
+        {
+            match shelf.query {
+                Some(query) => query.push_sql(&mut sql, params),
+                None => {
+                    params.push(shelf.dbid.to_string());
+                    sql.push_str(
+                        format!(
+                            " AND s.id IN (SELECT story_id FROM shelf_story WHERE shelf_id = ?{})",
+                            params.len()
+                        )
+                        .as_str(),
+                    );
                 }
             }
+        }
 
-            Ok(rows)
-        }();
+        Ok(sql)
+    }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(rows) => Ok(rows),
+    /// The search to run for search text. Full text search only matches from the start of
+    /// words, so if it finds nothing, fall back to matching anywhere in the title or description
+    fn resolve_story_search(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        filter: &StoryFilter,
+    ) -> Result<StorySearch, rusqlite::Error> {
+        match (search_text, search_text.and_then(build_search_query)) {
+            (Some(text), Some(query)) => {
+                let search = StorySearch::FullText(query);
+                if self.count_story_summaries_sql(has_data, &search, filter, None)? > 0 {
+                    Ok(search)
+                } else {
+                    Ok(StorySearch::Like(format!("%{}%", text.trim())))
+                }
+            }
+            _ => Ok(StorySearch::None),
         }
     }
 
-    /// Return story summary for a particular db id, or None
-    pub fn get_story_summary_by_id(&self, story_id: u32) -> Result<Option<StorySummary>, String> {
-        let result = || -> Result<Option<StorySummary>, rusqlite::Error> {
-            let params = vec![story_id];
-            let sql = String::from(
-                "SELECT s.id, s.bibliographic_title, i.ifid, s.last_played, s.time_played
-            FROM story_ifid i 
-            JOIN story s ON i.story_id = s.id 
-            WHERE s.id = ?1 
-            AND i.story_data is not null ",
+    /// Count the story list rows. If before_title is set, only rows with titles sorting
+    /// before it are counted
+    fn count_story_summaries_sql(
+        &self,
+        has_data: bool,
+        search: &StorySearch,
+        filter: &StoryFilter,
+        before_title: Option<&str>,
+    ) -> Result<usize, rusqlite::Error> {
+        let mut params = vec![];
+        let mut sql = format!(
+            "SELECT count(*) {}",
+            self.story_summaries_from_sql(has_data, search, filter, &mut params)?
+        );
+
+        if let Some(title) = before_title {
+            params.push(title.to_string());
+            sql.push_str(
+                format!(
+                    " AND s.bibliographic_title < ?{} COLLATE NOCASE",
+                    params.len()
+                )
+                .as_str(),
             );
-            let mut statement = self.connection.prepare(sql.as_str())?;
-
-            let row_iter = statement.query_map(params, |row| {
-                Ok(StorySummary {
-                    story_id: row.get(0)?,
-                    title: row.get(1)?,
-                    ifid: row.get(2)?,
-                    last_played: row.get(3)?,
-                    time_played: row.get(4)?,
-                })
-            })?;
+        }
 
-            let mut summary = None;
-            for row in row_iter {
-                match row {
-                    Ok(row) => summary = Some(row),
-                    Err(msg) => println!("Error: {}", msg),
-                }
-            }
+        let count: i64 = self
+            .connection
+            .query_row(sql.as_str(), params, |row| row.get(0))?;
+        Ok(count as usize)
+    }
 
-            Ok(summary)
-        }();
+    /// Query the story list. If page is set, only the rows from that offset and limit are
+    /// returned
+    fn query_story_summaries(
+        &self,
+        has_data: bool,
+        search: &StorySearch,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+        page: Option<(usize, usize)>,
+    ) -> Result<Vec<StorySummary>, rusqlite::Error> {
+        let mut params = vec![];
+        let mut sql = format!(
+            "SELECT {} {}",
+            STORY_SUMMARY_COLUMNS,
+            self.story_summaries_from_sql(has_data, search, filter, &mut params)?
+        );
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(rows) => Ok(rows),
+        let mut order_by = vec![];
+        if let Some(sort) = sort {
+            order_by.push(sort.order_by());
         }
-    }
+        if let StorySearch::FullText(_) = search {
+            order_by.push("story_fts.rank");
+        }
+        order_by.push("s.bibliographic_title COLLATE NOCASE, i.ifid");
+        sql.push_str(format!(" ORDER BY {}", order_by.join(", ")).as_str());
 
-    /// Return story summary for a particular IFID, or None
-    pub fn get_story_summary_by_ifid(&self, ifid: &str) -> Result<Option<StorySummary>, String> {
-        if let Ok(Some(story_id)) = self.get_story_id_for_ifid(ifid, false) {
-            return self.get_story_summary_by_id(story_id);
+        if let Some((offset, limit)) = page {
+            sql.push_str(format!(" LIMIT {} OFFSET {}", limit, offset).as_str());
         }
 
-        Ok(None)
-    }
+        let mut statement = self.connection.prepare(sql.as_str())?;
 
-    /// Return all ifids, linked to story data
-    pub fn get_story_id_for_ifid(
-        &self,
-        ifid: &str,
-        playable_only: bool,
-    ) -> Result<Option<u32>, String> {
-        let result = || -> Result<Option<u32>, rusqlite::Error> {
-            let mut sql = "SELECT i.story_id
-            FROM story_ifid i 
-            WHERE i.ifid = ?1"
-                .to_string();
-            if playable_only {
-                sql.push_str(" AND  story_data is not null");
-            }
+        let row_iter = statement.query_map(params, StorySummary::from_row)?;
 
-            let mut statement = self.connection.prepare(sql.as_str())?;
+        let mut rows = vec![];
 
-            let mut query = statement.query(params![ifid])?;
-            if let Some(row) = query.next()? {
-                Ok(Some(row.get(0)?))
-            } else {
-                Ok(None)
+        for row in row_iter {
+            match row {
+                Ok(row) => rows.push(row),
+                Err(msg) => println!("Error: {}", msg),
             }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(rows) => Ok(rows),
-        }
-    }
-
-    /// Insert story information from a Story object
-    pub fn create_story(&self, story: Story) -> Result<(), String> {
-        if story.identification.ifids.is_empty() {
-            return Err("Story has no ifids".to_string());
-        }
-
-        for ifid in &story.identification.ifids {
-            if self.get_story_id_for_ifid(ifid.as_str(), true)?.is_some() {
-                return Err(format!("Story data already exists for IFID {}", ifid));
-            }
-        }
-
-        if let Err(sqlerr) = self.create_story_sql(story) {
-            Err(format!("{:?}", sqlerr))
-        } else {
-            Ok(())
         }
+
+        Ok(rows)
     }
 
     fn create_story_sql(&self, story: Story) -> Result<()> {
@@ -1159,13 +2008,16 @@ impl IfdbConnection {
                         colophon_generator_version,
                         colophon_originated,
                         contact_url,
-                        contact_author_email)
-                        VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21)",
+                        contact_author_email,
+                        date_added)
+                        VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,CURRENT_TIMESTAMP)",
             params_vec,
 
         )?;
 
-        let story_id: String = self.connection.last_insert_rowid().to_string();
+        let story_id = self.connection.last_insert_rowid();
+        self.index_story_sql(story_id)?;
+        let story_id: String = story_id.to_string();
 
         for ifid in story.identification.ifids {
             self.connection.execute(
@@ -1198,54 +2050,9 @@ impl IfdbConnection {
         Ok(())
     }
 
-    /// Convienience method to set the last played for a story
-    pub fn update_last_played_to_now(&self, story_id: i64) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UpDATE  story SET last_played = ?1 WHERE id = ?2",
-                params![Utc::now().naive_local(), story_id,],
-            )?;
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    /// Add to time played for a story
-    pub fn add_to_time_played(&self, story_id: i64, elapsed: i64) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UpDATE  story SET time_played = time_played + ?1 WHERE id = ?2",
-                params![elapsed, story_id,],
-            )?;
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
     ///
     /// Story misc
     ///
-    ///
-    /// Update story information from a DbStory object
-    #[allow(dead_code)]
-    pub fn update_story(&self, dbstory: DbStory) -> Result<(), String> {
-        if let Err(sqlerr) = self.update_story_sql(dbstory.story_id, dbstory.story) {
-            Err(format!("{:?}", sqlerr))
-        } else {
-            Ok(())
-        }
-    }
-
     #[allow(dead_code)]
     fn update_story_sql(&self, story_id: u32, story: Story) -> Result<()> {
         let mut params_vec: Vec<Option<String>> = vec![
@@ -1329,19 +2136,43 @@ impl IfdbConnection {
             WHERE id = ?21",
             params_vec,
         )?;
-
-        // For the one-to-many options, simply delete any old records and re-insert
-        self.connection.execute(
-            "DELETE FROM story_resource WHERE story_id = ?",
-            params![story_id],
-        )?;
+        self.index_story_sql(i64::from(story_id))?;
+
+        // Resources are matched by leafname, so any stored file data is kept
+        let leafnames: Vec<&str> = story
+            .resources
+            .iter()
+            .map(|r| r.leafname.as_str())
+            .collect();
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, leafname FROM story_resource WHERE story_id = ?1")?;
+        let existing = statement
+            .query_map(params![story_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(i64, String)>>>()?;
+        for (resource_id, leafname) in existing.iter() {
+            if !leafnames.contains(&leafname.as_str()) {
+                self.connection.execute(
+                    "DELETE FROM story_resource WHERE id = ?1",
+                    params![resource_id],
+                )?;
+            }
+        }
         for resource in story.resources {
-            self.connection.execute(
-                "INSERT INTO story_resource (story_id, leafname,description ) VALUES (?1,?2,?3)",
-                params![story_id, resource.leafname, resource.description],
-            )?;
+            match existing.iter().find(|(_, l)| *l == resource.leafname) {
+                Some((resource_id, _)) => self.connection.execute(
+                    "UPDATE story_resource SET description = ?1 WHERE id = ?2",
+                    params![resource.description, resource_id],
+                )?,
+                None => self.connection.execute(
+                    "INSERT INTO story_resource (story_id, leafname,description ) VALUES (?1,?2,?3)",
+                    params![story_id, resource.leafname, resource.description],
+                )?,
+            };
         }
 
+        // For the other one-to-many options, simply delete any old records and re-insert
+
         self.connection.execute(
             "DELETE FROM story_release WHERE story_id = ?",
             params![story_id],
@@ -1369,6 +2200,21 @@ impl IfdbConnection {
         Ok(())
     }
 
+    /// Replace the full text index entry for a story with its current details
+    fn index_story_sql(&self, story_id: i64) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM story_fts WHERE rowid = ?1", params![story_id])?;
+        self.connection.execute(
+            "INSERT INTO story_fts (rowid, title, author, headline, genre, series, description)
+            SELECT id, bibliographic_title, bibliographic_author, bibliographic_headline,
+            bibliographic_genre, bibliographic_series, bibliographic_description
+            FROM story WHERE id = ?1",
+            params![story_id],
+        )?;
+
+        Ok(())
+    }
+
     /// Return ifids -- all if no story id, or for a specific story with id
     #[allow(clippy::map_entry)]
     fn fetch_ifids(
@@ -1527,230 +2373,44 @@ impl IfdbConnection {
 
         Ok(zcode_map)
     }
-    /// Add a cover image for an ifid. Record must exist.
-    pub fn store_cover_image(&self, ifid: &str, data: Vec<u8>) -> Result<(), String> {
-        let story_id = self.get_story_id_for_ifid(ifid, false)?;
-        if story_id.is_none() {
-            Err(format!("No story found for ifid {}", ifid))
-        } else {
-            let result = || -> Result<(), rusqlite::Error> {
-                self.connection.execute(
-                    "UPDATE  story SET cover_image = ?1 WHERE id = ?2",
-                    params![data, story_id],
-                )?;
-                Ok(())
-            }();
-            match result {
-                Err(e) => Err(format!("SQL error: {:?}", e)),
-                Ok(()) => Ok(()),
-            }
+    fn store_resource_data(&self, resource_id: i64, data: &[u8]) -> Result<(), IfdbError> {
+        match self.connection.execute(
+            "UPDATE story_resource SET data = ?1 WHERE id = ?2",
+            params![data, resource_id],
+        ) {
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
         }
     }
 
-    /// Set the story data for an ifid
-    pub fn add_story_data(
-        &self,
-        ifid: &str,
-        data: Vec<u8>,
-        default_name: &str,
-    ) -> Result<(), String> {
-        let story_id = self.get_story_id_for_ifid(ifid, false)?;
-        if story_id.is_none() {
-            let mut story = Story {
-                identification: Identification {
-                    ifids: vec![],
-                    format: Format::ZCODE,
-                },
-                bibliographic: Bibilographic {
-                    title: String::new(),
-                    author: String::new(),
-                    language: None,
-                    headline: None,
-                    first_published: None,
-                    genre: None,
-                    group: None,
-                    series: None,
-                    series_number: None,
-                    forgiveness: None,
-                    description: None,
-                },
-                resources: vec![],
-                contacts: None,
-                cover: None,
-                releases: vec![],
-                colophon: None,
-                zcode: None,
-            };
-            story.identification.ifids.push(ifid.to_string());
-            story.bibliographic.title.push_str(default_name);
-            story.bibliographic.author.push_str("Unknown");
-
-            self.create_story(story)?;
-        }
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE  story_ifid SET story_data = ?1 WHERE ifid = ?2",
-                params![data, ifid],
-            )?;
-
-            Ok(())
-        }();
+    /// Set a story's cover along with its thumbnail
+    fn store_cover_sql(&self, story_id: u32, cover: &[u8]) -> Result<()> {
+        self.connection.execute(
+            "UPDATE story SET cover_image = ?1 WHERE id = ?2",
+            params![cover, story_id],
+        )?;
+        self.store_cover_thumbnail_sql(story_id, cover)
+    }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
+    /// Replace the thumbnail of a story's cover. Covers that can't be decoded are kept, but
+    /// have no thumbnail
+    fn store_cover_thumbnail_sql(&self, story_id: u32, cover: &[u8]) -> Result<()> {
+        match make_cover_thumbnail(cover) {
+            Some(thumbnail) => self.connection.execute(
+                "INSERT OR REPLACE INTO cover_thumbnail (story_id, data) VALUES (?1, ?2)",
+                params![story_id, thumbnail],
+            )?,
+            None => self.connection.execute(
+                "DELETE FROM cover_thumbnail WHERE story_id = ?1",
+                params![story_id],
+            )?,
+        };
+        Ok(())
     }
 
     ///
     /// Saves
     ///
-    ///
-    ///
-    /// Return count of all saves in the database
-    pub fn count_saves(&self) -> Result<u32, String> {
-        let result = || -> Result<u32, rusqlite::Error> {
-            let mut statement = self.connection.prepare("SELECT count(id) FROM saves")?;
-
-            let mut query = statement.query(params![])?;
-
-            if let Some(row) = query.next()? {
-                Ok(row.get(0)?)
-            } else {
-                Ok(0)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
-    }
-
-    /// Return count of all autosaves for a story
-    pub fn count_autosaves_for_story(&self, ifid: String) -> Result<u32, String> {
-        let result = || -> Result<u32, rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("SELECT count(id) FROM saves WHERE ifid = ?1 AND save_type=?2")?;
-            let mut query = statement.query(params![ifid, SaveType::Autosave.to_string()])?;
-
-            if let Some(row) = query.next()? {
-                Ok(row.get(0)?)
-            } else {
-                Ok(0)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
-    }
-    /// Return the save for the given (case sensitive) name/ifid, or None if no such save
-    pub fn get_save(&self, ifid: String, name: String) -> Result<Option<DbSave>, String> {
-        let result = || -> Result<Option<DbSave>, rusqlite::Error> {
-            let mut statement = self.connection.prepare(
-                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version, id FROM saves WHERE ifid = ?1 AND name = ?2",
-            )?;
-            let mut query = statement.query(params![ifid, name])?;
-
-            if let Some(row) = query.next()? {
-                Ok(Some(self.get_save_from_row(ifid, row)?))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(result) => Ok(result),
-        }
-    }
-
-    /// Return the save for the given numeric id (within a given story), or None if no such save
-    #[allow(dead_code)]
-    pub fn get_save_by_id(&self, ifid: String, dbid: i64) -> Result<Option<DbSave>, String> {
-        let result = || -> Result<Option<DbSave>, rusqlite::Error> {
-            let mut statement = self.connection.prepare(
-                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id,version, id FROM saves WHERE ifid = ?1 AND id=?2",
-            )?;
-            let mut query = statement.query(params![ifid, dbid])?;
-
-            if let Some(row) = query.next()? {
-                Ok(Some(self.get_save_from_row(ifid, row)?))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(result) => Ok(result),
-        }
-    }
-
-    /// Return all saves for the given ifid, ordered with most recent first
-    pub fn fetch_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, String> {
-        let result = || -> Result<Vec<DbSave>, rusqlite::Error> {
-            let mut saves: Vec<DbSave> = Vec::new();
-            let mut statement = self.connection.prepare(
-                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version, id FROM saves WHERE ifid = ?1 ORDER BY saved_when DESC",
-            )?;
-            let mut query = statement.query(params![ifid])?;
-
-            while let Some(row) = query.next()? {
-                saves.push(self.get_save_from_row(ifid.clone(), row)?);
-            }
-
-            Ok(saves)
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(saves) => Ok(saves),
-        }
-    }
-
-    /// Return manual only saves for the given ifid, ordered with most recent first
-    pub fn fetch_manual_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, String> {
-        let result = || -> Result<Vec<DbSave>, rusqlite::Error> {
-            let mut saves: Vec<DbSave> = Vec::new();
-            let mut statement = self.connection.prepare(
-                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id,version,id FROM saves WHERE ifid = ?1 AND save_type = ?2 ORDER BY saved_when DESC",
-            )?;
-            let mut query = statement.query(params![ifid, SaveType::Normal.to_string()])?;
-
-            while let Some(row) = query.next()? {
-                saves.push(self.get_save_from_row(ifid.clone(), row)?);
-            }
-
-            Ok(saves)
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(saves) => Ok(saves),
-        }
-    }
-
-    /// Delete all autosaves for the story with the given IFID
-    pub fn delete_autosaves_for_story(&self, ifid: String) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("DELETE FROM saves WHERE ifid = ?1 AND save_type=?2")?;
-            statement.execute(params![ifid, SaveType::Autosave.to_string()])?;
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
     fn get_save_from_row(
         &self,
         ifid: String,
@@ -1798,1103 +2458,564 @@ impl IfdbConnection {
             version,
         })
     }
-    /// Store the given save data to the database, returning any errors
-    pub fn store_save(&self, dbsave: &DbSave, overwrite: bool) -> Result<i64, DbSaveError> {
-        let result = || -> Result<i64, rusqlite::Error> {
-            // If there is a save with the exact same data and save type as this save with the same
-            // parent id, just return that save instead. This avoids branching saves unless necessary
-            let mut statement = self.connection.prepare(
-                "SELECT id,data FROM saves WHERE ifid = ?1 AND save_type = ?2 AND parent_id = ?3 ORDER BY saved_when DESC",
-            )?;
 
-            let mut query = statement.query(params![
-                dbsave.ifid,
-                dbsave.save_type.to_string(),
-                dbsave.parent_id
-            ])?;
-
-            while let Some(row) = query.next()? {
-                let dbid = row.get(0)?;
-                let data: Vec<u8> = row.get(1)?;
-                if data == dbsave.data {
-                    return Ok(dbid);
-                }
-            }
-
-            let mut save_name = dbsave.name.clone();
-            match dbsave.save_type {
-                SaveType::Autosave => {
-                    // Autosaves should have unique names, since the name itself isn't important
-                    let mut statement = self.connection.prepare(
-                    "SELECT max(id) FROM saves WHERE ifid = ?1 GROUp BY ifid ORDER BY saved_when DESC",
-                    )?;
-                    let mut query = statement.query(params![dbsave.ifid])?;
-                    if let Some(row) = query.next()? {
-                        let max: u32 = row.get(0)?;
-                        save_name = format!("{} - {}", dbsave.name, max);
-                    }
-                    // }
-                }
-                SaveType::Normal => {
-                    if overwrite {
-                        self.connection.execute(
-                            "DELETE FROM saves WHERE ifid = ?1 AND name = ?2",
-                            params![dbsave.ifid, dbsave.name],
-                        )?;
+    /// Import every Quetzal save in a directory
+    pub fn import_saves_from_directory<F: Fn(LoadFileResult)>(
+        &self,
+        path_str: &str,
+        loaded_callback: F,
+    ) {
+        match fs::read_dir(path_str) {
+            Ok(entries) => {
+                for entry in entries.filter_map(|entry| entry.ok()) {
+                    let path = entry.path();
+                    if importable_filetype(&path) != Some(SupportedFiletype::Save) {
+                        continue;
                     }
-                }
-            }
-
-            let next_pc: Option<i32> = dbsave.next_pc.map(|v| v as i32);
-            self.connection.execute(
-                "INSERT INTO saves (ifid, name, save_type, saved_when, data, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15)",
-                params![dbsave.ifid, save_name, dbsave.save_type.to_string(), dbsave.saved_when, dbsave.data, dbsave.pc as i32, dbsave.text_buffer_address, dbsave.parse_buffer_address,
-                                    next_pc, dbsave.left_status,dbsave.right_status, dbsave.latest_text, dbsave.room_id, dbsave.parent_id, dbsave.version]
-            )?;
-
-            let dbid = self.connection.last_insert_rowid();
-
-            Ok(dbid)
-        }();
-        match result {
-            Err(e) => {
-                let msg = format!("SQL error: {:?}", e);
-                if let rusqlite::Error::SqliteFailure(_, Some(msg)) = e {
-                    if msg == "UNIQUE constraint failed: saves.ifid, saves.name" {
-                        return Err(DbSaveError::ExistingSave);
+                    if let (Some(path_str), Some(filename)) =
+                        (path.to_str(), path.file_stem().and_then(OsStr::to_str))
+                    {
+                        loaded_callback(self.load_save_from_path(path_str, filename));
                     }
                 }
-                Err(DbSaveError::Other(msg))
             }
-            Ok(dbid) => Ok(dbid),
+            Err(msg) => loaded_callback(LoadFileResult::SaveFailure(
+                path_str.to_string(),
+                msg.to_string(),
+            )),
         }
     }
 
     ///
-    /// Sessions
+    /// Tags and shelves
     ///
-    ///
-    /// Return the session for the given IFID, creating it if necessary
-    pub fn get_or_create_session(&self, ifid: String) -> Result<DBSession, String> {
-        let result = || -> Result<DBSession, rusqlite::Error> {
-            let mut statement = self.connection.prepare(
-                "SELECT tools_open, details_open, debug_open,  transcript_active, command_out_active, transcript_name, command_out_name, clues_open, notes_open, map_open, last_clue_section, saves_open
-                FROM session WHERE ifid = ?1",
-            )?;
-            let mut query = statement.query(params![ifid])?;
-
-            if let Some(row) = query.next()? {
-                let tools_open: i32 = row.get(0)?;
-                let details_open: i32 = row.get(1)?;
-                let debug_open: i32 = row.get(2)?;
-                let transcript_active: i32 = row.get(3)?;
-                let command_out_active: i32 = row.get(4)?;
-                let transcript_name: String = row.get(5)?;
-                let command_out_name: String = row.get(6)?;
-                let clues_open: i32 = row.get(7)?;
-                let notes_open: i32 = row.get(8)?;
-                let map_open: i32 = row.get(9)?;
-                let last_clue_section: String = row.get(10)?;
-                let saves_open: i32 = row.get(11)?;
-
-                Ok(DBSession {
-                    ifid,
-                    tools_open: tools_open != 0,
-                    details_open: details_open != 0,
-                    debug_open: debug_open != 0,
-                    transcript_active: transcript_active != 0,
-                    command_out_active: command_out_active != 0,
-                    transcript_name,
-                    command_out_name,
-                    clues_open: clues_open != 0,
-                    notes_open: notes_open != 0,
-                    map_open: map_open != 0,
-                    saves_open: saves_open != 0,
-                    last_clue_section,
-                })
-            } else {
-                // Create a new session with defaults if it doesn't exist
-                let transcript_name = format!("transcript_{}.log", ifid);
-                let command_out_name = format!("commands_{}.commands", ifid);
-                self.connection.execute("INSERT INTO session 
-                (ifid, tools_open, details_open, debug_open,  transcript_active, command_out_active, transcript_name, command_out_name,clues_open, notes_open, map_open,saves_open, last_clue_section) 
-                VALUES (?1,0,0,0,0,0,?2,?3,0,0,0,0,'')",params![ifid, transcript_name, command_out_name])?;
-                Ok(DBSession {
-                    ifid,
-                    tools_open: false,
-                    details_open: false,
-                    debug_open: false,
-                    transcript_name,
-                    transcript_active: false,
-                    command_out_name,
-                    command_out_active: false,
-                    clues_open: false,
-                    notes_open: false,
-                    map_open: false,
-                    saves_open: false,
-                    last_clue_section: String::new(),
-                })
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(session) => Ok(session),
-        }
+    fn delete_unused_tags(&self) -> Result<()> {
+        self.connection.execute(
+            "DELETE FROM tag WHERE id NOT IN (SELECT tag_id FROM story_tag)",
+            params![],
+        )?;
+        Ok(())
     }
 
-    pub fn store_session(&self, session: DBSession) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE session SET tools_open = ?1, details_open= ?2, debug_open= ?3,  transcript_active= ?4, command_out_active= ?5, transcript_name= ?6, command_out_name= ?7, clues_open=?8, notes_open=?9, map_open=?10, last_clue_section=?11, saves_open=?12 WHERE ifid = ?13",
-                params![bool_to_int(session.tools_open),bool_to_int(session.details_open),bool_to_int(session.debug_open),
-                bool_to_int(session.transcript_active),bool_to_int(session.command_out_active),
-                session.transcript_name,session.command_out_name,
-                bool_to_int(session.clues_open),bool_to_int(session.notes_open),bool_to_int(session.map_open),
-                session.last_clue_section,
-                bool_to_int(session.saves_open),
-                session.ifid],
-            )?;
-            Ok(())
-        }();
+    fn shelf_from_row(row: &rusqlite::Row<'_>) -> Result<DbShelf> {
+        let query: Option<String> = row.get(2)?;
+        Ok(DbShelf {
+            dbid: row.get(0)?,
+            name: row.get(1)?,
+            // An unreadable query is treated as matching everything
+            query: query.map(|q| serde_json::from_str(&q).unwrap_or_default()),
+        })
+    }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
+    fn get_shelf_sql(&self, shelf_id: i64) -> Result<Option<DbShelf>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id, name, query FROM shelf WHERE id = ?1")?;
+        let mut query = statement.query(params![shelf_id])?;
+        match query.next()? {
+            Some(row) => Ok(Some(Self::shelf_from_row(row)?)),
+            None => Ok(None),
         }
     }
 
     ///
-    /// Clues
+    /// Preferences/Themes
     ///
-    /// Count all clues in the database and return the result
-    pub fn count_clues(&self) -> Result<u32, String> {
-        let result = || -> Result<u32, rusqlite::Error> {
-            let mut statement = self.connection.prepare("SELECT count(id) FROM clue")?;
+    fn color_from_components(
+        &self,
+        r: Option<i64>,
+        g: Option<i64>,
+        b: Option<i64>,
+        a: Option<i64>,
+    ) -> Option<DbColor> {
+        if let Some(r) = r {
+            if let Some(g) = g {
+                if let Some(b) = b {
+                    if let Some(a) = a {
+                        return Some(DbColor { r, g, b, a });
+                    }
+                }
+            }
+        }
 
-            let mut query = statement.query(params![])?;
+        None
+    }
 
-            if let Some(row) = query.next()? {
-                Ok(row.get(0)?)
-            } else {
-                Ok(0)
-            }
-        }();
+    fn insert_window_details(&self, details: &mut WindowDetails) -> Result<(), rusqlite::Error> {
+        self.connection.execute(
+            "INSERT INTO window_details (story_id, window_type, x, y, width, height, open) VALUES (?1,?2,?3,?4,?5,?6,?7)",
+            params![
+                details.story_id,
+                details.window_type.to_string(),
+                details.x,
+                details.y,
+                details.width,
+                details.height,
+                bool_to_int(details.open)
+            ],
+        )?;
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
-        }
+        details.dbid = self.connection.last_insert_rowid();
+
+        Ok(())
     }
 
-    pub fn reveal_clue(&self, clue_id: u32) -> Result<Option<Clue>, String> {
-        let result = || -> Result<Option<Clue>, rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("SELECT revealed, text FROM clue WHERE id = ?1")?;
+    fn update_window_details(&self, details: &mut WindowDetails) -> Result<(), rusqlite::Error> {
+        self.connection.execute(
+            "UPDATE window_details SET x=?1,y=?2,width=?3,height=?4,open=?5 WHERE id = ?6",
+            params![
+                details.x,
+                details.y,
+                details.width,
+                details.height,
+                bool_to_int(details.open),
+                details.dbid
+            ],
+        )?;
+
+        Ok(())
+    }
 
-            let mut query = statement.query(params![clue_id])?;
+    /** Initialize the settings table, inserting a record if it doesn't exist.  */
+    fn initialize_settings_if_needed(&self) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            let mut needs_create = true;
 
-            let mut clue = None;
+            let mut statement = self.connection.prepare("SELECT count(*) from settings")?;
 
+            let mut query = statement.query(params![])?;
             if let Some(row) = query.next()? {
-                let is_revealed: u32 = row.get(0)?;
-                let text: String = row.get(1)?;
-
-                clue = Some(Clue {
-                    dbid: clue_id,
-                    is_revealed: is_revealed == 1,
-                    text,
-                });
+                let c: i64 = row.get(0)?;
+                needs_create = c == 0;
             }
 
-            if clue.is_some() {
+            if needs_create {
                 self.connection.execute(
-                    "UpDATE clue SET revealed=1  WHERE id = ?1",
-                    params![clue_id],
+                    "INSERT INTO settings (playing_story_id) VALUES (null)",
+                    params![],
                 )?;
             }
 
-            Ok(clue)
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(clues) => Ok(clues),
-        }
-    }
-
-    pub fn hide_clue(&self, clue_id: u32) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE clue SET revealed=0  WHERE id = ?1",
-                params![clue_id],
-            )?;
-
             Ok(())
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
+            Err(e) => Err(e.into()),
             Ok(()) => Ok(()),
         }
     }
 
-    #[allow(dead_code)]
-    /// Does the story with the provided ID have clues?
-    pub fn story_has_clues(&self, story_id: u32) -> Result<bool, String> {
-        let result = || -> Result<bool, rusqlite::Error> {
+    /** Return the directory daily backups are written to, if daily backups are on */
+    pub fn get_backup_directory(&self) -> Result<Option<String>, IfdbError> {
+        let result = || -> Result<Option<String>, rusqlite::Error> {
             let mut statement = self
                 .connection
-                .prepare("SELECT s.id FROM clue_section s  WHERE s.story_id = ?1")?;
+                .prepare("SELECT backup_directory from settings")?;
 
-            let mut query = statement.query(params![story_id])?;
+            let mut query = statement.query(params![])?;
 
-            Ok(query.next()?.is_some())
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(None)
+            }
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(clues) => Ok(clues),
+            Err(e) => Err(e.into()),
+            Ok(directory) => Ok(directory),
         }
     }
 
-    pub fn get_clues_for_story(&self, story_id: u32) -> Result<Vec<ClueSection>, String> {
-        let result = || -> Result<Vec<ClueSection>, rusqlite::Error> {
-            let mut sections = HashMap::new();
-            let mut section_keys = vec![];
-
-            let mut statement = self.connection.prepare(
-                "SELECT s.name, s.id FROM clue_section s  WHERE s.story_id = ?1 ORDER BY s.id",
-            )?;
-
-            let mut query = statement.query(params![story_id])?;
-
-            while let Some(row) = query.next()? {
-                let section_name: String = row.get(0)?;
-                let dbid: u32 = row.get(1)?;
-
-                if !sections.contains_key(&section_name) {
-                    section_keys.push(section_name.clone());
-                    sections.insert(
-                        section_name.clone(),
-                        ClueSection {
-                            dbid,
-                            story_id,
-                            name: section_name.clone(),
-                            subsections: vec![],
-                        },
-                    );
-                }
-            }
-
-            // Run again, map subsections
-            let mut statement = self
-                .connection
-                .prepare("SELECT s.name, sb.name, sb.id FROM clue_subsection sb JOIN clue_section s ON s.id = sb.section_id WHERE s.story_id = ?1 ORDER BY sb.id")?;
-
-            let mut query = statement.query(params![story_id])?;
-
-            while let Some(row) = query.next()? {
-                let section_name: String = row.get(0)?;
-                let subsection_name: String = row.get(1)?;
-                let dbid: u32 = row.get(2)?;
-
-                sections.entry(section_name).and_modify(|section| {
-                    section.subsections.push(ClueSubsection {
-                        dbid,
-                        name: subsection_name.clone(),
-                        clues: vec![],
-                    });
-                });
-            }
+    /** Store the directory for daily backups. None turns daily backups off */
+    pub fn store_backup_directory(&self, directory: Option<String>) -> Result<(), IfdbError> {
+        self.initialize_settings_if_needed()?;
 
-            // Run again, mapping clues
-            let mut statement = self.connection.prepare(
-                "SELECT s.name, sb.name,  c.text, c.revealed,c.id 
-                     FROM clue c
-                      JOIN clue_subsection sb ON sb.id = c.subsection_id
-                      JOIN clue_section s ON s.id = sb.section_id 
-                      WHERE s.story_id = ?1 
-                      ORDER BY c.id",
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE settings set backup_directory = ?1",
+                params![directory],
             )?;
 
-            let mut query = statement.query(params![story_id])?;
-
-            while let Some(row) = query.next()? {
-                let section_name: String = row.get(0)?;
-                let subsection_name: String = row.get(1)?;
-                let text: String = row.get(2)?;
-                let is_revealed: u32 = row.get(3)?;
-                let dbid: u32 = row.get(4)?;
-
-                sections.entry(section_name).and_modify(|section| {
-                    for subsection in &mut section.subsections {
-                        if subsection.name == subsection_name {
-                            let mut clue = Clue {
-                                dbid,
-                                text: text.clone(),
-                                is_revealed: false,
-                            };
-                            if is_revealed == 1 {
-                                clue.is_revealed = true;
-                                clue.text = clue.revealed_text();
-                            }
-                            subsection.clues.push(clue);
-                        }
-                    }
-                });
-            }
-
-            // Convert to results
-            let mut results = vec![];
-            for k in section_keys {
-                results.push(sections.get(&k).unwrap().clone());
-            }
-
-            Ok(results)
+            Ok(())
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(clues) => Ok(clues),
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
         }
     }
 
-    pub fn add_clue(
-        &self,
-        story_id: u32,
-        section_name: String,
-        subsection_name: String,
-        clue_text: String,
-    ) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            // Get or create the section
-            let mut statement = self
-                .connection
-                .prepare("SELECT id FROM clue_section WHERE story_id = ?1 AND name=?2")?;
-
-            let mut query = statement.query(params![story_id, section_name])?;
-
-            let mut section_id: u32 = 0;
-
-            if let Some(row) = query.next()? {
-                section_id = row.get(0)?;
-            }
-
-            if section_id == 0 {
-                self.connection.execute(
-                    "INSERT INTO clue_section (story_id, name) VALUES (?1,?2)",
-                    params![story_id, section_name],
-                )?;
-
-                section_id = self.connection.last_insert_rowid() as u32;
-            }
-
-            // Get or create the subsection
+    /** Return the days items stay in the trash before being purged, if they are purged */
+    pub fn get_trash_purge_days(&self) -> Result<Option<u32>, IfdbError> {
+        let result = || -> Result<Option<u32>, rusqlite::Error> {
             let mut statement = self
                 .connection
-                .prepare("SELECT id FROM clue_subsection WHERE section_id = ?1 AND name=?2")?;
+                .prepare("SELECT trash_purge_days from settings")?;
 
-            let mut query = statement.query(params![section_id, subsection_name])?;
-
-            let mut subsection_id: u32 = 0;
+            let mut query = statement.query(params![])?;
 
             if let Some(row) = query.next()? {
-                subsection_id = row.get(0)?;
-            }
-
-            if subsection_id == 0 {
-                self.connection.execute(
-                    "INSERT INTO clue_subsection (section_id, name) VALUES (?1,?2)",
-                    params![section_id, subsection_name],
-                )?;
-
-                subsection_id = self.connection.last_insert_rowid() as u32;
-            }
-
-            // Create clue if needed
-            let mut statement = self
-                .connection
-                .prepare("SELECT id FROM clue WHERE subsection_id = ?1 AND text=?2")?;
-
-            let mut query = statement.query(params![subsection_id, clue_text])?;
-
-            if query.next()?.is_none() {
-                self.connection.execute(
-                    "INSERT INTO clue (subsection_id, text, revealed) VALUES (?1,?2,0)",
-                    params![subsection_id, clue_text],
-                )?;
+                Ok(row.get(0)?)
+            } else {
+                Ok(None)
             }
-
-            Ok(())
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
+            Err(e) => Err(e.into()),
+            Ok(days) => Ok(days),
         }
     }
 
-    ///
-    /// Notes
-    ///
-    /// Return count of all notes in the database
-    pub fn count_notes(&self) -> Result<u32, String> {
-        let result = || -> Result<u32, rusqlite::Error> {
-            let mut statement = self.connection.prepare("SELECT count(id) FROM notes")?;
+    /** Store the days items stay in the trash. None keeps them until the trash is emptied */
+    pub fn store_trash_purge_days(&self, days: Option<u32>) -> Result<(), IfdbError> {
+        self.initialize_settings_if_needed()?;
 
-            let mut query = statement.query(params![])?;
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection
+                .execute("UPDATE settings set trash_purge_days = ?1", params![days])?;
 
-            if let Some(row) = query.next()? {
-                Ok(row.get(0)?)
-            } else {
-                Ok(0)
-            }
+            Ok(())
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(data) => Ok(data),
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
         }
     }
 
-    pub fn get_notes_for_story(
-        &self,
-        story_id: i64,
-        include_done: bool,
-    ) -> Result<Vec<Note>, String> {
-        let result = || -> Result<Vec<Note>, rusqlite::Error> {
-            let mut notes = vec![];
-            let mut sql = String::from("SELECT notes.id, notes.room_id, r.name, notes, done FROM notes LEFT JOIN map_room r ON r.story_id = notes.story_id AND r.room_id=notes.room_id  WHERE notes.story_id = ?1");
-
-            if !include_done {
-                sql.push_str(" AND done = 0 ");
-            }
-
-            sql.push_str(" ORDER BY notes.room_id, notes.id");
-
-            let mut statement = self.connection.prepare(sql.as_str())?;
-
-            let mut query = statement.query(params![story_id])?;
-            while let Some(row) = query.next()? {
-                let dbid: i64 = row.get(0)?;
-                let room_id: i32 = row.get(1)?;
-                let room_name: Option<String> = row.get(2)?;
-                let note_text: String = row.get(3)?;
-                let done: i32 = row.get(4)?;
-
-                notes.push(Note {
-                    dbid,
-                    story_id,
-                    room_name,
-                    room_id: room_id as u32,
-                    notes: note_text,
-                    done: done == 1,
-                });
-            }
+    /// Purge items that have been in the trash longer than the days set. Returns how many
+    /// were purged
+    pub fn purge_expired_trash(&self) -> Result<usize, IfdbError> {
+        match self.get_trash_purge_days()? {
+            Some(days) => self.purge_trash(Some(
+                Utc::now().naive_utc() - chrono::Duration::days(i64::from(days)),
+            )),
+            None => Ok(0),
+        }
+    }
 
-            Ok(notes)
+    fn is_story_trashed(&self, story_id: u32) -> Result<bool, IfdbError> {
+        let result = || -> Result<bool, rusqlite::Error> {
+            self.connection.query_row(
+                "SELECT deleted_when IS NOT NULL FROM story WHERE id = ?1",
+                params![story_id],
+                |row| row.get(0),
+            )
         }();
 
         match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(notes) => Ok(notes),
+            Err(e) => Err(e.into()),
+            Ok(trashed) => Ok(trashed),
         }
     }
 
-    /// Shortcut method to change done status on note.
-    pub fn set_note_done(&self, dbid: i64, done: bool) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UpDATE notes SET done=?1 WHERE id = ?2",
-                params![bool_to_int(done), dbid],
-            )?;
+    ///
+    /// Backups
+    ///
+    /// Return the names of all migrations applied to this database
+    fn fetch_migration_names(connection: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+        let mut statement = connection.prepare("SELECT name FROM migrations ORDER BY name ASC")?;
+        let rows = statement.query_map(NO_PARAMS, |row| row.get(0))?;
+        rows.collect()
+    }
 
-            Ok(())
+    /// Write a zip archive holding a consistent snapshot of the database plus a manifest
+    pub fn backup_to_path(&self, path: &str) -> Result<(), IfdbError> {
+        // VACUUM INTO writes a snapshot that is consistent even while the app is running
+        let snapshot_path = std::env::temp_dir().join(format!(
+            "ferrif-snapshot-{}-{}.db",
+            std::process::id(),
+            Utc::now().format("%Y%m%d%H%M%S%f")
+        ));
+        let snapshot_str = snapshot_path.to_string_lossy().to_string();
+
+        let result = || -> Result<(Vec<String>, Vec<u8>), IfdbError> {
+            let migrations = IfdbConnection::fetch_migration_names(&self.connection)?;
+            self.connection
+                .execute("VACUUM INTO ?1", params![snapshot_str])?;
+            let data = fs::read(&snapshot_path)?;
+            Ok((migrations, data))
         }();
+        let _ = fs::remove_file(&snapshot_path);
+        let (migrations, data) = result?;
+
+        let manifest = serde_json::json!({
+            "format": BACKUP_FORMAT_VERSION,
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "created": Utc::now().to_rfc3339(),
+            "migrations": migrations,
+        });
+
+        let write_archive = || -> zip::result::ZipResult<()> {
+            let mut archive = zip::ZipWriter::new(File::create(path)?);
+            let options = zip::write::FileOptions::default();
+            archive.start_file(BACKUP_MANIFEST_NAME, options)?;
+            archive.write_all(manifest.to_string().as_bytes())?;
+            archive.start_file(BACKUP_DATABASE_NAME, options)?;
+            archive.write_all(&data)?;
+            archive.finish()?;
+            Ok(())
+        };
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
+        write_archive().map_err(|e| IfdbError::Io(format!("Error writing backup {}: {}", path, e)))
     }
 
-    /// Shortcut method to change notes on note.
-    pub fn set_note_notes(&self, dbid: i64, notes: String, room_id: i32) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE notes SET notes=?1, room_id=?2 WHERE id = ?3",
-                params![notes, room_id, dbid],
-            )?;
+    /// Replace the database at database_path with the one in a backup archive. The backup is
+    /// rejected if it was made by a newer version of the app. The replaced database is kept
+    /// alongside with a .before-restore extension and the time, and its path returned. Must be
+    /// called with no open connections.
+    pub fn restore_from_backup(
+        backup_path: &str,
+        database_path: &str,
+    ) -> Result<Option<String>, IfdbError> {
+        let mut archive = zip::ZipArchive::new(File::open(backup_path)?).map_err(|e| {
+            IfdbError::Corrupt(format!("Error reading backup {}: {}", backup_path, e))
+        })?;
+
+        let manifest: Value = match archive.by_name(BACKUP_MANIFEST_NAME) {
+            Ok(file) => serde_json::from_reader(file)
+                .map_err(|e| IfdbError::Corrupt(format!("Backup manifest is invalid: {}", e)))?,
+            Err(_) => return Err(IfdbError::Corrupt(String::from("Backup has no manifest"))),
+        };
+        if manifest["format"].as_u64() != Some(BACKUP_FORMAT_VERSION) {
+            return Err(IfdbError::Invalid(format!(
+                "Unsupported backup format {}",
+                manifest["format"]
+            )));
+        }
 
+        let restore_path = format!("{}.restoring", database_path);
+        let mut data = vec![];
+        match archive.by_name(BACKUP_DATABASE_NAME) {
+            Ok(mut file) => {
+                file.read_to_end(&mut data)?;
+            }
+            Err(_) => return Err(IfdbError::Corrupt(String::from("Backup has no database"))),
+        };
+        fs::write(&restore_path, data)?;
+
+        // Check the snapshot before swapping it in. The connection is closed at the end of
+        // the block so the file can be moved
+        let check = || -> Result<(), IfdbError> {
+            let connection = Connection::open(&restore_path)?;
+            let migrations = IfdbConnection::fetch_migration_names(&connection).map_err(|e| {
+                IfdbError::Corrupt(format!("Backup database is not readable: {:?}", e))
+            })?;
+            if let Some(unknown) = migrations
+                .iter()
+                .find(|name| !IfdbConnection::is_known_migration(name))
+            {
+                return Err(IfdbError::Invalid(format!(
+                    "Backup was made by a newer version of Ferrif (database version {})",
+                    unknown
+                )));
+            }
             Ok(())
         }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
+        if let Err(msg) = check {
+            let _ = fs::remove_file(&restore_path);
+            return Err(msg);
         }
-    }
 
-    /// Save a note. Returns the dbid of the updated/saved note
-    pub fn save_note(&self, note: Note) -> Result<i64, String> {
-        let result = || -> Result<i64, rusqlite::Error> {
-            // Insert the room into the map for future reference, if no map record exists
-            if let Some(room_name) = note.room_name {
-                self.connection.execute(
-                    "INSERT INTO map_room(story_id,room_id,name) 
-                SELECT ?1,?2,?3
-                WHERE NOT EXISTS(SELECT 1 FROM map_room WHERE story_id = ?4 AND room_id = ?5)",
-                    params![
-                        note.story_id,
-                        note.room_id,
-                        room_name,
-                        note.story_id,
-                        note.room_id
-                    ],
-                )?;
+        // Each restore keeps the database it replaces under a new name, so restoring again
+        // doesn't lose the library from before the first restore
+        let kept_path = if Path::new(database_path).exists() {
+            let stamped = format!(
+                "{}.before-restore-{}",
+                database_path,
+                Local::now().format("%Y-%m-%d-%H%M%S")
+            );
+            let mut kept_path = stamped.clone();
+            let mut count = 1;
+            while Path::new(&kept_path).exists() {
+                count += 1;
+                kept_path = format!("{}-{}", stamped, count);
             }
-            if note.dbid != 0 {
-                self.connection.execute(
-                    "UpDATE notes SET story_id = ?1, room_id = ?2, notes = ?3, done = ?4 WHERE id = ?5",
-                    params![note.story_id, note.room_id, note.notes,bool_to_int(note.done), note.dbid],
-                )?;
+            fs::rename(database_path, &kept_path)?;
+            Some(kept_path)
+        } else {
+            None
+        };
+        fs::rename(&restore_path, database_path)?;
+        Ok(kept_path)
+    }
 
-                Ok(note.dbid)
-            } else {
-                self.connection.execute(
-                    "INSERT INTO notes (story_id, room_id, notes, done) VALUES (?1,?2,?3,?4)",
-                    params![
-                        note.story_id,
-                        note.room_id,
-                        note.notes,
-                        bool_to_int(note.done)
-                    ],
-                )?;
+    /// If daily backups are on and there is no backup for today, write one and remove the
+    /// oldest backups. Returns the path of any backup written
+    pub fn run_daily_backup(&self) -> Result<Option<String>, IfdbError> {
+        let directory = match self.get_backup_directory()? {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
 
-                Ok(self.connection.last_insert_rowid())
-            }
-        }();
+        let backup_path = Path::new(&directory).join(format!(
+            "{}{}.zip",
+            DAILY_BACKUP_PREFIX,
+            Local::now().format("%Y-%m-%d")
+        ));
+        if backup_path.exists() {
+            return Ok(None);
+        }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(dbid) => Ok(dbid),
+        let backup_str = backup_path.to_string_lossy().to_string();
+        self.backup_to_path(backup_str.as_str())?;
+
+        // Names sort by date, so everything before the last few can go
+        let mut backups: Vec<String> = fs::read_dir(&directory)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .filter(|name| name.starts_with(DAILY_BACKUP_PREFIX) && name.ends_with(".zip"))
+            .collect();
+        backups.sort();
+        if backups.len() > DAILY_BACKUPS_KEPT {
+            for name in &backups[..backups.len() - DAILY_BACKUPS_KEPT] {
+                if let Err(msg) = fs::remove_file(Path::new(&directory).join(name)) {
+                    println!("Error removing old backup {}: {}", name, msg);
+                }
+            }
         }
+
+        Ok(Some(backup_str))
     }
 
     ///
-    /// Mapping
+    /// Migrations
     ///
-    pub fn get_rooms_for_story(&self, story_id: u32) -> Result<Vec<MapRoom>, String> {
-        let result = || -> Result<Vec<MapRoom>, rusqlite::Error> {
-            let mut notes = vec![];
-            let  sql = String::from("SELECT r.id, r.room_id, r.name FROM map_room r WHERE r.story_id = ?1 ORDER BY r.name");
-
-            let mut statement = self.connection.prepare(sql.as_str())?;
-
-            let mut query = statement.query(params![story_id])?;
-            while let Some(row) = query.next()? {
-                let dbid: i64 = row.get(0)?;
-                let room_id: i32 = row.get(1)?;
-                let room_name: Option<String> = row.get(2)?;
-
-                notes.push(MapRoom {
-                    dbid,
-                    room_id: room_id as u32,
-                    name: match room_name {
-                        None => String::from("Nowhere"),
-                        Some(s) => s,
-                    },
-                    story_id: story_id as i64,
-                });
-            }
+    /// Run migrations to make sure this database is up to sync. A database with migrations
+    /// this version doesn't know about is left alone. Otherwise, if any migrations are
+    /// pending, the database is copied aside first, then each migration runs in its own
+    /// transaction so a failure leaves the database as the last migration left it. Returns
+    /// notes for the player about anything a migration could not do
+    pub fn migrate(&self) -> Result<Vec<String>, MigrationError> {
+        self.migrate_with_backup(true)
+    }
 
-            Ok(notes)
-        }();
+    /// Migrate a copy of a database, such as a snapshot taken to merge from. The original is
+    /// untouched, so no backup is made
+    pub fn migrate_without_backup(&self) -> Result<Vec<String>, MigrationError> {
+        self.migrate_with_backup(false)
+    }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(rooms) => Ok(rooms),
+    fn migrate_with_backup(&self, backup: bool) -> Result<Vec<String>, MigrationError> {
+        // See if migration table exists. Scoped so the statement is finished before the
+        // backup, which can't run while a statement is in progress
+        {
+            let mut statement = self
+                .connection
+                .prepare("SELECT name FROM sqlite_master WHERE type='table' AND name= ?1")?;
+            let mut rows = statement.query(params![MIGRATION_TABLE_NAME])?;
+            if rows.next()?.is_none() {
+                println!("Creating migration table");
+                self.create_migration_table()?;
+            }
         }
-    }
 
-    ///
-    /// Fonts
-    ///
-    pub fn get_fonts(&self) -> Result<Vec<DbFont>, String> {
-        let result = || -> Result<Vec<DbFont>, rusqlite::Error> {
-            let mut fonts = vec![];
-            let sql = String::from(
-                "SELECT f.id, f.name, f.data, f.monospace FROM fonts f   ORDER BY name",
-            );
+        let applied = IfdbConnection::fetch_migration_names(&self.connection)?;
+        let unknown: Vec<String> = applied
+            .iter()
+            .filter(|name| !IfdbConnection::is_known_migration(name))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(MigrationError::Newer(unknown));
+        }
 
-            let mut statement = self.connection.prepare(sql.as_str())?;
+        let pending: Vec<(&str, MigrationStep)> = IfdbConnection::migration_steps()
+            .into_iter()
+            .filter(|(name, _)| !applied.iter().any(|applied| applied == name))
+            .collect();
+        if pending.is_empty() {
+            return Ok(vec![]);
+        }
 
-            let mut query = statement.query(params![])?;
-            while let Some(row) = query.next()? {
-                let dbid: i64 = row.get(0)?;
-                let name: String = row.get(1)?;
-                let data: Vec<u8> = row.get(2)?;
-                let monospace: bool = row.get(3)?;
-
-                fonts.push(DbFont {
-                    dbid,
-                    name,
-                    data,
-                    monospace,
-                });
+        // A new database has nothing to lose
+        let backup_path = if !backup || applied.is_empty() {
+            None
+        } else {
+            self.backup_before_migration()
+                .map_err(MigrationError::Backup)?
+        };
+
+        let mut notes = vec![];
+        for (name, step) in pending {
+            println!("Running migration {}", name);
+            let result = || -> Result<Vec<String>> {
+                let transaction = self.connection.unchecked_transaction()?;
+                let step_notes = step(self)?;
+                transaction.commit()?;
+                Ok(step_notes)
+            }();
+            match result {
+                Ok(step_notes) => notes.extend(step_notes),
+                Err(e) => {
+                    self.cache.clear();
+                    return Err(MigrationError::Failed(
+                        name.to_string(),
+                        e.into(),
+                        backup_path,
+                    ));
+                }
             }
+        }
 
-            Ok(fonts)
-        }();
+        self.cache.clear();
+        Ok(notes)
+    }
 
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(fonts) => Ok(fonts),
-        }
+    /// Every migration this version of the app knows about, in the order they run
+    fn migration_steps() -> Vec<(&'static str, MigrationStep)> {
+        vec![
+            (MIGRATION_1, IfdbConnection::run_migration_1),
+            (MIGRATION_2, IfdbConnection::run_migration_2),
+            (MIGRATION_3, IfdbConnection::run_migration_3),
+            (MIGRATION_4, IfdbConnection::run_migration_4),
+            (MIGRATION_5, IfdbConnection::run_migration_5),
+            (MIGRATION_6, IfdbConnection::run_migration_6),
+            (MIGRATION_7, IfdbConnection::run_migration_7),
+            (MIGRATION_8, IfdbConnection::run_migration_8),
+            (MIGRATION_9, IfdbConnection::run_migration_9),
+            (MIGRATION_10, IfdbConnection::run_migration_10),
+            (MIGRATION_11, IfdbConnection::run_migration_11),
+            (MIGRATION_12, IfdbConnection::run_migration_12),
+            (MIGRATION_13, IfdbConnection::run_migration_13),
+            (MIGRATION_14, IfdbConnection::run_migration_14),
+            (MIGRATION_15, IfdbConnection::run_migration_15),
+            (MIGRATION_16, IfdbConnection::run_migration_16),
+            (MIGRATION_17, IfdbConnection::run_migration_17),
+            (MIGRATION_18, IfdbConnection::run_migration_18),
+            (MIGRATION_19, IfdbConnection::run_migration_19),
+            (MIGRATION_20, IfdbConnection::run_migration_20),
+            (MIGRATION_21, IfdbConnection::run_migration_21),
+            (MIGRATION_22, IfdbConnection::run_migration_22),
+            (MIGRATION_23, IfdbConnection::run_migration_23),
+            (MIGRATION_24, IfdbConnection::run_migration_24),
+        ]
     }
 
-    pub fn add_font(&self, name: &str, data: Vec<u8>, monospace: bool) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "INSERT INTO fonts (name, data, monospace) VALUES (?1,?2,?3)",
-                params![name, data, monospace],
-            )?;
-            Ok(())
-        }();
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
+    /// True if the migration is one this version of the app knows about
+    fn is_known_migration(name: &str) -> bool {
+        IfdbConnection::migration_steps()
+            .iter()
+            .any(|(known, _)| *known == name)
     }
-    pub fn update_font_metadata(&self, font: DbFont) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE fonts SET name=?1,  monospace=?2 WHERE id=?3",
-                params![font.name, font.monospace, font.dbid],
-            )?;
-            Ok(())
-        }();
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
+
+    /// Copy a database file aside before migrating it, replacing any earlier copy. Returns
+    /// the path of the copy, or None for a database not stored in a file
+    fn backup_before_migration(&self) -> Result<Option<String>, IfdbError> {
+        if !Path::new(&self.database_path).is_file() {
+            return Ok(None);
+        }
+
+        let backup_path = format!("{}.before-migration", self.database_path);
+        if Path::new(&backup_path).exists() {
+            fs::remove_file(&backup_path)?;
         }
-    }
-
-    pub fn delete_font(&self, dbid: i64) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection
-                .execute("DELETE FROM fonts WHERE id=?1", params![dbid])?;
-            Ok(())
-        }();
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    ///
-    /// Preferences/Themes
-    ///
-    ///
-    fn color_from_components(
-        &self,
-        r: Option<i64>,
-        g: Option<i64>,
-        b: Option<i64>,
-        a: Option<i64>,
-    ) -> Option<DbColor> {
-        if let Some(r) = r {
-            if let Some(g) = g {
-                if let Some(b) = b {
-                    if let Some(a) = a {
-                        return Some(DbColor { r, g, b, a });
-                    }
-                }
-            }
-        }
-
-        None
-    }
-
-    pub fn get_theme(&self, name: &str) -> Result<Option<DbTheme>, String> {
-        let result = || -> Result<Option<DbTheme>, rusqlite::Error> {
-            let mut statement = self.connection.prepare(
-                "SELECT theme_type, font_size, font_id, 
-                 background_color_r, background_color_g,background_color_b, background_color_a,
-                  text_color_r, text_color_g,text_color_b, text_color_a, 
-                  stroke_color_r, stroke_color_g,stroke_color_b, stroke_color_a,
-                  secondary_background_color_r, secondary_background_color_g,secondary_background_color_b, secondary_background_color_a
-                   FROM themes WHERE name = ?1",
-            )?;
-            let mut query = statement.query(params![name])?;
-
-            if let Some(row) = query.next()? {
-                let theme_type: String = row.get(0)?;
-                let theme = DbTheme {
-                    name: name.to_string(),
-                    theme_type: match theme_type.as_str() {
-                        CUSTOM_THEME => ThemeType::Custom,
-                        DARK_THEME => ThemeType::Dark,
-                        _ => ThemeType::Light,
-                    },
-                    font_size: row.get(1)?,
-                    font_id: row.get(2)?,
-                    background_color: self.color_from_components(
-                        row.get(3)?,
-                        row.get(4)?,
-                        row.get(5)?,
-                        row.get(6)?,
-                    ),
-                    text_color: self.color_from_components(
-                        row.get(7)?,
-                        row.get(8)?,
-                        row.get(9)?,
-                        row.get(10)?,
-                    ),
-                    stroke_color: self.color_from_components(
-                        row.get(11)?,
-                        row.get(12)?,
-                        row.get(13)?,
-                        row.get(14)?,
-                    ),
-                    secondary_background_color: self.color_from_components(
-                        row.get(15)?,
-                        row.get(16)?,
-                        row.get(17)?,
-                        row.get(18)?,
-                    ),
-                };
-                Ok(Some(theme))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(result) => Ok(result),
-        }
-    }
-
-    pub fn store_theme(&self, theme: DbTheme) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            // Lazier than checking for update but works
-            self.connection
-                .execute("DELETE FROM themes WHERE name=?1", params![theme.name])?;
-            self.connection.execute(
-                "INSERT INTO themes (name,font_size, font_id, theme_type
-                ) VALUES (?1,?2,?3,?4);",
-                params![
-                    theme.name,
-                    theme.font_size,
-                    theme.font_id,
-                    match theme.theme_type {
-                        ThemeType::Custom => CUSTOM_THEME,
-                        ThemeType::Dark => DARK_THEME,
-                        ThemeType::Light => LIGHT_THEME,
-                    }
-                ],
-            )?;
-
-            if let Some(color) = theme.background_color {
-                self.connection.execute(
-                    "UPDATE themes SET background_color_r = ?1,
-                                background_color_g = ?2,
-                                background_color_b = ?3,
-                                background_color_a = ?4
-                                WHERE name = ?5
-                                ",
-                    params![color.r, color.g, color.b, color.a, theme.name],
-                )?;
-            }
-
-            if let Some(color) = theme.text_color {
-                self.connection.execute(
-                    "UPDATE themes SET text_color_r = ?1,
-                                text_color_g = ?2,
-                                text_color_b = ?3,
-                                text_color_a = ?4
-                                WHERE name = ?5
-                                ",
-                    params![color.r, color.g, color.b, color.a, theme.name],
-                )?;
-            }
-
-            if let Some(color) = theme.stroke_color {
-                self.connection.execute(
-                    "UPDATE themes SET stroke_color_r = ?1,
-                                    stroke_color_g = ?2,
-                                    stroke_color_b = ?3,
-                                    stroke_color_a = ?4
-                                    WHERE name = ?5
-                                ",
-                    params![color.r, color.g, color.b, color.a, theme.name],
-                )?;
-            }
-
-            if let Some(color) = theme.secondary_background_color {
-                self.connection.execute(
-                    "UPDATE themes SET secondary_background_color_r = ?1,
-                                    secondary_background_color_g = ?2,
-                                    secondary_background_color_b = ?3,
-                                    secondary_background_color_a = ?4
-                                    WHERE name = ?5
-                                ",
-                    params![color.r, color.g, color.b, color.a, theme.name],
-                )?;
-            }
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    /// Return the position and other information for a window, if any has been stored
-    pub fn get_window_details(
-        &self,
-        story_id: u32,
-        window_type: WindowType,
-    ) -> Result<Option<WindowDetails>, String> {
-        let result = || -> Result<Option<WindowDetails>, rusqlite::Error> {
-            let mut details = WindowDetails {
-                dbid: 0,
-                story_id: story_id as i64,
-                window_type: window_type.clone(),
-                x: 0f64,
-                y: 0f64,
-                width: 0f64,
-                height: 0f64,
-                open: false,
-            };
-            let mut statement = self
-                .connection
-                .prepare("SELECT id, x,y,width,height,open FROM window_details WHERE story_id = ?1 AND window_type = ?2")?;
-
-            let mut query = statement.query(params![story_id, window_type.to_string()])?;
-
-            if let Some(row) = query.next()? {
-                details.dbid = row.get(0)?;
-                details.x = row.get(1)?;
-                details.y = row.get(2)?;
-                details.width = row.get(3)?;
-                details.height = row.get(4)?;
-                let open: i64 = row.get(5)?;
-                details.open = open == 1;
-                Ok(Some(details))
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(details) => Ok(details),
-        }
-    }
-
-    /// Add or update the position and other information for a window. Will set the dbid
-    /// on the passed-in window details on add
-    pub fn store_window_details(&self, details: &mut WindowDetails) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            if details.dbid == 0 {
-                self.insert_window_details(details)
-            } else {
-                self.update_window_details(details)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    fn insert_window_details(&self, details: &mut WindowDetails) -> Result<(), rusqlite::Error> {
-        self.connection.execute(
-            "INSERT INTO window_details (story_id, window_type, x, y, width, height, open) VALUES (?1,?2,?3,?4,?5,?6,?7)",
-            params![
-                details.story_id,
-                details.window_type.to_string(),
-                details.x,
-                details.y,
-                details.width,
-                details.height,
-                bool_to_int(details.open)
-            ],
-        )?;
-
-        details.dbid = self.connection.last_insert_rowid();
-
-        Ok(())
-    }
-
-    fn update_window_details(&self, details: &mut WindowDetails) -> Result<(), rusqlite::Error> {
-        self.connection.execute(
-            "UPDATE window_details SET x=?1,y=?2,width=?3,height=?4,open=?5 WHERE id = ?6",
-            params![
-                details.x,
-                details.y,
-                details.width,
-                details.height,
-                bool_to_int(details.open),
-                details.dbid
-            ],
-        )?;
-
-        Ok(())
-    }
-
-    /** Initialize the settings table, inserting a record if it doesn't exist.  */
-    fn initialize_settings_if_needed(&self) -> Result<(), String> {
-        let result = || -> Result<(), rusqlite::Error> {
-            let mut needs_create = true;
-
-            let mut statement = self.connection.prepare("SELECT count(*) from settings")?;
-
-            let mut query = statement.query(params![])?;
-            if let Some(row) = query.next()? {
-                let c: i64 = row.get(0)?;
-                needs_create = c == 0;
-            }
-
-            if needs_create {
-                self.connection.execute(
-                    "INSERT INTO settings (playing_story_id) VALUES (null)",
-                    params![],
-                )?;
-            }
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    /** Store id of currently played story, or if None mark no story being played */
-    pub fn store_current_story(&self, story_id: Option<i64>) -> Result<(), String> {
-        self.initialize_settings_if_needed()?;
-
-        let result = || -> Result<(), rusqlite::Error> {
-            self.connection.execute(
-                "UPDATE settings set playing_story_id = ?1",
-                params![story_id],
-            )?;
-
-            Ok(())
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(()) => Ok(()),
-        }
-    }
-
-    /** Return the id for the currently played story, if any */
-    pub fn get_current_story(&self) -> Result<Option<i64>, String> {
-        let result = || -> Result<Option<i64>, rusqlite::Error> {
-            let mut statement = self
-                .connection
-                .prepare("SELECT playing_story_id from settings")?;
-
-            let mut query = statement.query(params![])?;
-
-            if let Some(row) = query.next()? {
-                let id: Option<i64> = row.get(0)?;
-                Ok(id)
-            } else {
-                Ok(None)
-            }
-        }();
-
-        match result {
-            Err(e) => Err(format!("SQL error: {:?}", e)),
-            Ok(id) => Ok(id),
-        }
-    }
-
-    ///
-    /// Migrations
-    ///
-    /// Run migrations to make sure this database is up to sync.
-    pub fn migrate(&self) -> Result<()> {
-        // See if migration table exists
-        let mut statement = self
-            .connection
-            .prepare("SELECT name FROM sqlite_master WHERE type='table' AND name= ?1")?;
-        let mut rows = statement.query(params![MIGRATION_TABLE_NAME])?;
-        if rows.next()?.is_none() {
-            println!("Creating migration table");
-            self.create_migration_table()?;
-        }
-
-        // Create map of migrations to identify which migrations need to be run
-        let mut migrations: HashMap<String, bool> = HashMap::new();
-        let mut statement = self
-            .connection
-            .prepare("SELECT name FROM migrations ORDER BY name ASC")?;
-        let rows = statement.query_map(NO_PARAMS, |row| row.get(0))?;
-        for name in rows {
-            migrations.insert(name?, true);
-        }
-
-        if !migrations.contains_key(MIGRATION_1) {
-            self.run_migration_1()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_2) {
-            self.run_migration_2()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_3) {
-            self.run_migration_3()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_4) {
-            self.run_migration_4()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_5) {
-            self.run_migration_5()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_6) {
-            self.run_migration_6()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_7) {
-            self.run_migration_7()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_8) {
-            self.run_migration_8()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_9) {
-            self.run_migration_9()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_10) {
-            self.run_migration_10()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_11) {
-            self.run_migration_11()?;
-        }
-
-        if !migrations.contains_key(MIGRATION_12) {
-            self.run_migration_12()?;
-        }
-
-        Ok(())
+        // VACUUM INTO writes a consistent copy without closing the connection
+        self.connection
+            .execute("VACUUM INTO ?1", params![backup_path])?;
+        Ok(Some(backup_path))
     }
 
     fn create_migration_table(&self) -> Result<()> {
@@ -2909,7 +3030,7 @@ impl IfdbConnection {
         Ok(())
     }
 
-    fn run_migration_1(&self) -> Result<()> {
+    fn run_migration_1(&self) -> Result<Vec<String>> {
         self.connection.execute(
             "CREATE TABLE story (
         id INTEGER PRIMARY KEY,
@@ -2977,310 +3098,741 @@ impl IfdbConnection {
             params![],
         )?;
         self.connection.execute(
-            "CREATE TABLE saves (
-        id INTEGER PRIMARY KEY,
-        ifid TEXT NOT NULL,
-        name TEXT NOT NULL,
-        save_type TEXT NOT NULL,
-        saved_when TEXT,
-        data BLOB, 
-        pc INTEGER NULL, 
-        text_buffer_address INTEGER NULL, 
-        parse_buffer_address INTEGER NULL, 
-        next_pc INTEGER NULL, 
-        room_id INTEGER NOT NULL,
-        save_group_id INTEGER NOT NULL,
-        left_status INTEGER NULL, 
-        right_status INTEGER NULL, 
-        latest_text INTEGER NULL);",
-            params![],
+            "CREATE TABLE saves (
+        id INTEGER PRIMARY KEY,
+        ifid TEXT NOT NULL,
+        name TEXT NOT NULL,
+        save_type TEXT NOT NULL,
+        saved_when TEXT,
+        data BLOB, 
+        pc INTEGER NULL, 
+        text_buffer_address INTEGER NULL, 
+        parse_buffer_address INTEGER NULL, 
+        next_pc INTEGER NULL, 
+        room_id INTEGER NOT NULL,
+        save_group_id INTEGER NOT NULL,
+        left_status INTEGER NULL, 
+        right_status INTEGER NULL, 
+        latest_text INTEGER NULL);",
+            params![],
+        )?;
+        self.connection.execute(
+            "CREATE UNIQUE INDEX save_name_idx ON saves(ifid, name);",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "CREATE TABLE session (
+        id INTEGER PRIMARY KEY,
+        ifid TEXT NOT NULL unique,
+        tools_open INTEGER,
+        details_open INTEGER,
+        debug_open INTEGER,
+        transcript_name TEXT,
+        transcript_active INTEGER,
+        command_out_name TEXT,
+        command_out_active INTEGER, 
+        saves_open INTEGER NULL,
+        clues_open INTEGER NULL,
+         notes_open INTEGER NULL, 
+         map_open INTEGER NULL, 
+         last_clue_section TEXT default '');",
+            params![],
+        )?;
+        self.connection.execute(
+            "CREATE TABLE clue (
+        id INTEGER PRIMARY KEY,
+        subsection_id INTEGER not null,
+        text TEXT NOT NULL,
+        revealed INTEGER NOT NULL);",
+            params![],
+        )?;
+        self.connection.execute(
+            "CREATE TABLE clue_subsection (
+        id INTEGER PRIMARY KEY,
+        section_id INTEGER not null,
+        name TEXT NOT NULL,
+        UNIQUE(section_id, name))",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "CREATE TABLE clue_section (
+        id INTEGER PRIMARY KEY,
+        story_id INTEGER not null,
+        name TEXT NOT NULL,
+        UNIQUE(story_id, name))",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "CREATE TABLE map_room (
+  id INTEGER PRIMARY KEY,
+  story_id INTEGER not null,
+  room_id INTEGER not null,
+  name TEXT not null
+);",
+            params![],
+        )?;
+        self.connection.execute(
+            "CREATE TABLE notes (
+  id INTEGER PRIMARY KEY,
+  story_id INTEGER not null,
+  room_id INTEGER not null,
+  notes TEXT not null
+););",
+            params![],
+        )?;
+        self.connection.execute(
+            "CREATE TABLE map_connection (
+  id INTEGER PRIMARY KEY,
+  map_id INTEGER not null,
+  to_room_id INTEGER not null,
+  from_room_id INTEGER not null,
+  direction TEXT not null,
+  reverse_direction TEXT not null,
+  notes TEXT null,
+  UNIQUE (map_id, to_room_id, direction)
+);",
+            params![],
+        )?;
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_1],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_2(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE map_room ADD COLUMN disconnected integer not null default 0 ",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_2],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_3(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE saves RENAME COLUMN save_group_id TO parent_id ",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_3],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_4(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE notes ADD COLUMN done INTEGER not null default 0 ",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_4],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_5(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE story ADD COLUMN time_played INTEGER not null default 0",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "ALTER TABLE story ADD COLUMN last_played TEXT null ",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_5],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_6(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "CREATE TABLE window_details (
+  id INTEGER PRIMARY KEY,
+  story_id INTEGER not null,
+  window_type TEXT not null,
+  x REAL not null,
+  y REAL not null,
+  width REAL not null,
+  height REAL not null,
+  open INTEGER not null,
+  UNIQUE (story_id, window_type)
+);",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_6],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_7(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "CREATE TABLE settings (
+                playing_story_id INTEGER null
+            );",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_7],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_8(&self) -> Result<Vec<String>> {
+        // This migration added a column that is no longer needed. Can't drop columns
+        // in SQLlite, so just removed from setup here
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_8],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_9(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "CREATE TABLE fonts (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR not null,
+                data BLOB not null
+            );",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_9],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_10(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "CREATE TABLE themes (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR not null UNIQUE,
+                theme_type VARCHAR not null,
+                font_size INT not null,
+                font_id INT null,
+                background_color_r INT NULL,
+                background_color_g INT NULL,
+                background_color_b INT NULL,
+                background_color_a INT NULL,
+
+                text_color_r INT NULL,
+                text_color_g INT NULL,
+                text_color_b INT NULL,
+                text_color_a INT NULL,
+                
+                stroke_color_r INT NULL,
+                stroke_color_g INT NULL,
+                stroke_color_b INT NULL,
+                stroke_color_a INT NULL,
+
+                secondary_background_color_r INT NULL,
+                secondary_background_color_g INT NULL,
+                secondary_background_color_b INT NULL,
+                secondary_background_color_a INT NULL                  
+            );",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_10],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_11(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE fonts ADD COLUMN monospace NOT NULL DEFAULT 1;                  ",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_11],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_12(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE saves ADD COLUMN version NOT NULL DEFAULT 1; ",
+            params![],
+        )?;
+
+        self.connection
+            .execute("UPDATE saves SET version=1; ", params![])?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_12],
+        )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_13(&self) -> Result<Vec<String>> {
+        // IFIDs for story files were previously calculated incorrectly. Recalculate them
+        // and re-key any stories, saves and sessions using the old IFID
+        let mut statement = self.connection.prepare(
+            "SELECT id, story_id, ifid, story_data FROM story_ifid WHERE story_data IS NOT NULL",
+        )?;
+        let rows: Vec<(i64, i64, String, Vec<u8>)> = statement
+            .query_map(NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect::<Result<_>>()?;
+
+        let mut notes = vec![];
+        for (id, story_id, old_ifid, data) in rows {
+            let new_ifid = match extract_ifid_from_bytes(&data) {
+                Ok(ifid) if ifid != old_ifid => ifid,
+                _ => continue,
+            };
+
+            let existing: Option<(i64, i64, bool)> = self
+                .connection
+                .query_row(
+                    "SELECT id, story_id, story_data IS NOT NULL FROM story_ifid WHERE ifid = ?1",
+                    params![new_ifid],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?;
+            if let Some((existing_id, existing_story_id, has_data)) = existing {
+                if has_data {
+                    notes.push(format!(
+                        "The story file with IFID {} was left as is because another story file already uses its correct IFID {}.",
+                        old_ifid, new_ifid
+                    ));
+                    continue;
+                }
+                // Row was added from ifiction data with no story file. That story has the
+                // details, so the story file moves into it, replacing the row
+                self.connection
+                    .execute("DELETE FROM story_ifid WHERE id = ?1", params![existing_id])?;
+                if existing_story_id != story_id {
+                    self.connection.execute(
+                        "UPDATE story_ifid SET story_id = ?1 WHERE id = ?2",
+                        params![existing_story_id, id],
+                    )?;
+                    self.merge_migrated_story(story_id, existing_story_id)?;
+                }
+            }
+
+            self.connection.execute(
+                "UPDATE story_ifid SET ifid = ?1 WHERE id = ?2",
+                params![new_ifid, id],
+            )?;
+            self.connection.execute(
+                "UPDATE saves SET ifid = ?1 WHERE ifid = ?2",
+                params![new_ifid, old_ifid],
+            )?;
+            self.connection.execute(
+                "UPDATE session SET ifid = ?1 WHERE ifid = ?2",
+                params![new_ifid, old_ifid],
+            )?;
+        }
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_13],
+        )?;
+
+        Ok(notes)
+    }
+
+    /// Move what was recorded while playing a story created for a story file into the story
+    /// the file now belongs to, then delete the story if it has no files left. Only uses the
+    /// tables that existed when migration 13 was written
+    fn merge_migrated_story(&self, from_story_id: i64, into_story_id: i64) -> Result<()> {
+        let remaining: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM story_ifid WHERE story_id = ?1",
+            params![from_story_id],
+            |row| row.get(0),
+        )?;
+        if remaining > 0 {
+            return Ok(());
+        }
+
+        self.connection.execute(
+            "UPDATE story SET
+                time_played = time_played + (SELECT time_played FROM story WHERE id = ?1),
+                last_played = COALESCE(last_played, (SELECT last_played FROM story WHERE id = ?1))
+            WHERE id = ?2",
+            params![from_story_id, into_story_id],
+        )?;
+        for sql in &[
+            "UPDATE notes SET story_id = ?2 WHERE story_id = ?1",
+            "UPDATE map_room SET story_id = ?2 WHERE story_id = ?1",
+            "UPDATE OR IGNORE clue_section SET story_id = ?2 WHERE story_id = ?1",
+            "UPDATE OR IGNORE window_details SET story_id = ?2 WHERE story_id = ?1",
+            "UPDATE settings SET playing_story_id = ?2 WHERE playing_story_id = ?1",
+        ] {
+            self.connection
+                .execute(sql, params![from_story_id, into_story_id])?;
+        }
+
+        // Whatever could not be moved belongs to the story being deleted
+        for sql in &[
+            "DELETE FROM clue WHERE subsection_id IN (SELECT b.id FROM clue_subsection b, clue_section c WHERE b.section_id = c.id AND c.story_id = ?1)",
+            "DELETE FROM clue_subsection WHERE section_id IN (SELECT id FROM clue_section WHERE story_id = ?1)",
+            "DELETE FROM clue_section WHERE story_id = ?1",
+            "DELETE FROM window_details WHERE story_id = ?1",
+            "DELETE FROM story_resource WHERE story_id = ?1",
+            "DELETE FROM story_release WHERE story_id = ?1",
+            "DELETE FROM story_zcode WHERE story_id = ?1",
+            "DELETE FROM story WHERE id = ?1",
+        ] {
+            self.connection.execute(sql, params![from_story_id])?;
+        }
+
+        Ok(())
+    }
+
+    fn run_migration_14(&self) -> Result<Vec<String>> {
+        self.connection.execute(
+            "ALTER TABLE settings ADD COLUMN backup_directory TEXT NULL",
+            params![],
+        )?;
+
+        self.connection.execute(
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_14],
         )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_15(&self) -> Result<Vec<String>> {
+        // Full text index over story details. rowid is the story id
         self.connection.execute(
-            "CREATE UNIQUE INDEX save_name_idx ON saves(ifid, name);",
+            "CREATE VIRTUAL TABLE story_fts USING fts5 (
+                title,
+                author,
+                headline,
+                genre,
+                series,
+                description,
+                prefix = '2 3',
+                tokenize = 'unicode61 remove_diacritics 2'
+            )",
             params![],
         )?;
 
         self.connection.execute(
-            "CREATE TABLE session (
-        id INTEGER PRIMARY KEY,
-        ifid TEXT NOT NULL unique,
-        tools_open INTEGER,
-        details_open INTEGER,
-        debug_open INTEGER,
-        transcript_name TEXT,
-        transcript_active INTEGER,
-        command_out_name TEXT,
-        command_out_active INTEGER, 
-        saves_open INTEGER NULL,
-        clues_open INTEGER NULL,
-         notes_open INTEGER NULL, 
-         map_open INTEGER NULL, 
-         last_clue_section TEXT default '');",
+            "INSERT INTO story_fts (rowid, title, author, headline, genre, series, description)
+            SELECT id, bibliographic_title, bibliographic_author, bibliographic_headline,
+            bibliographic_genre, bibliographic_series, bibliographic_description FROM story",
             params![],
         )?;
+
         self.connection.execute(
-            "CREATE TABLE clue (
-        id INTEGER PRIMARY KEY,
-        subsection_id INTEGER not null,
-        text TEXT NOT NULL,
-        revealed INTEGER NOT NULL);",
-            params![],
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_15],
         )?;
+
+        Ok(vec![])
+    }
+
+    fn run_migration_16(&self) -> Result<Vec<String>> {
+        // Existing stories have no date added and sort after new ones
         self.connection.execute(
-            "CREATE TABLE clue_subsection (
-        id INTEGER PRIMARY KEY,
-        section_id INTEGER not null,
-        name TEXT NOT NULL,
-        UNIQUE(section_id, name))",
+            "ALTER TABLE story ADD COLUMN date_added TIMESTAMP NULL",
             params![],
         )?;
 
+        for column in &[
+            "story_sort",
+            "filter_genre",
+            "filter_language",
+            "filter_forgiveness",
+            "filter_series",
+        ] {
+            self.connection.execute(
+                format!("ALTER TABLE settings ADD COLUMN {} TEXT NULL", column).as_str(),
+                params![],
+            )?;
+        }
+
         self.connection.execute(
-            "CREATE TABLE clue_section (
-        id INTEGER PRIMARY KEY,
-        story_id INTEGER not null,
-        name TEXT NOT NULL,
-        UNIQUE(story_id, name))",
-            params![],
+            "INSERT INTO migrations (name) VALUES (?1)",
+            params![MIGRATION_16],
         )?;
 
+        Ok(vec![])
+    }
+
+    fn run_migration_17(&self) -> Result<Vec<String>> {
         self.connection.execute(
-            "CREATE TABLE map_room (
-  id INTEGER PRIMARY KEY,
-  story_id INTEGER not null,
-  room_id INTEGER not null,
-  name TEXT not null
-);",
+            "CREATE TABLE tag (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE
+            )",
             params![],
         )?;
+
         self.connection.execute(
-            "CREATE TABLE notes (
-  id INTEGER PRIMARY KEY,
-  story_id INTEGER not null,
-  room_id INTEGER not null,
-  notes TEXT not null
-););",
+            "CREATE TABLE story_tag (
+                story_id INTEGER NOT NULL,
+                tag_id INTEGER NOT NULL,
+                PRIMARY KEY (story_id, tag_id)
+            )",
             params![],
         )?;
+
+        // Query is null for shelves that stories are added to by hand
         self.connection.execute(
-            "CREATE TABLE map_connection (
-  id INTEGER PRIMARY KEY,
-  map_id INTEGER not null,
-  to_room_id INTEGER not null,
-  from_room_id INTEGER not null,
-  direction TEXT not null,
-  reverse_direction TEXT not null,
-  notes TEXT null,
-  UNIQUE (map_id, to_room_id, direction)
-);",
+            "CREATE TABLE shelf (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                query TEXT NULL
+            )",
             params![],
         )?;
+
         self.connection.execute(
-            "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_1],
+            "CREATE TABLE shelf_story (
+                shelf_id INTEGER NOT NULL,
+                story_id INTEGER NOT NULL,
+                PRIMARY KEY (shelf_id, story_id)
+            )",
+            params![],
         )?;
 
-        Ok(())
-    }
+        self.connection.execute(
+            "ALTER TABLE settings ADD COLUMN filter_tag TEXT NULL",
+            params![],
+        )?;
 
-    fn run_migration_2(&self) -> Result<()> {
         self.connection.execute(
-            "ALTER TABLE map_room ADD COLUMN disconnected integer not null default 0 ",
+            "ALTER TABLE settings ADD COLUMN filter_shelf_id INTEGER NULL",
             params![],
         )?;
 
+        for (name, query) in default_shelves() {
+            self.connection.execute(
+                "INSERT INTO shelf (name, query) VALUES (?1, ?2)",
+                params![name, serde_json::to_string(&query).unwrap()],
+            )?;
+        }
+
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_2],
+            params![MIGRATION_17],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_3(&self) -> Result<()> {
+    fn run_migration_18(&self) -> Result<Vec<String>> {
         self.connection.execute(
-            "ALTER TABLE saves RENAME COLUMN save_group_id TO parent_id ",
+            "ALTER TABLE story ADD COLUMN play_status TEXT NOT NULL DEFAULT 'Unplayed'",
             params![],
         )?;
-
         self.connection.execute(
-            "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_3],
+            "ALTER TABLE story ADD COLUMN rating INTEGER NULL",
+            params![],
         )?;
+        self.connection
+            .execute("ALTER TABLE story ADD COLUMN review TEXT NULL", params![])?;
 
-        Ok(())
-    }
+        // Anything played already is at least in progress
+        self.connection.execute(
+            "UPDATE story SET play_status = ?1 WHERE last_played IS NOT NULL",
+            params![PlayStatus::InProgress.to_string()],
+        )?;
 
-    fn run_migration_4(&self) -> Result<()> {
         self.connection.execute(
-            "ALTER TABLE notes ADD COLUMN done INTEGER not null default 0 ",
+            "ALTER TABLE settings ADD COLUMN filter_play_status TEXT NULL",
             params![],
         )?;
 
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_4],
+            params![MIGRATION_18],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_5(&self) -> Result<()> {
+    fn run_migration_19(&self) -> Result<Vec<String>> {
         self.connection.execute(
-            "ALTER TABLE story ADD COLUMN time_played INTEGER not null default 0",
+            "CREATE TABLE imported_file (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                imported_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
             params![],
         )?;
 
         self.connection.execute(
-            "ALTER TABLE story ADD COLUMN last_played TEXT null ",
+            "CREATE TABLE watched_folder (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE
+            )",
             params![],
         )?;
 
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_5],
+            params![MIGRATION_19],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_6(&self) -> Result<()> {
+    fn run_migration_20(&self) -> Result<Vec<String>> {
+        // Contents of the auxiliary file, if it was found when the story was imported
         self.connection.execute(
-            "CREATE TABLE window_details (
-  id INTEGER PRIMARY KEY,
-  story_id INTEGER not null,
-  window_type TEXT not null,
-  x REAL not null,
-  y REAL not null,
-  width REAL not null,
-  height REAL not null,
-  open INTEGER not null,
-  UNIQUE (story_id, window_type)
-);",
+            "ALTER TABLE story_resource ADD COLUMN data BLOB NULL",
             params![],
         )?;
 
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_6],
+            params![MIGRATION_20],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_7(&self) -> Result<()> {
+    fn run_migration_21(&self) -> Result<Vec<String>> {
+        // Shrunken copies of covers for the gallery, made when a cover is stored
         self.connection.execute(
-            "CREATE TABLE settings (
-                playing_story_id INTEGER null
-            );",
+            "CREATE TABLE cover_thumbnail (
+                story_id INTEGER PRIMARY KEY,
+                data BLOB NOT NULL
+            )",
             params![],
         )?;
 
+        let story_ids = {
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM story WHERE cover_image IS NOT NULL")?;
+            let rows = statement.query_map(NO_PARAMS, |row| row.get::<_, u32>(0))?;
+            rows.collect::<Result<Vec<u32>>>()?
+        };
+        for story_id in story_ids {
+            let cover: Vec<u8> = self.connection.query_row(
+                "SELECT cover_image FROM story WHERE id = ?1",
+                params![story_id],
+                |row| row.get(0),
+            )?;
+            self.store_cover_thumbnail_sql(story_id, &cover)?;
+        }
+
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_7],
+            params![MIGRATION_21],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_8(&self) -> Result<()> {
-        // This migration added a column that is no longer needed. Can't drop columns
-        // in SQLlite, so just removed from setup here
+    fn run_migration_22(&self) -> Result<Vec<String>> {
+        // The release Play launches, for stories with more than one IFID. Null for the
+        // most recently imported
         self.connection.execute(
-            "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_8],
+            "ALTER TABLE story ADD COLUMN preferred_ifid TEXT NULL",
+            params![],
         )?;
 
-        Ok(())
-    }
-
-    fn run_migration_9(&self) -> Result<()> {
+        // The story list picks one release for each story
         self.connection.execute(
-            "CREATE TABLE fonts (
-                id INTEGER PRIMARY KEY,
-                name VARCHAR not null,
-                data BLOB not null
-            );",
+            "CREATE INDEX story_ifid_story_id ON story_ifid (story_id)",
             params![],
         )?;
 
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_9],
+            params![MIGRATION_22],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_10(&self) -> Result<()> {
+    fn run_migration_23(&self) -> Result<Vec<String>> {
+        // Changes made to a story other than through its details, such as replacing its
+        // story file. Recorded in UTC, like last_played
         self.connection.execute(
-            "CREATE TABLE themes (
+            "CREATE TABLE story_history (
                 id INTEGER PRIMARY KEY,
-                name VARCHAR not null UNIQUE,
-                theme_type VARCHAR not null,
-                font_size INT not null,
-                font_id INT null,
-                background_color_r INT NULL,
-                background_color_g INT NULL,
-                background_color_b INT NULL,
-                background_color_a INT NULL,
-
-                text_color_r INT NULL,
-                text_color_g INT NULL,
-                text_color_b INT NULL,
-                text_color_a INT NULL,
-                
-                stroke_color_r INT NULL,
-                stroke_color_g INT NULL,
-                stroke_color_b INT NULL,
-                stroke_color_a INT NULL,
-
-                secondary_background_color_r INT NULL,
-                secondary_background_color_g INT NULL,
-                secondary_background_color_b INT NULL,
-                secondary_background_color_a INT NULL                  
-            );",
+                story_id INTEGER NOT NULL,
+                ifid TEXT NULL,
+                recorded_when TIMESTAMP NOT NULL,
+                description TEXT NOT NULL
+            )",
             params![],
         )?;
 
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_10],
+            params![MIGRATION_23],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
-    fn run_migration_11(&self) -> Result<()> {
+    fn run_migration_24(&self) -> Result<Vec<String>> {
+        // Stories and autosaves in the trash are hidden rather than deleted. Recorded in
+        // UTC, like last_played
         self.connection.execute(
-            "ALTER TABLE fonts ADD COLUMN monospace NOT NULL DEFAULT 1;                  ",
+            "ALTER TABLE story ADD COLUMN deleted_when TIMESTAMP NULL",
             params![],
         )?;
-
         self.connection.execute(
-            "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_11],
+            "ALTER TABLE saves ADD COLUMN deleted_when TIMESTAMP NULL",
+            params![],
         )?;
 
-        Ok(())
-    }
-
-    fn run_migration_12(&self) -> Result<()> {
+        // Days items stay in the trash before being purged. Null keeps them until the
+        // trash is emptied
         self.connection.execute(
-            "ALTER TABLE saves ADD COLUMN version NOT NULL DEFAULT 1; ",
+            "ALTER TABLE settings ADD COLUMN trash_purge_days INTEGER NULL",
             params![],
         )?;
 
-        self.connection
-            .execute("UPDATE saves SET version=1; ", params![])?;
-
         self.connection.execute(
             "INSERT INTO migrations (name) VALUES (?1)",
-            params![MIGRATION_12],
+            params![MIGRATION_24],
         )?;
 
-        Ok(())
+        Ok(vec![])
     }
 
     ///
@@ -3291,14 +3843,19 @@ impl IfdbConnection {
         match extract_ifid_from_bytes(&contents) {
             Err(msg) => LoadFileResult::StoryFileFailureGeneral(filename.to_string(), msg),
             Ok(ifid_str) => {
-                if let Ok(Some(_)) = self.get_story_id_for_ifid(ifid_str.as_str(), true) {
-                    LoadFileResult::StoryFileFailureDuplicate(filename.to_string(), ifid_str)
+                if let Ok(Some(story_id)) = self.get_story_id_for_ifid(ifid_str.as_str(), true) {
+                    if let Ok(true) = self.is_story_trashed(story_id) {
+                        LoadFileResult::StoryFileFailureTrashed(filename.to_string(), ifid_str)
+                    } else {
+                        LoadFileResult::StoryFileFailureDuplicate(filename.to_string(), ifid_str)
+                    }
                 } else {
                     match self.add_story_data(ifid_str.as_str(), contents, filename) {
                         Ok(()) => LoadFileResult::StoryFileSuccess(filename.to_string(), ifid_str),
-                        Err(msg) => {
-                            LoadFileResult::StoryFileFailureGeneral(filename.to_string(), msg)
-                        }
+                        Err(msg) => LoadFileResult::StoryFileFailureGeneral(
+                            filename.to_string(),
+                            msg.to_string(),
+                        ),
                     }
                 }
             }
@@ -3316,18 +3873,78 @@ impl IfdbConnection {
         }
     }
 
-    /// Given a reference to a ZipFile, extract and load the story file.
-    fn load_story_from_zipfile(
-        &self,
-        zipfile: &mut ZipFile<'_>,
-        path_str: &str,
-        filename: &str,
-    ) -> LoadFileResult {
-        let mut contents = vec![];
-        if let Err(msg) = zipfile.read_to_end(&mut contents) {
-            LoadFileResult::StoryFileFailureGeneral(path_str.to_string(), format!("{}", msg))
-        } else {
-            self.load_story_file_from_bytes(contents, filename)
+    /// Load a blorb package from raw bytes. The ifiction metadata is loaded first so the
+    /// story record exists, then the story file, then the cover image for the story's IFID
+    fn load_blorb_from_bytes(&self, contents: Vec<u8>, filename: &str) -> Vec<LoadFileResult> {
+        let mut results = vec![];
+
+        match read_blorb(&contents) {
+            Err(msg) => results.push(LoadFileResult::BlorbFailure(filename.to_string(), msg)),
+            Ok(blorb) => {
+                if let Some(ifiction) = blorb.ifiction {
+                    results.append(
+                        &mut self
+                            .load_ifiction_from_reader(ifiction.as_slice(), filename.to_string()),
+                    );
+                }
+
+                match blorb.zcode {
+                    None => results.push(LoadFileResult::StoryFileFailureGeneral(
+                        filename.to_string(),
+                        String::from("No zcode story found in blorb"),
+                    )),
+                    Some(zcode) => {
+                        let story_result = self.load_story_file_from_bytes(zcode, filename);
+                        if let (LoadFileResult::StoryFileSuccess(_, ifid), Some(cover)) =
+                            (&story_result, blorb.cover)
+                        {
+                            let cover_result = match self.store_cover_image(ifid, cover) {
+                                Ok(()) => LoadFileResult::CoverImageSuccess(
+                                    filename.to_string(),
+                                    ifid.to_string(),
+                                ),
+                                Err(msg) => LoadFileResult::CoverImageFailure(
+                                    filename.to_string(),
+                                    msg.to_string(),
+                                ),
+                            };
+                            results.push(story_result);
+                            results.push(cover_result);
+                        } else {
+                            results.push(story_result);
+                        }
+                    }
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Given a path to a blorb package, extract and load the data
+    fn load_blorb_from_path(&self, path_str: &str, filename: &str) -> Vec<LoadFileResult> {
+        match fs::read(Path::new(path_str)) {
+            Ok(contents) => self.load_blorb_from_bytes(contents, filename),
+            Err(msg) => vec![LoadFileResult::BlorbFailure(
+                filename.to_string(),
+                format!("{}", msg),
+            )],
+        }
+    }
+
+    /// Load a Quetzal save from raw bytes
+    fn load_save_from_bytes(&self, contents: Vec<u8>, filename: &str) -> LoadFileResult {
+        match self.import_quetzal_save(contents, filename) {
+            Ok(ifid) => LoadFileResult::SaveSuccess(filename.to_string(), ifid),
+            Err(msg) => LoadFileResult::SaveFailure(filename.to_string(), msg.to_string()),
+        }
+    }
+
+    /// Given a path to a Quetzal save, load it. Filename is used as the save name
+    fn load_save_from_path(&self, path_str: &str, filename: &str) -> LoadFileResult {
+        match fs::read(Path::new(path_str)) {
+            Ok(contents) => self.load_save_from_bytes(contents, filename),
+            Err(msg) => LoadFileResult::SaveFailure(filename.to_string(), format!("{}", msg)),
         }
     }
 
@@ -3348,7 +3965,9 @@ impl IfdbConnection {
                 Ok(()) => {
                     LoadFileResult::CoverImageSuccess(filename.to_string(), ifid_str.to_string())
                 }
-                Err(msg) => LoadFileResult::CoverImageFailure(filename.to_string(), msg),
+                Err(msg) => {
+                    LoadFileResult::CoverImageFailure(filename.to_string(), msg.to_string())
+                }
             }
         }
     }
@@ -3362,22 +3981,6 @@ impl IfdbConnection {
         }
     }
 
-    /// Given a reference to a ZipFile, extract and load the data. Filename is assumed to be
-    /// the matching ifid
-    fn load_cover_image_from_zipfile(
-        &self,
-        zipfile: &mut ZipFile<'_>,
-        path_str: &str,
-        filename: &str,
-    ) -> LoadFileResult {
-        let mut contents = vec![];
-        if let Err(msg) = zipfile.read_to_end(&mut contents) {
-            LoadFileResult::CoverImageFailure(path_str.to_string(), format!("{}", msg))
-        } else {
-            self.load_cover_image_from_bytes(contents, filename)
-        }
-    }
-
     /// Load clues from json data
     fn load_clues_from_reader(&self, reader: impl Read, path: String) -> Vec<LoadFileResult> {
         let mut results = vec![];
@@ -3417,24 +4020,10 @@ impl IfdbConnection {
                                                                         ) = &subsection["name"]
                                                                         {
                                                                             for clue in clues {
-                                                                                // Database can be locked if multiple clues being loaded at once. Retry in those cases
-                                                                                let mut retry_count = 0;
-                                                                                let mut loaded = false;
-                                                                                while !loaded && retry_count < LOCK_RETRIES {
-                                                                                    if let Err(msg) = self.add_clue(story_id,section_name.to_string(),subsection_name.to_string(),format!("{}",clue)) {
-                                                                                        if msg.contains("locked") {
-                                                                                            retry_count += 1;
-                                                                                            thread::sleep(RETRY_DELAY);
-                                                                                            if retry_count == LOCK_RETRIES {                                                                                                
-                                                                                                results.push(LoadFileResult::ClueFailure(path.clone(),format!("Error loading clue for IFID {}: {}",ifid,msg)));
-                                                                                            }
-                                                                                        } else {
-                                                                                            results.push(LoadFileResult::ClueFailure(path.clone(),format!("Error loading clue for IFID {}: {}",ifid,msg)));
-                                                                                        }
-                                                                                    } else {
-                                                                                        clues_added+=1;
-                                                                                        loaded = true;
-                                                                                    }
+                                                                                if let Err(msg) = self.add_clue(story_id,section_name.to_string(),subsection_name.to_string(),format!("{}",clue)) {
+                                                                                    results.push(LoadFileResult::ClueFailure(path.clone(),format!("Error loading clue for IFID {}: {}",ifid,msg)));
+                                                                                } else {
+                                                                                    clues_added+=1;
                                                                                 }
                                                                             }
                                                                         }
@@ -3505,8 +4094,10 @@ impl IfdbConnection {
                                 ));
                             }
                             Err(msg) => {
-                                results
-                                    .push(LoadFileResult::IFictionStoryFailure(path.clone(), msg));
+                                results.push(LoadFileResult::IFictionStoryFailure(
+                                    path.clone(),
+                                    msg.to_string(),
+                                ));
                             }
                         },
                         Err(msg) => {
@@ -3540,138 +4131,445 @@ impl IfdbConnection {
         }
     }
 
-    /// Given a reference to a ZipFile, extract and load the clues data. Filename is assumed to be
-    /// the matching ifid
-    fn load_clues_from_zipfile(
+    /// Load a file extracted from an archive
+    fn load_archive_file(
         &self,
-        zipfile: &mut ZipFile<'_>,
-        path_str: &str,
+        filetype: SupportedFiletype,
+        file: ArchiveFile,
     ) -> Vec<LoadFileResult> {
-        self.load_clues_from_reader(BufReader::new(zipfile), path_str.to_string())
+        let path = Path::new(&file.path);
+        let filename = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .unwrap_or("no_filename");
+
+        match filetype {
+            SupportedFiletype::Ifiction => {
+                self.load_ifiction_from_reader(file.data.as_slice(), file.path.clone())
+            }
+            SupportedFiletype::Story if is_blorb(path) => {
+                self.load_blorb_from_bytes(file.data, filename)
+            }
+            SupportedFiletype::Story => vec![self.load_story_file_from_bytes(file.data, filename)],
+            SupportedFiletype::Cover => vec![self.load_cover_image_from_bytes(file.data, filename)],
+            SupportedFiletype::Clues => {
+                self.load_clues_from_reader(file.data.as_slice(), filename.to_string())
+            }
+            SupportedFiletype::Save => vec![self.load_save_from_bytes(file.data, filename)],
+        }
     }
 
-    /// Given a reference to a ZipFile, extract and load the ifiction data. Filename is assumed to be
-    /// the matching ifid
-    fn load_ifiction_from_zipfile(
+    /// Load all files in an archive (zip, tar or gzip), including any archives nested inside it
+    fn load_archive_from_path<F: Fn(LoadFileResult)>(&self, path: &str, loaded_callback: F) {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(msg) => {
+                loaded_callback(LoadFileResult::ZipfileFailure(
+                    path.to_string(),
+                    format!("{:?}", msg),
+                ));
+                return;
+            }
+        };
+
+        // Anything extracted before a limit is hit is still loaded
+        let mut reader = ArchiveReader::new(&ARCHIVE_LIMITS);
+        if let Err(msg) = reader.read_archive(&data, extract_filename_or_use_original(path), 0) {
+            if reader.limit_hit {
+                loaded_callback(LoadFileResult::ArchivePartFailure(path.to_string(), msg));
+            } else {
+                loaded_callback(LoadFileResult::ZipfileFailure(path.to_string(), msg));
+            }
+        }
+        for failure in reader.failures.drain(..) {
+            loaded_callback(failure);
+        }
+
+        let mut files = vec![];
+        let mut unsupported = vec![];
+        for file in reader.files {
+            match importable_filetype(Path::new(&file.path)) {
+                Some(filetype) => files.push((filetype, file)),
+                None => unsupported.push(file),
+            }
+        }
+
+        // Files are matched across the whole archive, loading in order so data is there for
+        // subsequent data. See IMPORT_ORDER
+        files.sort_by_key(|(filetype, _)| IMPORT_ORDER.iter().position(|f| f == filetype));
+
+        // Stories are loaded first, so any of the other files that are auxiliary files listed in
+        // their ifiction can be stored with them instead of loaded
+        let (story_files, other_files): (Vec<_>, Vec<_>) =
+            files.into_iter().partition(|(filetype, _)| {
+                matches!(
+                    filetype,
+                    SupportedFiletype::Ifiction | SupportedFiletype::Story
+                )
+            });
+        let mut ifids = vec![];
+        for (filetype, file) in story_files {
+            for result in self.load_archive_file(filetype, file) {
+                if let LoadFileResult::StoryFileSuccess(_, ifid)
+                | LoadFileResult::StoryFileFailureDuplicate(_, ifid)
+                | LoadFileResult::StoryFileFailureTrashed(_, ifid) = &result
+                {
+                    ifids.push(ifid.clone());
+                }
+                loaded_callback(result);
+            }
+        }
+
+        let candidates: Vec<&ArchiveFile> = other_files
+            .iter()
+            .map(|(_, file)| file)
+            .chain(unsupported.iter())
+            .collect();
+        let stored = self.store_resource_files(&ifids, &candidates, &loaded_callback);
+
+        for (filetype, file) in other_files {
+            if !stored.contains(&file.path) {
+                for result in self.load_archive_file(filetype, file) {
+                    loaded_callback(result);
+                }
+            }
+        }
+        for file in unsupported {
+            if !stored.contains(&file.path) {
+                loaded_callback(LoadFileResult::UnsupportedFormat(file.path));
+            }
+        }
+    }
+
+    /// Store the data for any auxiliary files of the stories with the given ifids that are in
+    /// files. Returns the paths of the files stored
+    fn store_resource_files<F: Fn(LoadFileResult)>(
+        &self,
+        ifids: &[String],
+        files: &[&ArchiveFile],
+        loaded_callback: &F,
+    ) -> Vec<String> {
+        let mut stored = vec![];
+        for ifid in ifids {
+            let resources = match self.get_story_id_for_ifid(ifid, false) {
+                Ok(Some(story_id)) => self.fetch_story_resources(story_id),
+                Ok(None) => Ok(vec![]),
+                Err(msg) => Err(msg),
+            };
+            let resources = match resources {
+                Ok(resources) => resources,
+                Err(msg) => {
+                    loaded_callback(LoadFileResult::ResourceFailure(
+                        ifid.clone(),
+                        msg.to_string(),
+                    ));
+                    continue;
+                }
+            };
+
+            // Resources already stored are kept, such as when a story is imported again
+            for resource in resources.iter().filter(|r| r.size.is_none()) {
+                if let Some(file) = files
+                    .iter()
+                    .find(|f| resource_matches_path(&resource.leafname, &f.path))
+                {
+                    loaded_callback(match self.store_resource_data(resource.dbid, &file.data) {
+                        Ok(()) => {
+                            stored.push(file.path.clone());
+                            LoadFileResult::ResourceSuccess(
+                                file.path.clone(),
+                                resource.leafname.clone(),
+                            )
+                        }
+                        Err(msg) => {
+                            LoadFileResult::ResourceFailure(file.path.clone(), msg.to_string())
+                        }
+                    });
+                }
+            }
+        }
+
+        stored
+    }
+
+    /// Loads content into a database from a path. Path can be an archive, a directory, or the
+    /// actual file. A file or archive is imported in a single transaction, and nothing is kept
+    /// if it hits a fatal error. Each file in a directory gets its own transaction, so one
+    /// broken file does not undo the rest
+    pub fn import_file<F: Fn(LoadFileResult)>(
+        &self,
+        path_str: &str,
+        filename_override: Option<String>,
+        loaded_callback: F,
+    ) {
+        self.import_in_transaction(path_str, filename_override, false, loaded_callback);
+    }
+
+    /// Run an import without saving anything, returning what would be loaded
+    pub fn preview_import(
         &self,
-        zipfile: &mut ZipFile<'_>,
         path_str: &str,
+        filename_override: Option<String>,
     ) -> Vec<LoadFileResult> {
-        self.load_ifiction_from_reader(BufReader::new(zipfile), path_str.to_string())
+        let results = RefCell::new(vec![]);
+        self.import_in_transaction(path_str, filename_override, true, |result| {
+            results.borrow_mut().push(result)
+        });
+        results.into_inner()
     }
 
-    /// Load all files in a zipfile
-    fn load_zipfile_from_path<F: Fn(LoadFileResult)>(
+    /// Import a path in a transaction. It is rolled back after a dry run, or if any
+    /// fatal errors are reported. A directory is imported a file at a time, unless it is a
+    /// dry run, which keeps everything in one transaction so later files can see earlier ones
+    fn import_in_transaction<F: Fn(LoadFileResult)>(
         &self,
-        path: &str,
+        path_str: &str,
+        filename_override: Option<String>,
+        dry_run: bool,
         loaded_callback: F,
-    ) -> Vec<LoadFileResult> {
-        let results = vec![];
-        let filetypes = [
-            SupportedFiletype::Ifiction,
-            SupportedFiletype::Story,
-            SupportedFiletype::Cover,
-            SupportedFiletype::Clues,
-        ];
+    ) {
+        if !dry_run && Path::new(path_str).is_dir() {
+            self.import_directory(path_str, true, &loaded_callback);
+        } else {
+            self.with_import_transaction(path_str, dry_run, &loaded_callback, |callback| {
+                self.import_path(path_str, filename_override, callback)
+            });
+        }
+    }
 
-        match fs::File::open(&path) {
-            Ok(file) => match zip::ZipArchive::new(file) {
-                Ok(mut archive) => {
-                    // Loop looking for files in a specific order. IFiction files should be loaded before story files,
-                    // loaded before image files, loaded before clue files. This ensures any data is there for subseuqent data
-                    for filetype in filetypes.iter() {
-                        for i in 0..archive.len() {
-                            if i > MAX_SUPPORTED_ZIPFILE_SIZE {
-                                loaded_callback(LoadFileResult::ZipfileFailure(
-                                    path.to_string(),
-                                    format!(
-                                        "Hit maximum supported number of files of {}",
-                                        MAX_SUPPORTED_ZIPFILE_SIZE
-                                    ),
-                                ));
-                                break;
-                            }
+    /// Run an import in a transaction, rolling it back after a dry run or a fatal error.
+    /// The transaction holds the write lock, so it should cover as little as possible
+    fn with_import_transaction<F, I>(
+        &self,
+        path_str: &str,
+        dry_run: bool,
+        loaded_callback: &F,
+        import: I,
+    ) where
+        F: Fn(LoadFileResult),
+        I: FnOnce(&dyn Fn(LoadFileResult)),
+    {
+        let transaction = match self.connection.unchecked_transaction() {
+            Ok(transaction) => transaction,
+            Err(e) => {
+                loaded_callback(LoadFileResult::ImportRolledBack(
+                    path_str.to_string(),
+                    IfdbError::from(e).to_string(),
+                ));
+                return;
+            }
+        };
 
-                            match archive.by_index(i) {
-                                Ok(mut file) => {
-                                    let tmpname = file.name().to_string();
-                                    let path_str = tmpname.as_str();
-                                    let path = Path::new(path_str);
-
-                                    let filename = path
-                                        .file_stem()
-                                        .and_then(OsStr::to_str)
-                                        .unwrap_or("no_filename");
-
-                                    if let Some(ext) = path.extension().and_then(OsStr::to_str) {
-                                        match ext {
-                                            "xml" | "ifiction" => {
-                                                if *filetype == SupportedFiletype::Ifiction {
-                                                    for result in self.load_ifiction_from_zipfile(
-                                                        &mut file, path_str,
-                                                    ) {
-                                                        loaded_callback(result);
-                                                    }
-                                                }
-                                            }
-                                            "png" | "jpg" | "jpeg" => {
-                                                if *filetype == SupportedFiletype::Cover {
-                                                    loaded_callback(
-                                                        self.load_cover_image_from_zipfile(
-                                                            &mut file, path_str, filename,
-                                                        ),
-                                                    );
-                                                }
-                                            }
-                                            "json" => {
-                                                if *filetype == SupportedFiletype::Clues {
-                                                    for result in self.load_clues_from_zipfile(
-                                                        &mut file, filename,
-                                                    ) {
-                                                        loaded_callback(result);
-                                                    }
-                                                }
-                                            }
-                                            "z1" | "z2" | "z3" | "z4" | "z5" | "z6" | "z7"
-                                            | "z8" => {
-                                                if *filetype == SupportedFiletype::Story {
-                                                    loaded_callback(self.load_story_from_zipfile(
-                                                        &mut file, path_str, filename,
-                                                    ));
-                                                }
-                                            }
-                                            _ => {
-                                                loaded_callback(LoadFileResult::UnsupportedFormat(
-                                                    path_str.to_string(),
-                                                ))
-                                            }
-                                        };
-                                    }
-                                }
-                                Err(msg) => loaded_callback(LoadFileResult::ZipfileFailure(
-                                    path.to_string(),
-                                    format!("{:?}", msg),
-                                )),
-                            }
-                        }
+        let fatal_error = RefCell::new(None);
+        import(&|result| {
+            if result.is_fatal() && fatal_error.borrow().is_none() {
+                *fatal_error.borrow_mut() = Some(result.to_string());
+            }
+            loaded_callback(result);
+        });
+
+        let result = match (dry_run, fatal_error.into_inner()) {
+            (true, _) => transaction.rollback(),
+            (false, Some(msg)) => {
+                loaded_callback(LoadFileResult::ImportRolledBack(path_str.to_string(), msg));
+                transaction.rollback()
+            }
+            (false, None) => transaction.commit(),
+        };
+        // Anything read during the import may have been rolled back
+        self.cache.clear();
+        if let Err(e) = result {
+            loaded_callback(LoadFileResult::ImportRolledBack(
+                path_str.to_string(),
+                IfdbError::from(e).to_string(),
+            ));
+        }
+    }
+
+    fn import_path<F: Fn(LoadFileResult)>(
+        &self,
+        path_str: &str,
+        filename_override: Option<String>,
+        loaded_callback: F,
+    ) {
+        if Path::new(path_str).is_dir() {
+            self.import_directory(path_str, false, &loaded_callback);
+        } else {
+            self.import_single_file(path_str, filename_override, loaded_callback);
+        }
+    }
+
+    /// Import every supported file under a directory, including subdirectories. Files already
+    /// imported are skipped unless they have changed since. With commit_each_file, each file
+    /// is imported in its own transaction
+    fn import_directory<F: Fn(LoadFileResult)>(
+        &self,
+        path_str: &str,
+        commit_each_file: bool,
+        loaded_callback: &F,
+    ) {
+        let mut files = vec![];
+        if let Err(msg) = find_importable_files(Path::new(path_str), 0, &mut files) {
+            loaded_callback(LoadFileResult::DirectoryFailure(path_str.to_string(), msg));
+            return;
+        }
+
+        files.sort_by_key(|(filetype, path)| {
+            (
+                IMPORT_ORDER.iter().position(|f| f == filetype),
+                path.clone(),
+            )
+        });
+
+        for (_, path) in files {
+            let file_path_str = match path.to_str() {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let (size, modified) = match file_signature(&path) {
+                Ok(signature) => signature,
+                Err(msg) => {
+                    loaded_callback(LoadFileResult::DirectoryFailure(
+                        file_path_str.to_string(),
+                        msg,
+                    ));
+                    continue;
+                }
+            };
+
+            match self.is_file_imported(file_path_str, size, modified) {
+                Ok(true) => {
+                    loaded_callback(LoadFileResult::AlreadyImported(file_path_str.to_string()));
+                    continue;
+                }
+                Ok(false) => (),
+                Err(msg) => {
+                    loaded_callback(LoadFileResult::DirectoryFailure(
+                        file_path_str.to_string(),
+                        msg.to_string(),
+                    ));
+                    continue;
+                }
+            }
+
+            // Files that failed are left unrecorded so they are retried next time
+            let import = |callback: &dyn Fn(LoadFileResult)| {
+                let failed = Cell::new(false);
+                self.import_single_file(file_path_str, None, |result| {
+                    if result.is_failure() {
+                        failed.set(true);
+                    }
+                    callback(result);
+                });
+
+                if !failed.get() {
+                    if let Err(msg) = self.record_imported_file(file_path_str, size, modified) {
+                        callback(LoadFileResult::DirectoryFailure(
+                            file_path_str.to_string(),
+                            msg.to_string(),
+                        ));
                     }
                 }
-                Err(msg) => loaded_callback(LoadFileResult::ZipfileFailure(
-                    path.to_string(),
-                    format!("{:?}", msg),
-                )),
-            },
-            Err(msg) => loaded_callback(LoadFileResult::ZipfileFailure(
-                path.to_string(),
-                format!("{:?}", msg),
-            )),
+            };
+            if commit_each_file {
+                self.with_import_transaction(file_path_str, false, loaded_callback, import);
+            } else {
+                import(loaded_callback);
+            }
         }
+    }
 
-        results
+    /// True if the file at path was imported before with the same size and modification time
+    fn is_file_imported(
+        &self,
+        path_str: &str,
+        size: i64,
+        modified: i64,
+    ) -> Result<bool, IfdbError> {
+        let result = || -> Result<bool, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT 1 FROM imported_file WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            )?;
+            statement.exists(params![path_str, size, modified])
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(x) => Ok(x),
+        }
     }
 
-    /// Loads content into a database from a path. Path can be a zip, or the actual file
-    pub fn import_file<F: Fn(LoadFileResult)>(
+    fn record_imported_file(
+        &self,
+        path_str: &str,
+        size: i64,
+        modified: i64,
+    ) -> Result<(), IfdbError> {
+        match self.connection.execute(
+            "INSERT OR REPLACE INTO imported_file (path, size, modified) VALUES (?1, ?2, ?3)",
+            params![path_str, size, modified],
+        ) {
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Paths of the folders rescanned for new files at startup
+    pub fn fetch_watched_folders(&self) -> Result<Vec<String>, IfdbError> {
+        let result = || -> Result<Vec<String>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT path FROM watched_folder ORDER BY path")?;
+            let rows = statement.query_map(params![], |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(x) => Ok(x),
+        }
+    }
+
+    pub fn add_watched_folder(&self, path_str: &str) -> Result<(), IfdbError> {
+        if !Path::new(path_str).is_dir() {
+            return Err(IfdbError::Invalid(format!("{} is not a folder.", path_str)));
+        }
+
+        match self.connection.execute(
+            "INSERT OR IGNORE INTO watched_folder (path) VALUES (?1)",
+            params![path_str],
+        ) {
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    pub fn remove_watched_folder(&self, path_str: &str) -> Result<(), IfdbError> {
+        match self.connection.execute(
+            "DELETE FROM watched_folder WHERE path = ?1",
+            params![path_str],
+        ) {
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Import any new or changed files in the watched folders, each file in its own
+    /// transaction
+    pub fn rescan_watched_folders<F: Fn(LoadFileResult)>(
+        &self,
+        loaded_callback: F,
+    ) -> Result<(), IfdbError> {
+        for folder in self.fetch_watched_folders()? {
+            self.import_file(folder.as_str(), None, &loaded_callback);
+        }
+
+        Ok(())
+    }
+
+    /// Loads content into a database from the path of a single file, which can be a zip
+    fn import_single_file<F: Fn(LoadFileResult)>(
         &self,
         path_str: &str,
         filename_override: Option<String>,
@@ -3707,13 +4605,124 @@ impl IfdbConnection {
                 "z1" | "z2" | "z3" | "z4" | "z5" | "z6" | "z7" | "z8" => {
                     loaded_callback(self.load_story_file_from_path(path_str, filename.as_str()));
                 }
-                "zip" => {
-                    self.load_zipfile_from_path(path_str, loaded_callback);
+                ext if SAVE_EXTENSIONS.contains(&ext) => {
+                    loaded_callback(self.load_save_from_path(path_str, filename.as_str()));
+                }
+                "zblorb" | "blb" => {
+                    for result in self.load_blorb_from_path(path_str, filename.as_str()) {
+                        loaded_callback(result);
+                    }
+                }
+                "zip" | "tar" | "tgz" | "gz" => {
+                    self.load_archive_from_path(path_str, loaded_callback);
                 }
                 _ => loaded_callback(LoadFileResult::UnsupportedFormat(path_str.to_string())),
             };
         }
     }
+
+    ///
+    /// Exporting data to files
+    ///
+    /// Write ifiction data for a single story, or for the whole library if no story is given
+    pub fn export_ifiction(
+        &self,
+        story_id: Option<u32>,
+        writer: impl Write,
+    ) -> Result<(), IfdbError> {
+        match self.get_stories_query(story_id, false) {
+            Ok(stories) => {
+                if let Some(story_id) = story_id {
+                    if stories.is_empty() {
+                        return Err(IfdbError::NotFound(format!(
+                            "No story found with id {}",
+                            story_id
+                        )));
+                    }
+                }
+                let stories: Vec<Story> = stories.into_iter().map(|s| s.story).collect();
+                write_stories_to_xml(writer, &stories).map_err(IfdbError::Io)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Columns of the story_fts index, which can also be used as field qualifiers in searches
+const SEARCH_FIELDS: &[&str] = &[
+    "title",
+    "author",
+    "headline",
+    "genre",
+    "series",
+    "description",
+];
+
+/// A word or phrase from the search box, optionally limited to one of SEARCH_FIELDS
+struct SearchTerm {
+    field: Option<String>,
+    value: String,
+    is_phrase: bool, // Phrases must match exactly, other terms match as a prefix
+}
+
+/// Split text from the search box into terms. Each word is a term, and can be limited to one
+/// field (author:meretzky). Quoted text is a single phrase
+fn parse_search_terms(text: &str) -> Vec<SearchTerm> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r#"(?:(\w+):)?(?:"([^"]*)"?|(\S+))"#).unwrap();
+    }
+
+    let mut terms = vec![];
+    for c in RE.captures_iter(text) {
+        let field = c
+            .get(1)
+            .map(|f| f.as_str().to_lowercase())
+            .filter(|f| SEARCH_FIELDS.contains(&f.as_str()));
+        let (value, is_phrase) = match (c.get(2), c.get(3)) {
+            (Some(phrase), _) => (phrase.as_str().to_string(), true),
+            (None, Some(word)) if field.is_some() => (word.as_str().to_string(), false),
+            // Unknown qualifiers are searched for as plain text
+            _ => (c[0].to_string(), false),
+        };
+
+        // Quotes are the only special characters inside an FTS5 string
+        let value = value.replace('"', "");
+        if value.trim().is_empty() {
+            continue;
+        }
+
+        terms.push(SearchTerm {
+            field,
+            value,
+            is_phrase,
+        });
+    }
+
+    terms
+}
+
+/// Convert text from the search box into an FTS5 query. Returns None if there is nothing to
+/// search for
+fn build_search_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = parse_search_terms(text)
+        .into_iter()
+        .map(|t| {
+            let mut term = format!("\"{}\"", t.value);
+            if !t.is_phrase {
+                term.push('*');
+            }
+            match t.field {
+                Some(field) => format!("{} : {}", field, term),
+                None => term,
+            }
+        })
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" AND "))
+    }
 }
 
 const MIN_ZCODE_SIZE: usize = 0x20;
@@ -3721,57 +4730,166 @@ const HEADER_CHECKSUM: usize = 0x1C;
 const HEADER_RELEASE_NUMBER: usize = 0x02;
 const HEADER_SERIAL: usize = 0x12;
 
+/// Shrink a png or jpeg cover to fit in COVER_THUMBNAIL_SIZE and encode it as a png. Returns
+/// None if the cover can't be decoded
+fn make_cover_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    let cover = match image::load_from_memory(data) {
+        Ok(cover) => cover,
+        Err(msg) => {
+            println!("Unable to decode cover image: {}", msg);
+            return None;
+        }
+    };
+    // Covers that already fit are kept at their size rather than enlarged
+    let thumbnail = if cover.width() > COVER_THUMBNAIL_SIZE || cover.height() > COVER_THUMBNAIL_SIZE
+    {
+        cover.thumbnail(COVER_THUMBNAIL_SIZE, COVER_THUMBNAIL_SIZE)
+    } else {
+        cover
+    };
+    let mut png = vec![];
+    match thumbnail.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png) {
+        Ok(()) => Some(png),
+        Err(msg) => {
+            println!("Unable to encode cover thumbnail: {}", msg);
+            None
+        }
+    }
+}
+
+/// Read the version, release, serial and checksum from the header of a story file, which
+/// may be just the start of the file. Returns None if it is too short to have a header
+pub fn zcode_from_header(data: &[u8]) -> Option<Zcode> {
+    if data.len() < MIN_ZCODE_SIZE {
+        return None;
+    }
+
+    let release_number: u16 =
+        ((data[HEADER_RELEASE_NUMBER] as u16) << 8) | (data[HEADER_RELEASE_NUMBER + 1] as u16);
+    let checksum: u16 = ((data[HEADER_CHECKSUM] as u16) << 8) | (data[HEADER_CHECKSUM + 1] as u16);
+    Some(Zcode {
+        version: Some(data[0] as u32),
+        release: Some(release_number.to_string()),
+        serial: Some(String::from_utf8_lossy(&data[HEADER_SERIAL..HEADER_SERIAL + 6]).to_string()),
+        checksum: Some(format!("{:04X}", checksum)),
+        compiler: None,
+        cover_picture: None,
+    })
+}
+
+/// Read a story file, or the story file in a blorb package, checking that it is one
+pub fn read_story_file(path: &Path) -> Result<Vec<u8>, IfdbError> {
+    let contents = fs::read(path)?;
+    let data = if is_blorb(path) {
+        read_blorb(&contents)
+            .map_err(IfdbError::Invalid)?
+            .zcode
+            .ok_or_else(|| IfdbError::Invalid(String::from("No zcode story found in blorb")))?
+    } else {
+        contents
+    };
+    extract_ifid_from_bytes(&data).map_err(IfdbError::Invalid)?;
+    Ok(data)
+}
+
+/// Describe a story file being replaced, for the story's history. A different checksum
+/// means saves made with the old file may not restore
+fn describe_story_file_replacement(filename: &str, old: &[u8], new: &[u8]) -> String {
+    let mut description = format!("Replaced story file with {}.", filename);
+    if let (Some(old), Some(new)) = (zcode_from_header(old), zcode_from_header(new)) {
+        if old.release != new.release || old.serial != new.serial {
+            description.push_str(
+                format!(
+                    " Release {}, serial {} became release {}, serial {}.",
+                    old.release.unwrap_or_default(),
+                    old.serial.unwrap_or_default(),
+                    new.release.unwrap_or_default(),
+                    new.serial.unwrap_or_default()
+                )
+                .as_str(),
+            );
+        }
+        if old.checksum == new.checksum {
+            description.push_str(
+                format!(" Checksum {} unchanged.", old.checksum.unwrap_or_default()).as_str(),
+            );
+        } else {
+            description.push_str(
+                format!(
+                    " Checksum changed from {} to {}.",
+                    old.checksum.unwrap_or_default(),
+                    new.checksum.unwrap_or_default()
+                )
+                .as_str(),
+            );
+        }
+    }
+    description
+}
+
+/// Find an IFID embedded in a story file as UUID://...// (see 2.2.1)
+fn find_embedded_ifid(data: &[u8]) -> Option<String> {
+    lazy_static! {
+        static ref RE: regex::bytes::Regex =
+            regex::bytes::Regex::new(r"UUID://([0-9A-Za-z-]{8,63})//").unwrap();
+    }
+
+    RE.captures(data)
+        .map(|c| String::from_utf8_lossy(&c[1]).to_uppercase())
+}
+
 fn extract_ifid_from_bytes(data: &[u8]) -> Result<String, String> {
     // Assumes this is a zcode file
     if data.len() < MIN_ZCODE_SIZE {
-        Err(format!(
+        return Err(format!(
             "Length of {} is too short to be a valid zcode file",
             data.len()
-        ))
-    } else {
-        let release_number: u16 =
-            ((data[HEADER_RELEASE_NUMBER] as u16) << 8) | (data[HEADER_RELEASE_NUMBER + 1] as u16);
-        let checksum: u16 =
-            ((data[HEADER_CHECKSUM] as u16) << 8) & (data[HEADER_CHECKSUM + 1] as u16);
-        let serial_number: [u8; 6] = [
-            data[HEADER_SERIAL],
-            data[HEADER_SERIAL + 1],
-            data[HEADER_SERIAL + 2],
-            data[HEADER_SERIAL + 3],
-            data[HEADER_SERIAL + 4],
-            data[HEADER_SERIAL + 5],
-        ];
-        // See 2.2.2.1 for algorithm
-        // Step 1
-        let _ = match serial_number[0] as char {
-            '9' => true,
-            '8' => true,
-            '0' => matches!(serial_number[1] as char, '0' | '1' | '2' | '3' | '4'),
-            _ => false,
-        };
-
-        // Step 2
-
-        // Step 3
-        let mut ifid = format!("ZCODE-{}-", release_number);
+        ));
+    }
 
-        // Step 4
-        for c in serial_number.iter() {
-            if c.is_ascii_alphanumeric() {
-                ifid.push(*c as char);
-            } else {
-                ifid.push('-');
-            }
-        }
+    // See 2.2.2.1 for algorithm
+    // Step 1: an IFID embedded in the file always takes precedence
+    if let Some(ifid) = find_embedded_ifid(data) {
+        return Ok(ifid);
+    }
 
-        if serial_number[0] >= b'0'
-            && serial_number[0] <= b'9'
-            && serial_number != [0_u8, 0_u8, 0_u8, 0_u8, 0_u8, 0_u8]
-            && serial_number[0] != b'8'
-        {
-            ifid.push_str(format!("-{:04X}", checksum).as_str());
+    let release_number: u16 =
+        ((data[HEADER_RELEASE_NUMBER] as u16) << 8) | (data[HEADER_RELEASE_NUMBER + 1] as u16);
+    let checksum: u16 = ((data[HEADER_CHECKSUM] as u16) << 8) | (data[HEADER_CHECKSUM + 1] as u16);
+    let serial_number: [u8; 6] = [
+        data[HEADER_SERIAL],
+        data[HEADER_SERIAL + 1],
+        data[HEADER_SERIAL + 2],
+        data[HEADER_SERIAL + 3],
+        data[HEADER_SERIAL + 4],
+        data[HEADER_SERIAL + 5],
+    ];
+
+    // Step 2: files with a serial dated before 2005 (or the 1980s/90s) are vintage and
+    // do not have a usable checksum
+    let vintage = match serial_number[0] as char {
+        '9' => true,
+        '8' => true,
+        '0' => matches!(serial_number[1] as char, '0' | '1' | '2' | '3' | '4'),
+        _ => false,
+    };
+
+    // Step 3
+    let mut ifid = format!("ZCODE-{}-", release_number);
+
+    // Step 4
+    for c in serial_number.iter() {
+        if c.is_ascii_alphanumeric() {
+            ifid.push(*c as char);
+        } else {
+            ifid.push('-');
         }
+    }
 
-        Ok(ifid)
+    // Step 5: only serials that are dates get a checksum
+    if serial_number[0].is_ascii_digit() && !vintage && &serial_number != b"000000" {
+        ifid.push_str(format!("-{:04X}", checksum).as_str());
     }
+
+    Ok(ifid)
 }
@@ -0,0 +1,40 @@
+///
+/// Reader for the header of Quetzal save files (.qzl)
+/// See http://inform-fiction.org/zmachine/standards/quetzal/
+///
+use super::iff::read_chunks;
+
+const IFZS_ID: &[u8; 4] = b"IFZS";
+const HEADER_ID: &[u8; 4] = b"IFhd";
+
+// 5.4: release (2 bytes), serial (6), checksum (2) and PC (3)
+const HEADER_SIZE: usize = 13;
+
+/// Identifies the story file a save was made from
+#[derive(PartialEq, Debug)]
+pub struct QuetzalHeader {
+    pub release: u16,
+    pub serial: [u8; 6],
+    pub checksum: u16,
+    pub pc: u32,
+}
+
+/// Read the IFhd chunk from Quetzal save data
+pub fn read_quetzal_header(data: &[u8]) -> Result<QuetzalHeader, String> {
+    let chunks = read_chunks(data, IFZS_ID)?;
+    match chunks.iter().find(|c| c.id == HEADER_ID) {
+        Some(chunk) if chunk.data.len() >= HEADER_SIZE => {
+            let d = chunk.data;
+            let mut serial = [0; 6];
+            serial.copy_from_slice(&d[2..8]);
+            Ok(QuetzalHeader {
+                release: u16::from_be_bytes([d[0], d[1]]),
+                serial,
+                checksum: u16::from_be_bytes([d[8], d[9]]),
+                pc: u32::from_be_bytes([0, d[10], d[11], d[12]]),
+            })
+        }
+        Some(_) => Err(String::from("Save header is too short")),
+        None => Err(String::from("No header found in save")),
+    }
+}
@@ -0,0 +1,2242 @@
+///
+/// The SQLite implementation of the storage trait
+///
+use super::cache::SummaryQuery;
+use super::*;
+
+impl IfdbStore for IfdbConnection {
+    ///
+    /// Stories
+    ///
+    fn count_stories(&self) -> Result<u32, IfdbError> {
+        let result = || -> Result<u32, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT count(id) FROM story WHERE deleted_when IS NULL")?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(0)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn get_story_id(&self, ifid: &str) -> Result<Option<u32>, IfdbError> {
+        let result = || -> Result<Option<u32>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT story_id FROM story_ifid WHERE  ifid = ?1")?;
+
+            let mut query = statement.query(params![ifid])?;
+
+            if let Some(row) = query.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn get_story_data(&self, story_id: u32, ifid: &str) -> Result<Option<Vec<u8>>, IfdbError> {
+        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT story_data FROM story_ifid WHERE story_id = ?1 AND ifid = ?2")?;
+
+            let mut query = statement.query(params![story_id, ifid])?;
+
+            if let Some(row) = query.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn get_story(&self, story_id: u32) -> Result<Option<DbStory>, IfdbError> {
+        match self.get_stories_query(Some(story_id), false) {
+            Ok(stories) => {
+                if stories.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(stories[0].clone()))
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete_story(&self, story_id: u32) -> Result<(), IfdbError> {
+        self.cache.clear();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection
+                .execute("DELETE FROM  story WHERE id = ?1", params![story_id,])?;
+            self.connection
+                .execute("DELETE FROM story_fts WHERE rowid = ?1", params![story_id,])?;
+            self.connection.execute(
+                "DELETE FROM  story_resource WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM  story_release WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM  story_zcode WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM saves WHERE ifid IN (SELECT ifid FROM story_ifid WHERE story_id = ?1)",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM session WHERE ifid IN (SELECT ifid FROM story_ifid WHERE story_id = ?1)",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM map_room WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection
+                .execute("DELETE FROM notes WHERE story_id = ?1", params![story_id,])?;
+            self.connection
+            .execute("DELETE FROM clue WHERE subsection_id IN (SELECT id from clue_subsection WHERE section_id IN (SELECT id from clue_section WHERE story_id = ?))", params![story_id,])?;
+            self.connection
+            .execute("DELETE FROM clue_subsection WHERE section_id IN (SELECT id from clue_section WHERE story_id = ?)", params![story_id,])?;
+
+            self.connection.execute(
+                "DELETE FROM clue_section WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM  window_details WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM cover_thumbnail WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM story_history WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM  story_ifid WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM shelf_story WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.connection.execute(
+                "DELETE FROM story_tag WHERE story_id = ?1",
+                params![story_id,],
+            )?;
+            self.delete_unused_tags()?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn fetch_ifids_for_story(
+        &self,
+        story_id: u32,
+        playable: bool,
+    ) -> Result<Vec<String>, IfdbError> {
+        let result = || -> Result<Vec<String>, rusqlite::Error> {
+            let mut ifids: Vec<String> = vec![];
+
+            let mut sql = String::from("SELECT  ifid FROM story_ifid  WHERE story_id = ?1 ");
+            if playable {
+                sql.push_str(" AND story_data is not null ");
+            }
+
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let mut query = statement.query(params![story_id])?;
+
+            while let Some(row) = query.next()? {
+                let ifid: String = row.get(0)?;
+                ifids.push(ifid);
+            }
+
+            Ok(ifids)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn fetch_sorted_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+    ) -> Result<Vec<StorySummary>, IfdbError> {
+        let cache_query = SummaryQuery::new(has_data, search_text, sort, filter);
+        if let Some(rows) = self.cache.get_story_summaries(&cache_query) {
+            return Ok(rows);
+        }
+
+        let result = || -> Result<Vec<StorySummary>, rusqlite::Error> {
+            let search = self.resolve_story_search(has_data, search_text, filter)?;
+            self.query_story_summaries(has_data, &search, sort, filter, None)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(rows) => Ok(self.cache.store_story_summaries(cache_query, rows)),
+        }
+    }
+
+    fn count_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        filter: &StoryFilter,
+        before_title: Option<&str>,
+    ) -> Result<usize, IfdbError> {
+        let cache_query = SummaryQuery::new(has_data, search_text, None, filter);
+        if let Some(count) = self.cache.get_story_count(&cache_query, before_title) {
+            return Ok(count);
+        }
+
+        let result = || -> Result<usize, rusqlite::Error> {
+            let search = self.resolve_story_search(has_data, search_text, filter)?;
+            self.count_story_summaries_sql(has_data, &search, filter, before_title)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(count) => Ok(self
+                .cache
+                .store_story_count(cache_query, before_title, count)),
+        }
+    }
+
+    fn fetch_story_summary_page(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<StorySummary>, IfdbError> {
+        let cache_query =
+            SummaryQuery::new(has_data, search_text, sort, filter).page(offset, limit);
+        if let Some(rows) = self.cache.get_story_summaries(&cache_query) {
+            return Ok(rows);
+        }
+
+        let result = || -> Result<Vec<StorySummary>, rusqlite::Error> {
+            let search = self.resolve_story_search(has_data, search_text, filter)?;
+            self.query_story_summaries(has_data, &search, sort, filter, Some((offset, limit)))
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(rows) => Ok(self.cache.store_story_summaries(cache_query, rows)),
+        }
+    }
+
+    fn fetch_story_filter_values(&self, field: StoryFilterField) -> Result<Vec<String>, IfdbError> {
+        let result = || -> Result<Vec<String>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                format!(
+                    "SELECT DISTINCT s.{0} FROM story s
+                    JOIN story_ifid i ON i.story_id = s.id
+                    WHERE i.story_data IS NOT NULL AND s.deleted_when IS NULL
+                    AND s.{0} IS NOT NULL AND s.{0} != ''
+                    ORDER BY s.{0}",
+                    field.column()
+                )
+                .as_str(),
+            )?;
+
+            let rows = statement.query_map(params![], |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(values) => Ok(values),
+        }
+    }
+
+    fn fetch_story_releases(&self, story_id: u32) -> Result<Vec<DbStoryRelease>, IfdbError> {
+        let result = || -> Result<Vec<DbStoryRelease>, rusqlite::Error> {
+            // Only the header of each story file is read
+            let mut statement = self.connection.prepare(
+                format!(
+                    "SELECT r.id, r.ifid, substr(r.story_data, 1, ?2) FROM story_ifid r
+                    JOIN story s ON r.story_id = s.id
+                    WHERE s.id = ?1 AND r.story_data is not null {}",
+                    RELEASE_PREFERENCE_ORDER
+                )
+                .as_str(),
+            )?;
+            let rows = statement.query_map(params![story_id, MIN_ZCODE_SIZE as i64], |row| {
+                let header: Vec<u8> = row.get(2)?;
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    DbStoryRelease {
+                        ifid: row.get(1)?,
+                        zcode: zcode_from_header(&header),
+                        preferred: false,
+                    },
+                ))
+            })?;
+            let mut releases = rows.collect::<Result<Vec<(i64, DbStoryRelease)>, _>>()?;
+            if let Some((_, release)) = releases.first_mut() {
+                release.preferred = true;
+            }
+            releases.sort_by_key(|(id, _)| *id);
+            Ok(releases.into_iter().map(|(_, release)| release).collect())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(releases) => Ok(releases),
+        }
+    }
+
+    fn store_preferred_release(&self, story_id: u32, ifid: &str) -> Result<(), IfdbError> {
+        if self.get_story_id_for_ifid(ifid, true)? != Some(story_id) {
+            return Err(IfdbError::NotFound(format!(
+                "No story file for IFID {} in story {}",
+                ifid, story_id
+            )));
+        }
+
+        self.cache.clear_library();
+        self.connection.execute(
+            "UPDATE story SET preferred_ifid = ?1 WHERE id = ?2",
+            params![ifid, story_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_story_summary_by_id(&self, story_id: u32) -> Result<Option<StorySummary>, IfdbError> {
+        let result = || -> Result<Option<StorySummary>, rusqlite::Error> {
+            let params = vec![story_id];
+            let sql = format!(
+                "SELECT {} FROM story_ifid i 
+            JOIN story s ON i.story_id = s.id 
+            WHERE s.id = ?1 
+            AND s.deleted_when IS NULL
+            AND i.id = (SELECT r.id FROM story_ifid r WHERE r.story_id = s.id
+                AND r.story_data is not null {} LIMIT 1)",
+                STORY_SUMMARY_COLUMNS, RELEASE_PREFERENCE_ORDER
+            );
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let row_iter = statement.query_map(params, StorySummary::from_row)?;
+
+            let mut summary = None;
+            for row in row_iter {
+                match row {
+                    Ok(row) => summary = Some(row),
+                    Err(msg) => println!("Error: {}", msg),
+                }
+            }
+
+            Ok(summary)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(rows) => Ok(rows),
+        }
+    }
+
+    fn get_story_id_for_ifid(
+        &self,
+        ifid: &str,
+        playable_only: bool,
+    ) -> Result<Option<u32>, IfdbError> {
+        let result = || -> Result<Option<u32>, rusqlite::Error> {
+            let mut sql = "SELECT i.story_id
+            FROM story_ifid i 
+            WHERE i.ifid = ?1"
+                .to_string();
+            if playable_only {
+                sql.push_str(" AND  story_data is not null");
+            }
+
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let mut query = statement.query(params![ifid])?;
+            if let Some(row) = query.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(rows) => Ok(rows),
+        }
+    }
+
+    fn create_story(&self, story: Story) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        if story.identification.ifids.is_empty() {
+            return Err(IfdbError::Invalid("Story has no ifids".to_string()));
+        }
+
+        for ifid in &story.identification.ifids {
+            if self.get_story_id_for_ifid(ifid.as_str(), true)?.is_some() {
+                return Err(IfdbError::Constraint(format!(
+                    "Story data already exists for IFID {}",
+                    ifid
+                )));
+            }
+        }
+
+        if let Err(sqlerr) = self.create_story_sql(story) {
+            Err(sqlerr.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn update_last_played_to_now(&self, story_id: i64) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UpDATE  story SET last_played = ?1 WHERE id = ?2",
+                params![Utc::now().naive_local(), story_id,],
+            )?;
+
+            // Opening a story for the first time starts it
+            self.connection.execute(
+                "UPDATE story SET play_status = ?1 WHERE id = ?2 AND play_status = ?3",
+                params![
+                    PlayStatus::InProgress.to_string(),
+                    story_id,
+                    PlayStatus::Unplayed.to_string()
+                ],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn get_story_review(&self, story_id: u32) -> Result<Option<StoryReview>, IfdbError> {
+        let result = || -> Result<Option<StoryReview>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT play_status, rating, review FROM story WHERE id = ?1")?;
+
+            let mut query = statement.query(params![story_id])?;
+            if let Some(row) = query.next()? {
+                let play_status: String = row.get(0)?;
+                Ok(Some(StoryReview {
+                    play_status: PlayStatus::from_db_str(&play_status),
+                    rating: row.get(1)?,
+                    review: row.get(2)?,
+                }))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(review) => Ok(review),
+        }
+    }
+
+    fn update_story_review(&self, story_id: u32, review: &StoryReview) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        if let Some(rating) = review.rating {
+            if !(1..=MAX_RATING).contains(&rating) {
+                return Err(IfdbError::Invalid(format!(
+                    "Rating must be from 1 to {}.",
+                    MAX_RATING
+                )));
+            }
+        }
+
+        let result = || -> Result<(), rusqlite::Error> {
+            // Empty reviews are stored as no review
+            let text = review
+                .review
+                .as_deref()
+                .map(str::trim)
+                .filter(|r| !r.is_empty());
+            self.connection.execute(
+                "UPDATE story SET play_status = ?1, rating = ?2, review = ?3 WHERE id = ?4",
+                params![
+                    review.play_status.to_string(),
+                    review.rating,
+                    text,
+                    story_id
+                ],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn add_to_time_played(&self, story_id: i64, elapsed: i64) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UpDATE  story SET time_played = time_played + ?1 WHERE id = ?2",
+                params![elapsed, story_id,],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn update_story(&self, dbstory: DbStory) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        if let Err(sqlerr) = self.update_story_sql(dbstory.story_id, dbstory.story) {
+            Err(sqlerr.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fetch_story_resources(&self, story_id: u32) -> Result<Vec<DbStoryResource>, IfdbError> {
+        let result = || -> Result<Vec<DbStoryResource>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT id, leafname, description, length(data) FROM story_resource
+                WHERE story_id = ?1 ORDER BY id",
+            )?;
+            let rows = statement.query_map(params![story_id], |row| {
+                Ok(DbStoryResource {
+                    dbid: row.get(0)?,
+                    leafname: row.get(1)?,
+                    description: row.get(2)?,
+                    size: row.get::<_, Option<i64>>(3)?.map(|size| size as usize),
+                })
+            })?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(x) => Ok(x),
+        }
+    }
+
+    fn get_resource_data(&self, resource_id: i64) -> Result<Option<Vec<u8>>, IfdbError> {
+        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT data FROM story_resource WHERE id = ?1")?;
+            let mut query = statement.query(params![resource_id])?;
+            match query.next()? {
+                Some(row) => row.get(0),
+                None => Ok(None),
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(x) => Ok(x),
+        }
+    }
+
+    fn store_cover_image(&self, ifid: &str, data: Vec<u8>) -> Result<(), IfdbError> {
+        match self.get_story_id_for_ifid(ifid, false)? {
+            None => Err(IfdbError::NotFound(format!(
+                "No story found for ifid {}",
+                ifid
+            ))),
+            Some(story_id) => match self.store_cover_sql(story_id, &data) {
+                Err(e) => Err(e.into()),
+                Ok(()) => Ok(()),
+            },
+        }
+    }
+
+    fn fetch_cover_image(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError> {
+        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT cover_image FROM story WHERE id = ?1")?;
+            let mut query = statement.query(params![story_id])?;
+            match query.next()? {
+                Some(row) => row.get(0),
+                None => Ok(None),
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(cover) => Ok(cover),
+        }
+    }
+
+    fn fetch_cover_thumbnail(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError> {
+        let result = || -> Result<Option<Vec<u8>>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT data FROM cover_thumbnail WHERE story_id = ?1")?;
+            let mut query = statement.query(params![story_id])?;
+            match query.next()? {
+                Some(row) => Ok(Some(row.get(0)?)),
+                None => Ok(None),
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(thumbnail) => Ok(thumbnail),
+        }
+    }
+
+    fn add_story_data(
+        &self,
+        ifid: &str,
+        data: Vec<u8>,
+        default_name: &str,
+    ) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let story_id = self.get_story_id_for_ifid(ifid, false)?;
+        if story_id.is_none() {
+            self.create_story(placeholder_story(ifid, default_name))?;
+        }
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE  story_ifid SET story_data = ?1 WHERE ifid = ?2",
+                params![data, ifid],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn replace_story_data(
+        &self,
+        story_id: u32,
+        ifid: &str,
+        new_ifid: &str,
+        data: Vec<u8>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError> {
+        self.cache.clear_library();
+        let recorded_when = Utc::now().naive_utc();
+        let result = || -> Result<i64, rusqlite::Error> {
+            let transaction = self.connection.unchecked_transaction()?;
+            self.connection.execute(
+                "UPDATE story_ifid SET ifid = ?1, story_data = ?2 WHERE story_id = ?3 AND ifid = ?4",
+                params![new_ifid, data, story_id, ifid],
+            )?;
+            if new_ifid != ifid {
+                self.connection.execute(
+                    "UPDATE saves SET ifid = ?1 WHERE ifid = ?2",
+                    params![new_ifid, ifid],
+                )?;
+                self.connection.execute(
+                    "UPDATE session SET ifid = ?1 WHERE ifid = ?2",
+                    params![new_ifid, ifid],
+                )?;
+                self.connection.execute(
+                    "UPDATE story_history SET ifid = ?1 WHERE ifid = ?2",
+                    params![new_ifid, ifid],
+                )?;
+                self.connection.execute(
+                    "UPDATE story SET preferred_ifid = ?1 WHERE id = ?2 AND preferred_ifid = ?3",
+                    params![new_ifid, story_id, ifid],
+                )?;
+            }
+            self.connection.execute(
+                "INSERT INTO story_history (story_id, ifid, recorded_when, description)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![story_id, new_ifid, recorded_when, description],
+            )?;
+            let dbid = self.connection.last_insert_rowid();
+            transaction.commit()?;
+            Ok(dbid)
+        }();
+        self.cache.clear_saves(ifid);
+        self.cache.clear_saves(new_ifid);
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(dbid) => Ok(DbStoryHistory {
+                dbid,
+                ifid: Some(new_ifid.to_string()),
+                recorded_when,
+                description: description.to_string(),
+            }),
+        }
+    }
+
+    fn add_story_history(
+        &self,
+        story_id: u32,
+        ifid: Option<&str>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError> {
+        let recorded_when = Utc::now().naive_utc();
+        let result = || -> Result<i64, rusqlite::Error> {
+            self.connection.execute(
+                "INSERT INTO story_history (story_id, ifid, recorded_when, description)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![story_id, ifid, recorded_when, description],
+            )?;
+            Ok(self.connection.last_insert_rowid())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(dbid) => Ok(DbStoryHistory {
+                dbid,
+                ifid: ifid.map(String::from),
+                recorded_when,
+                description: description.to_string(),
+            }),
+        }
+    }
+
+    fn fetch_story_history(&self, story_id: u32) -> Result<Vec<DbStoryHistory>, IfdbError> {
+        let result = || -> Result<Vec<DbStoryHistory>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT id, ifid, recorded_when, description FROM story_history
+                WHERE story_id = ?1 ORDER BY recorded_when DESC, id DESC",
+            )?;
+            let rows = statement.query_map(params![story_id], |row| {
+                Ok(DbStoryHistory {
+                    dbid: row.get(0)?,
+                    ifid: row.get(1)?,
+                    recorded_when: row.get(2)?,
+                    description: row.get(3)?,
+                })
+            })?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(history) => Ok(history),
+        }
+    }
+
+    ///
+    /// Saves
+    ///
+    fn count_saves(&self) -> Result<u32, IfdbError> {
+        let result = || -> Result<u32, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT count(id) FROM saves WHERE deleted_when IS NULL")?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(0)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn count_autosaves_for_story(&self, ifid: String) -> Result<u32, IfdbError> {
+        let result = || -> Result<u32, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT count(id) FROM saves WHERE ifid = ?1 AND save_type=?2 AND deleted_when IS NULL")?;
+            let mut query = statement.query(params![ifid, SaveType::Autosave.to_string()])?;
+
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(0)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn get_save(&self, ifid: String, name: String) -> Result<Option<DbSave>, IfdbError> {
+        let result = || -> Result<Option<DbSave>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version, id FROM saves WHERE ifid = ?1 AND name = ?2 AND deleted_when IS NULL",
+            )?;
+            let mut query = statement.query(params![ifid, name])?;
+
+            if let Some(row) = query.next()? {
+                Ok(Some(self.get_save_from_row(ifid, row)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    fn get_save_by_id(&self, ifid: String, dbid: i64) -> Result<Option<DbSave>, IfdbError> {
+        let result = || -> Result<Option<DbSave>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id,version, id FROM saves WHERE ifid = ?1 AND id=?2 AND deleted_when IS NULL",
+            )?;
+            let mut query = statement.query(params![ifid, dbid])?;
+
+            if let Some(row) = query.next()? {
+                Ok(Some(self.get_save_from_row(ifid, row)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    fn fetch_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError> {
+        if let Some(saves) = self.cache.get_saves(&ifid, false) {
+            return Ok(saves);
+        }
+
+        let result = || -> Result<Vec<DbSave>, rusqlite::Error> {
+            let mut saves: Vec<DbSave> = Vec::new();
+            let mut statement = self.connection.prepare(
+                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version, id FROM saves WHERE ifid = ?1 AND deleted_when IS NULL ORDER BY saved_when DESC",
+            )?;
+            let mut query = statement.query(params![ifid])?;
+
+            while let Some(row) = query.next()? {
+                saves.push(self.get_save_from_row(ifid.clone(), row)?);
+            }
+
+            Ok(saves)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(saves) => Ok(self.cache.store_saves(&ifid, false, saves)),
+        }
+    }
+
+    fn fetch_manual_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError> {
+        if let Some(saves) = self.cache.get_saves(&ifid, true) {
+            return Ok(saves);
+        }
+
+        let result = || -> Result<Vec<DbSave>, rusqlite::Error> {
+            let mut saves: Vec<DbSave> = Vec::new();
+            let mut statement = self.connection.prepare(
+                "SELECT name, saved_when, data, save_type, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id,version,id FROM saves WHERE ifid = ?1 AND save_type = ?2 AND deleted_when IS NULL ORDER BY saved_when DESC",
+            )?;
+            let mut query = statement.query(params![ifid, SaveType::Normal.to_string()])?;
+
+            while let Some(row) = query.next()? {
+                saves.push(self.get_save_from_row(ifid.clone(), row)?);
+            }
+
+            Ok(saves)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(saves) => Ok(self.cache.store_saves(&ifid, true, saves)),
+        }
+    }
+
+    fn store_save(&self, dbsave: &DbSave, overwrite: bool) -> Result<i64, IfdbError> {
+        self.cache.clear_saves(&dbsave.ifid);
+        let result = || -> Result<i64, rusqlite::Error> {
+            // If there is a save with the exact same data and save type as this save with the same
+            // parent id, just return that save instead. This avoids branching saves unless necessary
+            let mut statement = self.connection.prepare(
+                "SELECT id,data FROM saves WHERE ifid = ?1 AND save_type = ?2 AND parent_id = ?3 AND deleted_when IS NULL ORDER BY saved_when DESC",
+            )?;
+
+            let mut query = statement.query(params![
+                dbsave.ifid,
+                dbsave.save_type.to_string(),
+                dbsave.parent_id
+            ])?;
+
+            while let Some(row) = query.next()? {
+                let dbid = row.get(0)?;
+                let data: Vec<u8> = row.get(1)?;
+                if data == dbsave.data {
+                    return Ok(dbid);
+                }
+            }
+
+            let mut save_name = dbsave.name.clone();
+            match dbsave.save_type {
+                SaveType::Autosave => {
+                    // Autosaves should have unique names, since the name itself isn't important
+                    let mut statement = self.connection.prepare(
+                    "SELECT max(id) FROM saves WHERE ifid = ?1 GROUp BY ifid ORDER BY saved_when DESC",
+                    )?;
+                    let mut query = statement.query(params![dbsave.ifid])?;
+                    if let Some(row) = query.next()? {
+                        let max: u32 = row.get(0)?;
+                        save_name = format!("{} - {}", dbsave.name, max);
+                    }
+                    // }
+                }
+                SaveType::Normal => {
+                    if overwrite {
+                        self.connection.execute(
+                            "DELETE FROM saves WHERE ifid = ?1 AND name = ?2",
+                            params![dbsave.ifid, dbsave.name],
+                        )?;
+                    }
+                }
+            }
+
+            let next_pc: Option<i32> = dbsave.next_pc.map(|v| v as i32);
+            self.connection.execute(
+                "INSERT INTO saves (ifid, name, save_type, saved_when, data, pc, text_buffer_address, parse_buffer_address, next_pc, left_status, right_status, latest_text, room_id, parent_id, version) VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15)",
+                params![dbsave.ifid, save_name, dbsave.save_type.to_string(), dbsave.saved_when, dbsave.data, dbsave.pc as i32, dbsave.text_buffer_address, dbsave.parse_buffer_address,
+                                    next_pc, dbsave.left_status,dbsave.right_status, dbsave.latest_text, dbsave.room_id, dbsave.parent_id, dbsave.version]
+            )?;
+
+            let dbid = self.connection.last_insert_rowid();
+
+            Ok(dbid)
+        }();
+        // A save with the same name is reported as IfdbError::Constraint
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(dbid) => Ok(dbid),
+        }
+    }
+
+    fn find_ifid_for_release(
+        &self,
+        release: u16,
+        serial: &[u8; 6],
+        checksum: u16,
+    ) -> Result<Option<String>, IfdbError> {
+        let result = || -> Result<Option<String>, rusqlite::Error> {
+            // Offsets are the header positions used in extract_ifid_from_bytes, 1-based for substr
+            let mut statement = self.connection.prepare(
+                "SELECT ifid FROM story_ifid WHERE story_data IS NOT NULL
+                AND substr(story_data, 3, 2) = ?1
+                AND substr(story_data, 19, 6) = ?2
+                AND substr(story_data, 29, 2) = ?3",
+            )?;
+
+            let mut query = statement.query(params![
+                release.to_be_bytes().to_vec(),
+                serial.to_vec(),
+                checksum.to_be_bytes().to_vec()
+            ])?;
+            if let Some(row) = query.next()? {
+                Ok(Some(row.get(0)?))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(ifid) => Ok(ifid),
+        }
+    }
+
+    ///
+    /// Trash
+    ///
+    fn set_story_trashed(&self, story_id: u32, trashed: bool) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        // A story already in the trash keeps the time it was first deleted
+        let deleted_when = if trashed {
+            Some(Utc::now().naive_utc())
+        } else {
+            None
+        };
+        let count = self.connection.execute(
+            "UPDATE story SET deleted_when = CASE WHEN ?1 IS NULL THEN NULL
+            ELSE COALESCE(deleted_when, ?1) END WHERE id = ?2",
+            params![deleted_when, story_id],
+        )?;
+
+        if count == 0 {
+            Err(IfdbError::NotFound(format!(
+                "No story with id {}",
+                story_id
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_autosaves_trashed(&self, ifid: &str, trashed: bool) -> Result<(), IfdbError> {
+        self.cache.clear_saves(ifid);
+        let result = || -> Result<usize, rusqlite::Error> {
+            if trashed {
+                self.connection.execute(
+                    "UPDATE saves SET deleted_when = ?1
+                    WHERE ifid = ?2 AND save_type = ?3 AND deleted_when IS NULL",
+                    params![Utc::now().naive_utc(), ifid, SaveType::Autosave.to_string()],
+                )
+            } else {
+                self.connection.execute(
+                    "UPDATE saves SET deleted_when = NULL WHERE ifid = ?1 AND save_type = ?2",
+                    params![ifid, SaveType::Autosave.to_string()],
+                )
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn purge_trashed_autosaves(&self, ifid: &str) -> Result<(), IfdbError> {
+        self.cache.clear_saves(ifid);
+        self.connection.execute(
+            "DELETE FROM saves WHERE ifid = ?1 AND save_type = ?2 AND deleted_when IS NOT NULL",
+            params![ifid, SaveType::Autosave.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn fetch_trash(&self) -> Result<Vec<DbTrashItem>, IfdbError> {
+        let result = || -> Result<Vec<DbTrashItem>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT id, bibliographic_title, deleted_when FROM story
+                WHERE deleted_when IS NOT NULL",
+            )?;
+            let stories = statement.query_map(params![], |row| {
+                Ok(DbTrashItem {
+                    story_id: row.get(0)?,
+                    title: row.get(1)?,
+                    contents: TrashContents::Story,
+                    deleted_when: row.get(2)?,
+                })
+            })?;
+            let mut items = stories.collect::<Result<Vec<DbTrashItem>, rusqlite::Error>>()?;
+
+            let mut statement = self.connection.prepare(
+                "SELECT s.id, s.bibliographic_title, v.ifid, COUNT(v.id), MAX(v.deleted_when)
+                FROM saves v
+                JOIN story_ifid i ON i.ifid = v.ifid
+                JOIN story s ON s.id = i.story_id
+                WHERE v.deleted_when IS NOT NULL
+                GROUP BY s.id, s.bibliographic_title, v.ifid",
+            )?;
+            let autosaves = statement.query_map(params![], |row| {
+                Ok(DbTrashItem {
+                    story_id: row.get(0)?,
+                    title: row.get(1)?,
+                    contents: TrashContents::Autosaves(row.get(2)?, row.get(3)?),
+                    deleted_when: row.get(4)?,
+                })
+            })?;
+            for item in autosaves {
+                items.push(item?);
+            }
+
+            items.sort_by(|a, b| {
+                b.deleted_when
+                    .cmp(&a.deleted_when)
+                    .then_with(|| a.title.cmp(&b.title))
+            });
+            Ok(items)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(items) => Ok(items),
+        }
+    }
+
+    ///
+    /// Sessions
+    ///
+    fn get_or_create_session(&self, ifid: String) -> Result<DBSession, IfdbError> {
+        let result = || -> Result<DBSession, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT tools_open, details_open, debug_open,  transcript_active, command_out_active, transcript_name, command_out_name, clues_open, notes_open, map_open, last_clue_section, saves_open
+                FROM session WHERE ifid = ?1",
+            )?;
+            let mut query = statement.query(params![ifid])?;
+
+            if let Some(row) = query.next()? {
+                let tools_open: i32 = row.get(0)?;
+                let details_open: i32 = row.get(1)?;
+                let debug_open: i32 = row.get(2)?;
+                let transcript_active: i32 = row.get(3)?;
+                let command_out_active: i32 = row.get(4)?;
+                let transcript_name: String = row.get(5)?;
+                let command_out_name: String = row.get(6)?;
+                let clues_open: i32 = row.get(7)?;
+                let notes_open: i32 = row.get(8)?;
+                let map_open: i32 = row.get(9)?;
+                let last_clue_section: String = row.get(10)?;
+                let saves_open: i32 = row.get(11)?;
+
+                Ok(DBSession {
+                    ifid,
+                    tools_open: tools_open != 0,
+                    details_open: details_open != 0,
+                    debug_open: debug_open != 0,
+                    transcript_active: transcript_active != 0,
+                    command_out_active: command_out_active != 0,
+                    transcript_name,
+                    command_out_name,
+                    clues_open: clues_open != 0,
+                    notes_open: notes_open != 0,
+                    map_open: map_open != 0,
+                    saves_open: saves_open != 0,
+                    last_clue_section,
+                })
+            } else {
+                // Create a new session with defaults if it doesn't exist
+                let transcript_name = format!("transcript_{}.log", ifid);
+                let command_out_name = format!("commands_{}.commands", ifid);
+                self.connection.execute("INSERT INTO session 
+                (ifid, tools_open, details_open, debug_open,  transcript_active, command_out_active, transcript_name, command_out_name,clues_open, notes_open, map_open,saves_open, last_clue_section) 
+                VALUES (?1,0,0,0,0,0,?2,?3,0,0,0,0,'')",params![ifid, transcript_name, command_out_name])?;
+                Ok(DBSession {
+                    ifid,
+                    tools_open: false,
+                    details_open: false,
+                    debug_open: false,
+                    transcript_name,
+                    transcript_active: false,
+                    command_out_name,
+                    command_out_active: false,
+                    clues_open: false,
+                    notes_open: false,
+                    map_open: false,
+                    saves_open: false,
+                    last_clue_section: String::new(),
+                })
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(session) => Ok(session),
+        }
+    }
+
+    fn store_session(&self, session: DBSession) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE session SET tools_open = ?1, details_open= ?2, debug_open= ?3,  transcript_active= ?4, command_out_active= ?5, transcript_name= ?6, command_out_name= ?7, clues_open=?8, notes_open=?9, map_open=?10, last_clue_section=?11, saves_open=?12 WHERE ifid = ?13",
+                params![bool_to_int(session.tools_open),bool_to_int(session.details_open),bool_to_int(session.debug_open),
+                bool_to_int(session.transcript_active),bool_to_int(session.command_out_active),
+                session.transcript_name,session.command_out_name,
+                bool_to_int(session.clues_open),bool_to_int(session.notes_open),bool_to_int(session.map_open),
+                session.last_clue_section,
+                bool_to_int(session.saves_open),
+                session.ifid],
+            )?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    ///
+    /// Clues
+    ///
+    fn count_clues(&self) -> Result<u32, IfdbError> {
+        let result = || -> Result<u32, rusqlite::Error> {
+            let mut statement = self.connection.prepare("SELECT count(id) FROM clue")?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(0)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn reveal_clue(&self, clue_id: u32) -> Result<Option<Clue>, IfdbError> {
+        self.cache.clear_clues();
+        let result = || -> Result<Option<Clue>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT revealed, text FROM clue WHERE id = ?1")?;
+
+            let mut query = statement.query(params![clue_id])?;
+
+            let mut clue = None;
+
+            if let Some(row) = query.next()? {
+                let is_revealed: u32 = row.get(0)?;
+                let text: String = row.get(1)?;
+
+                clue = Some(Clue {
+                    dbid: clue_id,
+                    is_revealed: is_revealed == 1,
+                    text,
+                });
+            }
+
+            if clue.is_some() {
+                self.connection.execute(
+                    "UpDATE clue SET revealed=1  WHERE id = ?1",
+                    params![clue_id],
+                )?;
+            }
+
+            Ok(clue)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(clues) => Ok(clues),
+        }
+    }
+
+    fn hide_clue(&self, clue_id: u32) -> Result<(), IfdbError> {
+        self.cache.clear_clues();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE clue SET revealed=0  WHERE id = ?1",
+                params![clue_id],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn story_has_clues(&self, story_id: u32) -> Result<bool, IfdbError> {
+        let result = || -> Result<bool, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT s.id FROM clue_section s  WHERE s.story_id = ?1")?;
+
+            let mut query = statement.query(params![story_id])?;
+
+            Ok(query.next()?.is_some())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(clues) => Ok(clues),
+        }
+    }
+
+    fn get_clues_for_story(&self, story_id: u32) -> Result<Vec<ClueSection>, IfdbError> {
+        if let Some(clues) = self.cache.get_clues(story_id) {
+            return Ok(clues);
+        }
+
+        let result = || -> Result<Vec<ClueSection>, rusqlite::Error> {
+            let mut sections = HashMap::new();
+            let mut section_keys = vec![];
+
+            let mut statement = self.connection.prepare(
+                "SELECT s.name, s.id FROM clue_section s  WHERE s.story_id = ?1 ORDER BY s.id",
+            )?;
+
+            let mut query = statement.query(params![story_id])?;
+
+            while let Some(row) = query.next()? {
+                let section_name: String = row.get(0)?;
+                let dbid: u32 = row.get(1)?;
+
+                if !sections.contains_key(&section_name) {
+                    section_keys.push(section_name.clone());
+                    sections.insert(
+                        section_name.clone(),
+                        ClueSection {
+                            dbid,
+                            story_id,
+                            name: section_name.clone(),
+                            subsections: vec![],
+                        },
+                    );
+                }
+            }
+
+            // Run again, map subsections
+            let mut statement = self
+                .connection
+                .prepare("SELECT s.name, sb.name, sb.id FROM clue_subsection sb JOIN clue_section s ON s.id = sb.section_id WHERE s.story_id = ?1 ORDER BY sb.id")?;
+
+            let mut query = statement.query(params![story_id])?;
+
+            while let Some(row) = query.next()? {
+                let section_name: String = row.get(0)?;
+                let subsection_name: String = row.get(1)?;
+                let dbid: u32 = row.get(2)?;
+
+                sections.entry(section_name).and_modify(|section| {
+                    section.subsections.push(ClueSubsection {
+                        dbid,
+                        name: subsection_name.clone(),
+                        clues: vec![],
+                    });
+                });
+            }
+
+            // Run again, mapping clues
+            let mut statement = self.connection.prepare(
+                "SELECT s.name, sb.name,  c.text, c.revealed,c.id 
+                     FROM clue c
+                      JOIN clue_subsection sb ON sb.id = c.subsection_id
+                      JOIN clue_section s ON s.id = sb.section_id 
+                      WHERE s.story_id = ?1 
+                      ORDER BY c.id",
+            )?;
+
+            let mut query = statement.query(params![story_id])?;
+
+            while let Some(row) = query.next()? {
+                let section_name: String = row.get(0)?;
+                let subsection_name: String = row.get(1)?;
+                let text: String = row.get(2)?;
+                let is_revealed: u32 = row.get(3)?;
+                let dbid: u32 = row.get(4)?;
+
+                sections.entry(section_name).and_modify(|section| {
+                    for subsection in &mut section.subsections {
+                        if subsection.name == subsection_name {
+                            let mut clue = Clue {
+                                dbid,
+                                text: text.clone(),
+                                is_revealed: false,
+                            };
+                            if is_revealed == 1 {
+                                clue.is_revealed = true;
+                                clue.text = clue.revealed_text();
+                            }
+                            subsection.clues.push(clue);
+                        }
+                    }
+                });
+            }
+
+            // Convert to results
+            let mut results = vec![];
+            for k in section_keys {
+                results.push(sections.get(&k).unwrap().clone());
+            }
+
+            Ok(results)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(clues) => Ok(self.cache.store_clues(story_id, clues)),
+        }
+    }
+
+    fn add_clue(
+        &self,
+        story_id: u32,
+        section_name: String,
+        subsection_name: String,
+        clue_text: String,
+    ) -> Result<(), IfdbError> {
+        self.cache.clear_clues();
+        let result = || -> Result<(), rusqlite::Error> {
+            // Get or create the section
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM clue_section WHERE story_id = ?1 AND name=?2")?;
+
+            let mut query = statement.query(params![story_id, section_name])?;
+
+            let mut section_id: u32 = 0;
+
+            if let Some(row) = query.next()? {
+                section_id = row.get(0)?;
+            }
+
+            if section_id == 0 {
+                self.connection.execute(
+                    "INSERT INTO clue_section (story_id, name) VALUES (?1,?2)",
+                    params![story_id, section_name],
+                )?;
+
+                section_id = self.connection.last_insert_rowid() as u32;
+            }
+
+            // Get or create the subsection
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM clue_subsection WHERE section_id = ?1 AND name=?2")?;
+
+            let mut query = statement.query(params![section_id, subsection_name])?;
+
+            let mut subsection_id: u32 = 0;
+
+            if let Some(row) = query.next()? {
+                subsection_id = row.get(0)?;
+            }
+
+            if subsection_id == 0 {
+                self.connection.execute(
+                    "INSERT INTO clue_subsection (section_id, name) VALUES (?1,?2)",
+                    params![section_id, subsection_name],
+                )?;
+
+                subsection_id = self.connection.last_insert_rowid() as u32;
+            }
+
+            // Create clue if needed
+            let mut statement = self
+                .connection
+                .prepare("SELECT id FROM clue WHERE subsection_id = ?1 AND text=?2")?;
+
+            let mut query = statement.query(params![subsection_id, clue_text])?;
+
+            if query.next()?.is_none() {
+                self.connection.execute(
+                    "INSERT INTO clue (subsection_id, text, revealed) VALUES (?1,?2,0)",
+                    params![subsection_id, clue_text],
+                )?;
+            }
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    ///
+    /// Notes
+    ///
+    fn count_notes(&self) -> Result<u32, IfdbError> {
+        let result = || -> Result<u32, rusqlite::Error> {
+            let mut statement = self.connection.prepare("SELECT count(id) FROM notes")?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                Ok(row.get(0)?)
+            } else {
+                Ok(0)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(data) => Ok(data),
+        }
+    }
+
+    fn get_notes_for_story(
+        &self,
+        story_id: i64,
+        include_done: bool,
+    ) -> Result<Vec<Note>, IfdbError> {
+        if let Some(notes) = self.cache.get_notes(story_id, include_done) {
+            return Ok(notes);
+        }
+
+        let result = || -> Result<Vec<Note>, rusqlite::Error> {
+            let mut notes = vec![];
+            let mut sql = String::from("SELECT notes.id, notes.room_id, r.name, notes, done FROM notes LEFT JOIN map_room r ON r.story_id = notes.story_id AND r.room_id=notes.room_id  WHERE notes.story_id = ?1");
+
+            if !include_done {
+                sql.push_str(" AND done = 0 ");
+            }
+
+            sql.push_str(" ORDER BY notes.room_id, notes.id");
+
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let mut query = statement.query(params![story_id])?;
+            while let Some(row) = query.next()? {
+                let dbid: i64 = row.get(0)?;
+                let room_id: i32 = row.get(1)?;
+                let room_name: Option<String> = row.get(2)?;
+                let note_text: String = row.get(3)?;
+                let done: i32 = row.get(4)?;
+
+                notes.push(Note {
+                    dbid,
+                    story_id,
+                    room_name,
+                    room_id: room_id as u32,
+                    notes: note_text,
+                    done: done == 1,
+                });
+            }
+
+            Ok(notes)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(notes) => Ok(self.cache.store_notes(story_id, include_done, notes)),
+        }
+    }
+
+    fn set_note_done(&self, dbid: i64, done: bool) -> Result<(), IfdbError> {
+        self.cache.clear_notes();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UpDATE notes SET done=?1 WHERE id = ?2",
+                params![bool_to_int(done), dbid],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn set_note_notes(&self, dbid: i64, notes: String, room_id: i32) -> Result<(), IfdbError> {
+        self.cache.clear_notes();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE notes SET notes=?1, room_id=?2 WHERE id = ?3",
+                params![notes, room_id, dbid],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn save_note(&self, note: Note) -> Result<i64, IfdbError> {
+        self.cache.clear_notes();
+        let result = || -> Result<i64, rusqlite::Error> {
+            // Insert the room into the map for future reference, if no map record exists
+            if let Some(room_name) = note.room_name {
+                self.connection.execute(
+                    "INSERT INTO map_room(story_id,room_id,name) 
+                SELECT ?1,?2,?3
+                WHERE NOT EXISTS(SELECT 1 FROM map_room WHERE story_id = ?4 AND room_id = ?5)",
+                    params![
+                        note.story_id,
+                        note.room_id,
+                        room_name,
+                        note.story_id,
+                        note.room_id
+                    ],
+                )?;
+            }
+            if note.dbid != 0 {
+                self.connection.execute(
+                    "UpDATE notes SET story_id = ?1, room_id = ?2, notes = ?3, done = ?4 WHERE id = ?5",
+                    params![note.story_id, note.room_id, note.notes,bool_to_int(note.done), note.dbid],
+                )?;
+
+                Ok(note.dbid)
+            } else {
+                self.connection.execute(
+                    "INSERT INTO notes (story_id, room_id, notes, done) VALUES (?1,?2,?3,?4)",
+                    params![
+                        note.story_id,
+                        note.room_id,
+                        note.notes,
+                        bool_to_int(note.done)
+                    ],
+                )?;
+
+                Ok(self.connection.last_insert_rowid())
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(dbid) => Ok(dbid),
+        }
+    }
+
+    ///
+    /// Mapping
+    ///
+    fn get_rooms_for_story(&self, story_id: u32) -> Result<Vec<MapRoom>, IfdbError> {
+        let result = || -> Result<Vec<MapRoom>, rusqlite::Error> {
+            let mut notes = vec![];
+            let  sql = String::from("SELECT r.id, r.room_id, r.name FROM map_room r WHERE r.story_id = ?1 ORDER BY r.name");
+
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let mut query = statement.query(params![story_id])?;
+            while let Some(row) = query.next()? {
+                let dbid: i64 = row.get(0)?;
+                let room_id: i32 = row.get(1)?;
+                let room_name: Option<String> = row.get(2)?;
+
+                notes.push(MapRoom {
+                    dbid,
+                    room_id: room_id as u32,
+                    name: match room_name {
+                        None => String::from("Nowhere"),
+                        Some(s) => s,
+                    },
+                    story_id: story_id as i64,
+                });
+            }
+
+            Ok(notes)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(rooms) => Ok(rooms),
+        }
+    }
+
+    ///
+    /// Tags and shelves
+    ///
+    fn fetch_tags(&self) -> Result<Vec<String>, IfdbError> {
+        let result = || -> Result<Vec<String>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT name FROM tag ORDER BY name COLLATE NOCASE")?;
+            let rows = statement.query_map(params![], |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(tags) => Ok(tags),
+        }
+    }
+
+    fn fetch_tags_by_story(&self) -> Result<HashMap<u32, Vec<String>>, IfdbError> {
+        if let Some(tags) = self.cache.get_tags_by_story() {
+            return Ok(tags);
+        }
+
+        let result = || -> Result<HashMap<u32, Vec<String>>, rusqlite::Error> {
+            let mut tags: HashMap<u32, Vec<String>> = HashMap::new();
+            let mut statement = self.connection.prepare(
+                "SELECT st.story_id, t.name FROM story_tag st
+                JOIN tag t ON t.id = st.tag_id
+                ORDER BY t.name COLLATE NOCASE",
+            )?;
+
+            let mut query = statement.query(params![])?;
+            while let Some(row) = query.next()? {
+                let story_id: u32 = row.get(0)?;
+                tags.entry(story_id).or_default().push(row.get(1)?);
+            }
+
+            Ok(tags)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(tags) => Ok(self.cache.store_tags_by_story(tags)),
+        }
+    }
+
+    fn add_tag_to_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(IfdbError::Invalid(String::from("Please enter a tag name.")));
+        }
+
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "INSERT OR IGNORE INTO tag (name) VALUES (?1)",
+                params![name],
+            )?;
+            self.connection.execute(
+                "INSERT OR IGNORE INTO story_tag (story_id, tag_id) SELECT ?1, id FROM tag WHERE name = ?2",
+                params![story_id, name],
+            )?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn remove_tag_from_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "DELETE FROM story_tag WHERE story_id = ?1 AND tag_id IN (SELECT id FROM tag WHERE name = ?2)",
+                params![story_id, name],
+            )?;
+            self.delete_unused_tags()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn fetch_shelves(&self) -> Result<Vec<DbShelf>, IfdbError> {
+        let result = || -> Result<Vec<DbShelf>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT id, name, query FROM shelf ORDER BY name COLLATE NOCASE")?;
+            let rows = statement.query_map(params![], Self::shelf_from_row)?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(shelves) => Ok(shelves),
+        }
+    }
+
+    fn create_shelf(&self, name: &str, query: Option<ShelfQuery>) -> Result<i64, IfdbError> {
+        self.cache.clear_library();
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(IfdbError::Invalid(String::from(
+                "Please enter a shelf name.",
+            )));
+        }
+
+        let result = || -> Result<i64, rusqlite::Error> {
+            self.connection.execute(
+                "INSERT INTO shelf (name, query) VALUES (?1, ?2)",
+                params![name, query.map(|q| serde_json::to_string(&q).unwrap())],
+            )?;
+            Ok(self.connection.last_insert_rowid())
+        }();
+
+        match result {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(IfdbError::Constraint(format!(
+                    "There is already a shelf named {}.",
+                    name
+                )))
+            }
+            Err(e) => Err(e.into()),
+            Ok(id) => Ok(id),
+        }
+    }
+
+    fn delete_shelf(&self, shelf_id: i64) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "DELETE FROM shelf_story WHERE shelf_id = ?1",
+                params![shelf_id],
+            )?;
+            self.connection
+                .execute("DELETE FROM shelf WHERE id = ?1", params![shelf_id])?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn fetch_shelf_ids_for_story(&self, story_id: u32) -> Result<Vec<i64>, IfdbError> {
+        let result = || -> Result<Vec<i64>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT shelf_id FROM shelf_story WHERE story_id = ?1")?;
+            let rows = statement.query_map(params![story_id], |row| row.get(0))?;
+            rows.collect()
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(ids) => Ok(ids),
+        }
+    }
+
+    fn add_story_to_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "INSERT OR IGNORE INTO shelf_story (shelf_id, story_id) VALUES (?1, ?2)",
+                params![shelf_id, story_id],
+            )?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn remove_story_from_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError> {
+        self.cache.clear_library();
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "DELETE FROM shelf_story WHERE shelf_id = ?1 AND story_id = ?2",
+                params![shelf_id, story_id],
+            )?;
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    ///
+    /// Fonts
+    ///
+    fn get_fonts(&self) -> Result<Vec<DbFont>, IfdbError> {
+        let result = || -> Result<Vec<DbFont>, rusqlite::Error> {
+            let mut fonts = vec![];
+            let sql = String::from(
+                "SELECT f.id, f.name, f.data, f.monospace FROM fonts f   ORDER BY name",
+            );
+
+            let mut statement = self.connection.prepare(sql.as_str())?;
+
+            let mut query = statement.query(params![])?;
+            while let Some(row) = query.next()? {
+                let dbid: i64 = row.get(0)?;
+                let name: String = row.get(1)?;
+                let data: Vec<u8> = row.get(2)?;
+                let monospace: bool = row.get(3)?;
+
+                fonts.push(DbFont {
+                    dbid,
+                    name,
+                    data,
+                    monospace,
+                });
+            }
+
+            Ok(fonts)
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(fonts) => Ok(fonts),
+        }
+    }
+
+    fn add_font(&self, name: &str, data: Vec<u8>, monospace: bool) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "INSERT INTO fonts (name, data, monospace) VALUES (?1,?2,?3)",
+                params![name, data, monospace],
+            )?;
+            Ok(())
+        }();
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn update_font_metadata(&self, font: DbFont) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE fonts SET name=?1,  monospace=?2 WHERE id=?3",
+                params![font.name, font.monospace, font.dbid],
+            )?;
+            Ok(())
+        }();
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn delete_font(&self, dbid: i64) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection
+                .execute("DELETE FROM fonts WHERE id=?1", params![dbid])?;
+            Ok(())
+        }();
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    ///
+    /// Preferences/Themes
+    ///
+    fn get_theme(&self, name: &str) -> Result<Option<DbTheme>, IfdbError> {
+        let result = || -> Result<Option<DbTheme>, rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT theme_type, font_size, font_id, 
+                 background_color_r, background_color_g,background_color_b, background_color_a,
+                  text_color_r, text_color_g,text_color_b, text_color_a, 
+                  stroke_color_r, stroke_color_g,stroke_color_b, stroke_color_a,
+                  secondary_background_color_r, secondary_background_color_g,secondary_background_color_b, secondary_background_color_a
+                   FROM themes WHERE name = ?1",
+            )?;
+            let mut query = statement.query(params![name])?;
+
+            if let Some(row) = query.next()? {
+                let theme_type: String = row.get(0)?;
+                let theme = DbTheme {
+                    name: name.to_string(),
+                    theme_type: match theme_type.as_str() {
+                        CUSTOM_THEME => ThemeType::Custom,
+                        DARK_THEME => ThemeType::Dark,
+                        _ => ThemeType::Light,
+                    },
+                    font_size: row.get(1)?,
+                    font_id: row.get(2)?,
+                    background_color: self.color_from_components(
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                        row.get(6)?,
+                    ),
+                    text_color: self.color_from_components(
+                        row.get(7)?,
+                        row.get(8)?,
+                        row.get(9)?,
+                        row.get(10)?,
+                    ),
+                    stroke_color: self.color_from_components(
+                        row.get(11)?,
+                        row.get(12)?,
+                        row.get(13)?,
+                        row.get(14)?,
+                    ),
+                    secondary_background_color: self.color_from_components(
+                        row.get(15)?,
+                        row.get(16)?,
+                        row.get(17)?,
+                        row.get(18)?,
+                    ),
+                };
+                Ok(Some(theme))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(result) => Ok(result),
+        }
+    }
+
+    fn store_theme(&self, theme: DbTheme) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            // Lazier than checking for update but works
+            self.connection
+                .execute("DELETE FROM themes WHERE name=?1", params![theme.name])?;
+            self.connection.execute(
+                "INSERT INTO themes (name,font_size, font_id, theme_type
+                ) VALUES (?1,?2,?3,?4);",
+                params![
+                    theme.name,
+                    theme.font_size,
+                    theme.font_id,
+                    match theme.theme_type {
+                        ThemeType::Custom => CUSTOM_THEME,
+                        ThemeType::Dark => DARK_THEME,
+                        ThemeType::Light => LIGHT_THEME,
+                    }
+                ],
+            )?;
+
+            if let Some(color) = theme.background_color {
+                self.connection.execute(
+                    "UPDATE themes SET background_color_r = ?1,
+                                background_color_g = ?2,
+                                background_color_b = ?3,
+                                background_color_a = ?4
+                                WHERE name = ?5
+                                ",
+                    params![color.r, color.g, color.b, color.a, theme.name],
+                )?;
+            }
+
+            if let Some(color) = theme.text_color {
+                self.connection.execute(
+                    "UPDATE themes SET text_color_r = ?1,
+                                text_color_g = ?2,
+                                text_color_b = ?3,
+                                text_color_a = ?4
+                                WHERE name = ?5
+                                ",
+                    params![color.r, color.g, color.b, color.a, theme.name],
+                )?;
+            }
+
+            if let Some(color) = theme.stroke_color {
+                self.connection.execute(
+                    "UPDATE themes SET stroke_color_r = ?1,
+                                    stroke_color_g = ?2,
+                                    stroke_color_b = ?3,
+                                    stroke_color_a = ?4
+                                    WHERE name = ?5
+                                ",
+                    params![color.r, color.g, color.b, color.a, theme.name],
+                )?;
+            }
+
+            if let Some(color) = theme.secondary_background_color {
+                self.connection.execute(
+                    "UPDATE themes SET secondary_background_color_r = ?1,
+                                    secondary_background_color_g = ?2,
+                                    secondary_background_color_b = ?3,
+                                    secondary_background_color_a = ?4
+                                    WHERE name = ?5
+                                ",
+                    params![color.r, color.g, color.b, color.a, theme.name],
+                )?;
+            }
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    ///
+    /// Windows and settings
+    ///
+    fn get_window_details(
+        &self,
+        story_id: u32,
+        window_type: WindowType,
+    ) -> Result<Option<WindowDetails>, IfdbError> {
+        let result = || -> Result<Option<WindowDetails>, rusqlite::Error> {
+            let mut details = WindowDetails {
+                dbid: 0,
+                story_id: story_id as i64,
+                window_type: window_type.clone(),
+                x: 0f64,
+                y: 0f64,
+                width: 0f64,
+                height: 0f64,
+                open: false,
+            };
+            let mut statement = self
+                .connection
+                .prepare("SELECT id, x,y,width,height,open FROM window_details WHERE story_id = ?1 AND window_type = ?2")?;
+
+            let mut query = statement.query(params![story_id, window_type.to_string()])?;
+
+            if let Some(row) = query.next()? {
+                details.dbid = row.get(0)?;
+                details.x = row.get(1)?;
+                details.y = row.get(2)?;
+                details.width = row.get(3)?;
+                details.height = row.get(4)?;
+                let open: i64 = row.get(5)?;
+                details.open = open == 1;
+                Ok(Some(details))
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(details) => Ok(details),
+        }
+    }
+
+    fn store_window_details(&self, details: &mut WindowDetails) -> Result<(), IfdbError> {
+        let result = || -> Result<(), rusqlite::Error> {
+            if details.dbid == 0 {
+                self.insert_window_details(details)
+            } else {
+                self.update_window_details(details)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn store_current_story(&self, story_id: Option<i64>) -> Result<(), IfdbError> {
+        self.initialize_settings_if_needed()?;
+
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE settings set playing_story_id = ?1",
+                params![story_id],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn get_current_story(&self) -> Result<Option<i64>, IfdbError> {
+        let result = || -> Result<Option<i64>, rusqlite::Error> {
+            let mut statement = self
+                .connection
+                .prepare("SELECT playing_story_id from settings")?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                let id: Option<i64> = row.get(0)?;
+                Ok(id)
+            } else {
+                Ok(None)
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(id) => Ok(id),
+        }
+    }
+
+    fn get_story_list_settings(&self) -> Result<(StorySort, StoryFilter), IfdbError> {
+        let result = || -> Result<(StorySort, StoryFilter), rusqlite::Error> {
+            let mut statement = self.connection.prepare(
+                "SELECT story_sort, filter_genre, filter_language, filter_forgiveness, filter_series, filter_tag, filter_shelf_id, filter_play_status from settings",
+            )?;
+
+            let mut query = statement.query(params![])?;
+
+            if let Some(row) = query.next()? {
+                let sort: Option<String> = row.get(0)?;
+                let play_status: Option<String> = row.get(7)?;
+                Ok((
+                    sort.map(|s| StorySort::from_db_str(&s))
+                        .unwrap_or(StorySort::Title),
+                    StoryFilter {
+                        genre: row.get(1)?,
+                        language: row.get(2)?,
+                        forgiveness: row.get(3)?,
+                        series: row.get(4)?,
+                        tag: row.get(5)?,
+                        shelf_id: row.get(6)?,
+                        play_status: play_status.map(|s| PlayStatus::from_db_str(&s)),
+                    },
+                ))
+            } else {
+                Ok((StorySort::Title, StoryFilter::default()))
+            }
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(settings) => Ok(settings),
+        }
+    }
+
+    fn store_story_list_settings(
+        &self,
+        sort: StorySort,
+        filter: &StoryFilter,
+    ) -> Result<(), IfdbError> {
+        self.initialize_settings_if_needed()?;
+
+        let result = || -> Result<(), rusqlite::Error> {
+            self.connection.execute(
+                "UPDATE settings set story_sort = ?1, filter_genre = ?2, filter_language = ?3, filter_forgiveness = ?4, filter_series = ?5, filter_tag = ?6, filter_shelf_id = ?7, filter_play_status = ?8",
+                params![
+                    sort.to_string(),
+                    filter.genre,
+                    filter.language,
+                    filter.forgiveness,
+                    filter.series,
+                    filter.tag,
+                    filter.shelf_id,
+                    filter.play_status.map(PlayStatus::to_string)
+                ],
+            )?;
+
+            Ok(())
+        }();
+
+        match result {
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,546 @@
+///
+/// Storage used by the library and the interpreter windows. Implemented by the SQLite
+/// database, and by an in-memory store for tests
+///
+use super::ifiction::Story;
+use super::quetzal::read_quetzal_header;
+use super::{
+    describe_story_file_replacement, extract_ifid_from_bytes, Clue, ClueSection, DBSession, DbFont,
+    DbSave, DbShelf, DbStory, DbStoryHistory, DbStoryRelease, DbStoryResource, DbTheme,
+    DbTrashItem, IfdbError, MapRoom, Note, SaveType, ShelfQuery, StoryFilter, StoryFilterField,
+    StoryReview, StorySort, StorySummary, TrashContents, WindowDetails, WindowType,
+    DEFAULT_SAVE_VERSION, MAX_SAVE_NAME_ATTEMPTS,
+};
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+
+pub trait IfdbStore {
+    /// Take the result of a database call from the connection and always return a default value,
+    /// outputting any error to log
+    fn wrap_db_error(&self, r: Result<u32, IfdbError>) -> u32 {
+        match r {
+            Ok(v) => v,
+            Err(msg) => {
+                println!("Error running sql. {}", msg);
+                0
+            }
+        }
+    }
+
+    ///
+    /// Stories
+    ///
+    /// Count all stories in the database and return the result
+    fn count_stories(&self) -> Result<u32, IfdbError>;
+
+    /// Return the story ID for an IFID, or None if story is not in the database
+    fn get_story_id(&self, ifid: &str) -> Result<Option<u32>, IfdbError>;
+
+    /// Return story data for a given ifid and story id
+    fn get_story_data(&self, story_id: u32, ifid: &str) -> Result<Option<Vec<u8>>, IfdbError>;
+
+    /// Return a story for a given id
+    fn get_story(&self, story_id: u32) -> Result<Option<DbStory>, IfdbError>;
+
+    /// Permanently delete a story and everything stored with it
+    fn delete_story(&self, story_id: u32) -> Result<(), IfdbError>;
+
+    /// Return ifids -- all if no story id, or for a specific story with id
+    #[allow(dead_code)]
+    fn fetch_ifids_for_story(
+        &self,
+        story_id: u32,
+        playable: bool,
+    ) -> Result<Vec<String>, IfdbError>;
+
+    /// Return a summary of each story, with the IFID of the release Play launches
+    fn fetch_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+    ) -> Result<Vec<StorySummary>, IfdbError> {
+        self.fetch_sorted_story_summaries(has_data, search_text, None, &StoryFilter::default())
+    }
+
+    /// Return story summaries matching the search text and filter. With no sort, the best
+    /// search matches come first, otherwise matches are only used to break ties
+    fn fetch_sorted_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+    ) -> Result<Vec<StorySummary>, IfdbError>;
+
+    /// Count the story summaries matching the search text and filter. If before_title is set,
+    /// only summaries with titles sorting before it are counted, which is the row the first
+    /// title starting with it has when sorted by title
+    fn count_story_summaries(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        filter: &StoryFilter,
+        before_title: Option<&str>,
+    ) -> Result<usize, IfdbError> {
+        let summaries = self.fetch_sorted_story_summaries(has_data, search_text, None, filter)?;
+        Ok(match before_title {
+            Some(title) => {
+                let title = title.to_ascii_lowercase();
+                summaries
+                    .iter()
+                    .filter(|s| s.title.to_ascii_lowercase() < title)
+                    .count()
+            }
+            None => summaries.len(),
+        })
+    }
+
+    /// Return limit story summaries starting from offset, in the same order as
+    /// fetch_sorted_story_summaries. Used to show large libraries a page at a time
+    fn fetch_story_summary_page(
+        &self,
+        has_data: bool,
+        search_text: Option<&str>,
+        sort: Option<StorySort>,
+        filter: &StoryFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<StorySummary>, IfdbError> {
+        Ok(self
+            .fetch_sorted_story_summaries(has_data, search_text, sort, filter)?
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+
+    /// Return the values used for a field by playable stories, for filtering the story list
+    fn fetch_story_filter_values(&self, field: StoryFilterField) -> Result<Vec<String>, IfdbError>;
+
+    /// Return the playable releases of a story, in the order they were imported
+    fn fetch_story_releases(&self, story_id: u32) -> Result<Vec<DbStoryRelease>, IfdbError>;
+
+    /// Choose the release Play launches for a story. Until one is chosen, Play launches the
+    /// most recently imported
+    fn store_preferred_release(&self, story_id: u32, ifid: &str) -> Result<(), IfdbError>;
+
+    /// Return story summary for a particular db id, or None
+    fn get_story_summary_by_id(&self, story_id: u32) -> Result<Option<StorySummary>, IfdbError>;
+
+    /// Return story summary for a particular IFID, or None
+    fn get_story_summary_by_ifid(&self, ifid: &str) -> Result<Option<StorySummary>, IfdbError> {
+        if let Ok(Some(story_id)) = self.get_story_id_for_ifid(ifid, false) {
+            return self.get_story_summary_by_id(story_id);
+        }
+
+        Ok(None)
+    }
+
+    /// Return all ifids, linked to story data
+    fn get_story_id_for_ifid(
+        &self,
+        ifid: &str,
+        playable_only: bool,
+    ) -> Result<Option<u32>, IfdbError>;
+
+    /// Insert story information from a Story object
+    fn create_story(&self, story: Story) -> Result<(), IfdbError>;
+
+    /// Convienience method to set the last played for a story
+    fn update_last_played_to_now(&self, story_id: i64) -> Result<(), IfdbError>;
+
+    /// Return the player's status, rating and review for a story
+    fn get_story_review(&self, story_id: u32) -> Result<Option<StoryReview>, IfdbError>;
+
+    /// Store the player's status, rating and review for a story
+    fn update_story_review(&self, story_id: u32, review: &StoryReview) -> Result<(), IfdbError>;
+
+    /// Add to time played for a story
+    fn add_to_time_played(&self, story_id: i64, elapsed: i64) -> Result<(), IfdbError>;
+
+    /// Update story information from a DbStory object
+    #[allow(dead_code)]
+    fn update_story(&self, dbstory: DbStory) -> Result<(), IfdbError>;
+
+    /// Return the auxiliary files for a story, without their data
+    fn fetch_story_resources(&self, story_id: u32) -> Result<Vec<DbStoryResource>, IfdbError>;
+
+    /// Return the contents of an auxiliary file, if it was stored
+    fn get_resource_data(&self, resource_id: i64) -> Result<Option<Vec<u8>>, IfdbError>;
+
+    /// Add a cover image for an ifid. Record must exist.
+    fn store_cover_image(&self, ifid: &str, data: Vec<u8>) -> Result<(), IfdbError>;
+
+    /// Return a story's cover image as it was imported, if it has one
+    fn fetch_cover_image(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError>;
+
+    /// Return the png thumbnail made when a story's cover was stored. None if the story has no
+    /// cover, or it can't be decoded
+    fn fetch_cover_thumbnail(&self, story_id: u32) -> Result<Option<Vec<u8>>, IfdbError>;
+
+    /// Set the story data for an ifid
+    fn add_story_data(
+        &self,
+        ifid: &str,
+        data: Vec<u8>,
+        default_name: &str,
+    ) -> Result<(), IfdbError>;
+
+    /// Replace the story file of one of a story's releases, keeping its saves, sessions and
+    /// everything else stored with the story. The new file may be a later release with
+    /// another IFID, which the release and its saves and session move to, but not one already
+    /// in the library. Returns the entry recorded in the story's history
+    fn replace_story_file(
+        &self,
+        story_id: u32,
+        ifid: &str,
+        data: Vec<u8>,
+        filename: &str,
+    ) -> Result<DbStoryHistory, IfdbError> {
+        let old = self.get_story_data(story_id, ifid)?.ok_or_else(|| {
+            IfdbError::NotFound(format!(
+                "No story file for IFID {} in story {}",
+                ifid, story_id
+            ))
+        })?;
+        let new_ifid = extract_ifid_from_bytes(&data).map_err(IfdbError::Invalid)?;
+        if new_ifid != ifid {
+            match self.get_story_id_for_ifid(&new_ifid, false)? {
+                Some(other_id) if other_id == story_id => {
+                    return Err(IfdbError::Constraint(format!(
+                        "{} is the story file for another release of this story, with IFID {}",
+                        filename, new_ifid
+                    )))
+                }
+                Some(_) => {
+                    return Err(IfdbError::Constraint(format!(
+                        "{} is a story file for another story, with IFID {}",
+                        filename, new_ifid
+                    )))
+                }
+                None => (),
+            }
+        }
+
+        let description = describe_story_file_replacement(filename, &old, &data);
+        self.replace_story_data(story_id, ifid, &new_ifid, data, &description)
+    }
+
+    /// Store the replacement story file for a release and record it in the story's history,
+    /// together. If the IFID changes, the release, its saves, its session and its history
+    /// move to the new one
+    fn replace_story_data(
+        &self,
+        story_id: u32,
+        ifid: &str,
+        new_ifid: &str,
+        data: Vec<u8>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError>;
+
+    /// Record something done to a story in its history
+    fn add_story_history(
+        &self,
+        story_id: u32,
+        ifid: Option<&str>,
+        description: &str,
+    ) -> Result<DbStoryHistory, IfdbError>;
+
+    /// Return a story's history, most recent first
+    fn fetch_story_history(&self, story_id: u32) -> Result<Vec<DbStoryHistory>, IfdbError>;
+
+    ///
+    /// Saves
+    ///
+    /// Return count of all saves in the database
+    fn count_saves(&self) -> Result<u32, IfdbError>;
+
+    /// Return count of all autosaves for a story
+    fn count_autosaves_for_story(&self, ifid: String) -> Result<u32, IfdbError>;
+
+    /// Return the save for the given (case sensitive) name/ifid, or None if no such save
+    fn get_save(&self, ifid: String, name: String) -> Result<Option<DbSave>, IfdbError>;
+
+    /// Return the save for the given numeric id (within a given story), or None if no such save
+    #[allow(dead_code)]
+    fn get_save_by_id(&self, ifid: String, dbid: i64) -> Result<Option<DbSave>, IfdbError>;
+
+    /// Return all saves for the given ifid, ordered with most recent first
+    fn fetch_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError>;
+
+    /// Return manual only saves for the given ifid, ordered with most recent first
+    fn fetch_manual_saves_for_ifid(&self, ifid: String) -> Result<Vec<DbSave>, IfdbError>;
+
+    /// Delete all autosaves for the story with the given IFID. They are moved to the trash,
+    /// where they can be restored
+    fn delete_autosaves_for_story(&self, ifid: String) -> Result<(), IfdbError> {
+        self.set_autosaves_trashed(&ifid, true)
+    }
+
+    /// Store the given save data to the database, returning any errors
+    fn store_save(&self, dbsave: &DbSave, overwrite: bool) -> Result<i64, IfdbError>;
+
+    /// Return the IFID of the story file with the given release, serial and checksum
+    fn find_ifid_for_release(
+        &self,
+        release: u16,
+        serial: &[u8; 6],
+        checksum: u16,
+    ) -> Result<Option<String>, IfdbError>;
+
+    /// Add Quetzal save data as a normal save, matching it to a story through the release,
+    /// serial and checksum in its header. If the name is taken a number is added to it.
+    /// Returns the IFID of the story the save was added to
+    fn import_quetzal_save(&self, data: Vec<u8>, name: &str) -> Result<String, IfdbError> {
+        let header = read_quetzal_header(&data).map_err(IfdbError::Corrupt)?;
+        let ifid =
+            match self.find_ifid_for_release(header.release, &header.serial, header.checksum)? {
+                Some(ifid) => ifid,
+                None => {
+                    return Err(IfdbError::NotFound(format!(
+                        "No story found for release {} serial {}",
+                        header.release,
+                        String::from_utf8_lossy(&header.serial)
+                    )))
+                }
+            };
+
+        let mut dbsave = DbSave {
+            dbid: 0,
+            version: DEFAULT_SAVE_VERSION,
+            ifid: ifid.clone(),
+            name: name.to_string(),
+            saved_when: format!("{}", Utc::now()),
+            save_type: SaveType::Normal,
+            data,
+            pc: header.pc as usize,
+            text_buffer_address: None,
+            parse_buffer_address: None,
+            next_pc: None,
+            left_status: None,
+            right_status: None,
+            latest_text: None,
+            parent_id: 0,
+            room_id: 0,
+        };
+
+        for attempt in 1..=MAX_SAVE_NAME_ATTEMPTS {
+            if attempt > 1 {
+                dbsave.name = format!("{} ({})", name, attempt);
+            }
+            match self.store_save(&dbsave, false) {
+                Ok(_) => return Ok(ifid),
+                Err(IfdbError::Constraint(_)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(IfdbError::Constraint(format!(
+            "Too many saves named {}",
+            name
+        )))
+    }
+
+    /// Write the Quetzal data for a save to a file
+    fn export_save(&self, ifid: String, dbid: i64, path: &str) -> Result<(), IfdbError> {
+        match self.get_save_by_id(ifid, dbid)? {
+            Some(save) => Ok(fs::write(path, save.data)?),
+            None => Err(IfdbError::NotFound(String::from("Save not found."))),
+        }
+    }
+
+    ///
+    /// Trash
+    ///
+    /// Move a story to the trash, or restore it. A story in the trash is hidden, along with
+    /// its saves, notes, clues and map, until it is restored or purged
+    fn set_story_trashed(&self, story_id: u32, trashed: bool) -> Result<(), IfdbError>;
+
+    /// Move the autosaves for a release to the trash, or restore them
+    fn set_autosaves_trashed(&self, ifid: &str, trashed: bool) -> Result<(), IfdbError>;
+
+    /// Permanently delete the autosaves for a release that are in the trash
+    fn purge_trashed_autosaves(&self, ifid: &str) -> Result<(), IfdbError>;
+
+    /// Return everything in the trash, most recently deleted first
+    fn fetch_trash(&self) -> Result<Vec<DbTrashItem>, IfdbError>;
+
+    /// Take an item out of the trash, showing it again
+    fn restore_from_trash(&self, item: &DbTrashItem) -> Result<(), IfdbError> {
+        match &item.contents {
+            TrashContents::Story => self.set_story_trashed(item.story_id, false),
+            TrashContents::Autosaves(ifid, _) => self.set_autosaves_trashed(ifid, false),
+        }
+    }
+
+    /// Permanently delete an item in the trash
+    fn purge_from_trash(&self, item: &DbTrashItem) -> Result<(), IfdbError> {
+        match &item.contents {
+            TrashContents::Story => self.delete_story(item.story_id),
+            TrashContents::Autosaves(ifid, _) => self.purge_trashed_autosaves(ifid),
+        }
+    }
+
+    /// Permanently delete everything in the trash, or only what was deleted before a time.
+    /// Returns how many items were purged
+    fn purge_trash(&self, deleted_before: Option<NaiveDateTime>) -> Result<usize, IfdbError> {
+        let items: Vec<DbTrashItem> = self
+            .fetch_trash()?
+            .into_iter()
+            .filter(|item| match deleted_before {
+                Some(before) => item.deleted_when < before,
+                None => true,
+            })
+            .collect();
+        for item in &items {
+            self.purge_from_trash(item)?;
+        }
+        Ok(items.len())
+    }
+
+    ///
+    /// Sessions
+    ///
+    /// Return the session for the given IFID, creating it if necessary
+    fn get_or_create_session(&self, ifid: String) -> Result<DBSession, IfdbError>;
+
+    fn store_session(&self, session: DBSession) -> Result<(), IfdbError>;
+
+    ///
+    /// Clues
+    ///
+    /// Count all clues in the database and return the result
+    fn count_clues(&self) -> Result<u32, IfdbError>;
+
+    fn reveal_clue(&self, clue_id: u32) -> Result<Option<Clue>, IfdbError>;
+
+    fn hide_clue(&self, clue_id: u32) -> Result<(), IfdbError>;
+
+    /// Does the story with the provided ID have clues?
+    #[allow(dead_code)]
+    fn story_has_clues(&self, story_id: u32) -> Result<bool, IfdbError>;
+
+    fn get_clues_for_story(&self, story_id: u32) -> Result<Vec<ClueSection>, IfdbError>;
+
+    fn add_clue(
+        &self,
+        story_id: u32,
+        section_name: String,
+        subsection_name: String,
+        clue_text: String,
+    ) -> Result<(), IfdbError>;
+
+    ///
+    /// Notes
+    ///
+    /// Return count of all notes in the database
+    fn count_notes(&self) -> Result<u32, IfdbError>;
+
+    fn get_notes_for_story(
+        &self,
+        story_id: i64,
+        include_done: bool,
+    ) -> Result<Vec<Note>, IfdbError>;
+
+    /// Shortcut method to change done status on note.
+    fn set_note_done(&self, dbid: i64, done: bool) -> Result<(), IfdbError>;
+
+    /// Shortcut method to change notes on note.
+    fn set_note_notes(&self, dbid: i64, notes: String, room_id: i32) -> Result<(), IfdbError>;
+
+    /// Save a note. Returns the dbid of the updated/saved note
+    fn save_note(&self, note: Note) -> Result<i64, IfdbError>;
+
+    ///
+    /// Mapping
+    ///
+    fn get_rooms_for_story(&self, story_id: u32) -> Result<Vec<MapRoom>, IfdbError>;
+
+    ///
+    /// Tags and shelves
+    ///
+    /// Return the names of all tags
+    fn fetch_tags(&self) -> Result<Vec<String>, IfdbError>;
+
+    /// Return the tag names for every story that has tags, keyed by story id
+    fn fetch_tags_by_story(&self) -> Result<HashMap<u32, Vec<String>>, IfdbError>;
+
+    /// Return the tag names for a story
+    fn fetch_tags_for_story(&self, story_id: u32) -> Result<Vec<String>, IfdbError> {
+        Ok(self
+            .fetch_tags_by_story()?
+            .remove(&story_id)
+            .unwrap_or_default())
+    }
+
+    /// Tag a story, creating the tag if it doesn't exist
+    fn add_tag_to_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError>;
+
+    /// Remove a tag from a story. Tags no longer used by any story are deleted
+    fn remove_tag_from_story(&self, story_id: u32, name: &str) -> Result<(), IfdbError>;
+
+    /// Return all shelves, ordered by name
+    fn fetch_shelves(&self) -> Result<Vec<DbShelf>, IfdbError>;
+
+    /// Add a shelf, returning its id. Shelves with a query are smart shelves
+    fn create_shelf(&self, name: &str, query: Option<ShelfQuery>) -> Result<i64, IfdbError>;
+
+    /// Delete a shelf. The stories on it are not affected
+    fn delete_shelf(&self, shelf_id: i64) -> Result<(), IfdbError>;
+
+    /// Return the ids of the shelves a story has been added to
+    fn fetch_shelf_ids_for_story(&self, story_id: u32) -> Result<Vec<i64>, IfdbError>;
+
+    fn add_story_to_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError>;
+
+    fn remove_story_from_shelf(&self, shelf_id: i64, story_id: u32) -> Result<(), IfdbError>;
+
+    ///
+    /// Fonts
+    ///
+    fn get_fonts(&self) -> Result<Vec<DbFont>, IfdbError>;
+
+    fn add_font(&self, name: &str, data: Vec<u8>, monospace: bool) -> Result<(), IfdbError>;
+
+    fn update_font_metadata(&self, font: DbFont) -> Result<(), IfdbError>;
+
+    fn delete_font(&self, dbid: i64) -> Result<(), IfdbError>;
+
+    ///
+    /// Preferences/Themes
+    ///
+    fn get_theme(&self, name: &str) -> Result<Option<DbTheme>, IfdbError>;
+
+    fn store_theme(&self, theme: DbTheme) -> Result<(), IfdbError>;
+
+    ///
+    /// Windows and settings
+    ///
+    /// Return the position and other information for a window, if any has been stored
+    fn get_window_details(
+        &self,
+        story_id: u32,
+        window_type: WindowType,
+    ) -> Result<Option<WindowDetails>, IfdbError>;
+
+    /// Add or update the position and other information for a window. Will set the dbid
+    /// on the passed-in window details on add
+    fn store_window_details(&self, details: &mut WindowDetails) -> Result<(), IfdbError>;
+
+    /// Store id of currently played story, or if None mark no story being played
+    fn store_current_story(&self, story_id: Option<i64>) -> Result<(), IfdbError>;
+
+    /// Return the id for the currently played story, if any
+    fn get_current_story(&self) -> Result<Option<i64>, IfdbError>;
+
+    /// Return the sort and filter last used for the story list
+    fn get_story_list_settings(&self) -> Result<(StorySort, StoryFilter), IfdbError>;
+
+    /// Store the sort and filter for the story list
+    fn store_story_list_settings(
+        &self,
+        sort: StorySort,
+        filter: &StoryFilter,
+    ) -> Result<(), IfdbError>;
+}
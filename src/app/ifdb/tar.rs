@@ -0,0 +1,124 @@
+///
+/// Reader for tar archives, in the POSIX ustar format plus the GNU and pax long name extensions
+/// See https://www.gnu.org/software/tar/manual/html_node/Standard.html
+///
+use std::ops::Range;
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME_FIELD: Range<usize> = 0..100;
+const SIZE_FIELD: Range<usize> = 124..136;
+const CHECKSUM_FIELD: Range<usize> = 148..156;
+const TYPE_FLAG: usize = 156;
+const MAGIC_FIELD: Range<usize> = 257..262;
+const PREFIX_FIELD: Range<usize> = 345..500;
+
+const USTAR_MAGIC: &[u8; 5] = b"ustar";
+
+// Entry types we use. Directories, links and anything else are skipped
+const REGULAR_FILE: u8 = b'0';
+const OLD_REGULAR_FILE: u8 = b'\0';
+const CONTIGUOUS_FILE: u8 = b'7';
+const GNU_LONG_NAME: u8 = b'L';
+const PAX_HEADER: u8 = b'x';
+
+/// A file in a tar archive
+pub struct TarEntry<'a> {
+    pub name: String,
+    pub data: &'a [u8],
+}
+
+/// Read a NUL-terminated string field
+fn read_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// Read a numeric field, stored as octal digits padded with spaces or NULs
+fn read_octal(field: &[u8]) -> Option<usize> {
+    let s = read_str(field);
+    let s = s.trim();
+    if s.is_empty() {
+        Some(0)
+    } else {
+        usize::from_str_radix(s, 8).ok()
+    }
+}
+
+/// The checksum is the sum of the header bytes, with the checksum field itself read as spaces
+fn checksum_matches(header: &[u8]) -> bool {
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if CHECKSUM_FIELD.contains(&i) {
+                b' ' as usize
+            } else {
+                *b as usize
+            }
+        })
+        .sum();
+    read_octal(&header[CHECKSUM_FIELD]) == Some(sum)
+}
+
+/// Name of the entry in a header. ustar headers can split long names into a prefix and name
+fn header_name(header: &[u8]) -> String {
+    let name = read_str(&header[NAME_FIELD]);
+    if &header[MAGIC_FIELD] == USTAR_MAGIC {
+        let prefix = read_str(&header[PREFIX_FIELD]);
+        if !prefix.is_empty() {
+            return format!("{}/{}", prefix, name);
+        }
+    }
+    name
+}
+
+/// Find the path in a pax extended header. Each record is "length key=value\n"
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(|path| path.to_string()))
+}
+
+/// List the files in a tar archive
+pub fn read_tar(data: &[u8]) -> Result<Vec<TarEntry<'_>>, String> {
+    let mut entries = vec![];
+    let mut long_name = None;
+    let mut offset = 0;
+
+    while offset + BLOCK_SIZE <= data.len() {
+        let header = &data[offset..offset + BLOCK_SIZE];
+
+        // The archive ends with blocks of zeros
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+
+        if !checksum_matches(header) {
+            return Err(format!("Invalid tar header at offset {}", offset));
+        }
+
+        let start = offset + BLOCK_SIZE;
+        let body = read_octal(&header[SIZE_FIELD])
+            .and_then(|size| start.checked_add(size))
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| format!("Entry at offset {} extends past end of archive", offset))?;
+
+        match header[TYPE_FLAG] {
+            REGULAR_FILE | OLD_REGULAR_FILE | CONTIGUOUS_FILE => {
+                let name = long_name.take().unwrap_or_else(|| header_name(header));
+                entries.push(TarEntry { name, data: body });
+            }
+            GNU_LONG_NAME => long_name = Some(read_str(body)),
+            PAX_HEADER => long_name = pax_path(body).or(long_name),
+            _ => long_name = None,
+        }
+
+        // Entries are padded to a whole number of blocks
+        let padding = (BLOCK_SIZE - body.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        offset = start + body.len() + padding;
+    }
+
+    Ok(entries)
+}
@@ -1,23 +1,38 @@
 // Note: no separate test for store_save as this is tested as part of the various
 // other save tests
 
+#[allow(unused_imports)]
+use super::ifiction::{read_stories_from_xml, validate_ifictiondate};
 use super::ifiction::{
     Bibilographic, Colophon, Contacts, Cover, CoverFormat, Forgiveness, Format, IFictionDate,
     Identification, Release, Resource, Story, Zcode,
 };
 #[allow(unused_imports)]
+use super::memory::MemoryStore;
+#[allow(unused_imports)]
+use super::merge::MergeChange;
+#[allow(unused_imports)]
+use super::store::IfdbStore;
+#[allow(unused_imports)]
+use super::tar::read_tar;
+#[allow(unused_imports)]
 use super::{
-    DbColor, DbFont, DbSave, DbTheme, IfdbConnection, LoadFileResult, Note, SaveType, ThemeType,
-    WindowDetails, WindowType,
+    build_search_query, extract_ifid_from_bytes, resource_matches_path, ArchiveLimits, DbColor,
+    DbFont, DbSave, DbTheme, DbTrashItem, IfdbConnection, IfdbError, LoadFileResult,
+    MigrationError, Note, PlayStatus, SaveType, ShelfQuery, StoryFilter, StoryFilterField,
+    StoryReview, StorySort, ThemeType, TrashContents, WindowDetails, WindowType,
+    COVER_THUMBNAIL_SIZE,
 };
 #[allow(unused_imports)]
+use image::GenericImageView;
+#[allow(unused_imports)]
 use rusqlite::params;
 
 #[allow(unused_imports)]
 use std::path::PathBuf;
 
 #[allow(dead_code)]
-static INITIAL_DATA_IFID: &str = "ZCODE-1-200427-0000";
+static INITIAL_DATA_IFID: &str = "ZCODE-1-200427-5AFE";
 
 #[allow(dead_code)]
 static INITIAL_STORY_DB_ID: u32 = 1;
@@ -45,19 +60,110 @@ fn setup_test_db() -> IfdbConnection {
     }
 }
 
-#[test]
-fn test_count_stories() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn setup_memory_db() -> MemoryStore {
+    // Configures an in-memory store with the same single story as the test database
+    let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    d.push("resources");
+    d.push("basic_2.z3");
+    let data = std::fs::read(d).expect("Error reading story");
+    let ifid = extract_ifid_from_bytes(&data).expect("Error reading IFID");
+
+    let store = MemoryStore::new();
+    store
+        .add_story_data(&ifid, data, "basic_2")
+        .expect("Error adding story");
+    store
+}
+
+// Tests of the storage trait are run against both the SQLite and in-memory stores. The
+// other tests need SQLite: they check its tables directly or write rows behind the store's
+// back, or they test importing, full text ranking, migrations, merging, backups,
+// maintenance or the cache, which only the SQLite store has
+macro_rules! store_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[test]
+                fn $name() {
+                    super::$name(&super::setup_test_db());
+                }
+            )*
+        }
+
+        mod memory {
+            $(
+                #[test]
+                fn $name() {
+                    super::$name(&super::setup_memory_db());
+                }
+            )*
+        }
+    };
+}
 
+store_tests!(
+    test_count_stories,
+    test_get_story_id,
+    test_get_story_data,
+    test_get_story,
+    test_delete_story,
+    test_fetch_ifids_for_story,
+    test_fetch_story_summaries,
+    test_fetch_story_summary_page,
+    test_story_list_settings,
+    test_story_tags,
+    test_shelves,
+    test_story_review,
+    test_get_story_summary_by_id,
+    test_get_story_summary_by_ifid,
+    test_get_story_id_for_ifid,
+    test_create_story_simple,
+    test_create_story_full,
+    test_update_last_played_to_now,
+    test_add_to_time_played,
+    test_update_story,
+    test_update_story_clear_fields,
+    test_store_cover_image,
+    test_add_story_data,
+    test_story_releases,
+    test_replace_story_file,
+    test_count_saves,
+    test_count_autosaves_for_story,
+    test_get_save,
+    test_import_quetzal_save,
+    test_store_save_duplicate_name,
+    test_get_save_by_id,
+    test_fetch_saves_for_ifid,
+    test_fetch_manual_saves_for_ifid,
+    test_delete_autosaves_for_story,
+    test_trash,
+    test_get_or_create_session,
+    test_store_session,
+    test_count_clues,
+    test_reveal_and_hide_clue,
+    test_story_has_clues,
+    test_add_clue_and_get_clues_for_story,
+    test_count_notes,
+    test_save_note_and_get_notes_for_story,
+    test_set_note_done,
+    test_set_note_notes,
+    test_get_rooms_for_story,
+    test_get_and_store_window_details,
+    test_get_and_store_current_story,
+    test_font_crud,
+    test_get_and_store_theme,
+);
+
+#[cfg(test)]
+fn test_count_stories(connection: &dyn IfdbStore) {
     // Assumes the setup has a single story loaded
     assert_eq!(1, connection.count_stories().expect("Failed with error."));
 }
 
-#[test]
-fn test_get_story_id() {
-    let connection = setup_test_db();
-
-    // Assumes the setup has a single story with IFID ZCODE-1-200427-0000
+#[cfg(test)]
+fn test_get_story_id(connection: &dyn IfdbStore) {
+    // Assumes the setup has a single story with IFID ZCODE-1-200427-5AFE
     // Since it's the first story loaded, will have ID of 1
 
     assert_eq!(
@@ -74,11 +180,9 @@ fn test_get_story_id() {
         .is_none());
 }
 
-#[test]
-fn test_get_story_data() {
-    let connection = setup_test_db();
-
-    // Assumes the setup has a single story with IFID ZCODE-1-200427-0000
+#[cfg(test)]
+fn test_get_story_data(connection: &dyn IfdbStore) {
+    // Assumes the setup has a single story with IFID ZCODE-1-200427-5AFE
     // Since it's the first story loaded, will have ID of 1
     // For simplicity, just check the length and not the raw bytes
     assert_eq!(
@@ -100,10 +204,9 @@ fn test_get_story_data() {
         .is_none());
 }
 
-#[test]
-fn test_get_story() {
-    let connection = setup_test_db();
-    // Assumes the setup has a single story with IFID ZCODE-1-200427-0000
+#[cfg(test)]
+fn test_get_story(connection: &dyn IfdbStore) {
+    // Assumes the setup has a single story with IFID ZCODE-1-200427-5AFE
     // Since it's the first story loaded, will have ID of 1
     // For simplicity, just check the length and not the raw bytes
     let story = connection
@@ -141,7 +244,7 @@ fn test_get_story() {
 }
 
 #[cfg(test)]
-fn add_test_data_for_story(connection: &IfdbConnection, story_id: u32, ifid: &str) {
+fn add_test_data_for_story(connection: &dyn IfdbStore, story_id: u32, ifid: &str) {
     let mut save = create_full_save(SaveType::Normal);
     save.ifid = ifid.to_string();
     connection.store_save(&save, false).expect("Error saving");
@@ -183,6 +286,10 @@ fn add_test_data_for_story(connection: &IfdbConnection, story_id: u32, ifid: &st
     connection
         .store_window_details(&mut details)
         .expect("Error storing");
+
+    connection
+        .add_story_history(story_id, Some(ifid), "Test history")
+        .expect("Error recording history");
 }
 
 #[cfg(test)]
@@ -289,6 +396,22 @@ fn check_no_data_for_story(connection: &IfdbConnection, story_id: u32) {
             story_id,
         )
     );
+    assert_eq!(
+        0,
+        sql_count(
+            connection,
+            "SELECT COUNT(*) FROM story_history WHERE story_id = ?1",
+            story_id,
+        )
+    );
+    assert_eq!(
+        0,
+        sql_count(
+            connection,
+            "SELECT COUNT(*) FROM story_tag WHERE story_id = ?1",
+            story_id,
+        )
+    );
 }
 
 #[cfg(test)]
@@ -400,6 +523,14 @@ fn check_data_for_story(connection: &IfdbConnection, story_id: u32) {
             story_id,
         )
     );
+    assert_eq!(
+        1,
+        sql_count(
+            connection,
+            "SELECT COUNT(*) FROM story_history WHERE story_id = ?1",
+            story_id,
+        )
+    );
 }
 
 #[cfg(test)]
@@ -419,8 +550,53 @@ fn sql_count(connection: &IfdbConnection, sql: &str, story_id: u32) -> u32 {
     result.expect("SQL error")
 }
 
+#[cfg(test)]
+fn test_delete_story(connection: &dyn IfdbStore) {
+    assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
+    assert!(connection.create_story(full_story("ZCODE-55555")).is_ok());
+    add_test_data_for_story(connection, 2, "ZCODE-12345");
+    add_test_data_for_story(connection, 3, "ZCODE-55555");
+    connection
+        .add_tag_to_story(2, "Deleted")
+        .expect("Error adding tag");
+
+    // Whether the story is there, then counts of its saves, clues, notes, rooms, history
+    // and tags
+    let stored = |story_id: u32, ifid: &str| {
+        (
+            connection.get_story(story_id).unwrap().is_some(),
+            connection
+                .fetch_saves_for_ifid(ifid.to_string())
+                .unwrap()
+                .len(),
+            connection.get_clues_for_story(story_id).unwrap().len(),
+            connection
+                .get_notes_for_story(i64::from(story_id), true)
+                .unwrap()
+                .len(),
+            connection.get_rooms_for_story(story_id).unwrap().len(),
+            connection.fetch_story_history(story_id).unwrap().len(),
+            connection.fetch_tags_for_story(story_id).unwrap().len(),
+        )
+    };
+    assert_eq!((true, 1, 1, 1, 1, 1, 1), stored(2, "ZCODE-12345"));
+
+    connection.delete_story(2).expect("Failed with error");
+    assert_eq!((false, 0, 0, 0, 0, 0, 0), stored(2, "ZCODE-12345"));
+    assert_eq!((true, 1, 1, 1, 1, 1, 0), stored(3, "ZCODE-55555"));
+    assert!(connection
+        .get_window_details(2, WindowType::Main)
+        .unwrap()
+        .is_none());
+    assert!(connection
+        .get_window_details(3, WindowType::Main)
+        .unwrap()
+        .is_some());
+    assert!(connection.fetch_tags().unwrap().is_empty());
+}
+
 #[test]
-fn test_delete_story() {
+fn test_delete_story_removes_rows() {
     let connection = setup_test_db();
     // Setup pre-save data and validate the counts
     assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
@@ -432,6 +608,9 @@ fn test_delete_story() {
 
     add_test_data_for_story(&connection, first_story_id, first_ifid);
     add_test_data_for_story(&connection, second_story_id, second_ifid);
+    connection
+        .add_tag_to_story(first_story_id, "Deleted")
+        .expect("Error adding tag");
     check_data_for_story(&connection, first_story_id);
     check_data_for_story(&connection, second_story_id);
 
@@ -446,10 +625,100 @@ fn test_delete_story() {
 }
 
 #[test]
-fn test_fetch_ifids_for_story() {
+fn test_remove_orphaned_rows() {
+    let connection = setup_test_db();
+    assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
+    let first_story_id = 2;
+    let first_ifid = "ZCODE-12345";
+    assert!(connection.create_story(full_story("ZCODE-55555")).is_ok());
+    let second_story_id = 3;
+    let second_ifid = "ZCODE-55555";
+
+    add_test_data_for_story(&connection, first_story_id, first_ifid);
+    add_test_data_for_story(&connection, second_story_id, second_ifid);
+    connection
+        .add_tag_to_story(first_story_id, "Orphan")
+        .expect("Error adding tag");
+    assert!(connection
+        .find_orphaned_rows()
+        .expect("Error finding orphans")
+        .is_empty());
+
+    // Remove only the story, as versions without delete_story's cleanup did
+    connection
+        .connection
+        .execute("DELETE FROM story WHERE id = ?1", params![first_story_id])
+        .expect("Error deleting story");
+
+    let orphans = connection
+        .find_orphaned_rows()
+        .expect("Error finding orphans");
+    let tables: Vec<&str> = orphans.iter().map(|o| o.table.as_str()).collect();
+    for table in &[
+        "story_ifid",
+        "story_fts",
+        "saves",
+        "session",
+        "notes",
+        "clue_section",
+        "clue_subsection",
+        "clue",
+        "window_details",
+        "story_tag",
+        "tag",
+    ] {
+        assert!(tables.contains(table), "No orphans found in {}", table);
+    }
+    assert!(orphans.iter().all(|o| o.count > 0));
+
+    assert_eq!(
+        orphans,
+        connection
+            .remove_orphaned_rows()
+            .expect("Error removing orphans")
+    );
+    assert!(connection
+        .find_orphaned_rows()
+        .expect("Error finding orphans")
+        .is_empty());
+    check_no_data_for_story(&connection, first_story_id);
+    check_data_for_story(&connection, second_story_id);
+}
+
+#[test]
+fn test_database_maintenance() {
     let connection = setup_test_db();
+    assert!(connection
+        .check_integrity()
+        .expect("Error checking integrity")
+        .is_empty());
+
+    let sizes = connection.table_sizes().expect("Error fetching sizes");
+    let story = sizes
+        .iter()
+        .find(|s| s.name == "story")
+        .expect("No size for story table");
+    assert_eq!(1, story.rows);
+    assert!(story.bytes > 0);
+    assert!(sizes.windows(2).all(|w| w[0].bytes >= w[1].bytes));
+
+    // Deleting a story with a large save leaves its pages free until vacuumed
+    let mut save = create_simple_save(SaveType::Normal);
+    save.ifid = INITIAL_DATA_IFID.to_string();
+    save.data = vec![0; 100_000];
+    connection.store_save(&save, false).expect("Error saving");
+    connection.delete_story(1).expect("Error deleting story");
+    let (before, after) = connection.vacuum().expect("Error vacuuming");
+    assert!(after < before);
+    assert_eq!(
+        after,
+        connection.database_size().expect("Error fetching size")
+    );
+}
 
-    // Assumes the setup has a single story with IFID ZCODE-1-200427-0000
+#[cfg(test)]
+fn test_fetch_ifids_for_story(connection: &dyn IfdbStore) {
+    // Assumes the setup has a single story with IFID ZCODE-1-200427-5AFE
     // Since it's the first story loaded, will have ID of 1
     // For simplicity, just check the length and not the raw bytes
     assert_eq!(
@@ -465,10 +734,8 @@ fn test_fetch_ifids_for_story() {
         .is_empty());
 }
 
-#[test]
-fn test_fetch_story_summaries() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_fetch_story_summaries(connection: &dyn IfdbStore) {
     let summaries = connection
         .fetch_story_summaries(true, None)
         .expect("Failed with error.");
@@ -512,76 +779,745 @@ fn test_fetch_story_summaries() {
     );
 }
 
+#[cfg(test)]
+fn search_titles(connection: &dyn IfdbStore, text: &str) -> Vec<String> {
+    connection
+        .fetch_story_summaries(false, Some(text))
+        .expect("Failed with error.")
+        .into_iter()
+        .map(|s| s.title)
+        .collect()
+}
+
+#[test]
+fn test_build_search_query() {
+    assert_eq!(None, build_search_query(""));
+    assert_eq!(None, build_search_query("  \"\" "));
+    assert_eq!(
+        Some(String::from("\"zork\"* AND \"under\"*")),
+        build_search_query("zork under")
+    );
+    assert_eq!(
+        Some(String::from("author : \"Meretzky\"*")),
+        build_search_query("Author:Meretzky")
+    );
+    assert_eq!(
+        Some(String::from("title : \"great underground\"")),
+        build_search_query("title:\"great underground\"")
+    );
+    // Unknown fields and stray quotes are treated as text
+    assert_eq!(
+        Some(String::from("\"year:1982\"* AND \"abc\"*")),
+        build_search_query("year:1982 a\"bc")
+    );
+}
+
 #[test]
-fn test_get_story_summary_by_id() {
+fn test_fetch_story_summaries_full_text() {
     let connection = setup_test_db();
 
-    let story = connection
-        .get_story_summary_by_id(1)
-        .unwrap()
-        .expect("Failed with error.");
-    assert_eq!(1, story.story_id);
-    assert_eq!(INITIAL_DATA_IFID, story.ifid);
-    assert_eq!("basic_2", story.title);
-    assert_eq!(0, story.time_played);
-    assert!(story.last_played.is_none());
+    let mut story = full_story("ZCODE-12345");
+    story.bibliographic.title = String::from("Planetfall");
+    story.bibliographic.author = String::from("Steve Meretzky");
+    story.bibliographic.headline = Some(String::from("An interstellar comedy of Planetfall"));
+    story.bibliographic.description = Some(String::from("Planetfall on Resida"));
+    connection
+        .create_story(story)
+        .expect("Error creating story");
+
+    let mut story = full_story("ZCODE-55555");
+    story.bibliographic.title = String::from("Zork");
+    story.bibliographic.author = String::from("Marc Blank");
+    story.bibliographic.genre = Some(String::from("Fantasy"));
+    story.bibliographic.description = Some(String::from("Not Planetfall"));
+    connection
+        .create_story(story)
+        .expect("Error creating story");
 
-    assert!(connection
-        .get_story_summary_by_id(2)
-        .expect("Failed with error")
-        .is_none());
+    // Prefix matches, across fields and accents
+    assert_eq!(vec!["Planetfall"], search_titles(&connection, "meretz"));
+    assert_eq!(vec!["Zork"], search_titles(&connection, "fant"));
+    assert_eq!(vec!["Planetfall"], search_titles(&connection, "résida"));
+    assert_eq!(vec!["Zork"], search_titles(&connection, "zork blank"));
+
+    // Field qualifiers
+    assert_eq!(
+        vec!["Planetfall"],
+        search_titles(&connection, "author:Meretzky")
+    );
+    assert!(search_titles(&connection, "title:Meretzky").is_empty());
+
+    // Ranked by relevance, not title
+    assert_eq!(
+        vec!["Planetfall", "Zork"],
+        search_titles(&connection, "planetfall")
+    );
+
+    // Index follows updates and deletes
+    let mut story = connection.get_story(3).unwrap().expect("No story");
+    story.story.bibliographic.author = String::from("Dave Lebling");
+    connection
+        .update_story(story)
+        .expect("Error updating story");
+    assert!(search_titles(&connection, "author:blank").is_empty());
+    assert_eq!(vec!["Zork"], search_titles(&connection, "author:lebling"));
+
+    connection.delete_story(3).expect("Error deleting story");
+    assert!(search_titles(&connection, "lebling").is_empty());
+    assert_eq!(
+        1,
+        sql_count(
+            &connection,
+            "SELECT COUNT(*) FROM story_fts WHERE rowid = ?1 OR rowid = ?1 + 1",
+            2
+        )
+    );
+}
+
+#[cfg(test)]
+fn sorted_titles(connection: &dyn IfdbStore, sort: StorySort, filter: &StoryFilter) -> Vec<String> {
+    connection
+        .fetch_sorted_story_summaries(false, None, Some(sort), filter)
+        .expect("Failed with error.")
+        .into_iter()
+        .map(|s| s.title)
+        .collect()
 }
 
 #[test]
-fn test_get_story_summary_by_ifid() {
+fn test_fetch_sorted_story_summaries() {
     let connection = setup_test_db();
 
-    let story = connection
-        .get_story_summary_by_ifid(INITIAL_DATA_IFID)
-        .unwrap()
-        .expect("Failed with error.");
-    assert_eq!(1, story.story_id);
-    assert_eq!(INITIAL_DATA_IFID, story.ifid);
-    assert_eq!("basic_2", story.title);
-    assert_eq!(0, story.time_played);
-    assert!(story.last_played.is_none());
+    let mut story = full_story("ZCODE-11111");
+    story.bibliographic.title = String::from("Alpha");
+    story.bibliographic.author = String::from("Zed");
+    story.bibliographic.first_published = Some(IFictionDate::Year(1990));
+    story.bibliographic.genre = Some(String::from("Fantasy"));
+    story.bibliographic.language = Some(String::from("de"));
+    connection
+        .create_story(story)
+        .expect("Error creating story");
+
+    let mut story = full_story("ZCODE-22222");
+    story.bibliographic.title = String::from("Beta");
+    story.bibliographic.author = String::from("Amy");
+    story.bibliographic.first_published = Some(IFictionDate::Year(1980));
+    story.bibliographic.genre = Some(String::from("Science Fiction"));
+    story.bibliographic.series = None;
+    connection
+        .create_story(story)
+        .expect("Error creating story");
+
+    // Fixture story has no date added, as if it was loaded before dates were recorded
+    connection
+        .connection
+        .execute("UPDATE story SET date_added = NULL WHERE id = 1", params![])
+        .unwrap();
+    connection
+        .connection
+        .execute(
+            "UPDATE story SET date_added = '2020-01-01 00:00:00' WHERE id = 2",
+            params![],
+        )
+        .unwrap();
+    connection.add_to_time_played(3, 500).unwrap();
+    connection.update_last_played_to_now(2).unwrap();
+
+    let all = StoryFilter::default();
+    assert_eq!(
+        vec!["Alpha", "basic_2", "Beta"],
+        sorted_titles(&connection, StorySort::Title, &all)
+    );
+    assert_eq!(
+        vec!["Amy", "Zed"],
+        connection
+            .fetch_sorted_story_summaries(false, None, Some(StorySort::Author), &all)
+            .unwrap()
+            .into_iter()
+            .filter(|s| s.story_id != 1)
+            .map(|s| connection
+                .get_story(s.story_id)
+                .unwrap()
+                .unwrap()
+                .story
+                .bibliographic
+                .author)
+            .collect::<Vec<String>>()
+    );
+    assert_eq!(
+        vec!["Beta", "Alpha", "basic_2"],
+        sorted_titles(&connection, StorySort::FirstPublished, &all)
+    );
+    assert_eq!(
+        vec!["Alpha", "basic_2", "Beta"],
+        sorted_titles(&connection, StorySort::LastPlayed, &all)
+    );
+    assert_eq!(
+        vec!["Beta", "Alpha", "basic_2"],
+        sorted_titles(&connection, StorySort::TimePlayed, &all)
+    );
+    assert_eq!(
+        vec!["Beta", "Alpha", "basic_2"],
+        sorted_titles(&connection, StorySort::DateAdded, &all)
+    );
+
+    // Filters
+    let filter = StoryFilter {
+        genre: Some(String::from("Fantasy")),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["Alpha"],
+        sorted_titles(&connection, StorySort::Title, &filter)
+    );
+    let filter = StoryFilter {
+        language: Some(String::from("en")),
+        forgiveness: Some(String::from("Cruel")),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["Beta"],
+        sorted_titles(&connection, StorySort::Title, &filter)
+    );
+    let filter = StoryFilter {
+        series: Some(String::from("A Series")),
+        genre: Some(String::from("Science Fiction")),
+        ..StoryFilter::default()
+    };
+    assert!(sorted_titles(&connection, StorySort::Title, &filter).is_empty());
+
+    // Search still applies
+    assert_eq!(
+        vec!["Beta"],
+        connection
+            .fetch_sorted_story_summaries(false, Some("amy"), Some(StorySort::Title), &all)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.title)
+            .collect::<Vec<String>>()
+    );
 
+    // Filter values only come from playable stories
     assert!(connection
-        .get_story_summary_by_id(2)
-        .expect("Failed with error")
-        .is_none());
+        .fetch_story_filter_values(StoryFilterField::Genre)
+        .unwrap()
+        .is_empty());
+    connection
+        .connection
+        .execute(
+            "UPDATE story_ifid SET story_data = X'00' WHERE story_id IN (2, 3)",
+            params![],
+        )
+        .unwrap();
+    assert_eq!(
+        vec!["Fantasy", "Science Fiction"],
+        connection
+            .fetch_story_filter_values(StoryFilterField::Genre)
+            .unwrap()
+    );
 }
 
-#[test]
-fn test_get_story_id_for_ifid() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_fetch_story_summary_page(connection: &dyn IfdbStore) {
+    for i in 0..120 {
+        let mut story = full_story(format!("ZCODE-PAGE-{}", i).as_str());
+        story.bibliographic.title = format!("Story {:03}", i);
+        connection.create_story(story).unwrap();
+    }
+    let filter = StoryFilter::default();
     assert_eq!(
-        1,
+        121,
         connection
-            .get_story_id_for_ifid(INITIAL_DATA_IFID, false)
+            .count_story_summaries(false, None, &filter, None)
             .unwrap()
-            .expect("Failed with error.")
     );
 
+    // Pages are in the same order as the full list
+    let all = connection
+        .fetch_sorted_story_summaries(false, None, Some(StorySort::Title), &filter)
+        .unwrap();
+    let mut paged = vec![];
+    for offset in (0..121).step_by(50) {
+        paged.extend(
+            connection
+                .fetch_story_summary_page(false, None, Some(StorySort::Title), &filter, offset, 50)
+                .unwrap(),
+        );
+    }
+    assert_eq!(all, paged);
     assert!(connection
-        .get_story_summary_by_id(2)
-        .expect("Failed with error")
-        .is_none());
+        .fetch_story_summary_page(false, None, Some(StorySort::Title), &filter, 200, 50)
+        .unwrap()
+        .is_empty());
+
+    // Counting titles before a letter finds the row the letter starts at
+    let count_before = |title| {
+        connection
+            .count_story_summaries(false, None, &filter, Some(title))
+            .unwrap()
+    };
+    assert_eq!(0, count_before("b"));
+    assert_eq!(1, count_before("S"));
+    assert_eq!(121, count_before("z"));
+
+    // Searches are counted the same way they are listed
+    assert_eq!(
+        1,
+        connection
+            .count_story_summaries(false, Some("042"), &filter, None)
+            .unwrap()
+    );
+    assert_eq!(
+        vec!["Story 042"],
+        connection
+            .fetch_story_summary_page(false, Some("042"), Some(StorySort::Title), &filter, 0, 50)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.title)
+            .collect::<Vec<String>>()
+    );
 }
 
 #[test]
-fn test_create_story_simple() {
-    // Ensure that a story with no optional values stores
+fn test_large_story_list() {
     let connection = setup_test_db();
+    connection.connection.execute_batch("BEGIN").unwrap();
+    for i in 0..10_000 {
+        let mut story = full_story(format!("ZCODE-LARGE-{}", i).as_str());
+        story.bibliographic.title = format!("Story {:05}", i);
+        connection.create_story(story).unwrap();
+    }
+    connection.connection.execute_batch("COMMIT").unwrap();
 
-    let story = Story {
-        identification: Identification {
-            ifids: vec![String::from("ZCODE-12345")],
-            format: Format::ZCODE,
-        },
-        bibliographic: Bibilographic {
-            title: String::from("A Title"),
+    let filter = StoryFilter::default();
+    assert_eq!(
+        10_001,
+        connection
+            .count_story_summaries(false, None, &filter, None)
+            .unwrap()
+    );
+    let page = connection
+        .fetch_story_summary_page(false, None, Some(StorySort::Title), &filter, 9_950, 50)
+        .unwrap();
+    assert_eq!(50, page.len());
+    assert_eq!("Story 09949", page[0].title);
+    assert_eq!("Story 09998", page[49].title);
+    assert_eq!(
+        1,
+        connection
+            .count_story_summaries(false, None, &filter, Some("s"))
+            .unwrap()
+    );
+}
+
+#[cfg(test)]
+fn test_story_list_settings(connection: &dyn IfdbStore) {
+    assert_eq!(
+        (StorySort::Title, StoryFilter::default()),
+        connection.get_story_list_settings().unwrap()
+    );
+
+    let filter = StoryFilter {
+        genre: Some(String::from("Fantasy")),
+        series: Some(String::from("Zork")),
+        ..StoryFilter::default()
+    };
+    connection
+        .store_story_list_settings(StorySort::LastPlayed, &filter)
+        .unwrap();
+    assert_eq!(
+        (StorySort::LastPlayed, filter),
+        connection.get_story_list_settings().unwrap()
+    );
+
+    assert_eq!(StorySort::Title, StorySort::from_db_str("Unknown"));
+    assert_eq!(
+        StorySort::DateAdded,
+        StorySort::from_db_str(StorySort::DateAdded.to_string())
+    );
+}
+
+#[cfg(test)]
+fn test_story_tags(connection: &dyn IfdbStore) {
+    connection
+        .create_story(full_story("ZCODE-12345"))
+        .expect("Error creating story");
+
+    assert!(matches!(
+        connection.add_tag_to_story(1, "  "),
+        Err(IfdbError::Invalid(_))
+    ));
+    connection.add_tag_to_story(1, "Infocom").unwrap();
+    connection.add_tag_to_story(1, " favourite ").unwrap();
+    // Tag names are not case sensitive
+    connection.add_tag_to_story(2, "infocom").unwrap();
+    connection.add_tag_to_story(2, "Infocom").unwrap();
+
+    assert_eq!(
+        vec!["favourite", "Infocom"],
+        connection.fetch_tags().unwrap()
+    );
+    assert_eq!(
+        vec!["favourite", "Infocom"],
+        connection.fetch_tags_for_story(1).unwrap()
+    );
+    assert_eq!(vec!["Infocom"], connection.fetch_tags_for_story(2).unwrap());
+
+    let filter = StoryFilter {
+        tag: Some(String::from("favourite")),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["basic_2"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+
+    // Unused tags are removed
+    connection.remove_tag_from_story(1, "favourite").unwrap();
+    assert_eq!(vec!["Infocom"], connection.fetch_tags().unwrap());
+    connection.delete_story(2).unwrap();
+    connection.remove_tag_from_story(1, "INFOCOM").unwrap();
+    assert!(connection.fetch_tags().unwrap().is_empty());
+    assert!(connection.fetch_tags_for_story(1).unwrap().is_empty());
+}
+
+#[cfg(test)]
+fn test_shelves(connection: &dyn IfdbStore) {
+    connection
+        .create_story(full_story("ZCODE-12345"))
+        .expect("Error creating story");
+
+    // Default smart shelves
+    let shelves = connection.fetch_shelves().unwrap();
+    assert_eq!(2, shelves.len());
+    assert!(shelves.iter().all(|s| s.query.is_some()));
+
+    let shelf_id = connection.create_shelf("To play", None).unwrap();
+    assert!(matches!(
+        connection.create_shelf("TO PLAY", None),
+        Err(IfdbError::Constraint(_))
+    ));
+    assert!(matches!(
+        connection.create_shelf("", None),
+        Err(IfdbError::Invalid(_))
+    ));
+
+    let filter = StoryFilter {
+        shelf_id: Some(shelf_id),
+        ..StoryFilter::default()
+    };
+    assert!(sorted_titles(connection, StorySort::Title, &filter).is_empty());
+    connection.add_story_to_shelf(shelf_id, 2).unwrap();
+    connection.add_story_to_shelf(shelf_id, 2).unwrap();
+    assert_eq!(
+        vec!["A Title"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+    assert_eq!(
+        vec![shelf_id],
+        connection.fetch_shelf_ids_for_story(2).unwrap()
+    );
+    connection.remove_story_from_shelf(shelf_id, 2).unwrap();
+    assert!(connection.fetch_shelf_ids_for_story(2).unwrap().is_empty());
+
+    // Smart shelves re-run their query
+    connection.add_tag_to_story(2, "Infocom").unwrap();
+    let query = ShelfQuery {
+        tag: Some(String::from("Infocom")),
+        played: Some(false),
+        ..ShelfQuery::default()
+    };
+    let smart_id = connection
+        .create_shelf("Unplayed Infocom", Some(query.clone()))
+        .unwrap();
+    let filter = StoryFilter {
+        shelf_id: Some(smart_id),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["A Title"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+    assert_eq!(
+        Some(query),
+        connection
+            .fetch_shelves()
+            .unwrap()
+            .into_iter()
+            .find(|s| s.dbid == smart_id)
+            .unwrap()
+            .query
+    );
+
+    connection.update_last_played_to_now(2).unwrap();
+    assert!(sorted_titles(connection, StorySort::Title, &filter).is_empty());
+
+    let recent_id = connection
+        .create_shelf(
+            "Recent",
+            Some(ShelfQuery {
+                played_within_days: Some(30),
+                search: Some(String::from("author:author")),
+                group: Some(String::from("A Group")),
+                ..ShelfQuery::default()
+            }),
+        )
+        .unwrap();
+    let filter = StoryFilter {
+        shelf_id: Some(recent_id),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["A Title"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+    // Deleted shelves no longer filter
+    connection.delete_shelf(recent_id).unwrap();
+    assert_eq!(
+        2,
+        sorted_titles(connection, StorySort::Title, &filter).len()
+    );
+
+    // Saved searches keep the search text and filters
+    let query = ShelfQuery::from_filter(
+        " zork ",
+        &StoryFilter {
+            genre: Some(String::from("Fantasy")),
+            shelf_id: Some(shelf_id),
+            ..StoryFilter::default()
+        },
+    );
+    assert_eq!(Some(String::from("zork")), query.search);
+    assert_eq!(Some(String::from("Fantasy")), query.genre);
+    assert_eq!(
+        None,
+        ShelfQuery::from_filter("", &StoryFilter::default()).search
+    );
+}
+
+#[test]
+fn test_shelves_played_within() {
+    let connection = setup_test_db();
+    connection
+        .create_story(full_story("ZCODE-12345"))
+        .expect("Error creating story");
+    connection.update_last_played_to_now(2).unwrap();
+
+    let recent_id = connection
+        .create_shelf(
+            "Recent",
+            Some(ShelfQuery {
+                played_within_days: Some(30),
+                ..ShelfQuery::default()
+            }),
+        )
+        .unwrap();
+    let filter = StoryFilter {
+        shelf_id: Some(recent_id),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["A Title"],
+        sorted_titles(&connection, StorySort::Title, &filter)
+    );
+    connection
+        .connection
+        .execute(
+            "UPDATE story SET last_played = '2001-01-01 00:00:00' WHERE id = 2",
+            params![],
+        )
+        .unwrap();
+    // Written behind the cache's back, so it needs clearing
+    connection.cache.clear();
+    assert!(sorted_titles(&connection, StorySort::Title, &filter).is_empty());
+
+    // Either side of the boundary, stored as update_last_played_to_now stores it
+    let boundary = chrono::Utc::now().naive_utc() - chrono::Duration::days(30);
+    for (last_played, expected) in &[
+        (boundary + chrono::Duration::minutes(5), 1),
+        (boundary - chrono::Duration::minutes(5), 0),
+    ] {
+        connection
+            .connection
+            .execute(
+                "UPDATE story SET last_played = ?1 WHERE id = 2",
+                params![last_played],
+            )
+            .unwrap();
+        connection.cache.clear();
+        assert_eq!(
+            *expected,
+            sorted_titles(&connection, StorySort::Title, &filter).len()
+        );
+    }
+}
+
+#[cfg(test)]
+fn test_story_review(connection: &dyn IfdbStore) {
+    connection
+        .create_story(full_story("ZCODE-12345"))
+        .expect("Error creating story");
+
+    let review = connection.get_story_review(1).unwrap().unwrap();
+    assert_eq!(
+        StoryReview {
+            play_status: PlayStatus::Unplayed,
+            rating: None,
+            review: None
+        },
+        review
+    );
+    assert!(connection.get_story_review(99).unwrap().is_none());
+
+    // Playing a story for the first time starts it
+    connection.update_last_played_to_now(1).unwrap();
+    assert_eq!(
+        PlayStatus::InProgress,
+        connection.get_story_review(1).unwrap().unwrap().play_status
+    );
+
+    let review = StoryReview {
+        play_status: PlayStatus::Finished,
+        rating: Some(4),
+        review: Some(String::from(" Loved it ")),
+    };
+    connection.update_story_review(1, &review).unwrap();
+    connection.update_last_played_to_now(1).unwrap();
+    let stored = connection.get_story_review(1).unwrap().unwrap();
+    assert_eq!(PlayStatus::Finished, stored.play_status);
+    assert_eq!(Some(4), stored.rating);
+    assert_eq!(Some(String::from("Loved it")), stored.review);
+
+    let summary = connection.get_story_summary_by_id(1).unwrap().unwrap();
+    assert_eq!(PlayStatus::Finished, summary.play_status);
+    assert_eq!(Some(4), summary.rating);
+
+    // Ratings are 1 to 5, and empty reviews are removed
+    for rating in [0, 6].iter() {
+        assert!(connection
+            .update_story_review(
+                1,
+                &StoryReview {
+                    rating: Some(*rating),
+                    ..review.clone()
+                }
+            )
+            .is_err());
+    }
+    connection
+        .update_story_review(
+            1,
+            &StoryReview {
+                rating: None,
+                review: Some(String::from("  ")),
+                ..review
+            },
+        )
+        .unwrap();
+    let stored = connection.get_story_review(1).unwrap().unwrap();
+    assert_eq!(None, stored.rating);
+    assert_eq!(None, stored.review);
+
+    // Status can be used as a filter
+    let filter = StoryFilter {
+        play_status: Some(PlayStatus::Unplayed),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["A Title"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+    let shelf_id = connection
+        .create_shelf(
+            "Finished",
+            Some(ShelfQuery {
+                play_status: Some(PlayStatus::Finished),
+                ..ShelfQuery::default()
+            }),
+        )
+        .unwrap();
+    let filter = StoryFilter {
+        shelf_id: Some(shelf_id),
+        ..StoryFilter::default()
+    };
+    assert_eq!(
+        vec!["basic_2"],
+        sorted_titles(connection, StorySort::Title, &filter)
+    );
+
+    let filter = StoryFilter {
+        play_status: Some(PlayStatus::Abandoned),
+        ..StoryFilter::default()
+    };
+    connection
+        .store_story_list_settings(StorySort::Title, &filter)
+        .unwrap();
+    assert_eq!(filter, connection.get_story_list_settings().unwrap().1);
+}
+
+#[cfg(test)]
+fn test_get_story_summary_by_id(connection: &dyn IfdbStore) {
+    let story = connection
+        .get_story_summary_by_id(1)
+        .unwrap()
+        .expect("Failed with error.");
+    assert_eq!(1, story.story_id);
+    assert_eq!(INITIAL_DATA_IFID, story.ifid);
+    assert_eq!("basic_2", story.title);
+    assert_eq!(0, story.time_played);
+    assert!(story.last_played.is_none());
+
+    assert!(connection
+        .get_story_summary_by_id(2)
+        .expect("Failed with error")
+        .is_none());
+}
+
+#[cfg(test)]
+fn test_get_story_summary_by_ifid(connection: &dyn IfdbStore) {
+    let story = connection
+        .get_story_summary_by_ifid(INITIAL_DATA_IFID)
+        .unwrap()
+        .expect("Failed with error.");
+    assert_eq!(1, story.story_id);
+    assert_eq!(INITIAL_DATA_IFID, story.ifid);
+    assert_eq!("basic_2", story.title);
+    assert_eq!(0, story.time_played);
+    assert!(story.last_played.is_none());
+
+    assert!(connection
+        .get_story_summary_by_id(2)
+        .expect("Failed with error")
+        .is_none());
+}
+
+#[cfg(test)]
+fn test_get_story_id_for_ifid(connection: &dyn IfdbStore) {
+    assert_eq!(
+        1,
+        connection
+            .get_story_id_for_ifid(INITIAL_DATA_IFID, false)
+            .unwrap()
+            .expect("Failed with error.")
+    );
+
+    assert!(connection
+        .get_story_summary_by_id(2)
+        .expect("Failed with error")
+        .is_none());
+}
+
+#[cfg(test)]
+fn test_create_story_simple(connection: &dyn IfdbStore) {
+    // Ensure that a story with no optional values stores
+
+    let story = Story {
+        identification: Identification {
+            ifids: vec![String::from("ZCODE-12345")],
+            format: Format::ZCODE,
+        },
+        bibliographic: Bibilographic {
+            title: String::from("A Title"),
             author: String::from("An Author"),
             language: None,
             headline: None,
@@ -695,10 +1631,9 @@ fn full_story(ifid: &str) -> Story {
     }
 }
 
-#[test]
-fn test_create_story_full() {
+#[cfg(test)]
+fn test_create_story_full(connection: &dyn IfdbStore) {
     // Ensure that a story with no optional values stores
-    let connection = setup_test_db();
 
     assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
 
@@ -784,9 +1719,8 @@ fn test_create_story_full() {
         assert_eq!(1, zcode.cover_picture.unwrap());
     }
 }
-#[test]
-fn test_update_last_played_to_now() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_update_last_played_to_now(connection: &dyn IfdbStore) {
     let story = connection
         .get_story(1)
         .unwrap()
@@ -806,9 +1740,8 @@ fn test_update_last_played_to_now() {
     assert!(story.last_played.is_some());
 }
 
-#[test]
-fn test_add_to_time_played() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_add_to_time_played(connection: &dyn IfdbStore) {
     let story = connection
         .get_story(1)
         .unwrap()
@@ -826,10 +1759,8 @@ fn test_add_to_time_played() {
     assert_eq!(3, story.time_played);
 }
 
-#[test]
-fn test_update_story() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_update_story(connection: &dyn IfdbStore) {
     assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
 
     let mut story = connection
@@ -979,6 +1910,45 @@ fn test_update_story() {
     }
 }
 
+#[cfg(test)]
+fn test_update_story_clear_fields(connection: &dyn IfdbStore) {
+    assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
+
+    let mut story = connection.get_story(2).unwrap().unwrap();
+    story.story.bibliographic.language = None;
+    story.story.bibliographic.first_published = None;
+    story.story.bibliographic.series_number = None;
+    story.story.bibliographic.forgiveness = None;
+    story.story.bibliographic.description = None;
+    story.story.contacts = None;
+    assert!(connection.update_story(story).is_ok());
+
+    let story = connection.get_story(2).unwrap().unwrap().story;
+    assert_eq!("A Title", story.bibliographic.title);
+    assert!(story.bibliographic.language.is_none());
+    assert!(story.bibliographic.first_published.is_none());
+    assert!(story.bibliographic.series_number.is_none());
+    assert!(story.bibliographic.forgiveness.is_none());
+    assert!(story.bibliographic.description.is_none());
+    assert!(story.contacts.is_none());
+}
+
+#[test]
+fn test_validate_ifictiondate() {
+    assert_eq!(Ok(IFictionDate::Year(1983)), validate_ifictiondate("1983"));
+    assert_eq!(
+        Ok(IFictionDate::YearMonthDay(2020, 2, 29)),
+        validate_ifictiondate(" 2020-02-29 ")
+    );
+
+    assert!(validate_ifictiondate("2021-02-30").is_err());
+    assert!(validate_ifictiondate("2021-13-01").is_err());
+    assert!(validate_ifictiondate("2021-1-1").is_err());
+    assert!(validate_ifictiondate("21").is_err());
+    assert!(validate_ifictiondate("abcd").is_err());
+    assert!(validate_ifictiondate("").is_err());
+}
+
 #[test]
 fn test_fetch_ifids() {
     let connection = setup_test_db();
@@ -987,14 +1957,14 @@ fn test_fetch_ifids() {
     let ifids = connection.fetch_ifids(None, true).unwrap();
     assert_eq!(1, ifids.len());
     assert_eq!(
-        vec![String::from("ZCODE-1-200427-0000")],
+        vec![String::from("ZCODE-1-200427-5AFE")],
         *ifids.get(&1).unwrap()
     );
 
     let ifids = connection.fetch_ifids(None, false).unwrap();
     assert_eq!(2, ifids.len());
     assert_eq!(
-        vec![String::from("ZCODE-1-200427-0000")],
+        vec![String::from("ZCODE-1-200427-5AFE")],
         *ifids.get(&1).unwrap()
     );
     assert_eq!(vec![String::from("ZCODE-12345")], *ifids.get(&2).unwrap());
@@ -1002,7 +1972,7 @@ fn test_fetch_ifids() {
     let ifids = connection.fetch_ifids(Some(1), false).unwrap();
     assert_eq!(1, ifids.len());
     assert_eq!(
-        vec![String::from("ZCODE-1-200427-0000")],
+        vec![String::from("ZCODE-1-200427-5AFE")],
         *ifids.get(&1).unwrap()
     );
 }
@@ -1154,20 +2124,123 @@ fn test_fetch_zcode() {
     );
 }
 
-#[test]
-fn test_store_cover_image() {
-    // Just a smoketest as there's no code yet to pull cover images out of the database
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_store_cover_image(connection: &dyn IfdbStore) {
+    assert!(connection
+        .fetch_cover_image(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .is_none());
+    assert!(connection
+        .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .is_none());
 
+    // Not an image, so there is no thumbnail
     connection
         .store_cover_image(INITIAL_DATA_IFID, vec![0, 1, 2])
         .expect("Error saving");
+    assert_eq!(
+        Some(vec![0, 1, 2]),
+        connection
+            .fetch_cover_image(INITIAL_STORY_DB_ID)
+            .expect("Error loading")
+    );
+    assert!(connection
+        .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .is_none());
+
+    // Large covers are shrunk to fit
+    connection
+        .store_cover_image(INITIAL_DATA_IFID, test_png(400, 300))
+        .expect("Error saving");
+    let thumbnail = connection
+        .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .expect("No thumbnail");
+    let image = image::load_from_memory(&thumbnail).expect("Error decoding thumbnail");
+    assert_eq!((COVER_THUMBNAIL_SIZE, 120), image.dimensions());
+    assert_eq!(
+        Some(thumbnail),
+        connection
+            .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+            .expect("Error loading")
+    );
+
+    // Replacing the cover replaces the thumbnail. Small covers keep their size
+    connection
+        .store_cover_image(INITIAL_DATA_IFID, test_png(40, 60))
+        .expect("Error saving");
+    let thumbnail = connection
+        .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .expect("No thumbnail");
+    let image = image::load_from_memory(&thumbnail).expect("Error decoding thumbnail");
+    assert_eq!((40, 60), image.dimensions());
+}
+
+#[cfg(test)]
+fn test_png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = vec![];
+    image::DynamicImage::new_rgb8(width, height)
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .expect("Error encoding png");
+    png
+}
+
+#[test]
+fn test_cover_thumbnail_deleted_with_story() {
+    let connection = setup_test_db();
+    connection
+        .store_cover_image(INITIAL_DATA_IFID, test_png(400, 300))
+        .expect("Error saving");
+
+    let count_thumbnails = || -> i64 {
+        connection
+            .connection
+            .query_row("SELECT COUNT(*) FROM cover_thumbnail", params![], |row| {
+                row.get(0)
+            })
+            .expect("Error counting thumbnails")
+    };
+    assert_eq!(1, count_thumbnails());
+    connection
+        .delete_story(INITIAL_STORY_DB_ID)
+        .expect("Error deleting");
+    assert_eq!(0, count_thumbnails());
 }
 
 #[test]
-fn test_add_story_data() {
+fn test_migration_21_makes_thumbnails() {
     let connection = setup_test_db();
 
+    // Covers stored before there were thumbnails
+    connection
+        .connection
+        .execute("DROP TABLE cover_thumbnail", params![])
+        .expect("Error dropping table");
+    connection
+        .connection
+        .execute(
+            "UPDATE story SET cover_image = ?1 WHERE id = ?2",
+            params![test_png(400, 300), INITIAL_STORY_DB_ID],
+        )
+        .expect("Error storing cover");
+
+    connection.run_migration_21().expect("Error migrating");
+    let thumbnail = connection
+        .fetch_cover_thumbnail(INITIAL_STORY_DB_ID)
+        .expect("Error loading")
+        .expect("No thumbnail");
+    let image = image::load_from_memory(&thumbnail).expect("Error decoding thumbnail");
+    assert_eq!((COVER_THUMBNAIL_SIZE, 120), image.dimensions());
+}
+
+#[cfg(test)]
+fn test_add_story_data(connection: &dyn IfdbStore) {
     // Existing story
     connection
         .add_story_data(INITIAL_DATA_IFID, vec![0, 1, 2], "test")
@@ -1195,12 +2268,232 @@ fn test_add_story_data() {
 }
 
 #[cfg(test)]
-fn create_simple_save(save_type: SaveType) -> DbSave {
-    DbSave {
-        dbid: 0,
-        version: 2,
-        ifid: INITIAL_DATA_IFID.to_string(),
-        name: String::from("test"),
+fn test_story_releases(connection: &dyn IfdbStore) {
+    let mut story = full_story("ZCODE-1-200101");
+    story.identification.ifids = vec![
+        String::from("ZCODE-1-200101"),
+        String::from("ZCODE-2-200202"),
+        String::from("ZCODE-3-200303"),
+    ];
+    connection
+        .create_story(story)
+        .expect("Error creating story");
+    connection
+        .add_story_data("ZCODE-1-200101", zcode_header(1, b"200101", 0x1234), "test")
+        .expect("Error saving");
+    connection
+        .add_story_data("ZCODE-2-200202", zcode_header(2, b"200202", 0xABCD), "test")
+        .expect("Error saving");
+
+    let playing = || {
+        let summaries: Vec<String> = connection
+            .fetch_story_summaries(true, None)
+            .expect("Error loading")
+            .into_iter()
+            .filter(|s| s.story_id == 2)
+            .map(|s| s.ifid)
+            .collect();
+        let summary = connection
+            .get_story_summary_by_id(2)
+            .expect("Error loading")
+            .unwrap();
+        // One row in the story list, for the release Play launches
+        assert_eq!(vec![summary.ifid.clone()], summaries);
+        summary.ifid
+    };
+
+    // The release without a story file is not listed
+    let releases = connection.fetch_story_releases(2).expect("Error loading");
+    assert_eq!(
+        vec!["ZCODE-1-200101", "ZCODE-2-200202"],
+        releases
+            .iter()
+            .map(|r| r.ifid.as_str())
+            .collect::<Vec<&str>>()
+    );
+    let zcode = releases[1].zcode.as_ref().expect("No header read");
+    assert_eq!(Some(3), zcode.version);
+    assert_eq!(Some("2"), zcode.release.as_deref());
+    assert_eq!(Some("200202"), zcode.serial.as_deref());
+    assert_eq!(Some("ABCD"), zcode.checksum.as_deref());
+    assert_eq!(
+        "Release 2, serial 200202, checksum ABCD",
+        releases[1].to_string()
+    );
+
+    // Until one is chosen, the most recently imported release is played
+    assert_eq!(
+        vec![false, true],
+        releases.iter().map(|r| r.preferred).collect::<Vec<bool>>()
+    );
+    assert_eq!("ZCODE-2-200202", playing());
+
+    connection
+        .store_preferred_release(2, "ZCODE-1-200101")
+        .expect("Error choosing release");
+    assert_eq!(
+        vec![true, false],
+        connection
+            .fetch_story_releases(2)
+            .expect("Error loading")
+            .iter()
+            .map(|r| r.preferred)
+            .collect::<Vec<bool>>()
+    );
+    assert_eq!("ZCODE-1-200101", playing());
+
+    // Only releases of the story with story files can be chosen
+    assert!(matches!(
+        connection.store_preferred_release(2, "ZCODE-3-200303"),
+        Err(IfdbError::NotFound(_))
+    ));
+    assert!(matches!(
+        connection.store_preferred_release(1, "ZCODE-2-200202"),
+        Err(IfdbError::NotFound(_))
+    ));
+    assert_eq!("ZCODE-1-200101", playing());
+    assert!(connection
+        .fetch_story_releases(3)
+        .expect("Error loading")
+        .is_empty());
+}
+
+#[cfg(test)]
+fn test_replace_story_file(connection: &dyn IfdbStore) {
+    connection
+        .store_save(&create_simple_save(SaveType::Normal), false)
+        .expect("Error saving");
+
+    // A corrected file with the same IFID
+    let fixed = zcode_header(1, b"200427", 0x5AFE);
+    let entry = connection
+        .replace_story_file(
+            INITIAL_STORY_DB_ID,
+            INITIAL_DATA_IFID,
+            fixed.clone(),
+            "fixed.z3",
+        )
+        .expect("Error replacing");
+    assert_eq!(Some(INITIAL_DATA_IFID), entry.ifid.as_deref());
+    assert_eq!(
+        "Replaced story file with fixed.z3. Checksum 5AFE unchanged.",
+        entry.description
+    );
+    assert_eq!(
+        Some(fixed),
+        connection
+            .get_story_data(INITIAL_STORY_DB_ID, INITIAL_DATA_IFID)
+            .expect("Error loading")
+    );
+
+    // A later release moves the release, its saves and its session to the new IFID
+    let mut session = connection
+        .get_or_create_session(INITIAL_DATA_IFID.to_string())
+        .expect("Error loading session");
+    session.details_open = true;
+    connection
+        .store_session(session)
+        .expect("Error storing session");
+    let upgrade = zcode_header(2, b"200501", 0x1234);
+    let upgrade_ifid = "ZCODE-2-200501-1234";
+    let count_saves = |ifid: &str| {
+        connection
+            .fetch_saves_for_ifid(ifid.to_string())
+            .expect("Error loading saves")
+            .len()
+    };
+    // Saves for both IFIDs are fetched first, so any cached lists are replaced
+    assert_eq!(1, count_saves(INITIAL_DATA_IFID));
+    assert_eq!(0, count_saves(upgrade_ifid));
+    let entry = connection
+        .replace_story_file(
+            INITIAL_STORY_DB_ID,
+            INITIAL_DATA_IFID,
+            upgrade.clone(),
+            "upgrade.z3",
+        )
+        .expect("Error replacing");
+    assert_eq!(Some(upgrade_ifid), entry.ifid.as_deref());
+    assert_eq!(
+        Some(upgrade),
+        connection
+            .get_story_data(INITIAL_STORY_DB_ID, upgrade_ifid)
+            .expect("Error loading")
+    );
+    assert!(connection
+        .get_story_id_for_ifid(INITIAL_DATA_IFID, false)
+        .expect("Error loading")
+        .is_none());
+    assert_eq!(
+        Some(INITIAL_STORY_DB_ID),
+        connection
+            .get_story_id_for_ifid(upgrade_ifid, false)
+            .expect("Error loading")
+    );
+    assert_eq!(1, connection.count_stories().expect("Error counting"));
+    assert_eq!(1, count_saves(upgrade_ifid));
+    assert_eq!(0, count_saves(INITIAL_DATA_IFID));
+    assert!(
+        connection
+            .get_or_create_session(upgrade_ifid.to_string())
+            .expect("Error loading session")
+            .details_open
+    );
+
+    let history = connection
+        .fetch_story_history(INITIAL_STORY_DB_ID)
+        .expect("Error loading");
+    assert_eq!(
+        vec![
+            "Replaced story file with upgrade.z3. Release 1, serial 200427 became release 2, serial 200501. Checksum changed from 5AFE to 1234.",
+            "Replaced story file with fixed.z3. Checksum 5AFE unchanged.",
+        ],
+        history
+            .iter()
+            .map(|h| h.description.as_str())
+            .collect::<Vec<&str>>()
+    );
+    assert!(history
+        .iter()
+        .all(|h| h.ifid.as_deref() == Some(upgrade_ifid)));
+
+    // Files that aren't story files, or belong to another story, are refused
+    assert!(matches!(
+        connection.replace_story_file(INITIAL_STORY_DB_ID, upgrade_ifid, vec![0, 1, 2], "short.z3"),
+        Err(IfdbError::Invalid(_))
+    ));
+    let other = zcode_header(3, b"200601", 0xABCD);
+    connection
+        .add_story_data("ZCODE-3-200601-ABCD", other.clone(), "other")
+        .expect("Error saving");
+    assert!(matches!(
+        connection.replace_story_file(INITIAL_STORY_DB_ID, upgrade_ifid, other.clone(), "other.z3"),
+        Err(IfdbError::Constraint(_))
+    ));
+    assert!(matches!(
+        connection.replace_story_file(INITIAL_STORY_DB_ID, "ZCODE-9-200901", other, "other.z3"),
+        Err(IfdbError::NotFound(_))
+    ));
+    assert_eq!(
+        2,
+        connection
+            .fetch_story_history(INITIAL_STORY_DB_ID)
+            .expect("Error loading")
+            .len()
+    );
+    assert!(connection
+        .fetch_story_history(2)
+        .expect("Error loading")
+        .is_empty());
+}
+
+#[cfg(test)]
+fn create_simple_save(save_type: SaveType) -> DbSave {
+    DbSave {
+        dbid: 0,
+        version: 2,
+        ifid: INITIAL_DATA_IFID.to_string(),
+        name: String::from("test"),
         saved_when: String::new(),
         data: vec![1, 2, 3],
         save_type,
@@ -1238,9 +2531,8 @@ fn create_full_save(save_type: SaveType) -> DbSave {
     }
 }
 
-#[test]
-fn test_count_saves() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_count_saves(connection: &dyn IfdbStore) {
     assert_eq!(0, connection.count_saves().expect("Error counting saves"));
     let save = create_simple_save(SaveType::Normal);
 
@@ -1252,9 +2544,8 @@ fn test_count_saves() {
     assert_eq!(2, connection.count_saves().expect("Error counting saves"));
 }
 
-#[test]
-fn test_count_autosaves_for_story() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_count_autosaves_for_story(connection: &dyn IfdbStore) {
     assert_eq!(
         0,
         connection
@@ -1288,9 +2579,8 @@ fn test_count_autosaves_for_story() {
     );
 }
 
-#[test]
-fn test_get_save() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_get_save(connection: &dyn IfdbStore) {
     let save = create_full_save(SaveType::Normal);
     connection.store_save(&save, false).expect("Error saving");
     let dbsave = connection
@@ -1311,9 +2601,49 @@ fn test_get_save() {
     }
 }
 
+#[cfg(test)]
+fn test_store_save_duplicate_name(connection: &dyn IfdbStore) {
+    let mut save = create_simple_save(SaveType::Normal);
+    connection.store_save(&save, false).expect("Error saving");
+
+    // A save with the same data is reused, so change it
+    save.data = vec![4, 5, 6];
+    assert!(matches!(
+        connection.store_save(&save, false),
+        Err(IfdbError::Constraint(_))
+    ));
+    connection
+        .store_save(&save, true)
+        .expect("Error overwriting save");
+}
+
 #[test]
-fn test_get_save_by_id() {
-    let connection = setup_test_db();
+fn test_ifdb_error_from_sqlite() {
+    let failure = |code| rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None);
+    assert!(matches!(
+        IfdbError::from(failure(rusqlite::ffi::SQLITE_BUSY)),
+        IfdbError::Busy(_)
+    ));
+    assert!(matches!(
+        IfdbError::from(failure(rusqlite::ffi::SQLITE_LOCKED)),
+        IfdbError::Busy(_)
+    ));
+    assert!(matches!(
+        IfdbError::from(failure(rusqlite::ffi::SQLITE_CORRUPT)),
+        IfdbError::Corrupt(_)
+    ));
+    assert!(matches!(
+        IfdbError::from(failure(rusqlite::ffi::SQLITE_FULL)),
+        IfdbError::Io(_)
+    ));
+    assert!(matches!(
+        IfdbError::from(rusqlite::Error::QueryReturnedNoRows),
+        IfdbError::NotFound(_)
+    ));
+}
+
+#[cfg(test)]
+fn test_get_save_by_id(connection: &dyn IfdbStore) {
     let save = create_full_save(SaveType::Normal);
     let dbid = connection.store_save(&save, false).expect("Error saving");
     let dbsave = connection
@@ -1334,9 +2664,8 @@ fn test_get_save_by_id() {
     }
 }
 
-#[test]
-fn test_fetch_saves_for_ifid() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_fetch_saves_for_ifid(connection: &dyn IfdbStore) {
     connection
         .store_save(&create_simple_save(SaveType::Autosave), false)
         .expect("Error saving");
@@ -1360,9 +2689,8 @@ fn test_fetch_saves_for_ifid() {
     assert_eq!(Some(String::from("Latest Text")), dbsave.latest_text);
 }
 
-#[test]
-fn test_fetch_manual_saves_for_ifid() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_fetch_manual_saves_for_ifid(connection: &dyn IfdbStore) {
     let save = create_full_save(SaveType::Normal);
     connection.store_save(&save, false).expect("Error saving");
     connection
@@ -1385,9 +2713,8 @@ fn test_fetch_manual_saves_for_ifid() {
     assert_eq!(Some(String::from("Latest Text")), dbsave.latest_text);
 }
 
-#[test]
-fn test_delete_autosaves_for_story() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_delete_autosaves_for_story(connection: &dyn IfdbStore) {
     connection
         .store_save(&create_simple_save(SaveType::Autosave), false)
         .expect("Error saving");
@@ -1411,10 +2738,166 @@ fn test_delete_autosaves_for_story() {
     assert_eq!(SaveType::Normal, dbsaves[0].save_type);
 }
 
+#[cfg(test)]
+fn test_trash(connection: &dyn IfdbStore) {
+    assert!(connection
+        .fetch_trash()
+        .expect("Error fetching trash")
+        .is_empty());
+
+    // A story in the trash is hidden until restored
+    connection
+        .set_story_trashed(1, true)
+        .expect("Error trashing story");
+    assert_eq!(0, connection.count_stories().expect("Error counting"));
+    assert!(connection
+        .fetch_story_summaries(false, None)
+        .expect("Error fetching summaries")
+        .is_empty());
+    assert!(connection
+        .get_story_summary_by_id(1)
+        .expect("Error fetching summary")
+        .is_none());
+    let trash: Vec<DbTrashItem> = connection.fetch_trash().expect("Error fetching trash");
+    assert_eq!(1, trash.len());
+    assert_eq!(1, trash[0].story_id);
+    assert_eq!("basic_2", trash[0].title);
+    assert_eq!(TrashContents::Story, trash[0].contents);
+
+    connection
+        .restore_from_trash(&trash[0])
+        .expect("Error restoring");
+    assert_eq!(1, connection.count_stories().expect("Error counting"));
+    assert!(connection
+        .fetch_trash()
+        .expect("Error fetching trash")
+        .is_empty());
+    assert!(connection.set_story_trashed(99, true).is_err());
+
+    // Deleting autosaves moves them to the trash
+    connection
+        .store_save(&create_simple_save(SaveType::Autosave), false)
+        .expect("Error saving");
+    connection
+        .delete_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+        .expect("Error deleting");
+    assert_eq!(
+        0,
+        connection
+            .count_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+            .expect("Error counting")
+    );
+    let trash = connection.fetch_trash().expect("Error fetching trash");
+    assert_eq!(1, trash.len());
+    assert_eq!(
+        TrashContents::Autosaves(INITIAL_DATA_IFID.to_string(), 1),
+        trash[0].contents
+    );
+
+    connection
+        .restore_from_trash(&trash[0])
+        .expect("Error restoring");
+    assert_eq!(
+        1,
+        connection
+            .count_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+            .expect("Error counting")
+    );
+
+    // Purging an item removes it for good
+    connection
+        .delete_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+        .expect("Error deleting");
+    let trash = connection.fetch_trash().expect("Error fetching trash");
+    connection
+        .purge_from_trash(&trash[0])
+        .expect("Error purging");
+    assert!(connection
+        .fetch_trash()
+        .expect("Error fetching trash")
+        .is_empty());
+    connection
+        .set_autosaves_trashed(INITIAL_DATA_IFID, false)
+        .expect("Error restoring");
+    assert_eq!(
+        0,
+        connection
+            .count_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+            .expect("Error counting")
+    );
+
+    // Only items deleted before the time given are purged
+    connection
+        .set_story_trashed(1, true)
+        .expect("Error trashing story");
+    let yesterday = chrono::Local::now().naive_local() - chrono::Duration::days(1);
+    assert_eq!(
+        0,
+        connection
+            .purge_trash(Some(yesterday))
+            .expect("Error purging")
+    );
+    assert_eq!(1, connection.purge_trash(None).expect("Error purging"));
+    assert!(connection
+        .fetch_trash()
+        .expect("Error fetching trash")
+        .is_empty());
+    assert!(connection
+        .get_story_id(INITIAL_DATA_IFID)
+        .expect("Error fetching id")
+        .is_none());
+}
+
 #[test]
-fn test_get_or_create_session() {
+fn test_trash_keeps_story_data() {
     let connection = setup_test_db();
+    assert!(connection.create_story(full_story("ZCODE-12345")).is_ok());
+    let story_id = 2;
+    add_test_data_for_story(&connection, story_id, "ZCODE-12345");
+
+    connection
+        .set_story_trashed(story_id, true)
+        .expect("Error trashing story");
+    check_data_for_story(&connection, story_id);
 
+    // Importing the story again points the player to the trash
+    let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    d.push("resources");
+    d.push("basic_2.z3");
+    connection
+        .set_story_trashed(1, true)
+        .expect("Error trashing story");
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(d.to_str().unwrap(), None, |r| results.borrow_mut().push(r));
+    assert!(matches!(
+        results.into_inner().as_slice(),
+        [LoadFileResult::StoryFileFailureTrashed(_, ifid)] if ifid == INITIAL_DATA_IFID
+    ));
+
+    // Nothing is purged until the days are set
+    assert_eq!(None, connection.get_trash_purge_days().expect("Error"));
+    assert_eq!(0, connection.purge_expired_trash().expect("Error purging"));
+    connection
+        .store_trash_purge_days(Some(30))
+        .expect("Error storing");
+    assert_eq!(Some(30), connection.get_trash_purge_days().expect("Error"));
+    assert_eq!(0, connection.purge_expired_trash().expect("Error purging"));
+    check_data_for_story(&connection, story_id);
+
+    connection
+        .connection
+        .execute(
+            "UPDATE story SET deleted_when = datetime('now', '-31 days') WHERE id = ?1",
+            params![story_id],
+        )
+        .expect("Error updating");
+    assert_eq!(1, connection.purge_expired_trash().expect("Error purging"));
+    check_no_data_for_story(&connection, story_id);
+    assert_eq!(1, connection.fetch_trash().expect("Error fetching").len());
+}
+
+#[cfg(test)]
+fn test_get_or_create_session(connection: &dyn IfdbStore) {
     let session = connection
         .get_or_create_session(INITIAL_DATA_IFID.to_string())
         .expect("Error creating session");
@@ -1425,11 +2908,11 @@ fn test_get_or_create_session() {
     assert!(!session.transcript_active);
     assert!(!session.command_out_active);
     assert_eq!(
-        String::from("transcript_ZCODE-1-200427-0000.log"),
+        String::from("transcript_ZCODE-1-200427-5AFE.log"),
         session.transcript_name
     );
     assert_eq!(
-        String::from("commands_ZCODE-1-200427-0000.commands"),
+        String::from("commands_ZCODE-1-200427-5AFE.commands"),
         session.command_out_name
     );
     assert!(!session.clues_open);
@@ -1439,10 +2922,8 @@ fn test_get_or_create_session() {
     assert!(!session.saves_open);
 }
 
-#[test]
-fn test_store_session() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_store_session(connection: &dyn IfdbStore) {
     let mut session = connection
         .get_or_create_session(INITIAL_DATA_IFID.to_string())
         .expect("Error creating session");
@@ -1450,9 +2931,9 @@ fn test_store_session() {
     session.tools_open = true;
     session.details_open = true;
     session.debug_open = true;
-    session.transcript_name = String::from("transcript2_ZCODE-1-200427-0000.log");
+    session.transcript_name = String::from("transcript2_ZCODE-1-200427-5AFE.log");
     session.transcript_active = true;
-    session.command_out_name = String::from("commands2_ZCODE-1-200427-0000.commands");
+    session.command_out_name = String::from("commands2_ZCODE-1-200427-5AFE.commands");
     session.command_out_active = true;
     session.clues_open = true;
     session.notes_open = true;
@@ -1474,11 +2955,11 @@ fn test_store_session() {
     assert!(session.transcript_active);
     assert!(session.command_out_active);
     assert_eq!(
-        String::from("transcript2_ZCODE-1-200427-0000.log"),
+        String::from("transcript2_ZCODE-1-200427-5AFE.log"),
         session.transcript_name
     );
     assert_eq!(
-        String::from("commands2_ZCODE-1-200427-0000.commands"),
+        String::from("commands2_ZCODE-1-200427-5AFE.commands"),
         session.command_out_name
     );
     assert!(session.clues_open);
@@ -1491,9 +2972,8 @@ fn test_store_session() {
 //
 // Clues
 //
-#[test]
-fn test_count_clues() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_count_clues(connection: &dyn IfdbStore) {
     assert_eq!(0, connection.count_clues().expect("Error counting clues"));
     connection
         .add_clue(
@@ -1506,9 +2986,8 @@ fn test_count_clues() {
     assert_eq!(1, connection.count_clues().expect("Error counting clues"));
 }
 
-#[test]
-fn test_reveal_and_hide_clue() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_reveal_and_hide_clue(connection: &dyn IfdbStore) {
     connection
         .add_clue(
             1,
@@ -1542,9 +3021,8 @@ fn test_reveal_and_hide_clue() {
     assert!(!clue.is_revealed);
 }
 
-#[test]
-fn test_story_has_clues() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_story_has_clues(connection: &dyn IfdbStore) {
     assert!(!connection
         .story_has_clues(INITIAL_STORY_DB_ID)
         .expect("Error checking clues"));
@@ -1561,9 +3039,8 @@ fn test_story_has_clues() {
         .expect("Error checking clues"));
 }
 
-#[test]
-fn test_add_clue_and_get_clues_for_story() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_add_clue_and_get_clues_for_story(connection: &dyn IfdbStore) {
     connection
         .add_clue(
             1,
@@ -1592,9 +3069,8 @@ fn test_add_clue_and_get_clues_for_story() {
 // Notes
 //
 
-#[test]
-fn test_count_notes() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_count_notes(connection: &dyn IfdbStore) {
     assert_eq!(0, connection.count_notes().expect("Error counting notes"));
     let note = Note {
         dbid: 0,
@@ -1609,9 +3085,8 @@ fn test_count_notes() {
     assert_eq!(1, connection.count_notes().expect("Error counting notes"));
 }
 
-#[test]
-fn test_save_note_and_get_notes_for_story() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_save_note_and_get_notes_for_story(connection: &dyn IfdbStore) {
     let notes = connection
         .get_notes_for_story(INITIAL_STORY_DB_ID as i64, false)
         .expect("Error getting notes");
@@ -1661,10 +3136,8 @@ fn test_save_note_and_get_notes_for_story() {
     assert_eq!(2, notes.len());
 }
 
-#[test]
-fn test_set_note_done() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_set_note_done(connection: &dyn IfdbStore) {
     let note = Note {
         dbid: 0,
         story_id: INITIAL_STORY_DB_ID as i64,
@@ -1707,10 +3180,8 @@ fn test_set_note_done() {
     assert!(!note.done);
 }
 
-#[test]
-fn test_set_note_notes() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_set_note_notes(connection: &dyn IfdbStore) {
     let note = Note {
         dbid: 0,
         story_id: INITIAL_STORY_DB_ID as i64,
@@ -1746,10 +3217,9 @@ fn test_set_note_notes() {
 //
 // Map
 //
-#[test]
-fn test_get_rooms_for_story() {
+#[cfg(test)]
+fn test_get_rooms_for_story(connection: &dyn IfdbStore) {
     // Note rooms are not currently added directly, instead added via notes
-    let connection = setup_test_db();
     let rooms = connection
         .get_rooms_for_story(INITIAL_STORY_DB_ID)
         .expect("Error fetching rooms");
@@ -1780,9 +3250,8 @@ fn test_get_rooms_for_story() {
 // Settings
 //
 
-#[test]
-fn test_get_and_store_window_details() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_get_and_store_window_details(connection: &dyn IfdbStore) {
     assert!(connection
         .get_window_details(INITIAL_STORY_DB_ID, WindowType::Main)
         .expect("Error getting details")
@@ -1835,9 +3304,8 @@ fn test_get_and_store_window_details() {
     assert_eq!(details.open, details2.open);
 }
 
-#[test]
-fn test_get_and_store_current_story() {
-    let connection = setup_test_db();
+#[cfg(test)]
+fn test_get_and_store_current_story(connection: &dyn IfdbStore) {
     assert!(connection
         .get_current_story()
         .expect("Error fetching current story")
@@ -1925,34 +3393,1048 @@ fn test_import_file_zip() {
 }
 
 #[test]
-fn test_import_file_cover() {
+fn test_import_file_nested_archive() {
     let connection = setup_test_db();
-    connection.import_file(
-        test_data_path("ZCODE-1-200427-0000.png").as_str(),
-        None,
-        |r: LoadFileResult| match r {
-            LoadFileResult::CoverImageSuccess(pathname, ifid) => {
-                assert_eq!("ZCODE-1-200427-0000", pathname);
-                assert_eq!("ZCODE-1-200427-0000", ifid);
-            }
-            _ => panic!("Expected success got {:?}", r),
-        },
+
+    // A tar.gz holding zip.zip, a readme and a cover in a folder with a long name
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(test_data_path("nested.tar.gz").as_str(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    assert_eq!(2, connection.count_clues().expect("Error counting clues"));
+    assert_eq!(
+        3,
+        connection.count_stories().expect("Error counting stories")
     );
+
+    let results = results.into_inner();
+    assert!(results.iter().any(|r| matches!(
+        r,
+        LoadFileResult::CoverImageSuccess(_, ifid) if ifid == "ZCODE-1-200427-5AFE"
+    )));
+    assert!(results.iter().any(|r| matches!(
+        r,
+        LoadFileResult::UnsupportedFormat(path) if path == "nested.tar/nested/readme.txt"
+    )));
 }
 
 #[test]
-fn test_font_crud() {
-    let connection = setup_test_db();
+fn test_import_archive_limits() {
+    // Each holds basic_3.z3 first, then goes past one of the limits: archives nested five
+    // deep, 501 files, or 257 MB uncompressed
+    for filename in ["limit_depth.zip", "limit_files.zip", "limit_size.zip"].iter() {
+        let connection = setup_test_db();
+        let results = std::cell::RefCell::new(vec![]);
+        connection.import_file(test_data_path(filename).as_str(), None, |r| {
+            results.borrow_mut().push(r)
+        });
+        let results = results.into_inner();
+        assert!(
+            results.iter().any(|r| matches!(
+                r,
+                LoadFileResult::ArchivePartFailure(_, msg) if msg.starts_with("Hit maximum")
+            )),
+            "No limit hit for {}",
+            filename
+        );
 
-    // No fonts to start
-    let fonts = connection.get_fonts();
-    assert!(fonts.is_ok());
-    assert_eq!(0, fonts.unwrap().len());
+        // What was extracted before the limit is still imported
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, LoadFileResult::StoryFileSuccess(_, _))));
+        assert_eq!(
+            2,
+            connection.count_stories().expect("Error counting stories")
+        );
+    }
+}
 
-    // Check can add font
-    assert!(connection.add_font("Test", vec![1, 2, 3], true).is_ok());
+#[test]
+fn test_read_tar_invalid() {
+    assert!(read_tar(&[]).unwrap().is_empty());
+    assert!(read_tar(&[1; 1024]).is_err());
+}
 
-    // Font is there when added
+#[test]
+fn test_preview_import() {
+    let connection = setup_test_db();
+
+    let results = connection.preview_import(test_data_path("zip.zip").as_str(), None);
+    assert_eq!(
+        2,
+        results
+            .iter()
+            .filter(|r| matches!(r, LoadFileResult::StoryFileSuccess(_, _)))
+            .count()
+    );
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::ClueSuccess(_, _))));
+
+    // Nothing was saved
+    assert_eq!(0, connection.count_clues().expect("Error counting clues"));
+    assert_eq!(
+        1,
+        connection.count_stories().expect("Error counting stories")
+    );
+
+    // The real import finds the same stories
+    connection.import_file(test_data_path("zip.zip").as_str(), None, |_| {});
+    assert_eq!(
+        3,
+        connection.count_stories().expect("Error counting stories")
+    );
+}
+
+#[test]
+fn test_import_rolled_back_on_fatal_error() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("rollback");
+    std::fs::copy(test_data_path("basic_3.z3"), dir.join("basic_3.z3")).expect("Error copying");
+    std::fs::write(dir.join("broken.zip"), "not a zip").expect("Error writing");
+
+    // A broken archive picked on its own is rolled back
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(dir.join("broken.zip").to_str().unwrap(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    assert!(matches!(
+        results.into_inner().last(),
+        Some(LoadFileResult::ImportRolledBack(_, _))
+    ));
+
+    // In a folder, only the broken file is rolled back. The story next to it is kept
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(dir.to_str().unwrap(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    let results = results.into_inner();
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::StoryFileSuccess(_, _))));
+    assert!(results.iter().any(
+        |r| matches!(r, LoadFileResult::ImportRolledBack(path, _) if path.ends_with("broken.zip"))
+    ));
+    assert_eq!(
+        2,
+        connection.count_stories().expect("Error counting stories")
+    );
+
+    // The story is recorded as imported, the broken zip is retried
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(dir.to_str().unwrap(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    let results = results.into_inner();
+    assert!(results.iter().any(
+        |r| matches!(r, LoadFileResult::AlreadyImported(path) if path.ends_with("basic_3.z3"))
+    ));
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::ZipfileFailure(_, _))));
+    assert_eq!(
+        2,
+        connection.count_stories().expect("Error counting stories")
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_import_archive_with_broken_inner_zip() {
+    let connection = setup_test_db();
+
+    // A zip holding basic_3.z3 and a broken zip. The story is kept
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(test_data_path("partly_broken.zip").as_str(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    let results = results.into_inner();
+    assert!(results.iter().any(
+        |r| matches!(r, LoadFileResult::ArchivePartFailure(path, _) if path.ends_with("broken.zip"))
+    ));
+    assert!(!results.iter().any(|r| r.is_fatal()));
+    assert!(!results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::ImportRolledBack(_, _))));
+    assert_eq!(
+        2,
+        connection.count_stories().expect("Error counting stories")
+    );
+}
+
+#[test]
+fn test_import_feelies() {
+    let connection = setup_test_db();
+
+    // The map and manual are listed in the ifiction and stored, the letter is missing
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(test_data_path("feelies.zip").as_str(), None, |r| {
+        results.borrow_mut().push(r)
+    });
+    let results = results.into_inner();
+    assert_eq!(
+        2,
+        results
+            .iter()
+            .filter(|r| matches!(r, LoadFileResult::ResourceSuccess(_, _)))
+            .count()
+    );
+    assert!(!results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::CoverImageFailure(_, _))));
+    assert!(results.iter().any(|r| matches!(
+        r,
+        LoadFileResult::UnsupportedFormat(path) if path == "feelies.zip/basic/readme.txt"
+    )));
+
+    let story_id = connection
+        .get_story_id_for_ifid("ZCODE-1-200629-F299", true)
+        .unwrap()
+        .unwrap();
+    let resources = connection.fetch_story_resources(story_id).unwrap();
+    assert_eq!(3, resources.len());
+    assert_eq!("Feelies/Map.png", resources[0].leafname);
+    assert_eq!(
+        Some(std::fs::read(test_data_path("ZCODE-1-200427-5AFE.png")).unwrap()),
+        connection.get_resource_data(resources[0].dbid).unwrap()
+    );
+    assert_eq!("manual.txt", resources[1].leafname);
+    assert_eq!(Some(47), resources[1].size);
+    assert_eq!("letter.pdf", resources[2].leafname);
+    assert_eq!(None, resources[2].size);
+    assert_eq!(
+        None,
+        connection.get_resource_data(resources[2].dbid).unwrap()
+    );
+
+    // Editing the story keeps the stored files for resources that are still listed
+    let mut story = connection.get_story(story_id).unwrap().unwrap();
+    story.story.resources.remove(0);
+    story.story.resources[0].description = String::from("The manual");
+    assert!(connection.update_story(story).is_ok());
+    let resources = connection.fetch_story_resources(story_id).unwrap();
+    assert_eq!(2, resources.len());
+    assert_eq!("manual.txt", resources[0].leafname);
+    assert_eq!("The manual", resources[0].description);
+    assert_eq!(Some(47), resources[0].size);
+}
+
+#[test]
+fn test_resource_matches_path() {
+    assert!(resource_matches_path("manual.txt", "story.zip/manual.txt"));
+    assert!(resource_matches_path(
+        "Manual.TXT",
+        "story.zip/game/manual.txt"
+    ));
+    assert!(resource_matches_path(
+        "Feelies/Map.png",
+        "story.zip/feelies/map.png"
+    ));
+    assert!(resource_matches_path(
+        "Feelies\\Map.png",
+        "story.zip/feelies/map.png"
+    ));
+    assert!(!resource_matches_path(
+        "Feelies/Map.png",
+        "story.zip/map.png"
+    ));
+    assert!(!resource_matches_path(
+        "manual.txt",
+        "story.zip/old_manual.txt"
+    ));
+    assert!(!resource_matches_path("", "story.zip/manual.txt"));
+}
+
+#[test]
+fn test_import_directory() {
+    let connection = setup_test_db();
+    let path = test_data_path("zip");
+
+    // Same files as zip.zip, found recursively
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_file(path.as_str(), None, |r| results.borrow_mut().push(r));
+    assert_eq!(2, connection.count_clues().expect("Error counting clues"));
+    assert_eq!(
+        3,
+        connection.count_stories().expect("Error counting stories")
+    );
+    let first = results.into_inner();
+    assert_eq!(
+        2,
+        first
+            .iter()
+            .filter(|r| matches!(r, LoadFileResult::StoryFileSuccess(_, _)))
+            .count()
+    );
+
+    // Unchanged files are skipped the second time, files that failed are retried
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_directory(path.as_str(), false, &|r| results.borrow_mut().push(r));
+    let second = results.into_inner();
+    assert_eq!(
+        4,
+        second
+            .iter()
+            .filter(|r| matches!(r, LoadFileResult::AlreadyImported(_)))
+            .count()
+    );
+    assert_eq!(
+        first.iter().filter(|r| r.is_failure()).count(),
+        second.iter().filter(|r| r.is_failure()).count()
+    );
+    assert_eq!(
+        3,
+        connection.count_stories().expect("Error counting stories")
+    );
+
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_directory(test_data_path("nosuchdir").as_str(), false, &|r| {
+        results.borrow_mut().push(r)
+    });
+    assert!(matches!(
+        results.into_inner()[..],
+        [LoadFileResult::DirectoryFailure(_, _)]
+    ));
+}
+
+#[test]
+fn test_watched_folders() {
+    let connection = setup_test_db();
+    let path = test_data_path("zip");
+
+    assert!(connection.fetch_watched_folders().unwrap().is_empty());
+    assert!(connection
+        .add_watched_folder(test_data_path("basic_3.z3").as_str())
+        .is_err());
+    assert!(connection.add_watched_folder(path.as_str()).is_ok());
+    // Adding twice is harmless
+    assert!(connection.add_watched_folder(path.as_str()).is_ok());
+    assert_eq!(
+        vec![path.clone()],
+        connection.fetch_watched_folders().unwrap()
+    );
+
+    assert!(connection.rescan_watched_folders(|_| {}).is_ok());
+    assert_eq!(
+        3,
+        connection.count_stories().expect("Error counting stories")
+    );
+
+    assert!(connection.remove_watched_folder(path.as_str()).is_ok());
+    assert!(connection.fetch_watched_folders().unwrap().is_empty());
+}
+
+#[test]
+fn test_import_file_cover() {
+    let connection = setup_test_db();
+    connection.import_file(
+        test_data_path("ZCODE-1-200427-5AFE.png").as_str(),
+        None,
+        |r: LoadFileResult| match r {
+            LoadFileResult::CoverImageSuccess(pathname, ifid) => {
+                assert_eq!("ZCODE-1-200427-5AFE", pathname);
+                assert_eq!("ZCODE-1-200427-5AFE", ifid);
+            }
+            _ => panic!("Expected success got {:?}", r),
+        },
+    );
+}
+
+#[test]
+fn test_import_file_blorb() {
+    let connection = setup_test_db();
+    connection.import_file(
+        test_data_path("basic_3.zblorb").as_str(),
+        None,
+        |r: LoadFileResult| match r {
+            LoadFileResult::IFictionStorySuccess(_, _)
+            | LoadFileResult::StoryFileSuccess(_, _)
+            | LoadFileResult::CoverImageSuccess(_, _) => (),
+            _ => panic!("Expected success got {:?}", r),
+        },
+    );
+    assert_eq!(
+        2,
+        connection.count_stories().expect("Error counting stories")
+    );
+
+    let story_id = connection
+        .get_story_id_for_ifid("ZCODE-1-200629-F299", true)
+        .expect("Error loading story id")
+        .expect("Story not found");
+    let story = connection
+        .get_story(story_id)
+        .expect("Error loading story")
+        .expect("Story not found");
+    assert_eq!("Basic Blorb", story.story.bibliographic.title);
+    assert_eq!(
+        1,
+        sql_count(
+            &connection,
+            "SELECT COUNT(*) FROM story WHERE cover_image IS NOT NULL AND id=?1",
+            story_id
+        )
+    );
+}
+
+#[test]
+fn test_import_file_blorb_invalid() {
+    let connection = setup_test_db();
+    // A story file is not a valid blorb package
+    let results =
+        connection.load_blorb_from_path(test_data_path("basic_2.z3").as_str(), "basic_2.zblorb");
+    assert_eq!(1, results.len());
+    assert!(matches!(results[0], LoadFileResult::BlorbFailure(_, _)));
+}
+
+#[cfg(test)]
+fn zcode_header(release: u16, serial: &[u8; 6], checksum: u16) -> Vec<u8> {
+    let mut data = vec![0; 0x40];
+    data[0] = 3;
+    data[0x02..0x04].copy_from_slice(&release.to_be_bytes());
+    data[0x12..0x18].copy_from_slice(serial);
+    data[0x1C..0x1E].copy_from_slice(&checksum.to_be_bytes());
+    data
+}
+
+#[test]
+fn test_extract_ifid_from_bytes() {
+    assert!(extract_ifid_from_bytes(&[0, 1, 2]).is_err());
+
+    // Checksum is included for modern serials
+    assert_eq!(
+        "ZCODE-7-210101-1234",
+        extract_ifid_from_bytes(&zcode_header(7, b"210101", 0x1234)).unwrap()
+    );
+
+    // Vintage serials and non-date serials have no checksum
+    assert_eq!(
+        "ZCODE-88-840726",
+        extract_ifid_from_bytes(&zcode_header(88, b"840726", 0x1234)).unwrap()
+    );
+    assert_eq!(
+        "ZCODE-2-930101",
+        extract_ifid_from_bytes(&zcode_header(2, b"930101", 0x1234)).unwrap()
+    );
+    assert_eq!(
+        "ZCODE-1-030101",
+        extract_ifid_from_bytes(&zcode_header(1, b"030101", 0x1234)).unwrap()
+    );
+    assert_eq!(
+        "ZCODE-1-000000",
+        extract_ifid_from_bytes(&zcode_header(1, b"000000", 0x1234)).unwrap()
+    );
+    assert_eq!(
+        "ZCODE-1-AB-CDE",
+        extract_ifid_from_bytes(&zcode_header(1, b"AB CDE", 0x1234)).unwrap()
+    );
+
+    // An embedded UUID always wins
+    let mut data = zcode_header(7, b"210101", 0x1234);
+    data.extend_from_slice(b"UUID://1974a053-7dB0-4103-93a1-767c1382c0b7//");
+    assert_eq!(
+        "1974A053-7DB0-4103-93A1-767C1382C0B7",
+        extract_ifid_from_bytes(&data).unwrap()
+    );
+}
+
+#[test]
+fn test_migrate_refuses_newer_database() {
+    let connection = setup_test_db();
+
+    connection
+        .connection
+        .execute(
+            "INSERT INTO migrations (name) VALUES ('9999_future')",
+            params![],
+        )
+        .expect("Error adding migration");
+
+    assert_eq!(
+        Err(MigrationError::Newer(vec!["9999_future".to_string()])),
+        connection.migrate()
+    );
+}
+
+#[test]
+fn test_failed_migration_is_rolled_back_after_backup() {
+    let dir = test_temp_dir("migrate");
+    let database_path = dir.join("ferrif.db");
+    let database_str = database_path.to_str().unwrap();
+    let backup_str = format!("{}.before-migration", database_str);
+
+    let connection = IfdbConnection::connect(database_str).expect("Error connecting");
+    connection.migrate().expect("Error migrating");
+    assert!(!PathBuf::from(&backup_str).exists());
+
+    // Column added by the last migration already exists, so running it again fails
+    connection
+        .connection
+        .execute(
+            "DELETE FROM migrations WHERE name = '0020_story_resource_data'",
+            params![],
+        )
+        .expect("Error resetting migration");
+    match connection.migrate() {
+        Err(MigrationError::Failed(name, _, backup_path)) => {
+            assert_eq!("0020_story_resource_data", name);
+            assert_eq!(Some(backup_str.clone()), backup_path);
+        }
+        other => panic!("Expected failed migration, got {:?}", other),
+    }
+    assert!(PathBuf::from(&backup_str).is_file());
+
+    let count: i64 = connection
+        .connection
+        .query_row(
+            "SELECT COUNT(*) FROM migrations WHERE name = '0020_story_resource_data'",
+            params![],
+            |row| row.get(0),
+        )
+        .expect("Error counting migrations");
+    assert_eq!(0, count);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_migration_13_rekeys_ifids() {
+    let connection = setup_test_db();
+
+    // Story stored under the IFID calculated before checksums were fixed
+    let old_ifid = "ZCODE-7-210101-0000";
+    let new_ifid = "ZCODE-7-210101-1234";
+    connection
+        .add_story_data(old_ifid, zcode_header(7, b"210101", 0x1234), "test")
+        .expect("Error adding story");
+    let story_id = connection
+        .get_story_id(old_ifid)
+        .expect("Error loading story")
+        .unwrap();
+
+    let mut save = create_simple_save(SaveType::Normal);
+    save.ifid = old_ifid.to_string();
+    connection.store_save(&save, false).expect("Error saving");
+    connection
+        .get_or_create_session(old_ifid.to_string())
+        .expect("Error creating session");
+
+    connection
+        .save_note(Note {
+            dbid: 0,
+            story_id: story_id as i64,
+            room_id: 1,
+            notes: String::from("A note"),
+            room_name: None,
+            done: false,
+        })
+        .expect("Error saving note");
+
+    // Story from ifiction data only, using the correct IFID
+    connection
+        .create_story(full_story(new_ifid))
+        .expect("Error creating story");
+    let ifiction_story_id = connection
+        .get_story_id(new_ifid)
+        .expect("Error loading story")
+        .unwrap();
+
+    connection
+        .connection
+        .execute(
+            "DELETE FROM migrations WHERE name = '0013_babel_ifids'",
+            params![],
+        )
+        .expect("Error resetting migration");
+    assert!(connection.migrate().expect("Error migrating").is_empty());
+
+    // The story file moves into the story with the ifiction details, and the story made
+    // for it while playing is removed
+    assert_eq!(
+        Some(ifiction_story_id),
+        connection
+            .get_story_id(new_ifid)
+            .expect("Error loading story")
+    );
+    let summary = connection
+        .get_story_summary_by_id(ifiction_story_id)
+        .expect("Error loading summary")
+        .expect("Story should have a summary");
+    assert_eq!("A Title", summary.title);
+    assert_eq!(new_ifid, summary.ifid);
+    assert!(connection
+        .get_story_summary_by_id(story_id)
+        .expect("Error loading summary")
+        .is_none());
+    assert_eq!(
+        1,
+        connection
+            .get_notes_for_story(ifiction_story_id as i64, true)
+            .expect("Error loading notes")
+            .len()
+    );
+    assert_eq!(
+        None,
+        connection
+            .get_story_id(old_ifid)
+            .expect("Error loading story")
+    );
+    assert_eq!(
+        1,
+        connection
+            .fetch_saves_for_ifid(new_ifid.to_string())
+            .expect("Error loading saves")
+            .len()
+    );
+    assert_eq!(
+        1,
+        sql_count(
+            &connection,
+            "SELECT COUNT(*) FROM session WHERE ifid = 'ZCODE-7-210101-1234' AND ?1 > 0",
+            story_id
+        )
+    );
+
+    // The original story is unchanged
+    assert_eq!(
+        Some(INITIAL_STORY_DB_ID),
+        connection
+            .get_story_id(INITIAL_DATA_IFID)
+            .expect("Error loading story")
+    );
+}
+
+#[test]
+fn test_migration_13_reports_ifid_in_use() {
+    let connection = setup_test_db();
+
+    // The same story file stored twice, once under the IFID calculated before checksums
+    // were fixed
+    let old_ifid = "ZCODE-7-210101-0000";
+    let new_ifid = "ZCODE-7-210101-1234";
+    connection
+        .add_story_data(old_ifid, zcode_header(7, b"210101", 0x1234), "old")
+        .expect("Error adding story");
+    connection
+        .add_story_data(new_ifid, zcode_header(7, b"210101", 0x1234), "new")
+        .expect("Error adding story");
+
+    connection
+        .connection
+        .execute(
+            "DELETE FROM migrations WHERE name = '0013_babel_ifids'",
+            params![],
+        )
+        .expect("Error resetting migration");
+    let notes = connection.migrate().expect("Error migrating");
+    assert_eq!(1, notes.len());
+    assert!(notes[0].contains(old_ifid));
+    assert!(notes[0].contains(new_ifid));
+    assert!(connection
+        .get_story_id(old_ifid)
+        .expect("Error loading story")
+        .is_some());
+}
+
+#[test]
+fn test_export_ifiction() {
+    let connection = setup_test_db();
+    let mut story = full_story("ZCODE-12345");
+    story.bibliographic.description = Some(String::from("First line\nSecond & <last> line"));
+    assert!(connection.create_story(story).is_ok());
+
+    // Single story round trips through the parser. Cover images are not exported.
+    let mut data = vec![];
+    connection
+        .export_ifiction(Some(2), &mut data)
+        .expect("Error exporting");
+    let stories = read_stories_from_xml(data.as_slice()).expect("Error parsing");
+    assert_eq!(1, stories.len());
+    let mut expected = connection
+        .get_story(2)
+        .expect("Error loading story")
+        .unwrap()
+        .story;
+    expected.cover.as_mut().unwrap().cover_image = None;
+    assert_eq!(Ok(expected), stories[0]);
+
+    // Whole library
+    let mut data = vec![];
+    connection
+        .export_ifiction(None, &mut data)
+        .expect("Error exporting");
+    let stories = read_stories_from_xml(data.as_slice()).expect("Error parsing");
+    assert_eq!(2, stories.len());
+    assert!(stories.iter().all(|s| s.is_ok()));
+
+    assert!(matches!(
+        connection.export_ifiction(Some(99), &mut vec![]),
+        Err(IfdbError::NotFound(_))
+    ));
+}
+
+// Backups
+
+#[cfg(test)]
+fn test_temp_dir(name: &str) -> PathBuf {
+    let mut d = std::env::temp_dir();
+    d.push(format!("ferrif-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&d);
+    std::fs::create_dir_all(&d).expect("Error creating temp dir");
+    d
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("backup");
+    let backup_path = dir.join("backup.zip");
+    let database_path = dir.join("restored.db");
+    let backup_str = backup_path.to_str().unwrap();
+    let database_str = database_path.to_str().unwrap();
+
+    connection
+        .backup_to_path(backup_str)
+        .expect("Error backing up");
+
+    // Existing database is kept to one side
+    std::fs::write(&database_path, "old").expect("Error writing database");
+    let first_kept = IfdbConnection::restore_from_backup(backup_str, database_str)
+        .expect("Error restoring")
+        .expect("Database not kept");
+    assert!(first_kept.starts_with(format!("{}.before-restore-", database_str).as_str()));
+    assert_eq!("old", std::fs::read_to_string(&first_kept).unwrap());
+
+    let restored = IfdbConnection::connect(database_str).expect("Error connecting");
+    restored.migrate().expect("Error migrating");
+    assert_eq!(1, restored.count_stories().expect("Error counting stories"));
+    assert_eq!(
+        Some(INITIAL_STORY_DB_ID),
+        restored
+            .get_story_id(INITIAL_DATA_IFID)
+            .expect("Error loading story")
+    );
+
+    // Restoring again keeps the database it replaces without losing the first one kept
+    drop(restored);
+    std::fs::write(&database_path, "newer").expect("Error writing database");
+    let second_kept = IfdbConnection::restore_from_backup(backup_str, database_str)
+        .expect("Error restoring")
+        .expect("Database not kept");
+    assert_ne!(first_kept, second_kept);
+    assert_eq!("old", std::fs::read_to_string(&first_kept).unwrap());
+    assert_eq!("newer", std::fs::read_to_string(&second_kept).unwrap());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_restore_rejects_newer_backup() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("restore-newer");
+    let backup_path = dir.join("backup.zip");
+    let database_path = dir.join("current.db");
+    let backup_str = backup_path.to_str().unwrap();
+    let database_str = database_path.to_str().unwrap();
+
+    connection
+        .connection
+        .execute(
+            "INSERT INTO migrations (name) VALUES ('9999_from_the_future')",
+            params![],
+        )
+        .expect("Error adding migration");
+    connection
+        .backup_to_path(backup_str)
+        .expect("Error backing up");
+
+    std::fs::write(&database_path, "current").expect("Error writing database");
+    assert!(IfdbConnection::restore_from_backup(backup_str, database_str).is_err());
+    assert_eq!("current", std::fs::read_to_string(&database_path).unwrap());
+    assert!(!dir.join("current.db.restoring").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_merge_databases() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("merge");
+    let other_path = dir.join("other.db");
+    let other_str = other_path.to_str().unwrap();
+
+    // Both start with the same autosave, then go their own ways
+    let mut root = create_simple_save(SaveType::Autosave);
+    root.name = String::from("autosave");
+    root.data = vec![9];
+    let local_root_id = connection.store_save(&root, false).unwrap();
+    connection
+        .store_save(&create_simple_save(SaveType::Normal), false)
+        .unwrap();
+
+    {
+        let other = IfdbConnection::connect(other_str).expect("Error connecting");
+        other.migrate().expect("Error migrating");
+        other.import_file(test_data_path("basic_2.z3").as_str(), None, |_| {});
+        other.import_file(test_data_path("basic_3.z3").as_str(), None, |_| {});
+
+        let other_root_id = other.store_save(&root, false).unwrap();
+        let mut child = root.clone();
+        child.data = vec![10];
+        child.parent_id = other_root_id;
+        other.store_save(&child, false).unwrap();
+        let mut conflict = create_simple_save(SaveType::Normal);
+        conflict.data = vec![4, 5, 6];
+        other.store_save(&conflict, false).unwrap();
+
+        other.add_to_time_played(1, 5000).unwrap();
+        other.add_tag_to_story(1, "Merged").unwrap();
+        other
+            .save_note(Note {
+                dbid: 0,
+                story_id: 1,
+                room_id: 17,
+                notes: String::from("Other notes"),
+                room_name: Some(String::from("Test room")),
+                done: true,
+            })
+            .unwrap();
+        other
+            .add_clue(
+                1,
+                String::from("Section"),
+                String::from("Subsection"),
+                String::from("Clue"),
+            )
+            .unwrap();
+        let clue_id = other.get_clues_for_story(1).unwrap()[0].subsections[0].clues[0].dbid;
+        other.reveal_clue(clue_id).unwrap();
+    }
+
+    let changes = connection
+        .merge_from_path(other_str)
+        .expect("Error merging");
+    assert!(changes.contains(&MergeChange::StoryAdded(String::from("basic_3"))));
+    assert!(changes.contains(&MergeChange::SaveRenamed(
+        String::from("basic_2"),
+        String::from("test"),
+        String::from("test (merged)")
+    )));
+    assert!(changes.contains(&MergeChange::AutosavesAdded(String::from("basic_2"), 1)));
+    assert!(changes.contains(&MergeChange::NotesAdded(String::from("basic_2"), 1)));
+    assert!(changes.contains(&MergeChange::CluesRevealed(String::from("basic_2"), 1)));
+
+    assert_eq!(2, connection.count_stories().unwrap());
+    let story_id = connection
+        .get_story_id_for_ifid("ZCODE-1-200629-F299", true)
+        .unwrap();
+    assert!(story_id.is_some());
+    assert_eq!(
+        5000,
+        connection
+            .get_story(INITIAL_STORY_DB_ID)
+            .unwrap()
+            .unwrap()
+            .time_played
+    );
+    assert_eq!(
+        vec!["Merged"],
+        connection
+            .fetch_tags_for_story(INITIAL_STORY_DB_ID)
+            .unwrap()
+    );
+
+    // The new autosave is grafted on to the shared one
+    let saves = connection
+        .fetch_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+        .unwrap();
+    assert_eq!(4, saves.len());
+    let child = saves.iter().find(|s| s.data == vec![10]).unwrap();
+    assert_eq!(local_root_id, child.parent_id);
+    assert_eq!(
+        vec![4, 5, 6],
+        connection
+            .get_save(INITIAL_DATA_IFID.to_string(), String::from("test (merged)"))
+            .unwrap()
+            .unwrap()
+            .data
+    );
+
+    let clues = connection.get_clues_for_story(INITIAL_STORY_DB_ID).unwrap();
+    assert!(clues[0].subsections[0].clues[0].is_revealed);
+
+    // Merging again changes nothing
+    assert_eq!(
+        Vec::<MergeChange>::new(),
+        connection
+            .merge_from_path(other_str)
+            .expect("Error merging")
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_merge_skips_trash() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("merge-trash");
+    let other_path = dir.join("other.db");
+    let other_str = other_path.to_str().unwrap();
+
+    {
+        let other = IfdbConnection::connect(other_str).expect("Error connecting");
+        other.migrate().expect("Error migrating");
+        other.import_file(test_data_path("basic_2.z3").as_str(), None, |_| {});
+        other.import_file(test_data_path("basic_3.z3").as_str(), None, |_| {});
+        other
+            .store_save(&create_simple_save(SaveType::Autosave), false)
+            .unwrap();
+        other
+            .set_autosaves_trashed(INITIAL_DATA_IFID, true)
+            .unwrap();
+        let story_id = other
+            .get_story_id_for_ifid("ZCODE-1-200629-F299", false)
+            .unwrap()
+            .unwrap();
+        other.set_story_trashed(story_id, true).unwrap();
+    }
+
+    assert_eq!(
+        Vec::<MergeChange>::new(),
+        connection
+            .merge_from_path(other_str)
+            .expect("Error merging")
+    );
+    assert_eq!(1, connection.count_stories().unwrap());
+    assert!(connection
+        .get_story_id_for_ifid("ZCODE-1-200629-F299", false)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        0,
+        connection
+            .count_autosaves_for_story(INITIAL_DATA_IFID.to_string())
+            .unwrap()
+    );
+    assert!(connection.fetch_trash().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_merge_from_older_database() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("merge-older");
+    let other_path = dir.join("other.db");
+    let other_str = other_path.to_str().unwrap();
+
+    // A database from before the last migration
+    {
+        let other = IfdbConnection::connect(other_str).expect("Error connecting");
+        other
+            .create_migration_table()
+            .expect("Error creating migrations");
+        let steps = IfdbConnection::migration_steps();
+        for (_, step) in &steps[..steps.len() - 1] {
+            step(&other).expect("Error migrating");
+        }
+    }
+
+    assert!(connection
+        .merge_from_path(other_str)
+        .expect("Error merging")
+        .is_empty());
+
+    // The snapshot is migrated without leaving a backup behind
+    let prefix = format!("ferrif-merge-{}-", std::process::id());
+    let leftovers: Vec<String> = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(&prefix) && name.ends_with(".before-migration"))
+        .collect();
+    assert!(leftovers.is_empty(), "Left behind {:?}", leftovers);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_merge_rejects_newer_database() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("merge-newer");
+    let other_path = dir.join("other.db");
+    let other_str = other_path.to_str().unwrap();
+
+    {
+        let other = IfdbConnection::connect(other_str).expect("Error connecting");
+        other.migrate().expect("Error migrating");
+        other.import_file(test_data_path("basic_3.z3").as_str(), None, |_| {});
+        other
+            .connection
+            .execute(
+                "INSERT INTO migrations (name) VALUES ('9999_from_the_future')",
+                params![],
+            )
+            .expect("Error adding migration");
+    }
+
+    assert!(connection.merge_from_path(other_str).is_err());
+    assert!(connection
+        .merge_from_path(dir.join("missing.db").to_str().unwrap())
+        .is_err());
+    assert_eq!(1, connection.count_stories().unwrap());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_run_daily_backup() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("daily");
+
+    // Off by default
+    assert_eq!(
+        None,
+        connection.run_daily_backup().expect("Error backing up")
+    );
+
+    connection
+        .store_backup_directory(Some(dir.to_str().unwrap().to_string()))
+        .expect("Error storing directory");
+    for day in 1..9 {
+        std::fs::write(dir.join(format!("ferrif-backup-2000-01-0{}.zip", day)), "")
+            .expect("Error writing backup");
+    }
+
+    assert!(connection
+        .run_daily_backup()
+        .expect("Error backing up")
+        .is_some());
+    // Only one backup a day
+    assert_eq!(
+        None,
+        connection.run_daily_backup().expect("Error backing up")
+    );
+
+    // Oldest backups are removed
+    assert_eq!(7, std::fs::read_dir(&dir).unwrap().count());
+    assert!(!dir.join("ferrif-backup-2000-01-01.zip").exists());
+    assert!(dir.join("ferrif-backup-2000-01-08.zip").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(test)]
+fn test_font_crud(connection: &dyn IfdbStore) {
+    // No fonts to start
+    let fonts = connection.get_fonts();
+    assert!(fonts.is_ok());
+    assert_eq!(0, fonts.unwrap().len());
+
+    // Check can add font
+    assert!(connection.add_font("Test", vec![1, 2, 3], true).is_ok());
+
+    // Font is there when added
     let fonts = connection.get_fonts();
     assert!(fonts.is_ok());
 
@@ -1995,10 +4477,8 @@ fn test_font_crud() {
     assert!(fonts.is_ok());
     assert_eq!(0, fonts.unwrap().len());
 }
-#[test]
-fn test_get_and_store_theme() {
-    let connection = setup_test_db();
-
+#[cfg(test)]
+fn test_get_and_store_theme(connection: &dyn IfdbStore) {
     let theme = connection.get_theme("test");
     assert!(theme.is_ok());
     assert!(theme.expect("Error").is_none());
@@ -2218,3 +4698,226 @@ fn test_get_and_store_theme() {
     assert!(theme.text_color.is_none());
     assert!(theme.secondary_background_color.is_none());
 }
+
+#[cfg(test)]
+fn test_import_quetzal_save(connection: &dyn IfdbStore) {
+    let data = std::fs::read(test_data_path("basic_2.qzl")).expect("Error reading save");
+    assert_eq!(
+        INITIAL_DATA_IFID,
+        connection
+            .import_quetzal_save(data.clone(), "basic_2")
+            .expect("Error importing save")
+    );
+
+    let saves = connection
+        .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+        .expect("Error loading saves");
+    assert_eq!(1, saves.len());
+    assert_eq!("basic_2", saves[0].name);
+    assert_eq!(2, saves[0].version);
+    assert_eq!(0x4F05, saves[0].pc);
+    assert_eq!(data, saves[0].data);
+
+    // Same data with the same name is not duplicated
+    assert_eq!(
+        INITIAL_DATA_IFID,
+        connection
+            .import_quetzal_save(data.clone(), "basic_2")
+            .expect("Error importing save")
+    );
+
+    // Different data under the same name gets a new name
+    let mut changed = data.clone();
+    let last = changed.len() - 2;
+    changed[last] = 9;
+    connection
+        .import_quetzal_save(changed, "basic_2")
+        .expect("Error importing save");
+    let names: Vec<String> = connection
+        .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+        .expect("Error loading saves")
+        .into_iter()
+        .map(|s| s.name)
+        .collect();
+    assert_eq!(2, names.len());
+    assert!(names.contains(&String::from("basic_2 (2)")));
+
+    // Saves that do not match a story in the library are rejected
+    let mut unknown = data;
+    unknown[0x15] = b'9';
+    assert!(connection.import_quetzal_save(unknown, "unknown").is_err());
+    assert!(connection
+        .import_quetzal_save(vec![1, 2, 3], "invalid")
+        .is_err());
+}
+
+#[test]
+fn test_import_quetzal_save_file() {
+    let connection = setup_test_db();
+    connection.import_file(
+        test_data_path("basic_2.qzl").as_str(),
+        None,
+        |r: LoadFileResult| match r {
+            LoadFileResult::SaveSuccess(_, ifid) => assert_eq!(INITIAL_DATA_IFID, ifid),
+            _ => panic!("Expected success got {:?}", r),
+        },
+    );
+    assert_eq!(
+        1,
+        connection
+            .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+            .expect("Error loading saves")
+            .len()
+    );
+
+    // .sav is imported the same way as from the saves directory import
+    let dir = test_temp_dir("sav");
+    let sav_path = dir.join("other.sav");
+    std::fs::copy(test_data_path("basic_2.qzl"), &sav_path).expect("Error copying");
+    connection.import_file(sav_path.to_str().unwrap(), None, |r| {
+        assert!(
+            matches!(r, LoadFileResult::SaveSuccess(_, _)),
+            "Expected success got {:?}",
+            r
+        )
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_export_and_import_saves_directory() {
+    let connection = setup_test_db();
+    let dir = test_temp_dir("saves");
+    std::fs::copy(test_data_path("basic_2.qzl"), dir.join("first.qzl")).expect("Error copying");
+    std::fs::write(dir.join("notes.txt"), "not a save").expect("Error writing");
+    std::fs::write(dir.join("broken.sav"), "not a save").expect("Error writing");
+
+    let results = std::cell::RefCell::new(vec![]);
+    connection.import_saves_from_directory(dir.to_str().unwrap(), |r| results.borrow_mut().push(r));
+    let results = results.into_inner();
+    assert_eq!(2, results.len());
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::SaveSuccess(_, _))));
+    assert!(results
+        .iter()
+        .any(|r| matches!(r, LoadFileResult::SaveFailure(_, _))));
+
+    let save = connection
+        .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+        .expect("Error loading saves")
+        .pop()
+        .expect("Save not imported");
+    assert_eq!("first", save.name);
+
+    let export_path = dir.join("exported.qzl");
+    connection
+        .export_save(
+            INITIAL_DATA_IFID.to_string(),
+            save.dbid,
+            export_path.to_str().unwrap(),
+        )
+        .expect("Error exporting save");
+    assert_eq!(
+        std::fs::read(test_data_path("basic_2.qzl")).unwrap(),
+        std::fs::read(&export_path).unwrap()
+    );
+    assert!(connection
+        .export_save(
+            INITIAL_DATA_IFID.to_string(),
+            999,
+            export_path.to_str().unwrap()
+        )
+        .is_err());
+}
+
+#[test]
+fn test_store_cache() {
+    let connection = setup_test_db();
+    let ratings = || {
+        connection
+            .fetch_story_summaries(false, None)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.rating)
+            .collect::<Vec<Option<u8>>>()
+    };
+    assert_eq!(vec![None], ratings());
+
+    // Reads are cached, so a change made directly in SQL is not seen
+    connection
+        .connection
+        .execute("UPDATE story SET rating = 3 WHERE id = 1", params![])
+        .unwrap();
+    assert_eq!(vec![None], ratings());
+
+    // Until a write through the connection clears the cache
+    connection.add_to_time_played(1, 10).unwrap();
+    assert_eq!(vec![Some(3)], ratings());
+
+    // Imports clear the cache
+    connection.import_file(test_data_path("basic_3.z3").as_str(), None, |_| {});
+    assert_eq!(2, ratings().len());
+
+    // Saves are cached per IFID and cleared when a save is stored
+    assert!(connection
+        .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+        .unwrap()
+        .is_empty());
+    connection
+        .store_save(&create_simple_save(SaveType::Normal), false)
+        .unwrap();
+    assert_eq!(
+        1,
+        connection
+            .fetch_manual_saves_for_ifid(INITIAL_DATA_IFID.to_string())
+            .unwrap()
+            .len()
+    );
+
+    // Notes are cleared when a note is saved or changed
+    assert!(connection
+        .get_notes_for_story(INITIAL_STORY_DB_ID as i64, false)
+        .unwrap()
+        .is_empty());
+    let note_id = connection
+        .save_note(Note {
+            dbid: 0,
+            story_id: INITIAL_STORY_DB_ID as i64,
+            room_id: 0,
+            room_name: None,
+            notes: String::from("A note"),
+            done: false,
+        })
+        .unwrap();
+    assert_eq!(
+        1,
+        connection
+            .get_notes_for_story(INITIAL_STORY_DB_ID as i64, false)
+            .unwrap()
+            .len()
+    );
+    connection.set_note_done(note_id, true).unwrap();
+    assert!(connection
+        .get_notes_for_story(INITIAL_STORY_DB_ID as i64, false)
+        .unwrap()
+        .is_empty());
+
+    // Clues are cleared when revealed
+    connection
+        .add_clue(
+            INITIAL_STORY_DB_ID,
+            String::from("Section"),
+            String::from("Subsection"),
+            String::from("Clue"),
+        )
+        .unwrap();
+    let clue_id = connection.get_clues_for_story(INITIAL_STORY_DB_ID).unwrap()[0].subsections[0]
+        .clues[0]
+        .dbid;
+    connection.reveal_clue(clue_id).unwrap();
+    assert!(
+        connection.get_clues_for_story(INITIAL_STORY_DB_ID).unwrap()[0].subsections[0].clues[0]
+            .is_revealed
+    );
+}
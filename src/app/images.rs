@@ -0,0 +1,12 @@
+use eframe::egui;
+use egui::{ColorImage, Context, TextureHandle};
+
+/// Decode a png or jpeg image and upload it as a texture for display
+pub fn load_texture(ctx: &Context, name: &str, data: &[u8]) -> Result<TextureHandle, String> {
+    let image = image::load_from_memory(data).map_err(|e| e.to_string())?;
+    let image = image.to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    let pixels = image.into_raw();
+
+    Ok(ctx.load_texture(name, ColorImage::from_rgba_unmultiplied(size, &pixels)))
+}
@@ -27,7 +27,7 @@ Ferrif is not a game itself. Instead, it is an \"interpreter\" that lets you pla
         "https://ifdb.org/viewgame?id=1rea34vqnz3mtyq1",
     );
     ui.label("
-Once you've downloaded the story file, click the \"Add Story\" button, find the file and click Open. You should then see a confirmation that the file loaded and can click \"Play\" to get started!
+Once you've downloaded the story file, click the \"Add Story\" button, find the file and click Open. Ferrif will show what it found in the file. Click \"Import\" to add it to your library, then click \"Play\" to get started!
     ");
 
     ui.separator();
@@ -38,13 +38,23 @@ Once you've downloaded the story file, click the \"Add Story\" button, find the
 
 Click \"Play\" next to a story to play that story. Only one story can be played at once.
 
-Click \"Details\" next to a story to open a details window. This will let you change the story name and clear any autosaves, as well as delete a story.
+Click \"Details\" next to a story to open a details window. This will let you edit the story's details and clear any autosaves, as well as delete a story.
+
+The sidebar in the Stories window lists your shelves and tags. Add tags to a story from its details, and add stories to shelves with the \"Shelves\" menu next to each story. Smart shelves (shown in italics) are saved searches: type a search, pick any filters, name the shelf and click \"Save search\".
 
 Ferrif stores all your stories and saves in a local database. Click on \"Stats\" to see more details about the contents of this database.
 
 Click \"Prefs\" to change the visual style of Ferrif. You can change the UI and Story settings separtely. You can click \"Import Font\" to load any .ttf font. Note that fonts used for story files must be monospace.
 
-\"Add Story\" can open a .zip file containing stories as well as the raw story files. It will look for any .z3 or .z5 files in the zip.
+\"Add Story\" can open a .zip, .tar or .tar.gz file containing stories as well as the raw story files. It will look for any .z3 or .z5 files in the archive, including inside archives within it.
+
+\"Add Folder\" imports every story, iFiction, cover, clue and zip file in a folder and its subfolders. Files already imported are skipped unless they have changed. Click \"Watch Folder\" once it's done to check that folder for new files each time Ferrif starts.
+
+Blorb packages (.zblorb or .blb) are also supported. The story, its details and its cover image are all imported together.
+
+If an archive has an iFiction file listing auxiliary files (\"feelies\" such as maps and manuals) and those files are in the archive too, they are stored with the story. They are listed under \"Feelies\" in the story's details, where text and images can be viewed and any of them saved to disk.
+
+Saves from other interpreters can be brought in as Quetzal files (.qzl or .sav) with \"Import save\" on the restore window. Each save is matched to its story by release, serial and checksum. \"Export\" next to a save writes it out as a .qzl file.
 ");
 
 
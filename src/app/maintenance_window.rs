@@ -0,0 +1,191 @@
+use super::db_errors::show_db_error;
+use super::ifdb::maintenance::{OrphanedRows, TableSize};
+use super::ifdb::IfdbConnection;
+use super::terp::windows::ButtonWindow;
+use eframe::egui;
+use egui::*;
+use native_dialog::{MessageDialog, MessageType};
+use num_format::{Locale, ToFormattedString};
+
+const DEFAULT_SIZE: Vec2 = Vec2 {
+    x: 500f32,
+    y: 500f32,
+};
+const DEFAULT_POS: Pos2 = Pos2 { x: 60f32, y: 60f32 };
+
+/// Results of the last check. Checks read every table, so they are run from a button rather
+/// than every frame
+pub struct MaintenanceWindowState {
+    pub window: ButtonWindow,
+    integrity_problems: Option<Vec<String>>, // None until checked. Empty if the database is healthy
+    orphans: Option<Vec<OrphanedRows>>,      // None until checked
+    table_sizes: Vec<TableSize>,
+    message: Option<String>, // What the last cleanup or compaction did
+}
+
+impl MaintenanceWindowState {
+    pub fn create() -> MaintenanceWindowState {
+        MaintenanceWindowState {
+            window: ButtonWindow::create(),
+            integrity_problems: None,
+            orphans: None,
+            table_sizes: vec![],
+            message: None,
+        }
+    }
+
+    fn check(&mut self, connection: &IfdbConnection) {
+        let result = connection.check_integrity().and_then(|problems| {
+            let orphans = connection.find_orphaned_rows()?;
+            let table_sizes = connection.table_sizes()?;
+            Ok((problems, orphans, table_sizes))
+        });
+        match result {
+            Ok((problems, orphans, table_sizes)) => {
+                self.integrity_problems = Some(problems);
+                self.orphans = Some(orphans);
+                self.table_sizes = table_sizes;
+            }
+            Err(e) => show_db_error("Check database", &e),
+        }
+    }
+}
+
+fn format_kb(bytes: u64) -> String {
+    format!("{} kB", (bytes / 1024).to_formatted_string(&Locale::en))
+}
+
+fn remove_orphans(connection: &IfdbConnection, state: &mut MaintenanceWindowState) {
+    match connection.remove_orphaned_rows() {
+        Ok(removed) => {
+            let count: usize = removed.iter().map(|o| o.count).sum();
+            state.message = Some(format!(
+                "Removed {} orphaned rows.",
+                count.to_formatted_string(&Locale::en)
+            ));
+            state.check(connection);
+        }
+        Err(e) => show_db_error("Remove orphaned rows", &e),
+    }
+}
+
+fn compact(connection: &IfdbConnection, state: &mut MaintenanceWindowState) {
+    match connection.vacuum() {
+        Ok((before, after)) => {
+            state.message = Some(format!(
+                "Compacted from {} to {}.",
+                format_kb(before),
+                format_kb(after)
+            ));
+            if state.integrity_problems.is_some() {
+                state.check(connection);
+            }
+        }
+        Err(e) => show_db_error("Compact database", &e),
+    }
+}
+
+fn draw_check_results(ui: &mut Ui, state: &MaintenanceWindowState) {
+    match &state.integrity_problems {
+        Some(problems) if problems.is_empty() => {
+            ui.label("Integrity check passed.");
+        }
+        Some(problems) => {
+            ui.colored_label(
+                Color32::RED,
+                format!(
+                    "Integrity check found {} problems. A backup can be restored with --restore.",
+                    problems.len()
+                ),
+            );
+            for problem in problems {
+                ui.label(problem);
+            }
+        }
+        None => {
+            ui.label("Not checked yet.");
+        }
+    }
+
+    if let Some(orphans) = &state.orphans {
+        if orphans.is_empty() {
+            ui.label("No orphaned rows.");
+        }
+        for orphan in orphans {
+            ui.label(orphan.to_string());
+        }
+    }
+
+    if !state.table_sizes.is_empty() {
+        ui.separator();
+        egui::Grid::new("maintenance_table_sizes")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("Table");
+                ui.strong("Rows");
+                ui.strong("Size");
+                ui.end_row();
+                for size in &state.table_sizes {
+                    ui.label(size.name.as_str());
+                    ui.label(size.rows.to_formatted_string(&Locale::en));
+                    ui.label(format_kb(size.bytes));
+                    ui.end_row();
+                }
+            });
+    }
+}
+
+pub fn draw_maintenance_window(
+    connection: &IfdbConnection,
+    ctx: &egui::Context,
+    parent_ui: &mut eframe::egui::Ui,
+    state: &mut MaintenanceWindowState,
+) {
+    let mut is_open = state.window.is_open();
+
+    if is_open {
+        egui::Window::new("Maintenance")
+            .open(&mut is_open)
+            .default_size(DEFAULT_SIZE)
+            .default_pos(DEFAULT_POS)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Check").clicked() {
+                        state.message = None;
+                        state.check(connection);
+                    }
+
+                    let has_orphans = matches!(&state.orphans, Some(orphans) if !orphans.is_empty());
+                    if ui
+                        .add_enabled(has_orphans, egui::Button::new("Remove orphaned rows"))
+                        .clicked()
+                        && MessageDialog::new()
+                            .set_type(MessageType::Warning)
+                            .set_title("Remove orphaned rows?")
+                            .set_text("Are you sure you want to remove the rows left behind by deleted stories? There is no undo.")
+                            .show_confirm()
+                            .unwrap()
+                    {
+                        remove_orphans(connection, state);
+                    }
+
+                    if ui.button("Compact").clicked() {
+                        compact(connection, state);
+                    }
+                });
+
+                if let Some(message) = &state.message {
+                    ui.label(message.as_str());
+                }
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    draw_check_results(ui, state);
+                });
+            });
+    }
+
+    state
+        .window
+        .draw_button_and_update_state("Maintenance", is_open, parent_ui);
+}
@@ -1,4 +1,6 @@
-use super::ifdb::{DbFont, IfdbConnection, ThemeType};
+use super::db_errors::show_db_error;
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{DbFont, ThemeType};
 use super::terp::theme::{FontOption, Theme, ThemeColors, DEFAULT_FONT_SIZE, FONT_SIZE_OPTIONS};
 use super::terp::windows::ButtonWindow;
 
@@ -66,7 +68,7 @@ impl PreferenceWindowState {
 
 fn draw_ui_subsection(
     ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut PreferenceWindowState,
 ) {
     CollapsingHeader::new("UI")
@@ -83,7 +85,7 @@ fn draw_ui_subsection(
 
 fn draw_story_subsection(
     ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut PreferenceWindowState,
 ) {
     CollapsingHeader::new("Story")
@@ -100,7 +102,7 @@ fn draw_story_subsection(
 
 fn draw_fonts_subsection(
     ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut PreferenceWindowState,
 ) {
     CollapsingHeader::new("Fonts")
@@ -113,7 +115,7 @@ fn draw_fonts_subsection(
 
 fn draw_edit_font(
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut PreferenceWindowState,
 ) {
     let selected_name = if let Some(font) = &state.font {
@@ -137,14 +139,14 @@ fn draw_edit_font(
     if let Some(font) = &state.font {
         if parent_ui.button("Delete Selected Font").clicked() {
             if let Err(msg) = connection.delete_font(font.dbid) {
-                println!("Error deleting font. {}", msg);
+                show_db_error("Fonts", &msg);
             }
             state.font = None;
         }
     }
 }
 
-fn draw_import_font(ui: &mut eframe::egui::Ui, connection: &IfdbConnection) {
+fn draw_import_font(ui: &mut eframe::egui::Ui, connection: &dyn IfdbStore) {
     if ui.button("Import Font").clicked() {
         if let Ok(Some(path)) = FileDialog::new()
             .add_filter("Font file", &["ttf", "otf"])
@@ -160,12 +162,10 @@ fn draw_import_font(ui: &mut eframe::egui::Ui, connection: &IfdbConnection) {
                 match fs::read(Path::new(path_str)) {
                     Ok(contents) => {
                         if let Err(msg) = connection.add_font(filename, contents, true) {
-                            println!("Error loading font: {}", msg);
+                            show_db_error("Fonts", &msg);
                         }
                     }
-                    Err(msg) => {
-                        println!("Error loading font: {}", msg);
-                    }
+                    Err(msg) => show_db_error("Fonts", &msg.into()),
                 }
             }
         }
@@ -173,7 +173,7 @@ fn draw_import_font(ui: &mut eframe::egui::Ui, connection: &IfdbConnection) {
 }
 
 pub fn draw_preferences_window(
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     ctx: &egui::Context,
     parent_ui: &mut eframe::egui::Ui,
     state: &mut PreferenceWindowState,
@@ -267,7 +267,7 @@ fn choose_color(parent_ui: &mut eframe::egui::Ui, color: Color32, label: &str) -
     Color32::from_rgba_unmultiplied(srgba[0], srgba[1], srgba[2], srgba[3])
 }
 
-fn load_font_options(connection: &IfdbConnection, allow_proportional: bool) -> Vec<FontOption> {
+fn load_font_options(connection: &dyn IfdbStore, allow_proportional: bool) -> Vec<FontOption> {
     let mut font_options: Vec<FontOption> = vec![FontOption {
         label: String::from("Default"),
         font: None,
@@ -0,0 +1,204 @@
+use super::db_errors::{draw_db_error, show_db_error};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{IfdbError, ShelfQuery, StoryFilter};
+use eframe::egui;
+use egui::*;
+use native_dialog::{MessageDialog, MessageType};
+
+pub struct ShelvesState {
+    new_shelf_name: String,
+}
+
+impl ShelvesState {
+    pub fn create() -> ShelvesState {
+        ShelvesState {
+            new_shelf_name: String::new(),
+        }
+    }
+}
+
+fn show_shelf_error(error: IfdbError) {
+    show_db_error("Shelves", &error);
+}
+
+/// Draw the list of shelves and tags. Selecting one limits the story list to its stories
+pub fn draw_shelves_sidebar(
+    connection: &dyn IfdbStore,
+    ui: &mut Ui,
+    state: &mut ShelvesState,
+    filter: &mut StoryFilter,
+    search_text: &str,
+) {
+    ScrollArea::vertical().show(ui, |ui| {
+        ui.heading("Shelves");
+        if ui
+            .selectable_label(
+                filter.shelf_id.is_none() && filter.tag.is_none(),
+                "All stories",
+            )
+            .clicked()
+        {
+            filter.shelf_id = None;
+            filter.tag = None;
+        }
+
+        match connection.fetch_shelves() {
+            Ok(shelves) => {
+                for shelf in shelves {
+                    // Smart shelves are shown in italics
+                    let mut text = RichText::new(shelf.name.clone());
+                    if shelf.query.is_some() {
+                        text = text.italics();
+                    }
+
+                    let selected = filter.shelf_id == Some(shelf.dbid);
+                    if ui.selectable_label(selected, text).clicked() {
+                        filter.shelf_id = if selected { None } else { Some(shelf.dbid) };
+                    }
+                }
+            }
+            Err(e) => draw_db_error(ui, "shelves", &e),
+        }
+
+        ui.separator();
+        ui.heading("Tags");
+        match connection.fetch_tags() {
+            Ok(tags) => {
+                if tags.is_empty() {
+                    ui.label("No tags yet. Add them from a story's details.");
+                }
+                for tag in tags {
+                    let selected = filter.tag.as_ref() == Some(&tag);
+                    if ui.selectable_label(selected, tag.clone()).clicked() {
+                        filter.tag = if selected { None } else { Some(tag) };
+                    }
+                }
+            }
+            Err(e) => draw_db_error(ui, "tags", &e),
+        }
+
+        ui.separator();
+        ui.add(TextEdit::singleline(&mut state.new_shelf_name).hint_text("New shelf name"));
+        ui.horizontal_wrapped(|ui| {
+            let mut query = None;
+            let mut create = ui.button("Add shelf").clicked();
+            if ui
+                .button("Save search")
+                .on_hover_text("Add a smart shelf with the current search and filters")
+                .clicked()
+            {
+                query = Some(ShelfQuery::from_filter(search_text, filter));
+                create = true;
+            }
+
+            if create {
+                match connection.create_shelf(state.new_shelf_name.as_str(), query) {
+                    Ok(shelf_id) => {
+                        state.new_shelf_name.clear();
+                        filter.shelf_id = Some(shelf_id);
+                    }
+                    Err(e) => show_shelf_error(e),
+                }
+            }
+        });
+
+        if let Some(shelf_id) = filter.shelf_id {
+            if ui.button("Delete shelf").clicked()
+                && MessageDialog::new()
+                    .set_type(MessageType::Warning)
+                    .set_title("Delete shelf?")
+                    .set_text("Are you sure you want to delete this shelf? Its stories are kept.")
+                    .show_confirm()
+                    .unwrap()
+            {
+                match connection.delete_shelf(shelf_id) {
+                    Ok(()) => filter.shelf_id = None,
+                    Err(e) => show_shelf_error(e),
+                }
+            }
+        }
+    });
+}
+
+/// Draw a checkbox for each shelf stories can be added to by hand
+pub fn draw_story_shelf_checkboxes(connection: &dyn IfdbStore, story_id: u32, ui: &mut Ui) {
+    if let (Ok(shelves), Ok(shelf_ids)) = (
+        connection.fetch_shelves(),
+        connection.fetch_shelf_ids_for_story(story_id),
+    ) {
+        for shelf in shelves.into_iter().filter(|s| s.query.is_none()) {
+            let mut checked = shelf_ids.contains(&shelf.dbid);
+            if ui.checkbox(&mut checked, shelf.name).changed() {
+                let result = if checked {
+                    connection.add_story_to_shelf(shelf.dbid, story_id)
+                } else {
+                    connection.remove_story_from_shelf(shelf.dbid, story_id)
+                };
+                if let Err(e) = result {
+                    show_shelf_error(e);
+                }
+            }
+        }
+    }
+}
+
+/// Draw a menu to add or remove a story from shelves and existing tags
+pub fn draw_story_shelves_menu(connection: &dyn IfdbStore, story_id: u32, ui: &mut Ui) {
+    ui.menu_button("Shelves", |ui| {
+        draw_story_shelf_checkboxes(connection, story_id, ui);
+
+        if let (Ok(tags), Ok(story_tags)) = (
+            connection.fetch_tags(),
+            connection.fetch_tags_for_story(story_id),
+        ) {
+            if !tags.is_empty() {
+                ui.separator();
+            }
+            for tag in tags {
+                let mut checked = story_tags.contains(&tag);
+                if ui.checkbox(&mut checked, tag.as_str()).changed() {
+                    let result = if checked {
+                        connection.add_tag_to_story(story_id, tag.as_str())
+                    } else {
+                        connection.remove_tag_from_story(story_id, tag.as_str())
+                    };
+                    if let Err(e) = result {
+                        show_shelf_error(e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Draw the tags for a story, with a field to add a new one. Clicking a tag removes it
+pub fn draw_story_tags(
+    connection: &dyn IfdbStore,
+    story_id: u32,
+    ui: &mut Ui,
+    new_tag: &mut String,
+) {
+    ui.horizontal_wrapped(|ui| {
+        if let Ok(tags) = connection.fetch_tags_for_story(story_id) {
+            for tag in tags {
+                if ui
+                    .small_button(tag.as_str())
+                    .on_hover_text("Remove tag")
+                    .clicked()
+                {
+                    if let Err(e) = connection.remove_tag_from_story(story_id, tag.as_str()) {
+                        show_shelf_error(e);
+                    }
+                }
+            }
+        }
+
+        ui.add(TextEdit::singleline(new_tag).hint_text("New tag"));
+        if ui.button("Add tag").clicked() {
+            match connection.add_tag_to_story(story_id, new_tag.as_str()) {
+                Ok(()) => new_tag.clear(),
+                Err(e) => show_shelf_error(e),
+            }
+        }
+    });
+}
@@ -1,8 +1,44 @@
-use super::ifdb::IfdbConnection;
+use super::db_errors::{draw_db_error, show_db_error};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{IfdbConnection, IfdbError};
 use eframe::egui;
+use native_dialog::FileDialog;
 use num_format::{Locale, ToFormattedString};
 use std::fs;
 
+fn show_backup_error(error: IfdbError) {
+    show_db_error("Backup failed", &error);
+}
+
+/// Ask the user for a path and write a backup archive to it
+fn backup_now(connection: &IfdbConnection) {
+    let path = FileDialog::new()
+        .set_filename("ferrif-backup.zip")
+        .add_filter("Backup archive", &["zip"])
+        .show_save_single_file()
+        .unwrap();
+
+    if let Some(path) = path {
+        if let Err(e) = connection.backup_to_path(&path.to_string_lossy()) {
+            show_backup_error(e);
+        }
+    }
+}
+
+/// Ask the user for a directory for daily backups and write the first one
+fn turn_on_daily_backups(connection: &IfdbConnection) {
+    let path = FileDialog::new().show_open_single_dir().unwrap();
+
+    if let Some(path) = path {
+        let result = connection
+            .store_backup_directory(Some(path.to_string_lossy().to_string()))
+            .and_then(|_| connection.run_daily_backup());
+        if let Err(e) = result {
+            show_backup_error(e);
+        }
+    }
+}
+
 pub fn stats_window_handler(
     _: &egui::Context,
     ui: &mut eframe::egui::Ui,
@@ -50,4 +86,25 @@ pub fn stats_window_handler(
             .wrap_db_error(connection.count_notes())
             .to_formatted_string(&Locale::en)
     ));
+
+    ui.separator();
+    if ui.button("Back up now").clicked() {
+        backup_now(connection);
+    }
+    match connection.get_backup_directory() {
+        Ok(Some(directory)) => {
+            ui.label(format!("Daily backups: {}", directory));
+            if ui.button("Turn off daily backups").clicked() {
+                if let Err(e) = connection.store_backup_directory(None) {
+                    show_db_error("Backups", &e);
+                }
+            }
+        }
+        Ok(None) => {
+            if ui.button("Turn on daily backups").clicked() {
+                turn_on_daily_backups(connection);
+            }
+        }
+        Err(e) => draw_db_error(ui, "backup settings", &e),
+    }
 }
@@ -1,9 +1,21 @@
-use super::ifdb::ifiction::{convert_forgiveness_to_str, convert_ifictiondate_to_str, Release, Colophon, Contacts, Bibilographic};
-use super::ifdb::IfdbConnection;
+use super::db_errors::{draw_db_error, show_db_error};
+use super::ifdb::ifiction::{
+    convert_forgiveness_to_str, convert_ifictiondate_to_str, validate_ifictiondate, Bibilographic,
+    Colophon, Contacts, Forgiveness, Release, Story, FORGIVENESS_LEVELS,
+};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{
+    read_story_file, zcode_from_header, DbStoryRelease, DbStoryResource, IfdbConnection,
+    MAX_RATING, PLAY_STATUSES,
+};
+use super::images::load_texture;
+use super::shelves::{draw_story_shelf_checkboxes, draw_story_tags};
 use super::terp::windows::FerrifWindow;
 use eframe::egui;
 use egui::*;
-use native_dialog::{MessageDialog, MessageType};
+use native_dialog::{FileDialog, MessageDialog, MessageType};
+use std::fs::File;
+use std::path::Path;
 
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub enum DetailsWindowEditState {
@@ -14,15 +26,228 @@ pub enum DetailsWindowEditState {
 pub struct DetailsWindowState {
     pub window: FerrifWindow,
     pub edit_state: DetailsWindowEditState,
-    pub title: String,
+    pub form: StoryEditForm,
+    pub form_errors: Vec<String>,
+    pub new_tag: String,
+    pub review_text: String,
+    review_story_id: Option<u32>, // Story the review text was loaded for
+    feelie: Option<FeelieView>,   // Auxiliary file open for viewing
+    cover: Option<(u32, Option<TextureHandle>)>, // Story the cover was loaded for, and its cover
+}
+
+const FEELIE_MISSING: &str = "File was not found when the story was imported.";
+
+enum FeelieContent {
+    Text(String),
+    Image(TextureHandle),
+}
+
+/// An auxiliary file ("feelie") shown in its own window
+struct FeelieView {
+    title: String,
+    content: FeelieContent,
+}
+
+impl FeelieView {
+    fn load(ctx: &Context, resource: &DbStoryResource, data: &[u8]) -> Result<FeelieView, String> {
+        let content = match feelie_extension(&resource.leafname).as_deref() {
+            Some("png" | "jpg" | "jpeg") => {
+                FeelieContent::Image(load_texture(ctx, &resource.leafname, data)?)
+            }
+            _ => FeelieContent::Text(String::from_utf8_lossy(data).to_string()),
+        };
+
+        Ok(FeelieView {
+            title: resource.leafname.clone(),
+            content,
+        })
+    }
+}
+
+fn feelie_extension(leafname: &str) -> Option<String> {
+    Path::new(leafname)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
 }
+
+/// Only text and images can be shown in the app. Anything else can be saved out
+fn can_view_feelie(leafname: &str) -> bool {
+    matches!(
+        feelie_extension(leafname).as_deref(),
+        Some("txt" | "text" | "md" | "html" | "htm" | "png" | "jpg" | "jpeg")
+    )
+}
+
 impl DetailsWindowState {
     pub fn create() -> DetailsWindowState {
         DetailsWindowState {
             window: FerrifWindow::create_empty(),
             edit_state: DetailsWindowEditState::NotEditing,
-            title: String::new(),
+            form: StoryEditForm::default(),
+            form_errors: vec![],
+            new_tag: String::new(),
+            review_text: String::new(),
+            review_story_id: None,
+            feelie: None,
+            cover: None,
+        }
+    }
+
+    /// Load the cover again next time it is drawn, as it may have been replaced by an import
+    pub fn reload_cover(&mut self) {
+        self.cover = None;
+    }
+
+    /// Load the cover for a story if it isn't already loaded
+    fn load_cover(&mut self, ctx: &Context, connection: &IfdbConnection, story_id: u32) {
+        if matches!(self.cover, Some((id, _)) if id == story_id) {
+            return;
         }
+
+        let texture = match connection.fetch_cover_image(story_id) {
+            Ok(Some(data)) => {
+                match load_texture(ctx, format!("cover-{}", story_id).as_str(), &data) {
+                    Ok(texture) => Some(texture),
+                    Err(msg) => {
+                        println!("Unable to load cover for story {}: {}", story_id, msg);
+                        None
+                    }
+                }
+            }
+            Ok(None) => None,
+            Err(msg) => {
+                println!("Unable to load cover for story {}: {}", story_id, msg);
+                None
+            }
+        };
+        self.cover = Some((story_id, texture));
+    }
+}
+
+/// Bibliographic and contact fields of a story as edited in the details window.
+/// Everything is kept as text until saved so partial input can be typed freely
+#[derive(Clone, Debug, Default)]
+pub struct StoryEditForm {
+    pub title: String,
+    pub author: String,
+    pub language: String,
+    pub headline: String,
+    pub first_published: String,
+    pub genre: String,
+    pub group: String,
+    pub series: String,
+    pub series_number: String,
+    pub forgiveness: Option<Forgiveness>,
+    pub description: String,
+    pub url: String,
+    pub author_email: String,
+}
+
+fn optional_text(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+impl StoryEditForm {
+    pub fn from_story(story: &Story) -> StoryEditForm {
+        let bibliographic = &story.bibliographic;
+        let (url, author_email) = match &story.contacts {
+            Some(contacts) => (contacts.url.clone(), contacts.author_email.clone()),
+            None => (None, None),
+        };
+
+        StoryEditForm {
+            title: bibliographic.title.clone(),
+            author: bibliographic.author.clone(),
+            language: bibliographic.language.clone().unwrap_or_default(),
+            headline: bibliographic.headline.clone().unwrap_or_default(),
+            first_published: convert_ifictiondate_to_str(bibliographic.first_published)
+                .unwrap_or_default(),
+            genre: bibliographic.genre.clone().unwrap_or_default(),
+            group: bibliographic.group.clone().unwrap_or_default(),
+            series: bibliographic.series.clone().unwrap_or_default(),
+            series_number: bibliographic
+                .series_number
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            forgiveness: bibliographic.forgiveness,
+            description: bibliographic.description.clone().unwrap_or_default(),
+            url: url.unwrap_or_default(),
+            author_email: author_email.unwrap_or_default(),
+        }
+    }
+
+    /// Validate the form and copy its values onto the story. Nothing is changed
+    /// if any field is invalid; instead every problem found is returned
+    pub fn apply_to(&self, story: &mut Story) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if self.title.trim().is_empty() {
+            errors.push(String::from("Title is required."));
+        }
+        if self.author.trim().is_empty() {
+            errors.push(String::from("Author is required."));
+        }
+
+        let first_published = match optional_text(&self.first_published) {
+            None => None,
+            Some(s) => match validate_ifictiondate(s.as_str()) {
+                Ok(d) => Some(d),
+                Err(msg) => {
+                    errors.push(msg);
+                    None
+                }
+            },
+        };
+
+        let series_number = match optional_text(&self.series_number) {
+            None => None,
+            Some(s) => match s.parse::<u32>() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    errors.push(format!("Series number {} is not a whole number.", s));
+                    None
+                }
+            },
+        };
+
+        let author_email = optional_text(&self.author_email);
+        if let Some(email) = &author_email {
+            if !email.contains('@') {
+                errors.push(format!("{} is not a valid email address.", email));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let bibliographic = &mut story.bibliographic;
+        bibliographic.title = self.title.trim().to_string();
+        bibliographic.author = self.author.trim().to_string();
+        bibliographic.language = optional_text(&self.language);
+        bibliographic.headline = optional_text(&self.headline);
+        bibliographic.first_published = first_published;
+        bibliographic.genre = optional_text(&self.genre);
+        bibliographic.group = optional_text(&self.group);
+        bibliographic.series = optional_text(&self.series);
+        bibliographic.series_number = series_number;
+        bibliographic.forgiveness = self.forgiveness;
+        bibliographic.description = optional_text(&self.description);
+
+        let url = optional_text(&self.url);
+        story.contacts = if url.is_none() && author_email.is_none() {
+            None
+        } else {
+            Some(Contacts { url, author_email })
+        };
+
+        Ok(())
     }
 }
 
@@ -36,6 +261,16 @@ fn draw_label_if_not_none(
     }
 }
 
+/// Draw a story's cover at full size, shrunk only if wider than the window
+fn draw_cover(texture: &TextureHandle, parent_ui: &mut eframe::egui::Ui) {
+    let mut size = texture.size_vec2();
+    let width = parent_ui.available_width();
+    if size.x > width {
+        size *= width / size.x;
+    }
+    parent_ui.image(texture, size);
+}
+
 /// Draw the bibliographic details for the currently selected story
 
 pub fn draw_story_details_window(
@@ -48,10 +283,30 @@ pub fn draw_story_details_window(
     let story_id = state.window.window_details.story_id as u32;
     let mut autosave_deleted = false;
     let mut edit_state = state.edit_state;
-    let mut edit_state_title = state.title.clone();
+    let mut form = state.form.clone();
+    let mut form_errors = state.form_errors.clone();
+    let mut save_requested = false;
+    let mut new_tag = state.new_tag.clone();
+
+    // Review text is edited in place, so only load it when the story changes
+    if state.review_story_id != Some(story_id) {
+        state.review_story_id = Some(story_id);
+        state.review_text = match connection.get_story_review(story_id) {
+            Ok(Some(review)) => review.review.unwrap_or_default(),
+            _ => String::new(),
+        };
+    }
+    let mut review_text = state.review_text.clone();
+    let mut feelie_requested = None;
 
     if state.window.window_details.open {
+        state.load_cover(ctx, connection, story_id);
+        let cover = state
+            .cover
+            .as_ref()
+            .and_then(|(_, texture)| texture.clone());
         if let Ok(Some(story)) = connection.get_story(story_id) {
+            let story_form = StoryEditForm::from_story(&story.story);
             let bibiographic = story.story.bibliographic;
             let contacts = story.story.contacts;
             let colophon = story.story.colophon;
@@ -62,53 +317,97 @@ pub fn draw_story_details_window(
                 .default_pos(state.window.get_pos())
                 .open(&mut state.window.window_details.open)
                 .show(ctx, |parent_ui| {
-                    parent_ui.horizontal_wrapped(|ui| match edit_state {
+                    match edit_state {
                         DetailsWindowEditState::NotEditing => {
-                            ui.add(egui::Label::new(RichText::new(title).heading()));
-                            if ui.button("Edit").clicked() {
-                                edit_state = DetailsWindowEditState::Editing;
-                                edit_state_title = bibiographic.title.clone();
+                            parent_ui.horizontal_wrapped(|ui| {
+                                ui.add(egui::Label::new(RichText::new(title.clone()).heading()));
+                                if ui.button("Edit").clicked() {
+                                    edit_state = DetailsWindowEditState::Editing;
+                                    form = story_form;
+                                    form_errors.clear();
+                                }
+                            });
+
+                            if let Some(texture) = &cover {
+                                draw_cover(texture, parent_ui);
                             }
+
+                            draw_bibliographic(bibiographic, parent_ui);
+
+                            draw_contacts(contacts, parent_ui);
                         }
                         DetailsWindowEditState::Editing => {
-                            ui.add(egui::TextEdit::singleline(&mut edit_state_title));
-                            if ui.button("Save").clicked() {
-                                edit_state = DetailsWindowEditState::NotEditing;
+                            draw_edit_form(&mut form, parent_ui);
+
+                            for msg in form_errors.iter() {
+                                parent_ui.colored_label(Color32::RED, msg);
                             }
+
+                            parent_ui.horizontal_wrapped(|ui| {
+                                if ui.button("Save").clicked() {
+                                    save_requested = true;
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    edit_state = DetailsWindowEditState::NotEditing;
+                                    form_errors.clear();
+                                }
+                            });
                         }
-                    });
+                    }
 
-                    draw_bibliographic(bibiographic, parent_ui);
-                    
-                    draw_contacts(contacts, parent_ui);
-                    
                     draw_colophon(colophon, parent_ui);
 
                     draw_releases(releases, parent_ui);
 
+                    feelie_requested = draw_feelies(connection, story_id, parent_ui);
+
+                    draw_review(connection, story_id, &mut review_text, parent_ui);
+
+                    draw_tags_and_shelves(connection, story_id, &mut new_tag, parent_ui);
+
                     autosave_deleted = draw_versions(connection, story_id, parent_ui);
 
-                    if draw_delete_button(connection, story_id, parent_ui) {
+                    draw_history(connection, story_id, parent_ui);
+
+                    if draw_actions(connection, story_id, title.as_str(), parent_ui) {
                         autosave_deleted = true;
                     }
                 });
         }
     }
 
-    state.title = edit_state_title;
-    // Persist changes to title if requested
-    if state.edit_state == DetailsWindowEditState::Editing
-        && edit_state == DetailsWindowEditState::NotEditing
-    {
-        if let Ok(Some(mut story)) = connection.get_story(story_id) {
-            story.story.bibliographic.title = state.title.clone();
-            if let Err(msg) = connection.update_story(story) {
-                println!("Error updating story title: {}", msg);
-            }
-        } else {
-            println!("Error updating story title")
+    state.new_tag = new_tag;
+    state.review_text = review_text;
+    if let Some(resource) = feelie_requested {
+        match connection.get_resource_data(resource.dbid) {
+            Ok(Some(data)) => match FeelieView::load(ctx, &resource, &data) {
+                Ok(view) => state.feelie = Some(view),
+                Err(msg) => show_feelie_error(msg),
+            },
+            Ok(None) => show_feelie_error(String::from(FEELIE_MISSING)),
+            Err(e) => show_db_error("Feelies", &e),
+        }
+    }
+    draw_feelie_window(ctx, &mut state.feelie);
+
+    // Persist the edited details if requested, staying in edit mode if they are invalid
+    if save_requested {
+        match connection.get_story(story_id) {
+            Ok(Some(mut story)) => match form.apply_to(&mut story.story) {
+                Ok(()) => match connection.update_story(story) {
+                    Ok(()) => {
+                        edit_state = DetailsWindowEditState::NotEditing;
+                        form_errors.clear();
+                    }
+                    Err(msg) => form_errors = vec![format!("Error updating story. {}", msg)],
+                },
+                Err(errors) => form_errors = errors,
+            },
+            _ => form_errors = vec![String::from("Error updating story. Story not found.")],
         }
     }
+    state.form = form;
+    state.form_errors = form_errors;
 
     state.edit_state = edit_state;
     // Did not end in a delete
@@ -156,6 +455,55 @@ fn draw_bibliographic(bibliographic: Bibilographic,parent_ui: &mut eframe::egui:
     
 }
 
+fn draw_text_row(ui: &mut Ui, label: &str, value: &mut String, hint: &str) {
+    ui.label(label);
+    ui.add(egui::TextEdit::singleline(value).hint_text(hint));
+    ui.end_row();
+}
+
+fn draw_edit_form(form: &mut StoryEditForm, parent_ui: &mut eframe::egui::Ui) {
+    egui::Grid::new("story_edit_form")
+        .num_columns(2)
+        .show(parent_ui, |ui| {
+            draw_text_row(ui, "Title", &mut form.title, "");
+            draw_text_row(ui, "Author", &mut form.author, "");
+            draw_text_row(ui, "Headline", &mut form.headline, "An Interactive Fiction");
+            draw_text_row(ui, "Language", &mut form.language, "en");
+            draw_text_row(
+                ui,
+                "First Published",
+                &mut form.first_published,
+                "YYYY or YYYY-MM-DD",
+            );
+            draw_text_row(ui, "Genre", &mut form.genre, "");
+            draw_text_row(ui, "Group", &mut form.group, "");
+            draw_text_row(ui, "Series", &mut form.series, "");
+            draw_text_row(ui, "Series Number", &mut form.series_number, "");
+
+            ui.label("Forgiveness");
+            egui::ComboBox::from_id_source("story_edit_forgiveness")
+                .selected_text(convert_forgiveness_to_str(form.forgiveness).unwrap_or_default())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut form.forgiveness, None, "None");
+                    for forgiveness in FORGIVENESS_LEVELS.iter() {
+                        ui.selectable_value(
+                            &mut form.forgiveness,
+                            Some(*forgiveness),
+                            convert_forgiveness_to_str(Some(*forgiveness)).unwrap(),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            draw_text_row(ui, "URL", &mut form.url, "");
+            draw_text_row(ui, "Email", &mut form.author_email, "");
+
+            ui.label("Description");
+            ui.add(egui::TextEdit::multiline(&mut form.description));
+            ui.end_row();
+        });
+}
+
 fn draw_contacts(contacts: Option<Contacts>, parent_ui: &mut eframe::egui::Ui) {
     if let Some(contact) = &contacts {
         CollapsingHeader::new("Contact Info")
@@ -217,49 +565,331 @@ fn draw_colophon(colophon:Option<Colophon>,
     }
 }
 
+fn show_feelie_error(msg: String) {
+    MessageDialog::new()
+        .set_type(MessageType::Error)
+        .set_title("Feelies")
+        .set_text(msg.as_str())
+        .show_alert()
+        .unwrap();
+}
+
+/// Ask the user for a path and write the contents of an auxiliary file to it
+fn save_feelie(connection: &IfdbConnection, resource: &DbStoryResource) {
+    let filename = Path::new(&resource.leafname)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("feelie");
+    let path = FileDialog::new()
+        .set_filename(filename)
+        .show_save_single_file()
+        .unwrap();
+
+    if let Some(path) = path {
+        let result = match connection.get_resource_data(resource.dbid) {
+            Ok(Some(data)) => std::fs::write(&path, data).map_err(|e| e.to_string()),
+            Ok(None) => Err(String::from(FEELIE_MISSING)),
+            Err(e) => return show_db_error("Feelies", &e),
+        };
+        if let Err(msg) = result {
+            show_feelie_error(format!("Unable to save {}. {}", resource.leafname, msg));
+        }
+    }
+}
+
+/// Draw the auxiliary files listed for a story. Returns a file to view, if one was picked
+fn draw_feelies(
+    connection: &IfdbConnection,
+    story_id: u32,
+    parent_ui: &mut eframe::egui::Ui,
+) -> Option<DbStoryResource> {
+    let mut requested = None;
+    let resources = match connection.fetch_story_resources(story_id) {
+        Ok(resources) => resources,
+        Err(e) => {
+            draw_db_error(parent_ui, "feelies", &e);
+            return None;
+        }
+    };
+
+    if !resources.is_empty() {
+        CollapsingHeader::new("Feelies")
+            .default_open(true)
+            .show(parent_ui, |ui| {
+                for resource in resources {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(RichText::new(resource.leafname.as_str()).strong());
+                        ui.label(resource.description.as_str());
+                        if resource.size.is_none() {
+                            ui.label(RichText::new("(not in the imported files)").italics());
+                        } else {
+                            if can_view_feelie(&resource.leafname) && ui.button("View").clicked() {
+                                requested = Some(resource.clone());
+                            }
+                            if ui.button("Save As...").clicked() {
+                                save_feelie(connection, &resource);
+                            }
+                        }
+                    });
+                }
+            });
+    }
+
+    requested
+}
+
+/// Draw the window for the auxiliary file being viewed, if any
+fn draw_feelie_window(ctx: &egui::Context, feelie: &mut Option<FeelieView>) {
+    let mut open = true;
+    if let Some(view) = feelie {
+        egui::Window::new(view.title.as_str())
+            .id(Id::new("feelie_window"))
+            .open(&mut open)
+            .vscroll(true)
+            .show(ctx, |ui| match &view.content {
+                FeelieContent::Text(text) => {
+                    ui.label(text.as_str());
+                }
+                FeelieContent::Image(texture) => {
+                    ui.image(texture, texture.size_vec2());
+                }
+            });
+    }
+    if !open {
+        *feelie = None;
+    }
+}
+
+fn draw_review(
+    connection: &IfdbConnection,
+    story_id: u32,
+    review_text: &mut String,
+    parent_ui: &mut eframe::egui::Ui,
+) {
+    CollapsingHeader::new("Your Review")
+        .default_open(true)
+        .show(parent_ui, |ui| {
+            if let Ok(Some(mut review)) = connection.get_story_review(story_id) {
+                let original = review.clone();
+
+                egui::ComboBox::from_label("Status")
+                    .selected_text(review.play_status.label())
+                    .show_ui(ui, |ui| {
+                        for status in PLAY_STATUSES.iter() {
+                            ui.selectable_value(&mut review.play_status, *status, status.label());
+                        }
+                    });
+
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Rating");
+                    ui.selectable_value(&mut review.rating, None, "None");
+                    for rating in 1..=MAX_RATING {
+                        ui.selectable_value(&mut review.rating, Some(rating), rating.to_string());
+                    }
+                });
+
+                ui.add(egui::TextEdit::multiline(review_text).hint_text("Your review"));
+                if ui.button("Save review").clicked() {
+                    review.review = Some(review_text.clone());
+                }
+
+                // Status and rating are saved as soon as they change
+                if review != original {
+                    if let Err(e) = connection.update_story_review(story_id, &review) {
+                        show_db_error("Review", &e);
+                    }
+                }
+            }
+        });
+}
+
+fn draw_tags_and_shelves(
+    connection: &IfdbConnection,
+    story_id: u32,
+    new_tag: &mut String,
+    parent_ui: &mut eframe::egui::Ui,
+) {
+    CollapsingHeader::new("Tags and Shelves")
+        .default_open(true)
+        .show(parent_ui, |ui| {
+            draw_story_tags(connection, story_id, ui, new_tag);
+            draw_story_shelf_checkboxes(connection, story_id, ui);
+        });
+}
+
+/// Each release of the story, read from its story file. Releases have their own saves and
+/// autosaves, and when there is more than one, the one Play launches can be chosen
 fn draw_versions(
     connection: &IfdbConnection,
     story_id: u32,
     parent_ui: &mut eframe::egui::Ui,
 ) -> bool {
     let mut autosaves_deleted = false;
-    if let Ok(ifids) = connection.fetch_ifids_for_story(story_id, true) {
-        for ifid in ifids {
-            let autosave_count =
-                connection.wrap_db_error(connection.count_autosaves_for_story(ifid.clone()));
-            if autosave_count > 0 {
-                CollapsingHeader::new(format!("Version {}", ifid))
-                .default_open(true)
-                .show(parent_ui, |ui| {
-                        let label = if autosave_count == 1 {
-                            format!("Delete {} autosave", autosave_count)
-                        } else {
-                            format!("Delete {} autosaves", autosave_count)
-                        };
-                        if ui.button(label).clicked() &&  MessageDialog::new()
-                        .set_type(MessageType::Warning)
-                        .set_title("Confirm delete")
-                        .set_text("Are you sure you want to delete these autosaves? There is no undo.")
-                        .show_confirm()
-                        .unwrap() {
-                            if let Err(msg) = connection.delete_autosaves_for_story(ifid.clone()) {
-                                println!("Error deleting autosaves. {}",msg);
-                            } else {
-                                autosaves_deleted = true;
-                            }
+    let releases = match connection.fetch_story_releases(story_id) {
+        Ok(releases) => releases,
+        Err(_) => return false,
+    };
+    let choosable = releases.len() > 1;
+
+    CollapsingHeader::new("Versions")
+        .default_open(true)
+        .show(parent_ui, |ui| {
+            for (i, release) in releases.iter().enumerate() {
+                if i > 0 {
+                    ui.separator();
+                }
+
+                if choosable {
+                    if ui
+                        .radio(release.preferred, release.to_string())
+                        .on_hover_text("Play this version")
+                        .clicked()
+                        && !release.preferred
+                    {
+                        if let Err(e) = connection.store_preferred_release(story_id, &release.ifid)
+                        {
+                            show_db_error("Choose version", &e);
                         }
-                    
+                    }
+                } else {
+                    ui.label(release.to_string());
+                }
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(RichText::new(format!("IFID {}", release.ifid)).small());
+                    if ui.small_button("Replace story file").clicked() {
+                        replace_story_file(connection, story_id, release);
+                    }
                 });
+
+                let autosave_count = connection
+                    .wrap_db_error(connection.count_autosaves_for_story(release.ifid.clone()));
+                if autosave_count > 0 {
+                    let label = if autosave_count == 1 {
+                        format!("Move {} autosave to the trash", autosave_count)
+                    } else {
+                        format!("Move {} autosaves to the trash", autosave_count)
+                    };
+                    // Autosaves can be restored from the trash, so there is no confirmation
+                    if ui.button(label).clicked() {
+                        if let Err(e) = connection.delete_autosaves_for_story(release.ifid.clone()) {
+                            show_db_error("Delete autosaves", &e);
+                        } else {
+                            autosaves_deleted = true;
+                        }
+                    }
+                }
             }
+        });
+
+    autosaves_deleted
+}
+
+/// Ask for a story file to replace a release's with. Saves are kept, so the user is warned if
+/// the checksum differs, as saves made with the old file may not restore
+fn replace_story_file(connection: &IfdbConnection, story_id: u32, release: &DbStoryRelease) {
+    let path = FileDialog::new()
+        .add_filter(
+            "Story file",
+            &[
+                "z1", "z2", "z3", "z4", "z5", "z6", "z7", "z8", "zblorb", "blb",
+            ],
+        )
+        .show_open_single_file()
+        .unwrap();
+    let path = match path {
+        Some(path) => path,
+        None => return,
+    };
+
+    let data = match read_story_file(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            show_db_error("Replace story file", &e);
+            return;
+        }
+    };
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let old_checksum = release.zcode.as_ref().and_then(|z| z.checksum.clone());
+    let new_checksum = zcode_from_header(&data).and_then(|z| z.checksum);
+    let (message_type, text) = if old_checksum == new_checksum {
+        (
+            MessageType::Info,
+            format!(
+                "Replace the story file for {} with {}? Saves, notes and clues are kept.",
+                release, filename
+            ),
+        )
+    } else {
+        (
+            MessageType::Warning,
+            format!(
+                "{} has checksum {} rather than {}, so saves made with the current story file may not restore. Replace the story file for {}? Saves, notes and clues are kept.",
+                filename,
+                new_checksum.unwrap_or_default(),
+                old_checksum.unwrap_or_default(),
+                release
+            ),
+        )
+    };
+
+    if MessageDialog::new()
+        .set_type(message_type)
+        .set_title("Replace story file?")
+        .set_text(text.as_str())
+        .show_confirm()
+        .unwrap()
+    {
+        if let Err(e) = connection.replace_story_file(story_id, &release.ifid, data, &filename) {
+            show_db_error("Replace story file", &e);
         }
     }
+}
 
-    autosaves_deleted
+/// Changes made to the story, such as replacing its story file, most recent first
+fn draw_history(connection: &IfdbConnection, story_id: u32, parent_ui: &mut eframe::egui::Ui) {
+    let history = match connection.fetch_story_history(story_id) {
+        Ok(history) => history,
+        Err(_) => return,
+    };
+
+    if !history.is_empty() {
+        CollapsingHeader::new("History")
+            .default_open(false)
+            .show(parent_ui, |ui| {
+                for entry in history {
+                    ui.strong(entry.formatted_recorded_when());
+                    ui.label(entry.description);
+                }
+            });
+    }
+}
+
+/// Ask the user for a path and write the story's ifiction data to it
+fn export_ifiction(connection: &IfdbConnection, story_id: u32, title: &str) {
+    let path = FileDialog::new()
+        .set_filename(format!("{}.iFiction", title).as_str())
+        .add_filter("iFiction file", &["iFiction", "xml"])
+        .show_save_single_file()
+        .unwrap();
+
+    if let Some(path) = path {
+        let result = match File::create(&path) {
+            Ok(file) => connection.export_ifiction(Some(story_id), file),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            show_db_error("Export failed", &e);
+        }
+    }
 }
 
-fn draw_delete_button(
+fn draw_actions(
     connection: &IfdbConnection,
     story_id: u32,
+    title: &str,
     parent_ui: &mut eframe::egui::Ui,
 ) -> bool {
     let mut closed = false;
@@ -267,16 +897,14 @@ fn draw_delete_button(
     CollapsingHeader::new("Actions")
         .default_open(true)
         .show(parent_ui, |ui| {
-            if ui.button("Delete story").clicked()
-                && MessageDialog::new()
-                    .set_type(MessageType::Warning)
-                    .set_title("Delete story?")
-                    .set_text("Are you sure you want to delete this story? There is no undo.")
-                    .show_confirm()
-                    .unwrap()
-            {
-                if let Err(msg) = connection.delete_story(story_id) {
-                    println!("Error deleting autosaves. {}", msg);
+            if ui.button("Export iFiction").clicked() {
+                export_ifiction(connection, story_id, title);
+            }
+
+            // The story can be restored from the trash, so there is no confirmation
+            if ui.button("Move to trash").clicked() {
+                if let Err(e) = connection.set_story_trashed(story_id, true) {
+                    show_db_error("Delete story", &e);
                 } else {
                     closed = true;
                 }
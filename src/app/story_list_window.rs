@@ -1,20 +1,29 @@
 use native_dialog::{MessageDialog, MessageType};
 use std::collections::HashMap;
+use std::ops::Range;
 
-use super::ifdb::{DbSave, IfdbConnection, SaveType, StorySummary, WindowDetails};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{
+    DbSave, IfdbConnection, SaveType, StoryFilter, StorySort, StorySummary, WindowDetails,
+    COVER_THUMBNAIL_SIZE, MAX_RATING, PLAY_STATUSES, STORY_FILTER_FIELDS, STORY_SORTS,
+};
 use super::story_details_window::{draw_story_details_window, DetailsWindowState};
 
 use super::terp::windows::{ButtonWindow, FerrifWindow};
 
 use super::credits_window::credits_handler;
+use super::images::load_texture;
 use super::main_help_window::main_help_handler;
+use super::maintenance_window::{draw_maintenance_window, MaintenanceWindowState};
 use super::preferences_window::{
     draw_preferences_window, PreferenceWindowState, STORY_THEME_NAME, UI_THEME_NAME,
 };
+use super::shelves::{draw_shelves_sidebar, draw_story_shelves_menu, ShelvesState};
 use super::stats_window::stats_window_handler;
 use super::story_load_window::{draw_add_story_window, AddStoryWindowState};
 use super::terp::theme::apply_fonts_to_context;
 use super::terp::{EguiTerp, PostUpdateAction};
+use super::trash_window::{draw_trash_window, TrashWindowState};
 use zmachine::vm::{VMLoadError, VM};
 
 use eframe::egui;
@@ -39,6 +48,18 @@ const DEFAULT_STORY_WINDOW_RECT: Rect = Rect {
     },
 };
 
+// Stories fetched from the database at a time. Only the pages with visible rows are fetched
+const STORY_LIST_PAGE_SIZE: usize = 50;
+
+// Height of the separator drawn under each story
+const STORY_ROW_SEPARATOR_HEIGHT: f32 = 6.0;
+
+// Space around each cover in the gallery
+const GALLERY_CELL_MARGIN: f32 = 8.0;
+
+// Cover textures to keep. Past this, only the covers on screen are kept
+const MAX_COVER_TEXTURES: usize = 500;
+
 pub struct StoryListState {
     pub terps: HashMap<u32, EguiTerp>,
     playing_story: Option<StorySummary>,
@@ -46,22 +67,38 @@ pub struct StoryListState {
     story_list_window: FerrifWindow,
     terp_window: FerrifWindow,
     search_text: String,
+    sort: StorySort,
+    filter: StoryFilter,
+    shelves_state: ShelvesState,
     add_story_list_window_state: AddStoryWindowState,
     preferences_window_state: PreferenceWindowState,
     stats_window: ButtonWindow,
+    maintenance_window_state: MaintenanceWindowState,
+    trash_window_state: TrashWindowState,
     main_help_window: ButtonWindow,
     credits_window: ButtonWindow,
     story_changed: bool,
+    selected_row: Option<usize>, // Row in the story list chosen with the keyboard or mouse
+    scroll_to_row: Option<usize>, // Row to scroll into view
+    visible_rows: Range<usize>,  // Rows laid out in the last frame
+    gallery: bool,               // Show covers in a grid rather than a list
+    columns: usize,              // Stories across the list or gallery in the last frame
+    cover_textures: HashMap<u32, Option<TextureHandle>>, // Thumbnails by story id. None if the story has no cover
 }
 
 impl StoryListState {
     pub fn create() -> StoryListState {
         StoryListState {
             search_text: String::new(),
+            sort: StorySort::Title,
+            filter: StoryFilter::default(),
+            shelves_state: ShelvesState::create(),
             terps: HashMap::new(),
             add_story_list_window_state: AddStoryWindowState::create(),
             preferences_window_state: PreferenceWindowState::create(),
             stats_window: ButtonWindow::create(),
+            maintenance_window_state: MaintenanceWindowState::create(),
+            trash_window_state: TrashWindowState::create(),
             story_list_window: FerrifWindow::create_empty(),
             story_details_window: DetailsWindowState::create(),
             main_help_window: ButtonWindow::create(),
@@ -69,6 +106,12 @@ impl StoryListState {
             terp_window: FerrifWindow::create_empty(),
             playing_story: None,
             story_changed: false,
+            selected_row: None,
+            scroll_to_row: None,
+            visible_rows: 0..0,
+            gallery: false,
+            columns: 1,
+            cover_textures: HashMap::new(),
         }
     }
 
@@ -82,6 +125,85 @@ impl StoryListState {
         self.story_changed = false;
     }
 
+    /// Play a story chosen from the list, and remember it as the current story
+    fn play_story_from_list(&mut self, story: StorySummary, connection: &IfdbConnection) {
+        let story_id = story.story_id;
+        self.play_story(story);
+        if let Err(msg) = connection.store_current_story(Some(story_id as i64)) {
+            println!("Error storing current story: {}", msg);
+        }
+    }
+
+    /// Select a row in the story list and scroll it into view
+    fn select_row(&mut self, row: usize) {
+        self.selected_row = Some(row);
+        self.scroll_to_row = Some(row);
+    }
+
+    /// Thumbnail of a story's cover, uploaded the first time it is shown. Database errors
+    /// aren't cached, so the thumbnail is fetched again next frame
+    fn cover_texture(
+        &mut self,
+        ctx: &egui::Context,
+        connection: &IfdbConnection,
+        story_id: u32,
+    ) -> Option<TextureHandle> {
+        if let Some(texture) = self.cover_textures.get(&story_id) {
+            return texture.clone();
+        }
+
+        let texture = match connection.fetch_cover_thumbnail(story_id) {
+            Ok(Some(data)) => {
+                match load_texture(ctx, format!("cover-thumbnail-{}", story_id).as_str(), &data) {
+                    Ok(texture) => Some(texture),
+                    Err(msg) => {
+                        println!("Error loading cover for story {}: {}", story_id, msg);
+                        None
+                    }
+                }
+            }
+            Ok(None) => None,
+            Err(msg) => {
+                println!("Error loading cover for story {}: {}", story_id, msg);
+                return None;
+            }
+        };
+        self.cover_textures.insert(story_id, texture.clone());
+        texture
+    }
+
+    /// Fetch the stories in rows of the story list, a page at a time
+    fn fetch_story_rows(
+        &self,
+        connection: &IfdbConnection,
+        rows: Range<usize>,
+    ) -> Vec<StorySummary> {
+        let first_page_start = rows.start - rows.start % STORY_LIST_PAGE_SIZE;
+        let mut stories = vec![];
+        for page_start in (first_page_start..rows.end).step_by(STORY_LIST_PAGE_SIZE) {
+            match connection.fetch_story_summary_page(
+                true,
+                Some(self.search_text.as_str()),
+                Some(self.sort),
+                &self.filter,
+                page_start,
+                STORY_LIST_PAGE_SIZE,
+            ) {
+                Ok(page) => stories.extend(page),
+                Err(msg) => {
+                    println!("Error loading stories: {}", msg);
+                    break;
+                }
+            }
+        }
+
+        stories
+            .into_iter()
+            .skip(rows.start - first_page_start)
+            .take(rows.len())
+            .collect()
+    }
+
     pub fn play_story_ifid(&mut self, ifid: String, connection: &IfdbConnection) {
         match connection.get_story_summary_by_ifid(ifid.as_str()) {
             Ok(summary) => match summary {
@@ -180,6 +302,16 @@ impl StoryListState {
         }
     }
 
+    pub fn restore_story_list_settings(&mut self, connection: &IfdbConnection) {
+        match connection.get_story_list_settings() {
+            Ok((sort, filter)) => {
+                self.sort = sort;
+                self.filter = filter;
+            }
+            Err(msg) => println!("Error loading story list settings. {}", msg),
+        }
+    }
+
     fn open_play_error_alert(&self, msg: String) {
         MessageDialog::new()
             .set_type(MessageType::Warning)
@@ -309,6 +441,74 @@ impl StoryListState {
     }
 }
 
+/// Draw the sort and filter controls for the story list. Changes are stored as settings
+fn draw_sort_and_filter(connection: &IfdbConnection, ui: &mut Ui, state: &mut StoryListState) {
+    let sort = state.sort;
+    let filter = state.filter.clone();
+
+    let gallery = state.gallery;
+
+    ui.horizontal_wrapped(|ui| {
+        ui.selectable_value(&mut state.gallery, false, "List");
+        ui.selectable_value(&mut state.gallery, true, "Covers");
+
+        egui::ComboBox::from_label("Sort")
+            .selected_text(state.sort.label())
+            .show_ui(ui, |ui| {
+                for sort in STORY_SORTS.iter() {
+                    ui.selectable_value(&mut state.sort, *sort, sort.label());
+                }
+            });
+
+        for field in STORY_FILTER_FIELDS.iter() {
+            let value = state.filter.get_mut(*field);
+            egui::ComboBox::from_label(field.label())
+                .selected_text(value.clone().unwrap_or_else(|| String::from("Any")))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(value, None, "Any");
+                    // Only queried while the list is open
+                    match connection.fetch_story_filter_values(*field) {
+                        Ok(values) => {
+                            for v in values {
+                                let text = v.clone();
+                                ui.selectable_value(value, Some(v), text);
+                            }
+                        }
+                        Err(msg) => println!("Error loading filter values. {}", msg),
+                    }
+                });
+        }
+
+        egui::ComboBox::from_label("Status")
+            .selected_text(state.filter.play_status.map_or("Any", |s| s.label()))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut state.filter.play_status, None, "Any");
+                for status in PLAY_STATUSES.iter() {
+                    ui.selectable_value(
+                        &mut state.filter.play_status,
+                        Some(*status),
+                        status.label(),
+                    );
+                }
+            });
+    });
+
+    if sort != state.sort || filter != state.filter {
+        store_story_list_settings(connection, state);
+    }
+
+    // Keep the selected story in view when switching between the list and gallery
+    if gallery != state.gallery {
+        state.scroll_to_row = state.selected_row;
+    }
+}
+
+fn store_story_list_settings(connection: &IfdbConnection, state: &StoryListState) {
+    if let Err(msg) = connection.store_story_list_settings(state.sort, &state.filter) {
+        println!("Error storing story list settings. {}", msg);
+    }
+}
+
 /// Draw the stories list
 pub fn draw_story_list(
     connection: &IfdbConnection,
@@ -319,19 +519,32 @@ pub fn draw_story_list(
         .default_size(state.story_list_window.get_size())
         .default_pos(state.story_list_window.get_pos())
         .show(ctx, |ui| {
-            if let Ok(stories) =
-                connection.fetch_story_summaries(true, Some(state.search_text.as_str()))
-            {
+            if let Ok(story_count) = connection.count_story_summaries(
+                true,
+                Some(state.search_text.as_str()),
+                &state.filter,
+                None,
+            ) {
                 ui.horizontal_wrapped(|ui| {
-                    ui.add(egui::TextEdit::singleline(&mut state.search_text));
+                    ui.add(
+                        egui::TextEdit::singleline(&mut state.search_text)
+                            .hint_text("Search (e.g. author:Meretzky)"),
+                    );
 
                     draw_add_story_window(
-                        connection.database_path.clone(),
+                        connection,
                         ctx,
                         ui,
                         &mut state.add_story_list_window_state,
                     );
 
+                    // Imports can add or replace covers
+                    if state.add_story_list_window_state.imported {
+                        state.add_story_list_window_state.imported = false;
+                        state.cover_textures.clear();
+                        state.story_details_window.reload_cover();
+                    }
+
                     if let Some(story_ifid) = &state.add_story_list_window_state.play_story_ifid {
                         let ifid = story_ifid.clone();
                         state.play_story_ifid(ifid, connection);
@@ -354,6 +567,15 @@ pub fn draw_story_list(
                         stats_window_handler,
                     );
 
+                    draw_maintenance_window(
+                        connection,
+                        ctx,
+                        ui,
+                        &mut state.maintenance_window_state,
+                    );
+
+                    draw_trash_window(connection, ctx, ui, &mut state.trash_window_state);
+
                     state.main_help_window.add_window_button(
                         "Help",
                         ctx,
@@ -373,70 +595,37 @@ pub fn draw_story_list(
                     );
                 });
 
+                draw_sort_and_filter(connection, ui, state);
+
                 ui.separator();
 
-                ScrollArea::vertical()
-                    .max_height(f32::INFINITY)
-                    .show(ui, |ui| {
-                        // If there is a playing story (from a command line or other load), act just like the story was played from the ui
-                        if stories.is_empty() {
-                            ui.label("No stories loaded.");
-                        } else {
-                            for story in stories.iter() {
-                                ui.add(Label::new(RichText::new(story.title.clone()).heading()));
-                                if let Some(last_played) = story.last_played {
-                                    ui.label(format!(
-                                        "Last played: {}",
-                                        last_played.format("%a %b %e %T %Y")
-                                    ));
-                                    ui.label(format!(
-                                        "Time played: {}",
-                                        story.time_played_description()
-                                    ));
-                                }
-                                ui.horizontal_wrapped(|ui| {
-                                    // Draw the Play/Close buttons, and record if they are pressed
-                                    match &state.playing_story {
-                                        None => {
-                                            if ui.button("Play").clicked() {
-                                                state.play_story(story.clone());
-                                                if let Err(msg) = connection.store_current_story(
-                                                    Some(story.story_id as i64),
-                                                ) {
-                                                    println!(
-                                                        "Error storing current story: {}",
-                                                        msg
-                                                    );
-                                                }
-                                            }
-                                        }
-                                        Some(_) => {
-                                            if ui.button("Play").clicked() {
-                                                state.play_story(story.clone());
-                                                if let Err(msg) = connection.store_current_story(
-                                                    Some(story.story_id as i64),
-                                                ) {
-                                                    println!(
-                                                        "Error storing current story: {}",
-                                                        msg
-                                                    );
-                                                }
-                                            }
-                                        }
-                                    };
-
-                                    if ui.button("Details").clicked() {
-                                        state.story_details_window.window.window_details.story_id =
-                                            story.story_id as i64;
-                                        state.story_details_window.window.window_details.open =
-                                            true;
-                                    }
-                                });
-
-                                ui.separator();
-                            }
-                        }
+                let filter = state.filter.clone();
+                egui::SidePanel::left("shelves_sidebar")
+                    .resizable(true)
+                    .default_width(160.0)
+                    .show_inside(ui, |ui| {
+                        draw_shelves_sidebar(
+                            connection,
+                            ui,
+                            &mut state.shelves_state,
+                            &mut state.filter,
+                            state.search_text.as_str(),
+                        );
                     });
+                if filter != state.filter {
+                    store_story_list_settings(connection, state);
+                }
+
+                handle_story_list_keys(connection, ctx, state, story_count);
+
+                if story_count == 0 {
+                    ui.label("No stories loaded.");
+                } else if state.gallery {
+                    draw_story_gallery(connection, ctx, ui, state, story_count);
+                } else {
+                    let tags = connection.fetch_tags_by_story().unwrap_or_default();
+                    draw_story_rows(connection, ui, state, story_count, &tags);
+                }
             }
         });
 
@@ -449,6 +638,344 @@ pub fn draw_story_list(
     }
 }
 
+/// Draw the stories as a list, laying out only the visible rows
+fn draw_story_rows(
+    connection: &IfdbConnection,
+    ui: &mut Ui,
+    state: &mut StoryListState,
+    story_count: usize,
+    tags: &HashMap<u32, Vec<String>>,
+) {
+    state.columns = 1;
+    let row_height = story_row_height(ui);
+    let mut scroll_area = ScrollArea::vertical().max_height(f32::INFINITY);
+
+    // A row that isn't laid out is scrolled to first, then into view once drawn
+    if let Some(row) = state.scroll_to_row {
+        if !state.visible_rows.contains(&row) {
+            scroll_area = scroll_area
+                .vertical_scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+        }
+    }
+
+    scroll_area.show_rows(ui, row_height, story_count, |ui, rows| {
+        let stories = state.fetch_story_rows(connection, rows.clone());
+        for (row, story) in rows.clone().zip(stories) {
+            let (rect, _) =
+                ui.allocate_exact_size(vec2(ui.available_width(), row_height), Sense::hover());
+            let mut row_ui = ui.child_ui(rect, Layout::top_down(Align::Min));
+            row_ui.set_clip_rect(rect.intersect(ui.clip_rect()));
+            row_ui.style_mut().wrap = Some(false);
+            let story_tags = tags.get(&story.story_id);
+            draw_story_row(connection, &mut row_ui, state, story, row, story_tags);
+
+            if state.scroll_to_row == Some(row) {
+                ui.scroll_to_rect(rect, None);
+                state.scroll_to_row = None;
+            }
+        }
+        state.visible_rows = rows;
+    });
+}
+
+/// Draw the stories as a grid of covers, laying out only the visible rows of the grid. Each
+/// story is still a row of the story list, so selection and keys work the same as the list
+fn draw_story_gallery(
+    connection: &IfdbConnection,
+    ctx: &egui::Context,
+    ui: &mut Ui,
+    state: &mut StoryListState,
+    story_count: usize,
+) {
+    let cell_size = gallery_cell_size(ui);
+    let spacing = ui.spacing().item_spacing;
+    let columns =
+        (((ui.available_width() + spacing.x) / (cell_size.x + spacing.x)) as usize).max(1);
+    // Only drawn when there are stories
+    let grid_rows = (story_count - 1) / columns + 1;
+    state.columns = columns;
+
+    let mut scroll_area = ScrollArea::vertical()
+        .id_source("story_gallery")
+        .max_height(f32::INFINITY);
+    if let Some(row) = state.scroll_to_row {
+        if !state.visible_rows.contains(&row) {
+            scroll_area = scroll_area
+                .vertical_scroll_offset((row / columns) as f32 * (cell_size.y + spacing.y));
+        }
+    }
+
+    scroll_area.show_rows(ui, cell_size.y, grid_rows, |ui, grid_rows| {
+        let rows = grid_rows.start * columns..(grid_rows.end * columns).min(story_count);
+        let stories = state.fetch_story_rows(connection, rows.clone());
+        let mut stories = rows.clone().zip(stories).peekable();
+        for _ in grid_rows {
+            let (row_rect, _) =
+                ui.allocate_exact_size(vec2(ui.available_width(), cell_size.y), Sense::hover());
+            for column in 0..columns {
+                let (row, story) = match stories.next() {
+                    Some(story) => story,
+                    None => break,
+                };
+                let rect = Rect::from_min_size(
+                    row_rect.min + vec2(column as f32 * (cell_size.x + spacing.x), 0.0),
+                    cell_size,
+                );
+                draw_gallery_cell(connection, ctx, ui, state, story, row, rect);
+
+                if state.scroll_to_row == Some(row) {
+                    ui.scroll_to_rect(rect, None);
+                    state.scroll_to_row = None;
+                }
+            }
+        }
+        state.visible_rows = rows;
+    });
+
+    if state.cover_textures.len() > MAX_COVER_TEXTURES {
+        let visible: Vec<u32> = state
+            .fetch_story_rows(connection, state.visible_rows.clone())
+            .iter()
+            .map(|story| story.story_id)
+            .collect();
+        state
+            .cover_textures
+            .retain(|story_id, _| visible.contains(story_id));
+    }
+}
+
+/// Size of a story in the gallery: the cover, with the title and status under it
+fn gallery_cell_size(ui: &Ui) -> Vec2 {
+    vec2(
+        COVER_THUMBNAIL_SIZE as f32 + 2.0 * GALLERY_CELL_MARGIN,
+        COVER_THUMBNAIL_SIZE as f32
+            + 2.0 * ui.text_style_height(&TextStyle::Body)
+            + 2.0 * ui.spacing().item_spacing.y
+            + 2.0 * GALLERY_CELL_MARGIN,
+    )
+}
+
+/// Draw a story in the gallery. Clicking selects it, double clicking plays it, and the
+/// context menu has the same actions as the list
+fn draw_gallery_cell(
+    connection: &IfdbConnection,
+    ctx: &egui::Context,
+    ui: &mut Ui,
+    state: &mut StoryListState,
+    story: StorySummary,
+    row: usize,
+    rect: Rect,
+) {
+    let response = ui.interact(rect, ui.id().with(("gallery_cell", row)), Sense::click());
+    let visuals = ui
+        .style()
+        .interact_selectable(&response, state.selected_row == Some(row));
+    if state.selected_row == Some(row) || response.hovered() {
+        ui.painter()
+            .rect_filled(rect, visuals.rounding, visuals.bg_fill);
+    }
+
+    let mut cell_ui = ui.child_ui(
+        rect.shrink(GALLERY_CELL_MARGIN),
+        Layout::top_down(Align::Center),
+    );
+    cell_ui.set_clip_rect(rect.intersect(ui.clip_rect()));
+    let cover_size = Vec2::splat(COVER_THUMBNAIL_SIZE as f32);
+    match state.cover_texture(ctx, connection, story.story_id) {
+        Some(texture) => {
+            // Thumbnails fit in the square, so center them in it
+            let (cover_rect, _) = cell_ui.allocate_exact_size(cover_size, Sense::hover());
+            let image_rect = Rect::from_center_size(cover_rect.center(), texture.size_vec2());
+            cell_ui.put(image_rect, egui::Image::new(&texture, texture.size_vec2()));
+        }
+        None => {
+            // Placeholder with the title for stories without a cover
+            let (cover_rect, _) = cell_ui.allocate_exact_size(cover_size, Sense::hover());
+            cell_ui.painter().rect_filled(
+                cover_rect,
+                visuals.rounding,
+                ui.visuals().faint_bg_color,
+            );
+            cell_ui.put(
+                cover_rect.shrink(GALLERY_CELL_MARGIN),
+                Label::new(RichText::new(story.title.clone()).heading()).wrap(true),
+            );
+        }
+    }
+
+    cell_ui.style_mut().wrap = Some(false);
+    cell_ui.label(story.title.as_str());
+    cell_ui.label(story.play_status.label());
+
+    if response.clicked() {
+        state.selected_row = Some(row);
+    }
+    if response.double_clicked() {
+        state.play_story_from_list(story.clone(), connection);
+    }
+    response.context_menu(|ui| {
+        if ui.button("Play").clicked() {
+            state.play_story_from_list(story.clone(), connection);
+            ui.close_menu();
+        }
+        if ui.button("Details").clicked() {
+            state.story_details_window.window.window_details.story_id = story.story_id as i64;
+            state.story_details_window.window.window_details.open = true;
+            ui.close_menu();
+        }
+    });
+}
+
+/// Height of a story in the list. Every story has the same lines, so only the visible stories
+/// need to be laid out
+fn story_row_height(ui: &Ui) -> f32 {
+    ui.text_style_height(&TextStyle::Heading)
+        + 2.0 * ui.text_style_height(&TextStyle::Body)
+        + ui.spacing().interact_size.y
+        + STORY_ROW_SEPARATOR_HEIGHT
+        + 4.0 * ui.spacing().item_spacing.y
+}
+
+/// Draw a story in the list
+fn draw_story_row(
+    connection: &IfdbConnection,
+    ui: &mut Ui,
+    state: &mut StoryListState,
+    story: StorySummary,
+    row: usize,
+    tags: Option<&Vec<String>>,
+) {
+    let title = RichText::new(story.title.clone()).heading();
+    if ui
+        .selectable_label(state.selected_row == Some(row), title)
+        .clicked()
+    {
+        state.selected_row = Some(row);
+    }
+
+    let mut status = match story.rating {
+        Some(rating) => format!(
+            "{}, rated {}/{}",
+            story.play_status.label(),
+            rating,
+            MAX_RATING
+        ),
+        None => story.play_status.label().to_string(),
+    };
+    if let Some(tags) = tags {
+        status.push_str(format!(". Tags: {}", tags.join(", ")).as_str());
+    }
+    ui.label(status);
+
+    match story.last_played {
+        Some(last_played) => ui.label(format!(
+            "Last played: {}. Time played: {}",
+            last_played.format("%a %b %e %T %Y"),
+            story.time_played_description()
+        )),
+        None => ui.label("Not played yet"),
+    };
+
+    ui.horizontal(|ui| {
+        if ui.button("Play").clicked() {
+            state.play_story_from_list(story.clone(), connection);
+        }
+
+        if ui.button("Details").clicked() {
+            state.story_details_window.window.window_details.story_id = story.story_id as i64;
+            state.story_details_window.window.window_details.open = true;
+        }
+
+        draw_story_shelves_menu(connection, story.story_id, ui);
+    });
+
+    ui.add(Separator::default().spacing(STORY_ROW_SEPARATOR_HEIGHT));
+}
+
+/// Handle keys for the story list when nothing else wants the keyboard. The arrow keys, page
+/// up/down and home/end move the selected story, across the gallery as well as down it, and
+/// enter plays it. Typing a letter or number jumps to the first title starting with it when
+/// sorted by title
+fn handle_story_list_keys(
+    connection: &IfdbConnection,
+    ctx: &egui::Context,
+    state: &mut StoryListState,
+    story_count: usize,
+) {
+    if story_count == 0 || ctx.wants_keyboard_input() {
+        return;
+    }
+
+    let last_row = story_count - 1;
+    let selected = state.selected_row.map(|row| row.min(last_row));
+    let columns = state.columns;
+    let page = state
+        .visible_rows
+        .len()
+        .saturating_sub(2 * columns)
+        .max(columns);
+    let mut jump_to = None;
+    let mut play = false;
+    let mut title_start = None;
+    {
+        let input = ctx.input();
+        if input.key_pressed(Key::ArrowDown) {
+            jump_to = Some(
+                selected
+                    .map(|row| (row + columns).min(last_row))
+                    .unwrap_or(0),
+            );
+        } else if input.key_pressed(Key::ArrowUp) {
+            jump_to = Some(selected.map(|row| row.saturating_sub(columns)).unwrap_or(0));
+        } else if columns > 1 && input.key_pressed(Key::ArrowRight) {
+            jump_to = Some(selected.map(|row| (row + 1).min(last_row)).unwrap_or(0));
+        } else if columns > 1 && input.key_pressed(Key::ArrowLeft) {
+            jump_to = Some(selected.map(|row| row.saturating_sub(1)).unwrap_or(0));
+        } else if input.key_pressed(Key::PageDown) {
+            jump_to = Some(selected.map(|row| (row + page).min(last_row)).unwrap_or(0));
+        } else if input.key_pressed(Key::PageUp) {
+            jump_to = Some(selected.map(|row| row.saturating_sub(page)).unwrap_or(0));
+        } else if input.key_pressed(Key::Home) {
+            jump_to = Some(0);
+        } else if input.key_pressed(Key::End) {
+            jump_to = Some(last_row);
+        } else if input.key_pressed(Key::Enter) {
+            play = true;
+        }
+
+        if state.sort == StorySort::Title {
+            title_start = input.events.iter().find_map(|event| match event {
+                Event::Text(text) => text.chars().next().filter(|c| c.is_alphanumeric()),
+                _ => None,
+            });
+        }
+    }
+
+    if let Some(c) = title_start {
+        match connection.count_story_summaries(
+            true,
+            Some(state.search_text.as_str()),
+            &state.filter,
+            Some(c.to_string().as_str()),
+        ) {
+            Ok(row) => jump_to = Some(row.min(last_row)),
+            Err(msg) => println!("Error finding stories starting with {}: {}", c, msg),
+        }
+    }
+
+    if let Some(row) = jump_to {
+        state.select_row(row);
+    }
+
+    if play {
+        if let Some(story) =
+            selected.and_then(|row| state.fetch_story_rows(connection, row..row + 1).pop())
+        {
+            state.play_story_from_list(story, connection);
+        }
+    }
+}
+
 pub fn get_autosave(connection: &IfdbConnection, ifid: String, offset: usize) -> Option<DbSave> {
     let mut count = 0;
     match connection.fetch_saves_for_ifid(ifid) {
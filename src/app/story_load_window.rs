@@ -1,4 +1,5 @@
-use super::ifdb::{IfdbConnection, LoadFileResult};
+use super::db_errors::show_db_error;
+use super::ifdb::{IfdbConnection, IfdbError, LoadFileResult};
 
 use super::terp::windows::ButtonWindow;
 use eframe::egui;
@@ -14,11 +15,30 @@ const DEFAULT_SIZE: Vec2 = Vec2 {
 
 const DEFAULT_POS: Pos2 = Pos2 { x: 40f32, y: 40f32 };
 
+// Files and folders are previewed, and only imported once the user confirms
+#[derive(Clone, Debug, Copy, PartialEq)]
+enum ImportStage {
+    Idle,
+    Previewing,
+    Confirming,
+    Importing,
+    Done,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ImportSource {
+    File(String),
+    Folder(String),
+    WatchedFolders,
+}
+
 pub struct AddStoryWindowState {
     pub window: ButtonWindow,
-    pub is_loading: bool,
     pub messages: Vec<LoadFileResult>,
     pub play_story_ifid: Option<String>,
+    pub imported: bool, // Set when an import finishes, until the story list reloads covers
+    stage: ImportStage,
+    source: Option<ImportSource>,
     sender: SyncSender<LoadFileResult>,
     receiver: Receiver<LoadFileResult>,
 }
@@ -28,17 +48,156 @@ impl AddStoryWindowState {
         let (sender, receiver) = sync_channel(100);
         AddStoryWindowState {
             window: ButtonWindow::create(),
-            is_loading: false,
             play_story_ifid: None,
+            imported: false,
             messages: vec![],
+            stage: ImportStage::Idle,
+            source: None,
             sender,
             receiver,
         }
     }
+
+    /// Start a preview or import of source in the background
+    fn start(&mut self, database_path: String, source: ImportSource, dry_run: bool) {
+        self.messages.clear();
+        self.play_story_ifid = None;
+        self.stage = if dry_run {
+            ImportStage::Previewing
+        } else {
+            ImportStage::Importing
+        };
+        self.source = Some(source.clone());
+        spawn_import(database_path, self.sender.clone(), source, dry_run);
+    }
+
+    /// The folder being imported, if any, so it can be watched
+    fn folder(&self) -> Option<&String> {
+        match &self.source {
+            Some(ImportSource::Folder(path)) => Some(path),
+            _ => None,
+        }
+    }
 }
 
-pub fn draw_add_story_window(
+fn run_import(
+    connection: &IfdbConnection,
+    source: ImportSource,
+    dry_run: bool,
+    send: impl Fn(LoadFileResult),
+) {
+    let (path, filename_override) = match source {
+        ImportSource::File(path) => (path.clone(), Some(path)),
+        ImportSource::Folder(path) => (path, None),
+        ImportSource::WatchedFolders => {
+            if let Err(e) = connection.rescan_watched_folders(&send) {
+                send(LoadFileResult::DirectoryFailure(
+                    String::from("watched folders"),
+                    e.to_string(),
+                ));
+            }
+            return;
+        }
+    };
+
+    if dry_run {
+        for result in connection.preview_import(path.as_str(), filename_override) {
+            send(result);
+        }
+    } else {
+        connection.import_file(path.as_str(), filename_override, send);
+    }
+}
+
+/// Import in a background thread, sending results to the add story window. A dry run
+/// sends what would be imported without saving anything
+fn spawn_import(
     database_path: String,
+    sender: SyncSender<LoadFileResult>,
+    source: ImportSource,
+    dry_run: bool,
+) {
+    // Can't share the database connection as it's not thread safe
+    let _handle = thread::spawn(
+        move || match IfdbConnection::connect(database_path.as_str()) {
+            Ok(connection) => {
+                let send = |msg| {
+                    if let Err(err) = sender.send(msg) {
+                        println!("Error sending load message: {}", err);
+                    }
+                };
+                run_import(&connection, source, dry_run, send);
+                if let Err(err) = sender.clone().send(LoadFileResult::LoadCompleted()) {
+                    println!("Error sending load completed message after load. {}", err);
+                }
+            }
+            Err(msg) => {
+                panic!(
+                    "Unable to connect to database at {}. Error was: {}",
+                    database_path, msg
+                );
+            }
+        },
+    );
+}
+
+fn show_watch_error(error: IfdbError) {
+    show_db_error("Watched folders", &error);
+}
+
+/// Draw the watched folders, each of which can be removed, plus a button to rescan them all
+fn draw_watched_folders(
+    connection: &IfdbConnection,
+    ui: &mut Ui,
+    state: &mut AddStoryWindowState,
+    show_done: bool,
+) {
+    let watched_folders = connection.fetch_watched_folders().unwrap_or_default();
+
+    if let Some(folder) = state.folder() {
+        if show_done
+            && !watched_folders.contains(folder)
+            && ui
+                .button("Watch Folder")
+                .on_hover_text("Import new files from this folder each time Ferrif starts")
+                .clicked()
+        {
+            if let Err(e) = connection.add_watched_folder(folder) {
+                show_watch_error(e);
+            }
+        }
+    }
+
+    if watched_folders.is_empty() {
+        return;
+    }
+
+    CollapsingHeader::new("Watched Folders")
+        .default_open(false)
+        .show(ui, |ui| {
+            for folder in watched_folders {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(folder.as_str());
+                    if ui.small_button("Stop watching").clicked() {
+                        if let Err(e) = connection.remove_watched_folder(folder.as_str()) {
+                            show_watch_error(e);
+                        }
+                    }
+                });
+            }
+
+            if show_done && ui.button("Rescan").clicked() {
+                state.start(
+                    connection.database_path.clone(),
+                    ImportSource::WatchedFolders,
+                    false,
+                );
+            }
+        });
+}
+
+pub fn draw_add_story_window(
+    connection: &IfdbConnection,
     ctx: &egui::Context,
     parent_ui: &mut eframe::egui::Ui,
     state: &mut AddStoryWindowState,
@@ -47,13 +206,27 @@ pub fn draw_add_story_window(
 
     let mut done_clicked = false;
 
-    if is_open && state.is_loading {
-        if let Ok(received) = state.receiver.try_recv() {
+    if matches!(
+        state.stage,
+        ImportStage::Previewing | ImportStage::Importing
+    ) {
+        // Folders can produce many messages, so take everything waiting. This happens even
+        // if the window is closed so the import is never left waiting with its transaction open
+        while let Ok(received) = state.receiver.try_recv() {
             if let LoadFileResult::LoadCompleted() = received {
-                state.is_loading = false
+                // The import wrote through its own connection
+                connection.clear_cache();
+                state.stage = match state.stage {
+                    ImportStage::Previewing => ImportStage::Confirming,
+                    _ => {
+                        state.imported = true;
+                        ImportStage::Done
+                    }
+                };
             }
             state.messages.push(received);
         }
+        ctx.request_repaint();
     }
 
     if is_open {
@@ -62,43 +235,81 @@ pub fn draw_add_story_window(
             .default_size(DEFAULT_SIZE)
             .default_pos(DEFAULT_POS)
             .show(ctx, |ui| {
-                let mut show_done = false;
+                let show_done = state.stage == ImportStage::Done;
                 let mut play_story_ifid = None;
+                let mut already_imported = 0;
+                let mut has_fatal_error = false;
                 for message in &state.messages {
                     match message {
                         LoadFileResult::StoryFileSuccess(_, ifid) => {
                             play_story_ifid = Some(ifid.clone());
                         }
-                        LoadFileResult::LoadCompleted() => {
-                            show_done = true;
+                        LoadFileResult::AlreadyImported(_) => {
+                            already_imported += 1;
                         }
                         _ => {}
                     }
+                    has_fatal_error |= message.is_fatal();
                 }
 
-                ui.horizontal_wrapped(|buttons_ui| {
-                    if show_done {
+                ui.horizontal_wrapped(|buttons_ui| match state.stage {
+                    ImportStage::Confirming => {
+                        if has_fatal_error {
+                            buttons_ui
+                                .label("Nothing can be imported because of the errors below.");
+                        } else if buttons_ui.button("Import").clicked() {
+                            if let Some(source) = state.source.clone() {
+                                state.start(connection.database_path.clone(), source, false);
+                            }
+                        }
+                        if buttons_ui.button("Cancel").clicked() {
+                            done_clicked = true;
+                        }
+                    }
+                    ImportStage::Done => {
                         if buttons_ui.button("Done").clicked() {
                             done_clicked = true;
                         } else if play_story_ifid.is_some() && buttons_ui.button("Play").clicked() {
-                            state.play_story_ifid = play_story_ifid;
+                            state.play_story_ifid = play_story_ifid.clone();
                             done_clicked = true;
                         }
-                    } else {
+                    }
+                    ImportStage::Previewing => {
+                        buttons_ui.add(egui::Label::new(RichText::new("Checking...").italics()));
+                    }
+                    _ => {
                         buttons_ui.add(egui::Label::new(RichText::new("Loading...").italics()));
                     }
                 });
 
+                if state.stage == ImportStage::Confirming {
+                    ui.label("Nothing has been saved yet. This is what will be imported:");
+                }
+
+                draw_watched_folders(connection, ui, state, show_done);
+
+                if already_imported > 0 {
+                    ui.label(format!(
+                        "Skipped {} unchanged files that were imported before",
+                        already_imported
+                    ));
+                }
+
                 ScrollArea::vertical()
                     .max_height(f32::INFINITY)
                     .show(ui, |scroll_area| {
                         for message in &state.messages {
                             match message {
                                 LoadFileResult::StoryFileSuccess(path, _) => {
-                                    scroll_area.label(format!("Loaded {}", path));
+                                    if state.stage == ImportStage::Confirming {
+                                        scroll_area.label(format!("Will load {}", path));
+                                    } else {
+                                        scroll_area.label(format!("Loaded {}", path));
+                                    }
                                 }
-                                LoadFileResult::LoadCompleted() => {
-                                    // Print nothing for completed
+                                LoadFileResult::LoadCompleted()
+                                | LoadFileResult::AlreadyImported(_) => {
+                                    // Print nothing for completed or skipped files
                                 }
                                 _ => {
                                     scroll_area.label(format!("{:}", message));
@@ -117,46 +328,40 @@ pub fn draw_add_story_window(
         .window
         .draw_button_and_update_state("Add Story", is_open, parent_ui)
     {
-        state.messages.clear();
-        state.play_story_ifid = None;
-        state.is_loading = true;
         if let Ok(Some(path)) = FileDialog::new()
-            .add_filter("Story file", &["z3", "zip", "z4", "z5"])
+            .add_filter(
+                "Story file",
+                &["z3", "zip", "z4", "z5", "zblorb", "blb", "tar", "gz", "tgz"],
+            )
             .show_open_single_file()
         {
             if let Some(path_str) = path.into_os_string().to_str() {
-                let sender = state.sender.clone();
-                let s = String::from(path_str);
-                // Can't share the database connection as it's not thread safe
-                let _handle =
-                    thread::spawn(
-                        move || match IfdbConnection::connect(database_path.as_str()) {
-                            Ok(connection) => {
-                                connection.import_file(s.as_str(), Some(s.clone()), |msg| {
-                                    if let Err(err) = sender.send(msg) {
-                                        println!("Error sending load message: {}", err);
-                                    }
-                                });
-                                if let Err(err) =
-                                    sender.clone().send(LoadFileResult::LoadCompleted())
-                                {
-                                    println!(
-                                        "Error sending load completed message after load. {}",
-                                        err
-                                    );
-                                }
-                            }
-                            Err(msg) => {
-                                panic!(
-                                    "Unable to connect to database at {}. Error was: {}",
-                                    database_path, msg
-                                );
-                            }
-                        },
-                    );
+                state.start(
+                    connection.database_path.clone(),
+                    ImportSource::File(String::from(path_str)),
+                    true,
+                );
             }
         } else {
             state.window.set_open(false);
         }
     }
+
+    if !is_open
+        && parent_ui
+            .button("Add Folder")
+            .on_hover_text("Import every story, iFiction, cover, clue and zip file in a folder")
+            .clicked()
+    {
+        if let Ok(Some(path)) = FileDialog::new().show_open_single_dir() {
+            if let Some(path_str) = path.into_os_string().to_str() {
+                state.window.set_open(true);
+                state.start(
+                    connection.database_path.clone(),
+                    ImportSource::Folder(String::from(path_str)),
+                    true,
+                );
+            }
+        }
+    }
 }
@@ -17,7 +17,8 @@ use std::fs::File;
 use std::io::Read;
 use std::time::Instant;
 
-use super::ifdb::{DbSave, DbSaveError, IfdbConnection, SaveType};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{DbSave, IfdbError, SaveType, DEFAULT_SAVE_VERSION};
 use chrono::Utc;
 use clues_window::clues_window_handler;
 use command_output_window::{draw_command_output_window, CommandOutputWindowState};
@@ -37,7 +38,6 @@ use zmachine::vm::{VMState, GLOBAL_1, VM};
 use eguiio::{Eguiio, EguiioState};
 
 const AUTOSAVE_NAME: &str = "autosave";
-const DEFAULT_SAVE_VERSION: i64 = 2;
 
 // Avoid infinite loops caused by bugs by panicing if
 // too many instructions run without prompting
@@ -81,6 +81,8 @@ pub struct EguiTerp {
     story_help_window: ButtonWindow,
     // DB of the last save restored/autosaved
     last_save_id: i64,
+    pending_autosave: Option<DbSave>, // Autosave not stored yet as the database was busy
+    autosave_error: Option<String>,   // Why the last autosave failed. Shown in the window
 }
 
 impl EguiTerp {
@@ -115,6 +117,8 @@ impl EguiTerp {
             story_help_window: ButtonWindow::create(),
             play_timer: Instant::now(),
             restore_autosave: false,
+            pending_autosave: None,
+            autosave_error: None,
         };
 
         terp.io.screen.use_more(true);
@@ -124,7 +128,7 @@ impl EguiTerp {
 
     // Run the VM -- until either input requested or
     // iteration limit hit
-    fn run_vm(&mut self, connection: &IfdbConnection) {
+    fn run_vm(&mut self, connection: &dyn IfdbStore) {
         let mut counter: usize = 0;
 
         self.io.clear_status_change();
@@ -175,9 +179,15 @@ impl EguiTerp {
                 }
                 VMState::RestorePrompt => match self.saves_state.edit_state {
                     SavesWindowEditState::Ok => {
-                        if let Err(msg) =
-                            self.restore_game(self.saves_state.get_save_name(), connection)
-                        {
+                        let save_ifid = self
+                            .saves_state
+                            .get_save_ifid()
+                            .unwrap_or_else(|| self.ifid.clone());
+                        if let Err(msg) = self.restore_game(
+                            save_ifid,
+                            self.saves_state.get_save_name(),
+                            connection,
+                        ) {
                             self.io.print_to_screen(msg.as_str());
                         }
                         self.saves_state.close_and_reset_window();
@@ -251,7 +261,7 @@ impl EguiTerp {
     pub fn update(
         &mut self,
         ctx: &egui::Context,
-        connection: &IfdbConnection,
+        connection: &dyn IfdbStore,
         window: &FerrifWindow,
         theme: &Theme,
     ) -> PostUpdateAction {
@@ -280,6 +290,8 @@ impl EguiTerp {
             {
                 self.store_autosave(connection, pc, text_buffer_address, parse_buffer_address);
             }
+        } else if self.pending_autosave.is_some() {
+            self.store_pending_autosave(connection);
         }
 
         // Screen interface updates egui directly.
@@ -325,6 +337,7 @@ impl EguiTerp {
                         &mut self.notes_state,
                     );
                     draw_saves_window(
+                        self.story_id,
                         self.ifid.clone(),
                         self.title.clone(),
                         ctx,
@@ -419,6 +432,9 @@ impl EguiTerp {
                         _ => (),
                     };
                 });
+                if let Some(msg) = &self.autosave_error {
+                    ui.colored_label(egui::Color32::RED, format!("Autosave failed. {}", msg));
+                }
                 ui.visuals_mut().override_text_color = Some(theme.get_text_color());
 
                 self.io.draw_screen(
@@ -441,12 +457,15 @@ impl EguiTerp {
         // Record time to database in 10 second blocks
         let duration = self.play_timer.elapsed();
         if duration.as_millis() >= 1000 {
-            if let Err(msg) =
-                connection.add_to_time_played(self.story_id as i64, duration.as_millis() as i64)
-            {
-                println!("Error updating time played: {}", msg);
+            match connection.add_to_time_played(self.story_id as i64, duration.as_millis() as i64) {
+                // Keep the time and try again next frame
+                Err(IfdbError::Busy(_)) => (),
+                Err(msg) => {
+                    println!("Error updating time played: {}", msg);
+                    self.play_timer = Instant::now();
+                }
+                Ok(()) => self.play_timer = Instant::now(),
             }
-            self.play_timer = Instant::now();
         }
 
         if self.restore_autosave {
@@ -499,7 +518,7 @@ impl EguiTerp {
     }
 
     /// Restore the state of the terp from the database
-    pub fn restore_state_from_session(&mut self, connection: &IfdbConnection) {
+    pub fn restore_state_from_session(&mut self, connection: &dyn IfdbStore) {
         match connection.get_or_create_session(self.ifid.clone()) {
             Ok(session) => {
                 self.notes_state.set_open(session.notes_open);
@@ -515,7 +534,7 @@ impl EguiTerp {
         }
     }
     /// Persist any persistable state to the session
-    fn store_state_to_settings(&self, connection: &IfdbConnection) {
+    fn store_state_to_settings(&self, connection: &dyn IfdbStore) {
         match connection.get_or_create_session(self.ifid.clone()) {
             Ok(mut session) => {
                 session.notes_open = self.notes_state.is_open();
@@ -533,14 +552,15 @@ impl EguiTerp {
         }
     }
 
-    /// Restore the named save
+    /// Restore the named save, which may be from another release of the story
     /// Returns result object indicating success/failure
     fn restore_game(
         &mut self,
+        save_ifid: String,
         save_name: String,
-        connection: &IfdbConnection,
+        connection: &dyn IfdbStore,
     ) -> Result<(), String> {
-        match connection.get_save(self.ifid.clone(), save_name) {
+        match connection.get_save(save_ifid, save_name) {
             Ok(response) => match response {
                 Some(save) => {
                     if save.save_type == SaveType::Autosave {
@@ -576,7 +596,7 @@ impl EguiTerp {
 
     /// Save the game as a manual save with the provided save game name.
     /// Returns result object indicating success
-    fn save_game(&mut self, save_name: String, connection: &IfdbConnection) -> Result<(), String> {
+    fn save_game(&mut self, save_name: String, connection: &dyn IfdbStore) -> Result<(), String> {
         // Save window ended in a success, so add the save
         let dbsave = DbSave {
             dbid: 0,
@@ -602,14 +622,14 @@ impl EguiTerp {
                 self.last_save_id = dbid;
                 Ok(())
             }
-            Err(e) => Err(format!("Error saving: {:?}", e)),
+            Err(e) => Err(format!("Error saving: {}", e)),
         }
     }
 
     /// Store the current state of the VM as an autosave
     pub fn store_autosave(
         &mut self,
-        connection: &IfdbConnection,
+        connection: &dyn IfdbStore,
         pc: usize,
         text_buffer_address: u16,
         parse_buffer_address: u16,
@@ -636,23 +656,31 @@ impl EguiTerp {
             room_id: self.get_room_id(),
         };
 
-        match connection.store_save(&dbsave, false) {
-            Ok(dbid) => {
-                self.last_save_id = dbid;
-            }
-            Err(e) => match e {
-                DbSaveError::ExistingSave => {
-                    println!("Autosave for {} rejected due to duplicate save.", self.ifid);
-                }
-                DbSaveError::Other(msg) => {
-                    println!("Error autosaving for {}: {}", self.ifid, msg);
-                }
-            },
-        };
+        // Replaces any autosave still waiting, as this one is more recent
+        self.pending_autosave = Some(dbsave);
+        self.store_pending_autosave(connection);
 
         self.io.clear_text_buffer();
     }
 
+    /// Store the autosave waiting to be stored. Failures are shown in the window rather than
+    /// in a dialog, as they would otherwise interrupt every move
+    fn store_pending_autosave(&mut self, connection: &dyn IfdbStore) {
+        if let Some(dbsave) = self.pending_autosave.take() {
+            match connection.store_save(&dbsave, false) {
+                Ok(dbid) => {
+                    self.last_save_id = dbid;
+                    self.autosave_error = None;
+                }
+                // A duplicate autosave is expected and does not need to be stored
+                Err(IfdbError::Constraint(_)) => self.autosave_error = None,
+                // Keep the autosave and try again next frame
+                Err(IfdbError::Busy(_)) => self.pending_autosave = Some(dbsave),
+                Err(e) => self.autosave_error = Some(e.to_string()),
+            }
+        }
+    }
+
     pub fn prompt_and_load_commands(&mut self) {
         let path = FileDialog::new()
             .add_filter("Command file", &["commands"])
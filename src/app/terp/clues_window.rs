@@ -1,5 +1,6 @@
 /// Functions handling drawing the Clues window
-use crate::app::ifdb::IfdbConnection;
+use crate::app::db_errors::{draw_db_error, show_db_error};
+use crate::app::ifdb::store::IfdbStore;
 
 use eframe::egui;
 use egui::*;
@@ -8,7 +9,7 @@ use egui::*;
 pub fn clues_window_handler(
     _: &egui::Context,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     story_id: u32,
 ) {
     match connection.get_clues_for_story(story_id) {
@@ -46,18 +47,12 @@ pub fn clues_window_handler(
                                                     if let Err(msg) =
                                                         connection.reveal_clue(clue.dbid)
                                                     {
-                                                        println!(
-                                                            "Error revealing clue id {}. {}",
-                                                            clue.dbid, msg
-                                                        );
+                                                        show_db_error("Clues", &msg);
                                                     }
                                                 } else if let Err(msg) =
                                                     connection.hide_clue(clue.dbid)
                                                 {
-                                                    println!(
-                                                        "Error hiding clue id {}. {}",
-                                                        clue.dbid, msg
-                                                    );
+                                                    show_db_error("Clues", &msg);
                                                 }
                                             }
                                         }
@@ -67,11 +62,6 @@ pub fn clues_window_handler(
                     }
                 });
         }
-        Err(msg) => {
-            println!(
-                "draw_clues_window: Error loading clues for stories: {}",
-                msg
-            );
-        }
+        Err(msg) => draw_db_error(parent_ui, "clues", &msg),
     }
 }
@@ -1,6 +1,8 @@
 use super::windows::ButtonWindow;
 /// Functions handling drawing the Notes window
-use crate::app::ifdb::{IfdbConnection, Note};
+use crate::app::db_errors::show_db_error;
+use crate::app::ifdb::store::IfdbStore;
+use crate::app::ifdb::Note;
 
 use eframe::egui;
 use egui::{color::*, *};
@@ -72,7 +74,7 @@ pub fn draw_notes_window(
     title: String,
     ctx: &egui::Context,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut NotesWindowState,
 ) {
     let mut is_open = state.window.is_open();
@@ -121,7 +123,7 @@ fn draw_notes_window_header(parent_ui: &mut eframe::egui::Ui, state: &mut NotesW
 fn draw_notes_add_panel(
     story_id: u32,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut NotesWindowState,
 ) {
     let mut add_note = false;
@@ -176,7 +178,7 @@ fn draw_notes_add_panel(
                 room_name: add_room_name,
                 done: false,
             }) {
-                println!("Error creating note. {}.", msg);
+                show_db_error("Notes", &msg);
             }
             state.input_text.clear();
             state.state = NotesWindowEditState::View;
@@ -207,7 +209,7 @@ fn draw_notes_header(room_id: u32, room_name: Option<String>, ui: &mut eframe::e
 fn draw_notes_uneditable(
     story_id: u32,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut NotesWindowState,
 ) {
     ScrollArea::vertical()
@@ -242,7 +244,7 @@ fn draw_notes_uneditable(
 fn draw_notes_editing(
     story_id: u32,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut NotesWindowState,
 ) {
     ScrollArea::vertical()
@@ -337,7 +339,7 @@ fn draw_notes_editing(
                                             RoomSelection::Somewhere(room_id, _) => room_id as i32,
                                         },
                                     ) {
-                                        println!("Error saving note. {}.", msg);
+                                        show_db_error("Notes", &msg);
                                     }
                                     state.input_text.clear();
                                     state.state = NotesWindowEditState::View;
@@ -366,7 +368,7 @@ fn draw_notes_editing(
 fn draw_notes_editable(
     story_id: u32,
     parent_ui: &mut eframe::egui::Ui,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut NotesWindowState,
 ) {
     ScrollArea::vertical()
@@ -392,7 +394,7 @@ fn draw_notes_editable(
                             ui.checkbox(&mut checked, note_notes.clone());
                             if checked != note_done {
                                 if let Err(msg) = connection.set_note_done(note_id, checked) {
-                                    println!("Error marking note done as {}. {}.", checked, msg);
+                                    show_db_error("Notes", &msg);
                                 }
                             }
 
@@ -1,5 +1,10 @@
-use crate::app::ifdb::IfdbConnection;
+use crate::app::db_errors::show_db_error;
+use crate::app::ifdb::store::IfdbStore;
+use crate::app::ifdb::{DbSave, SAVE_EXTENSIONS};
 use eframe::egui;
+use native_dialog::{FileDialog, MessageDialog, MessageType};
+use std::ffi::OsStr;
+use std::fs;
 
 use egui::{color::*, *};
 // Color of the error messages
@@ -19,6 +24,7 @@ pub enum SavesWindowEditState {
 pub struct SavesWindowState {
     pub edit_state: SavesWindowEditState,
     input_text: String,
+    save_ifid: Option<String>, // Release of the save chosen to restore, if not the one playing
     error_message: String,
     just_opened: bool,
 }
@@ -28,6 +34,7 @@ impl SavesWindowState {
         SavesWindowState {
             edit_state: SavesWindowEditState::Closed,
             input_text: String::new(),
+            save_ifid: None,
             error_message: String::new(),
             just_opened: true,
         }
@@ -38,10 +45,16 @@ impl SavesWindowState {
         self.input_text.clone()
     }
 
+    /** Return the IFID of the save selected from the list, if it is from another release */
+    pub fn get_save_ifid(&self) -> Option<String> {
+        self.save_ifid.clone()
+    }
+
     /** Switch window into closed state */
     pub fn close_and_reset_window(&mut self) {
         self.edit_state = SavesWindowEditState::Closed;
         self.input_text.clear();
+        self.save_ifid = None;
         self.error_message.clear();
         self.just_opened = false;
     }
@@ -67,11 +80,161 @@ impl SavesWindowState {
     }
 }
 
+/// Ask for a Quetzal file and add it to the saves in the database
+fn import_save(ifid: &str, connection: &dyn IfdbStore, state: &mut SavesWindowState) {
+    let path = FileDialog::new()
+        .add_filter("Quetzal save", SAVE_EXTENSIONS)
+        .show_open_single_file()
+        .unwrap();
+
+    if let Some(path) = path {
+        let name = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .unwrap_or("Imported save")
+            .to_string();
+        let result = fs::read(&path)
+            .map_err(|e| e.into())
+            .and_then(|data| connection.import_quetzal_save(data, &name));
+        match result {
+            Ok(save_ifid) if save_ifid != ifid => {
+                MessageDialog::new()
+                    .set_type(MessageType::Info)
+                    .set_title("Save imported")
+                    .set_text(
+                        format!("{} belongs to another story ({}).", name, save_ifid).as_str(),
+                    )
+                    .show_alert()
+                    .unwrap();
+            }
+            Ok(_) => (),
+            Err(msg) => {
+                state.error_message = format!("Error importing save: {}", msg);
+            }
+        }
+    }
+}
+
+/// Ask for a path and write the save there as a Quetzal file
+fn export_save(ifid: String, dbid: i64, name: &str, connection: &dyn IfdbStore) {
+    let path = FileDialog::new()
+        .set_filename(format!("{}.qzl", name).as_str())
+        .add_filter("Quetzal save", &["qzl"])
+        .show_save_single_file()
+        .unwrap();
+
+    if let Some(path) = path {
+        if let Err(e) = connection.export_save(ifid, dbid, &path.to_string_lossy()) {
+            show_db_error("Export failed", &e);
+        }
+    }
+}
+
+/// Draw saves under the dates they were made, with a checkbox to restore each. Returns the
+/// name of the save checked
+fn draw_saves(
+    ui: &mut Ui,
+    ifid: &str,
+    saves: Vec<DbSave>,
+    connection: &dyn IfdbStore,
+) -> Option<String> {
+    let mut chosen = None;
+    let mut last_save_date = String::new();
+
+    for save in saves {
+        let save_date = save.formatted_saved_date();
+        if save_date != last_save_date {
+            if !last_save_date.is_empty() {
+                // Add separator if this is not the first date
+                ui.separator();
+            }
+
+            ui.add(Label::new(
+                RichText::new(save_date.clone()).color(DATE_HEADER_COLOR),
+            ));
+
+            last_save_date = save_date.clone();
+        }
+
+        let mut checked = false;
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(
+                    &mut checked,
+                    format!("{} ({})", save.name.clone(), save.formatted_saved_time()),
+                )
+                .clicked()
+            {
+                chosen = Some(save.name.clone());
+            }
+            if ui.small_button("Export").clicked() {
+                export_save(ifid.to_string(), save.dbid, &save.name, connection);
+            }
+        });
+    }
+
+    chosen
+}
+
+/// Draw the saves made with the story's other releases. Each release has its own story file,
+/// so these may not restore, and are only restored after a warning
+fn draw_other_release_saves(
+    ui: &mut Ui,
+    ifid: &str,
+    story_id: u32,
+    connection: &dyn IfdbStore,
+    state: &mut SavesWindowState,
+) {
+    let releases = match connection.fetch_story_releases(story_id) {
+        Ok(releases) => releases,
+        Err(msg) => {
+            state.error_message = format!("Error loading releases: {}", msg);
+            return;
+        }
+    };
+
+    for release in releases.into_iter().filter(|r| r.ifid != ifid) {
+        let saves = match connection.fetch_manual_saves_for_ifid(release.ifid.clone()) {
+            Ok(saves) => saves,
+            Err(msg) => {
+                state.error_message = format!("Error loading saves: {}", msg);
+                continue;
+            }
+        };
+        if saves.is_empty() {
+            continue;
+        }
+
+        ui.separator();
+        ui.strong(format!("Saves from {}", release));
+        if let Some(name) = draw_saves(ui, &release.ifid, saves, connection) {
+            if MessageDialog::new()
+                .set_type(MessageType::Warning)
+                .set_title("Restore save from another release?")
+                .set_text(
+                    format!(
+                        "{} was saved in another release of this story ({}). It may not restore, or may not play correctly. Restore it anyway?",
+                        name, release
+                    )
+                    .as_str(),
+                )
+                .show_confirm()
+                .unwrap()
+            {
+                state.input_text.push_str(name.as_str());
+                state.save_ifid = Some(release.ifid.clone());
+                state.edit_state = SavesWindowEditState::Ok;
+            }
+        }
+    }
+}
+
 pub fn draw_saves_window(
+    story_id: u32,
     ifid: String,
     title: String,
     ctx: &egui::Context,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
     state: &mut SavesWindowState,
 ) {
     let mut is_open = state.is_open();
@@ -86,40 +249,15 @@ pub fn draw_saves_window(
                         ));
                     }
 
-                    match connection.fetch_manual_saves_for_ifid(ifid) {
+                    if ui.button("Import save").clicked() {
+                        import_save(&ifid, connection, state);
+                    }
+
+                    match connection.fetch_manual_saves_for_ifid(ifid.clone()) {
                         Ok(saves) => {
-                            let mut last_save_date = String::new();
-
-                            for save in saves {
-                                let save_date = save.formatted_saved_date();
-                                if save_date != last_save_date {
-                                    if !last_save_date.is_empty() {
-                                        // Add separator if this is not the first date
-                                        ui.separator();
-                                    }
-
-                                    ui.add(Label::new(
-                                        RichText::new(save_date.clone()).color(DATE_HEADER_COLOR),
-                                    ));
-
-                                    last_save_date = save_date.clone();
-                                }
-
-                                let mut checked = false;
-                                if ui
-                                    .checkbox(
-                                        &mut checked,
-                                        format!(
-                                            "{} ({})",
-                                            save.name.clone(),
-                                            save.formatted_saved_time()
-                                        ),
-                                    )
-                                    .clicked()
-                                {
-                                    state.input_text.push_str(save.name.as_str());
-                                    state.edit_state = SavesWindowEditState::Ok;
-                                }
+                            if let Some(name) = draw_saves(ui, &ifid, saves, connection) {
+                                state.input_text.push_str(name.as_str());
+                                state.edit_state = SavesWindowEditState::Ok;
                             }
                         }
                         Err(msg) => {
@@ -128,6 +266,8 @@ pub fn draw_saves_window(
                                 .push_str(format!("Error loading notes: {}", msg).as_str());
                         }
                     };
+
+                    draw_other_release_saves(ui, &ifid, story_id, connection, state);
                 }
                 SavesWindowEditState::Saving => {
                     let mut save_game = false;
@@ -1,11 +1,11 @@
 /** Story help window displays help on a playing story's window */
-use crate::app::ifdb::IfdbConnection;
+use crate::app::ifdb::store::IfdbStore;
 use eframe::egui;
 use egui::*;
 pub fn story_window_handler(
     _: &egui::Context,
     ui: &mut eframe::egui::Ui,
-    _: &IfdbConnection,
+    _: &dyn IfdbStore,
     _: u32,
 ) {
     ScrollArea::vertical()
@@ -6,7 +6,9 @@
  * Colors are still processed separately. The screen interface on the story just uses
  * the colors directly
  */
-use super::super::ifdb::{DbColor, DbFont, DbTheme, IfdbConnection, ThemeType};
+use super::super::db_errors::show_db_error;
+use super::super::ifdb::store::IfdbStore;
+use super::super::ifdb::{DbColor, DbFont, DbTheme, ThemeType};
 use std::collections::BTreeMap;
 
 use eframe::egui;
@@ -99,7 +101,7 @@ pub struct Theme {
 }
 
 impl Theme {
-    pub fn restore_from_db(&mut self, db_theme: DbTheme, connection: &IfdbConnection) {
+    pub fn restore_from_db(&mut self, db_theme: DbTheme, connection: &dyn IfdbStore) {
         self.theme_type = db_theme.theme_type;
         if db_theme.font_size >= 0 && db_theme.font_size < FONT_SIZE_OPTIONS.len() as i64 {
             self.font_size = FONT_SIZE_OPTIONS[db_theme.font_size as usize];
@@ -160,7 +162,7 @@ impl Theme {
         }
     }
 
-    pub fn store_to_db(&self, name: String, connection: &IfdbConnection) {
+    pub fn store_to_db(&self, name: String, connection: &dyn IfdbStore) {
         let theme = DbTheme {
             name,
             theme_type: self.theme_type,
@@ -193,7 +195,7 @@ impl Theme {
         };
 
         if let Err(msg) = connection.store_theme(theme) {
-            println!("Unable to store theme. {}", msg);
+            show_db_error("Theme", &msg);
         }
     }
 }
@@ -269,7 +271,7 @@ pub fn apply_fonts_to_context(
     ui_theme: &Theme,
     story_theme: &mut Theme,
     ctx: &egui::Context,
-    connection: &IfdbConnection,
+    connection: &dyn IfdbStore,
 ) {
     // Apply fonts
     let mut fonts = FontDefinitions::default();
@@ -304,7 +306,7 @@ pub fn apply_fonts_to_context(
                 .unwrap();
 
             if let Err(msg) = connection.update_font_metadata(font) {
-                println!("Error updating font: {}", msg);
+                show_db_error("Fonts", &msg);
             }
         }
         story_theme.font = FontOption {
@@ -1,6 +1,6 @@
 use super::egui::{Pos2, Vec2};
 
-use crate::app::ifdb::{IfdbConnection, WindowDetails, WindowType};
+use crate::app::ifdb::{WindowDetails, WindowType};
 use eframe::egui;
 
 /// A window in the Ferrif app.
@@ -118,14 +118,14 @@ impl ButtonWindow {
     /** Prompt for a window with a button, and handle toggling the window open/closed.
      * If open, will call window_handler
      */
-    pub fn add_window_button(
+    pub fn add_window_button<S: ?Sized>(
         &mut self,
         button_name: &str,
         ctx: &egui::Context,
         parent_ui: &mut eframe::egui::Ui,
         story_id: u32,
-        connection: &IfdbConnection,
-        window_handler: fn(&egui::Context, &mut eframe::egui::Ui, &IfdbConnection, u32),
+        connection: &S,
+        window_handler: fn(&egui::Context, &mut eframe::egui::Ui, &S, u32),
     ) {
         let mut is_open = self.is_open();
 
@@ -0,0 +1,170 @@
+use super::db_errors::{draw_db_error, show_db_error};
+use super::ifdb::store::IfdbStore;
+use super::ifdb::{DbTrashItem, IfdbConnection, TrashContents};
+use super::terp::windows::ButtonWindow;
+use eframe::egui;
+use egui::*;
+use native_dialog::{MessageDialog, MessageType};
+
+const DEFAULT_SIZE: Vec2 = Vec2 {
+    x: 500f32,
+    y: 400f32,
+};
+const DEFAULT_POS: Pos2 = Pos2 { x: 80f32, y: 80f32 };
+const DEFAULT_PURGE_DAYS: u32 = 30;
+
+pub struct TrashWindowState {
+    pub window: ButtonWindow,
+    purge_days: Option<Option<u32>>, // None until read from the database
+}
+
+impl TrashWindowState {
+    pub fn create() -> TrashWindowState {
+        TrashWindowState {
+            window: ButtonWindow::create(),
+            purge_days: None,
+        }
+    }
+}
+
+fn describe_contents(contents: &TrashContents) -> String {
+    match contents {
+        TrashContents::Story => "Story".to_string(),
+        TrashContents::Autosaves(ifid, 1) => format!("1 autosave ({})", ifid),
+        TrashContents::Autosaves(ifid, count) => format!("{} autosaves ({})", count, ifid),
+    }
+}
+
+fn confirm(title: &str, text: &str) -> bool {
+    MessageDialog::new()
+        .set_type(MessageType::Warning)
+        .set_title(title)
+        .set_text(text)
+        .show_confirm()
+        .unwrap()
+}
+
+/// The setting to empty the trash automatically. Items older than the days set are purged
+/// at startup
+fn draw_purge_setting(connection: &IfdbConnection, ui: &mut Ui, state: &mut TrashWindowState) {
+    let mut purge_days = match state.purge_days {
+        Some(purge_days) => purge_days,
+        None => match connection.get_trash_purge_days() {
+            Ok(purge_days) => {
+                state.purge_days = Some(purge_days);
+                purge_days
+            }
+            Err(e) => {
+                draw_db_error(ui, "trash settings", &e);
+                return;
+            }
+        },
+    };
+
+    ui.horizontal(|ui| {
+        let mut automatic = purge_days.is_some();
+        let mut changed = ui
+            .checkbox(&mut automatic, "Empty automatically after")
+            .changed();
+        let mut days = purge_days.unwrap_or(DEFAULT_PURGE_DAYS);
+        changed |= ui
+            .add_enabled(
+                automatic,
+                egui::DragValue::new(&mut days).clamp_range(1..=3650),
+            )
+            .changed();
+        ui.label("days");
+
+        if changed {
+            purge_days = if automatic { Some(days) } else { None };
+            state.purge_days = Some(purge_days);
+            if let Err(e) = connection.store_trash_purge_days(purge_days) {
+                show_db_error("Store trash setting", &e);
+            }
+        }
+    });
+}
+
+fn draw_items(connection: &IfdbConnection, ui: &mut Ui, items: &[DbTrashItem]) {
+    egui::Grid::new("trash_items").striped(true).show(ui, |ui| {
+        ui.strong("Title");
+        ui.strong("Contents");
+        ui.strong("Deleted");
+        ui.end_row();
+        for item in items {
+            ui.label(item.title.as_str());
+            ui.label(describe_contents(&item.contents));
+            ui.label(item.formatted_deleted_when());
+            if ui.small_button("Restore").clicked() {
+                if let Err(e) = connection.restore_from_trash(item) {
+                    show_db_error("Restore from trash", &e);
+                }
+            }
+            if ui.small_button("Delete forever").clicked()
+                && confirm(
+                    "Delete forever?",
+                    "Are you sure you want to permanently delete this item? There is no undo.",
+                )
+            {
+                if let Err(e) = connection.purge_from_trash(item) {
+                    show_db_error("Delete from trash", &e);
+                }
+            }
+            ui.end_row();
+        }
+    });
+}
+
+pub fn draw_trash_window(
+    connection: &IfdbConnection,
+    ctx: &egui::Context,
+    parent_ui: &mut eframe::egui::Ui,
+    state: &mut TrashWindowState,
+) {
+    let mut is_open = state.window.is_open();
+
+    if is_open {
+        egui::Window::new("Trash")
+            .open(&mut is_open)
+            .default_size(DEFAULT_SIZE)
+            .default_pos(DEFAULT_POS)
+            .show(ctx, |ui| {
+                let items = match connection.fetch_trash() {
+                    Ok(items) => items,
+                    Err(e) => {
+                        draw_db_error(ui, "trash", &e);
+                        return;
+                    }
+                };
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!items.is_empty(), egui::Button::new("Empty trash"))
+                        .clicked()
+                        && confirm(
+                            "Empty trash?",
+                            "Are you sure you want to permanently delete everything in the trash? There is no undo.",
+                        )
+                    {
+                        if let Err(e) = connection.purge_trash(None) {
+                            show_db_error("Empty trash", &e);
+                        }
+                    }
+                });
+                draw_purge_setting(connection, ui, state);
+                ui.separator();
+
+                if items.is_empty() {
+                    ui.label("The trash is empty.");
+                } else {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        draw_items(connection, ui, &items);
+                    });
+                }
+            });
+    }
+
+    state
+        .window
+        .draw_button_and_update_state("Trash", is_open, parent_ui);
+}
@@ -9,18 +9,22 @@ mod app;
 #[macro_use]
 extern crate lazy_static;
 
-use app::ifdb::IfdbConnection;
+use app::ifdb::store::IfdbStore;
+use app::ifdb::{IfdbConnection, IfdbError, LoadFileResult, MigrationError};
 use app::FerrifApp;
 use clap::{App, Arg};
 use native_dialog::{MessageDialog, MessageType};
+use std::fs::File;
 use std::thread;
 
 const DEFAULT_DB_NAME: &str = "ferrif.db";
 
 enum AppError {
     NoPath,
-    MigrationError,
+    MigrationError(MigrationError),
     ConnectionError(String),
+    DatabaseError(String, IfdbError), // What was being done, and the error from the database
+    CheckFailed(usize), // Number of problems the integrity check found. Already printed
 }
 
 // Print text to stdout iff testmode feature is active
@@ -37,22 +41,38 @@ macro_rules! testmode_println {
 fn migrate_database(database_path: &str) -> Result<(), AppError> {
     testmode_println!("INIT: Running database migrations");
     match IfdbConnection::connect(database_path) {
-        Ok(connection) => {
-            if connection.migrate().is_err() {
-                return Err(AppError::MigrationError);
-            }
-        }
+        Ok(connection) => match connection.migrate() {
+            Ok(notes) if !notes.is_empty() => show_migration_notes(&notes),
+            Ok(_) => (),
+            Err(err) => return Err(AppError::MigrationError(err)),
+        },
         Err(msg) => {
-            return Err(AppError::ConnectionError(format!(
-                "Unable to connect to database at {}. Error was: {}",
-                database_path, msg
-            )));
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
         }
     }
 
     Ok(())
 }
 
+/** Tell the player about anything the database update could not do for them */
+fn show_migration_notes(notes: &[String]) {
+    let msg = format!("The Ferrif database was updated.\n\n{}", notes.join("\n"));
+
+    #[cfg(feature = "testmode")]
+    println!("{}", msg);
+
+    #[cfg(not(feature = "testmode"))]
+    MessageDialog::new()
+        .set_type(MessageType::Info)
+        .set_title("Database updated")
+        .set_text(msg.as_str())
+        .show_alert()
+        .unwrap();
+}
+
 /** Start the interpreter. Will panic if database connection fails */
 fn start_terp(
     database_path: &str,
@@ -71,10 +91,10 @@ fn start_terp(
             eframe::run_native(Box::new(app), native_options);
         }
         Err(msg) => {
-            return Err(AppError::ConnectionError(format!(
-                "Unable to connect to database at {}. Error was: {}",
-                database_path, msg
-            )));
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
         }
     }
 }
@@ -97,10 +117,10 @@ fn find_story_with_id(
                 }
             }
             Err(msg) => {
-                return Err(AppError::ConnectionError(format!(
-                    "Unable to connect to database at {}. Error was: {}",
-                    database_path, msg
-                )));
+                return Err(AppError::DatabaseError(
+                    format!("Unable to connect to database at {}", database_path),
+                    msg,
+                ));
             }
         }
     }
@@ -108,24 +128,39 @@ fn find_story_with_id(
     Ok(None)
 }
 
-/** Load a file -- story, cover image, ifiction, zip or otherwise */
-fn load_file(path_str: String, database_path: String) {
+/** Load a file -- story, cover image, ifiction, zip or otherwise. A dry run lists what would be loaded without saving it */
+fn load_file(path_str: String, database_path: String, dry_run: bool) {
     let handle = thread::spawn(
         move || match IfdbConnection::connect(database_path.as_str()) {
             Ok(connection) => {
-                connection.import_file(path_str.clone().as_str(), Some(path_str.clone()), |_| {});
+                if dry_run {
+                    for result in
+                        connection.preview_import(path_str.as_str(), Some(path_str.clone()))
+                    {
+                        println!("{}", result);
+                    }
+                } else {
+                    connection.import_file(path_str.as_str(), Some(path_str.clone()), |_| {});
+                }
             }
             Err(msg) => {
                 println!(
-                    "Unable to connect to database at {}. Error was: {}",
-                    database_path, msg
+                    "{}",
+                    database_error_message(
+                        format!("Unable to connect to database at {}", database_path).as_str(),
+                        &msg
+                    )
                 );
             }
         },
     );
     handle.join().unwrap();
 
-    println!("Load complete.");
+    if dry_run {
+        println!("Dry run complete. Nothing was saved.");
+    } else {
+        println!("Load complete.");
+    }
 }
 
 /** List all stories stored in the database. Will panic if database cannot be initialized */
@@ -141,22 +176,323 @@ fn list_stories(database_path: String) -> Result<(), AppError> {
             }
 
             Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to fetch stories from database at {}", database_path),
+                    msg,
+                ));
+            }
+        },
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to fetch stories from database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    Ok(())
+}
+/** Export ifiction data for a single story, or the whole library if no story is given */
+fn export_ifiction(
+    database_path: &str,
+    path_str: &str,
+    export_story_id: Option<&str>,
+) -> Result<(), AppError> {
+    let story_id = match export_story_id {
+        Some(id) => match find_story_with_id(database_path, export_story_id)? {
+            Some(story_id) => Some(story_id as u32),
+            None => {
                 return Err(AppError::ConnectionError(format!(
-                    "Unable to fetch stories from database at {}. Error was: {}",
-                    database_path, msg
-                )));
+                    "No story found with DBID or IFID {}",
+                    id
+                )))
+            }
+        },
+        None => None,
+    };
+
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => {
+            let result = match File::create(path_str) {
+                Ok(file) => connection.export_ifiction(story_id, file),
+                Err(msg) => Err(msg.into()),
+            };
+            if let Err(msg) = result {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to export to {}", path_str),
+                    msg,
+                ));
+            }
+        }
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    println!("Export complete.");
+    Ok(())
+}
+
+/** Write a backup archive of the database to a path */
+fn backup_database(database_path: &str, path_str: &str) -> Result<(), AppError> {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => {
+            if let Err(msg) = connection.backup_to_path(path_str) {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to back up database to {}", path_str),
+                    msg,
+                ));
+            }
+        }
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    println!("Backup complete.");
+    Ok(())
+}
+
+/** Import every Quetzal save in a directory, matching each to its story */
+fn import_saves(database_path: &str, path_str: &str) -> Result<(), AppError> {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => {
+            connection.import_saves_from_directory(path_str, |result| println!("{}", result));
+        }
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    println!("Import complete.");
+    Ok(())
+}
+
+/** Merge another Ferrif database into this one, listing everything changed */
+fn merge_database(database_path: &str, path_str: &str) -> Result<(), AppError> {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => match connection.merge_from_path(path_str) {
+            Ok(changes) => {
+                for change in changes.iter() {
+                    println!("{}", change);
+                }
+                if changes.is_empty() {
+                    println!("Nothing to merge.");
+                }
+            }
+            Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to merge {}. Nothing was changed", path_str),
+                    msg,
+                ));
             }
         },
         Err(msg) => {
-            return Err(AppError::ConnectionError(format!(
-                "Unable to fetch stories from database at {}. Error was: {}",
-                database_path, msg
-            )));
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    println!("Merge complete.");
+    Ok(())
+}
+
+/** Check the database, remove rows left behind by deleted stories and compact it, as asked */
+fn maintain_database(
+    database_path: &str,
+    check: bool,
+    remove_orphans: bool,
+    vacuum: bool,
+) -> Result<(), AppError> {
+    let connection = match IfdbConnection::connect(database_path) {
+        Ok(connection) => connection,
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    };
+
+    if check {
+        let result = connection.check_integrity().and_then(|problems| {
+            let orphans = connection.find_orphaned_rows()?;
+            let sizes = connection.table_sizes()?;
+            Ok((problems, orphans, sizes))
+        });
+        match result {
+            Ok((problems, orphans, sizes)) => {
+                if problems.is_empty() {
+                    println!("Integrity check passed.");
+                }
+                for problem in problems.iter() {
+                    println!("Integrity problem: {}", problem);
+                }
+                for orphan in orphans.iter() {
+                    println!("Orphaned: {}", orphan);
+                }
+                if orphans.is_empty() {
+                    println!("No orphaned rows.");
+                }
+                for size in sizes.iter() {
+                    println!("{}", size);
+                }
+                // Nothing else is done to a damaged database, and scripts see a failure
+                if !problems.is_empty() {
+                    return Err(AppError::CheckFailed(problems.len()));
+                }
+            }
+            Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    "Unable to check the database".to_string(),
+                    msg,
+                ));
+            }
+        }
+    }
+
+    if remove_orphans {
+        match connection.remove_orphaned_rows() {
+            Ok(removed) => {
+                for orphan in removed.iter() {
+                    println!("Removed: {}", orphan);
+                }
+                if removed.is_empty() {
+                    println!("No orphaned rows.");
+                }
+            }
+            Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    "Unable to remove orphaned rows. Nothing was changed".to_string(),
+                    msg,
+                ));
+            }
+        }
+    }
+
+    if vacuum {
+        match connection.vacuum() {
+            Ok((before, after)) => println!(
+                "Compacted from {} kB to {} kB.",
+                before / 1024,
+                after / 1024
+            ),
+            Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    "Unable to compact the database".to_string(),
+                    msg,
+                ));
+            }
         }
     }
 
+    println!("Maintenance complete.");
     Ok(())
 }
+
+/** Write today's backup if daily backups are on. Failures are reported but not fatal */
+fn run_daily_backup(database_path: &str) {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => match connection.run_daily_backup() {
+            Ok(Some(path)) => println!("Wrote daily backup to {}", path),
+            Ok(None) => (),
+            Err(msg) => println!(
+                "{}",
+                database_error_message("Unable to write daily backup", &msg)
+            ),
+        },
+        Err(msg) => println!(
+            "{}",
+            database_error_message(
+                format!("Unable to connect to database at {}", database_path).as_str(),
+                &msg
+            )
+        ),
+    }
+}
+
+/** Permanently delete what has been in the trash longer than the days set. Failures are
+ * reported but not fatal */
+fn purge_expired_trash(database_path: &str) {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => match connection.purge_expired_trash() {
+            Ok(0) => (),
+            Ok(count) => println!("Emptied {} items from the trash", count),
+            Err(msg) => println!(
+                "{}",
+                database_error_message("Unable to empty the trash", &msg)
+            ),
+        },
+        Err(msg) => println!(
+            "{}",
+            database_error_message(
+                format!("Unable to connect to database at {}", database_path).as_str(),
+                &msg
+            )
+        ),
+    }
+}
+
+/** Add a folder to the watched folders and import everything in it */
+fn watch_folder(database_path: &str, path_str: &str) -> Result<(), AppError> {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => {
+            if let Err(msg) = connection.add_watched_folder(path_str) {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to watch {}", path_str),
+                    msg,
+                ));
+            }
+            connection.import_file(path_str, None, |result| println!("{}", result));
+        }
+        Err(msg) => {
+            return Err(AppError::DatabaseError(
+                format!("Unable to connect to database at {}", database_path),
+                msg,
+            ));
+        }
+    }
+
+    println!("Import complete.");
+    Ok(())
+}
+
+/** Import new files from the watched folders. Failures are reported but not fatal */
+fn rescan_watched_folders(database_path: &str) {
+    match IfdbConnection::connect(database_path) {
+        Ok(connection) => {
+            let result = connection.rescan_watched_folders(|result| match result {
+                LoadFileResult::AlreadyImported(_) => (),
+                _ => println!("{}", result),
+            });
+            if let Err(msg) = result {
+                println!(
+                    "{}",
+                    database_error_message("Unable to scan watched folders", &msg)
+                );
+            }
+        }
+        Err(msg) => println!(
+            "{}",
+            database_error_message(
+                format!("Unable to connect to database at {}", database_path).as_str(),
+                &msg
+            )
+        ),
+    }
+}
+
 fn main_wrapped() -> Result<(), AppError> {
     #[cfg(feature = "testmode")]
     println!(
@@ -177,10 +513,18 @@ fn main_wrapped() -> Result<(), AppError> {
             Arg::with_name("load")
                 .short("l")
                 .long("load")
-                .help("Path to story file, ifiction file, or zipfile to load.")
+                .help("Path to story file, ifiction file, archive (zip, tar, tar.gz) or folder to load.")
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("List what --load would import without saving anything")
+                .required(false)
+                .requires("load")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("play")
                 .short("p")
@@ -196,6 +540,77 @@ fn main_wrapped() -> Result<(), AppError> {
                 .required(false)
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("export")
+                .long("export")
+                .help("Path to write iFiction data for the library to")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-story")
+                .long("export-story")
+                .help("DBID or IFID of a single story to export with --export")
+                .required(false)
+                .requires("export")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup")
+                .long("backup")
+                .help("Path to write a backup archive of the database to")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("restore")
+                .long("restore")
+                .help("Path of a backup archive to replace the database with")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .long("merge")
+                .help("Path of another Ferrif database to merge stories, saves, notes and clues from")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Check database integrity and list orphaned rows and table sizes. Exits with an error if the check finds problems")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("remove-orphans")
+                .long("remove-orphans")
+                .help("Remove saves, notes, clues and other rows left behind by deleted stories")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("vacuum")
+                .long("vacuum")
+                .help("Compact the database to reclaim space from deleted rows")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("import-saves")
+                .long("import-saves")
+                .help("Directory of Quetzal save files (.qzl or .sav) to import")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Folder to import from now and rescan each time Ferrif starts")
+                .required(false)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("defaults")
                 .long("defaults")
@@ -222,17 +637,82 @@ fn main_wrapped() -> Result<(), AppError> {
         };
     }
 
+    // Restore has to happen before anything opens the database
+    if let Some(path_str) = matches.value_of("restore") {
+        match IfdbConnection::restore_from_backup(path_str, database_path.as_str()) {
+            Ok(Some(kept_path)) => println!(
+                "Restore complete. The previous database was kept at {}.",
+                kept_path
+            ),
+            Ok(None) => println!("Restore complete."),
+            Err(msg) => {
+                return Err(AppError::DatabaseError(
+                    format!("Unable to restore backup {}", path_str),
+                    msg,
+                ))
+            }
+        }
+    }
+
     migrate_database(database_path.as_str())?;
 
+    if let Some(path_str) = matches.value_of("backup") {
+        backup_database(database_path.as_str(), path_str)?;
+        return Ok(());
+    }
+
     if matches.is_present("list") {
         list_stories(database_path)?;
         return Ok(());
     }
 
+    if let Some(path_str) = matches.value_of("export") {
+        export_ifiction(
+            database_path.as_str(),
+            path_str,
+            matches.value_of("export-story"),
+        )?;
+        return Ok(());
+    }
+
+    if let Some(path_str) = matches.value_of("merge") {
+        merge_database(database_path.as_str(), path_str)?;
+        return Ok(());
+    }
+
+    let check = matches.is_present("check");
+    let remove_orphans = matches.is_present("remove-orphans");
+    let vacuum = matches.is_present("vacuum");
+    if check || remove_orphans || vacuum {
+        maintain_database(database_path.as_str(), check, remove_orphans, vacuum)?;
+        return Ok(());
+    }
+
+    if let Some(path_str) = matches.value_of("import-saves") {
+        import_saves(database_path.as_str(), path_str)?;
+        return Ok(());
+    }
+
+    if let Some(path_str) = matches.value_of("watch") {
+        watch_folder(database_path.as_str(), path_str)?;
+        return Ok(());
+    }
+
+    run_daily_backup(database_path.as_str());
+
+    // After the backup, so it still holds what is purged
+    purge_expired_trash(database_path.as_str());
+
+    rescan_watched_folders(database_path.as_str());
+
     let use_defaults = matches.is_present("defaults");
 
     if let Some(path_str) = matches.value_of("load") {
-        load_file(path_str.to_string(), database_path.clone());
+        let dry_run = matches.is_present("dry-run");
+        load_file(path_str.to_string(), database_path.clone(), dry_run);
+        if dry_run {
+            return Ok(());
+        }
     }
     if let Some(play_id) = matches.value_of("play") {
         start_terp(database_path.as_str(), Some(play_id), use_defaults)?;
@@ -243,12 +723,37 @@ fn main_wrapped() -> Result<(), AppError> {
     Ok(())
 }
 
+/** Describe a database error, with advice for the errors the player can do something about */
+fn database_error_message(context: &str, error: &IfdbError) -> String {
+    match error {
+        IfdbError::Busy(_) => format!(
+            "{}. Another copy of Ferrif is using the database. Close it and try again.",
+            context
+        ),
+        IfdbError::Corrupt(msg) => format!(
+            "{}. The database could not be read ({}). A backup can be restored with --restore.",
+            context, msg
+        ),
+        IfdbError::NotFound(msg) | IfdbError::Invalid(msg) => format!("{}. {}", context, msg),
+        _ => format!("{}. Error was: {}", context, error),
+    }
+}
+
 fn main() {
     if let Err(err) = main_wrapped() {
         let msg = match err {
             AppError::NoPath => "Unable to find your home directory. You can still use Ferrif by running it from the command line with the path as the first parameter.".to_string(),
-            AppError::MigrationError => "Unable to update the Ferrif database.".to_string(),
-            AppError::ConnectionError(msg) => msg
+            AppError::MigrationError(err) => format!("Unable to update the Ferrif database. {}", err),
+            AppError::ConnectionError(msg) => msg,
+            AppError::DatabaseError(context, error) => database_error_message(&context, &error),
+            // Reported on the command line, for scripts, rather than in a dialog
+            AppError::CheckFailed(count) => {
+                println!(
+                    "Integrity check found {} problems. A backup can be restored with --restore.",
+                    count
+                );
+                std::process::exit(1);
+            }
         };
 
         #[cfg(feature = "testmode")]
@@ -261,5 +766,7 @@ fn main() {
             .set_text(msg.as_str())
             .show_alert()
             .unwrap();
+
+        std::process::exit(1);
     }
 }